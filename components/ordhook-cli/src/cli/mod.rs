@@ -2,7 +2,6 @@ use crate::config::file::ConfigFile;
 use crate::config::generator::generate_config;
 use clap::{Parser, Subcommand};
 use hiro_system_kit;
-use ordhook::chainhook_sdk::bitcoincore_rpc::{Auth, Client, RpcApi};
 use ordhook::chainhook_sdk::chainhooks::types::{BitcoinChainhookSpecification, HttpHook};
 use ordhook::chainhook_sdk::chainhooks::types::{
     BitcoinPredicateType, ChainhookFullSpecification, HookAction, OrdinalOperations,
@@ -16,24 +15,30 @@ use ordhook::chainhook_sdk::utils::BlockHeights;
 use ordhook::chainhook_sdk::utils::Context;
 use ordhook::config::Config;
 use ordhook::core::new_traversals_lazy_cache;
+use ordhook::core::pipeline::block_source::build_block_source;
 use ordhook::core::pipeline::download_and_pipeline_blocks;
 use ordhook::core::pipeline::processors::block_archiving::start_block_archiving_processor;
 use ordhook::core::pipeline::processors::start_inscription_indexing_processor;
 use ordhook::core::protocol::inscription_parsing::parse_inscriptions_and_standardize_block;
 use ordhook::core::protocol::satoshi_numbering::compute_satoshi_number;
+use ordhook::db::check::{check_ordhook_db, fix_ordhook_db};
 use ordhook::db::{
     delete_data_in_ordhook_db, find_all_inscription_transfers, find_all_inscriptions_in_block,
     find_all_transfers_in_block, find_block_bytes_at_block_height, find_inscription_with_id,
-    find_last_block_inserted, find_latest_inscription_block_height, find_missing_blocks,
-    get_default_ordhook_db_file_path, initialize_ordhook_db, open_ordhook_db_conn_rocks_db_loop,
-    open_readonly_ordhook_db_conn, open_readonly_ordhook_db_conn_rocks_db,
-    open_readwrite_ordhook_db_conn, BlockBytesCursor,
+    find_latest_inscription_block_height, get_default_ordhook_db_file_path, initialize_ordhook_db,
+    log_rocks_db_options, migrate_blocks_format, migrate_ordhook_db,
+    open_ordhook_db_conn_rocks_db_loop, open_readonly_ordhook_db_conn,
+    open_readonly_ordhook_db_conn_rocks_db, open_readwrite_ordhook_db_conn, BlockBytesCursor,
 };
 use ordhook::download::download_ordinals_dataset_if_required;
 use ordhook::hex;
 use ordhook::scan::bitcoin::scan_bitcoin_chainstate_via_rpc_using_predicate;
 use ordhook::service::observers::initialize_observers_db;
 use ordhook::service::{start_observer_forwarding, Service};
+use ordhook::snapshot::{
+    create_ordhook_db_snapshot, default_snapshot_base_name, snapshot_archive_path,
+};
+use ordhook::verify::{verify_inscriptions, InscriptionNumbering, InscriptionsReference};
 use reqwest::Client as HttpClient;
 use std::io::{BufReader, Read};
 use std::path::PathBuf;
@@ -63,6 +68,9 @@ enum Command {
     /// Perform maintenance operations on local databases
     #[clap(subcommand)]
     Db(OrdhookDbCommand),
+    /// Compare a sample of indexed inscriptions with a reference ord instance
+    #[clap(name = "verify", bin_name = "verify")]
+    Verify(VerifyCommand),
 }
 
 #[derive(Subcommand, PartialEq, Clone, Debug)]
@@ -361,9 +369,32 @@ enum OrdhookDbCommand {
     /// Check integrity
     #[clap(name = "check", bin_name = "check")]
     Check(CheckDbCommand),
+    /// Apply pending schema migrations
+    #[clap(name = "migrate", bin_name = "migrate")]
+    Migrate(MigrateOrdhookDbCommand),
     /// Db maintenance related commands
     #[clap(subcommand)]
     Repair(RepairCommand),
+    /// Db snapshots related commands
+    #[clap(subcommand)]
+    Snapshot(SnapshotCommand),
+}
+
+#[derive(Subcommand, PartialEq, Clone, Debug)]
+enum SnapshotCommand {
+    /// Create an archive of the local databases, that can be used for bootstrapping
+    #[clap(name = "create", bin_name = "create")]
+    Create(CreateSnapshotCommand),
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct CreateSnapshotCommand {
+    /// Path of the archive, without extension (--output ./signet-ordhook-sqlite-latest)
+    #[clap(long = "output")]
+    pub output: Option<String>,
+    /// Load config file path
+    #[clap(long = "config-path")]
+    pub config_path: Option<String>,
 }
 
 #[derive(Subcommand, PartialEq, Clone, Debug)]
@@ -490,6 +521,9 @@ struct MigrateOrdhookDbCommand {
     /// Load config file path
     #[clap(long = "config-path")]
     pub config_path: Option<String>,
+    /// Also rewrite the blocks stored in hord.rocksdb with the current format and compression
+    #[clap(long = "blocks")]
+    pub blocks: bool,
 }
 
 #[derive(Parser, PartialEq, Clone, Debug)]
@@ -501,6 +535,38 @@ struct CheckDbCommand {
     /// Load config file path
     #[clap(long = "config-path")]
     pub config_path: Option<String>,
+    /// Apply the available fixes
+    #[clap(long = "fix")]
+    pub fix: bool,
+    /// Write the JSON report to a file instead of stdout
+    #[clap(long = "output")]
+    pub output: Option<String>,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct VerifyCommand {
+    /// Starting block
+    pub start_block: u64,
+    /// Ending block
+    pub end_block: u64,
+    /// Number of inscriptions to compare
+    #[clap(long = "sample-size", default_value = "100")]
+    pub sample_size: usize,
+    /// Base URL of the reference ord server (--reference-url http://localhost:80)
+    #[clap(long = "reference-url", conflicts_with = "reference_file")]
+    pub reference_url: Option<String>,
+    /// JSON file listing reference inscriptions, formatted like ord server responses
+    #[clap(long = "reference-file", conflicts_with = "reference_url")]
+    pub reference_file: Option<String>,
+    /// Compare classic inscription numbers instead of jubilee numbers
+    #[clap(long = "classic-numbers")]
+    pub classic_numbers: bool,
+    /// Write the JSON report to a file instead of stdout
+    #[clap(long = "output")]
+    pub output: Option<String>,
+    /// Load config file path
+    #[clap(long = "config-path")]
+    pub config_path: Option<String>,
 }
 
 pub fn main() {
@@ -535,19 +601,16 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
             // If console:
             // - Replay based on SQLite queries
             // If post-to:
-            // - Replay that requires connection to bitcoind
+            // - Replay that requires connection to the block source
             let block_heights = parse_blocks_heights_spec(&cmd.blocks_interval, &cmd.blocks);
             let mut block_range = block_heights.get_sorted_entries();
             if let Some(ref post_to) = cmd.post_to {
-                info!(ctx.expect_logger(), "A fully synchronized bitcoind node is required for retrieving inscriptions content.");
-                info!(
-                    ctx.expect_logger(),
-                    "Checking {}...", config.network.bitcoind_rpc_url
-                );
-                let tip = check_bitcoind_connection(&config).await?;
+                info!(ctx.expect_logger(), "A fully synchronized block source is required for retrieving inscriptions content.");
+                info!(ctx.expect_logger(), "Checking block source...");
+                let tip = check_block_source_connection(&config, ctx)?;
                 if let Some(highest_desired) = block_range.pop_back() {
                     if tip < highest_desired {
-                        error!(ctx.expect_logger(), "Unable to scan desired block range: underlying bitcoind synchronized until block #{} ", tip);
+                        error!(ctx.expect_logger(), "Unable to scan desired block range: underlying block source synchronized until block #{} ", tip);
                     } else {
                         info!(ctx.expect_logger(), "Starting scan");
                     }
@@ -572,7 +635,7 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                 )
                 .await?;
             } else {
-                let _ = download_ordinals_dataset_if_required(&config, ctx).await;
+                download_ordinals_dataset_if_required(&config, ctx).await?;
                 let mut total_inscriptions = 0;
                 let mut total_transfers = 0;
 
@@ -630,7 +693,7 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
             let config: Config =
                 ConfigFile::default(cmd.regtest, cmd.testnet, cmd.mainnet, &cmd.config_path)?;
 
-            let _ = download_ordinals_dataset_if_required(&config, ctx).await;
+            download_ordinals_dataset_if_required(&config, ctx).await?;
 
             let inscriptions_db_conn =
                 open_readonly_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
@@ -676,7 +739,7 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
             )
             .await?;
             let transaction_identifier = TransactionIdentifier::new(&cmd.transaction_id);
-            let cache = new_traversals_lazy_cache(100);
+            let cache = new_traversals_lazy_cache(config.resources.get_memory_budget());
             let (res, _, mut back_trace) = compute_satoshi_number(
                 &config.get_ordhook_config().db_path,
                 &block.block_identifier,
@@ -684,8 +747,7 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                 cmd.input_index,
                 0,
                 &Arc::new(cache),
-                config.resources.ulimit,
-                config.resources.memory_available,
+                &config.resources,
                 true,
                 ctx,
             )?;
@@ -718,14 +780,11 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                     open_ordhook_db_conn_rocks_db_loop(
                         true,
                         &config.expected_cache_path(),
-                        config.resources.ulimit,
-                        config.resources.memory_available,
+                        &config.resources,
                         ctx,
                     );
                 }
 
-                let ordhook_config = config.get_ordhook_config();
-
                 info!(ctx.expect_logger(), "Starting service...",);
 
                 let start_block = match cmd.start_at_block {
@@ -736,9 +795,9 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                             warn!(
                                 ctx.expect_logger(),
                                 "Inscription ingestion will start at block #{}",
-                                ordhook_config.first_inscription_height
+                                config.network_params.first_inscription_height
                             );
-                            ordhook_config.first_inscription_height
+                            config.network_params.first_inscription_height
                         }
                     },
                 };
@@ -780,36 +839,48 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
         Command::Db(OrdhookDbCommand::New(cmd)) => {
             let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
             initialize_ordhook_db(&config.expected_cache_path(), ctx);
-            open_ordhook_db_conn_rocks_db_loop(
+            let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
                 true,
                 &config.expected_cache_path(),
-                config.resources.ulimit,
-                config.resources.memory_available,
+                &config.resources,
                 ctx,
             );
+            log_rocks_db_options(&blocks_db_rw, ctx);
         }
         Command::Db(OrdhookDbCommand::Sync(cmd)) => {
             let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
             initialize_ordhook_db(&config.expected_cache_path(), ctx);
+            {
+                let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                    true,
+                    &config.expected_cache_path(),
+                    &config.resources,
+                    ctx,
+                );
+                log_rocks_db_options(&blocks_db_rw, ctx);
+            }
             let service = Service::new(config, ctx.clone());
+            service.listen_for_shutdown_signals();
             service.update_state(None).await?;
         }
         Command::Db(OrdhookDbCommand::Repair(subcmd)) => match subcmd {
             RepairCommand::Blocks(cmd) => {
-                let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
-                let mut ordhook_config = config.get_ordhook_config();
+                let mut config = ConfigFile::default(false, false, false, &cmd.config_path)?;
                 if let Some(network_threads) = cmd.network_threads {
-                    ordhook_config.resources.bitcoind_rpc_threads = network_threads;
+                    config.resources.bitcoind_rpc_threads = network_threads;
                 }
                 let blocks = cmd.get_blocks();
+                let block_source = build_block_source(&config, ctx)?;
                 let block_ingestion_processor =
                     start_block_archiving_processor(&config, ctx, false, None);
                 download_and_pipeline_blocks(
                     &config,
+                    block_source.as_ref(),
                     blocks,
-                    ordhook_config.first_inscription_height,
+                    config.network_params.first_inscription_height,
                     Some(&block_ingestion_processor),
                     10_000,
+                    None,
                     ctx,
                 )
                 .await?;
@@ -817,8 +888,7 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                     let blocks_db = open_ordhook_db_conn_rocks_db_loop(
                         false,
                         &config.get_ordhook_config().db_path,
-                        config.resources.ulimit,
-                        config.resources.memory_available,
+                        &config.resources,
                         ctx,
                     );
                     for i in cmd.get_blocks().into_iter() {
@@ -835,10 +905,9 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                 }
             }
             RepairCommand::Inscriptions(cmd) => {
-                let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
-                let mut ordhook_config = config.get_ordhook_config();
+                let mut config = ConfigFile::default(false, false, false, &cmd.config_path)?;
                 if let Some(network_threads) = cmd.network_threads {
-                    ordhook_config.resources.bitcoind_rpc_threads = network_threads;
+                    config.resources.bitcoind_rpc_threads = network_threads;
                 }
                 let block_post_processor = match cmd.repair_observers {
                     Some(true) => {
@@ -849,15 +918,18 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                     _ => None,
                 };
                 let blocks = cmd.get_blocks();
+                let block_source = build_block_source(&config, ctx)?;
                 let inscription_indexing_processor =
                     start_inscription_indexing_processor(&config, ctx, block_post_processor);
 
                 download_and_pipeline_blocks(
                     &config,
+                    block_source.as_ref(),
                     blocks,
-                    ordhook_config.first_inscription_height,
+                    config.network_params.first_inscription_height,
                     Some(&inscription_indexing_processor),
                     10_000,
+                    None,
                     ctx,
                 )
                 .await?;
@@ -886,19 +958,198 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
                 }
             }
         },
+        Command::Db(OrdhookDbCommand::Snapshot(subcmd)) => match subcmd {
+            SnapshotCommand::Create(cmd) => {
+                let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
+                let base_path = match cmd.output {
+                    Some(output) => PathBuf::from(output),
+                    None => {
+                        PathBuf::from(default_snapshot_base_name(&config.network.bitcoin_network))
+                    }
+                };
+                let manifest = create_ordhook_db_snapshot(&config, &base_path, ctx)?;
+                info!(
+                    ctx.expect_logger(),
+                    "Snapshot {} created (block #{}, {})",
+                    snapshot_archive_path(&base_path).display(),
+                    manifest.tip_height,
+                    manifest.tip_block_hash
+                );
+            }
+        },
+        Command::Db(OrdhookDbCommand::Migrate(cmd)) => {
+            let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
+            let mut inscriptions_db_conn_rw =
+                open_readwrite_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+            let (from_version, to_version) = migrate_ordhook_db(&mut inscriptions_db_conn_rw, ctx)?;
+            if from_version == to_version {
+                info!(
+                    ctx.expect_logger(),
+                    "hord.sqlite is up to date (schema version {})", to_version
+                );
+            } else {
+                info!(
+                    ctx.expect_logger(),
+                    "hord.sqlite migrated from schema version {} to {}", from_version, to_version
+                );
+            }
+            if cmd.blocks {
+                let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                    true,
+                    &config.expected_cache_path(),
+                    &config.resources,
+                    ctx,
+                );
+                log_rocks_db_options(&blocks_db_rw, ctx);
+                let report = migrate_blocks_format(&blocks_db_rw, ctx)?;
+                info!(
+                    ctx.expect_logger(),
+                    "{} blocks rewritten out of {}, blocks using {} MB (previously {} MB), {} MB saved",
+                    report.blocks_rewritten,
+                    report.blocks_scanned,
+                    report.size_after / (1024 * 1024),
+                    report.size_before / (1024 * 1024),
+                    report.space_savings() / (1024 * 1024)
+                );
+                if !report.blocks_with_txid_prefix_collisions.is_empty() {
+                    warn!(
+                        ctx.expect_logger(),
+                        "Blocks including colliding txid prefixes must be rewritten with `ordhook db repair blocks --blocks {}`",
+                        report
+                            .blocks_with_txid_prefix_collisions
+                            .iter()
+                            .map(|block_height| block_height.to_string())
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    );
+                }
+            }
+        }
         Command::Db(OrdhookDbCommand::Check(cmd)) => {
             let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
-            {
+            config.check_sqlite_inscriptions_index("db check")?;
+            let mut report = {
                 let blocks_db = open_readonly_ordhook_db_conn_rocks_db(
                     &config.expected_cache_path(),
-                    config.resources.ulimit,
-                    config.resources.memory_available,
+                    &config.resources,
                     ctx,
                 )?;
-                let tip = find_last_block_inserted(&blocks_db);
-                println!("Tip: {}", tip);
-                let missing_blocks = find_missing_blocks(&blocks_db, 1, tip, ctx);
-                println!("{:?}", missing_blocks);
+                log_rocks_db_options(&blocks_db, ctx);
+                let inscriptions_db_conn =
+                    open_readonly_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+                check_ordhook_db(
+                    cmd.start_block,
+                    cmd.end_block,
+                    &blocks_db,
+                    &inscriptions_db_conn,
+                    ctx,
+                )?
+            };
+            if cmd.fix && !report.is_consistent() {
+                let blocks = {
+                    let inscriptions_db_conn_rw =
+                        open_readwrite_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+                    fix_ordhook_db(&mut report, &inscriptions_db_conn_rw, ctx)?
+                };
+                if !blocks.is_empty() {
+                    let block_source = build_block_source(&config, ctx)?;
+                    let inscription_indexing_processor =
+                        start_inscription_indexing_processor(&config, ctx, None);
+                    download_and_pipeline_blocks(
+                        &config,
+                        block_source.as_ref(),
+                        blocks.clone(),
+                        config.network_params.first_inscription_height,
+                        Some(&inscription_indexing_processor),
+                        10_000,
+                        None,
+                        ctx,
+                    )
+                    .await?;
+                    report.fixes.push(format!(
+                        "inscriptions data re-indexed at blocks {}",
+                        blocks
+                            .iter()
+                            .map(|block_height| format!("#{block_height}"))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ));
+                }
+            }
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| format!("unable to serialize report: {}", e.to_string()))?;
+            match cmd.output {
+                Some(ref path) => std::fs::write(path, json)
+                    .map_err(|e| format!("unable to write {}: {}", path, e.to_string()))?,
+                None => println!("{}", json),
+            }
+            let mut legacy_collisions = report
+                .txid_prefix_collisions
+                .iter()
+                .filter(|collision| !collision.encoded_with_full_txids)
+                .map(|collision| collision.block_height.to_string())
+                .collect::<Vec<_>>();
+            legacy_collisions.dedup();
+            if !legacy_collisions.is_empty() {
+                warn!(
+                    ctx.expect_logger(),
+                    "Blocks including colliding txid prefixes must be rewritten with `ordhook db repair blocks --blocks {}`",
+                    legacy_collisions.join(",")
+                );
+            }
+            if !report.is_consistent() && !cmd.fix {
+                error!(ctx.expect_logger(), "Database check failed");
+                process::exit(1);
+            }
+        }
+        Command::Verify(cmd) => {
+            let config = ConfigFile::default(false, false, false, &cmd.config_path)?;
+            let reference = match (cmd.reference_url, cmd.reference_file) {
+                (Some(url), None) => InscriptionsReference::OrdServer(url),
+                (None, Some(path)) => {
+                    InscriptionsReference::from_fixture_file(&PathBuf::from(path))?
+                }
+                _ => return Err("either --reference-url or --reference-file is required".into()),
+            };
+            let numbering = match cmd.classic_numbers {
+                true => InscriptionNumbering::Classic,
+                false => InscriptionNumbering::Jubilee,
+            };
+            let inscriptions_db_conn =
+                open_readonly_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+            let report = verify_inscriptions(
+                &config,
+                &reference,
+                cmd.start_block,
+                cmd.end_block,
+                cmd.sample_size,
+                &numbering,
+                &inscriptions_db_conn,
+                ctx,
+            )
+            .await?;
+            let json = serde_json::to_string_pretty(&report)
+                .map_err(|e| format!("unable to serialize report: {}", e.to_string()))?;
+            match cmd.output {
+                Some(ref path) => std::fs::write(path, json)
+                    .map_err(|e| format!("unable to write {}: {}", path, e.to_string()))?,
+                None => println!("{}", json),
+            }
+            if !report.unverified_owners.is_empty() {
+                warn!(
+                    ctx.expect_logger(),
+                    "Owners of {} inscriptions could not be verified with the block source",
+                    report.unverified_owners.len()
+                );
+            }
+            if !report.is_consistent() {
+                error!(
+                    ctx.expect_logger(),
+                    "{} divergences found, {} inscriptions missing from reference",
+                    report.divergences.len(),
+                    report.missing_from_reference.len()
+                );
+                process::exit(1);
             }
         }
         Command::Db(OrdhookDbCommand::Drop(cmd)) => {
@@ -906,10 +1157,10 @@ async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
             let blocks_db = open_ordhook_db_conn_rocks_db_loop(
                 true,
                 &config.expected_cache_path(),
-                config.resources.ulimit,
-                config.resources.memory_available,
+                &config.resources,
                 ctx,
             );
+            log_rocks_db_options(&blocks_db, ctx);
             let inscriptions_db_conn_rw =
                 open_readwrite_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
 
@@ -1000,27 +1251,10 @@ pub fn build_predicate_from_cli(
     Ok(predicate)
 }
 
-pub async fn check_bitcoind_connection(config: &Config) -> Result<u64, String> {
-    let auth = Auth::UserPass(
-        config.network.bitcoind_rpc_username.clone(),
-        config.network.bitcoind_rpc_password.clone(),
-    );
-
-    let bitcoin_rpc = match Client::new(&config.network.bitcoind_rpc_url, auth) {
-        Ok(con) => con,
-        Err(message) => {
-            return Err(format!("unable to connect to bitcoind: {}", message));
-        }
-    };
-
-    let end_block = match bitcoin_rpc.get_blockchain_info() {
-        Ok(result) => result.blocks,
-        Err(e) => {
-            return Err(format!("unable to connect to bitcoind: {}", e));
-        }
-    };
-
-    Ok(end_block)
+pub fn check_block_source_connection(config: &Config, ctx: &Context) -> Result<u64, String> {
+    build_block_source(config, ctx)?
+        .get_chain_tip(ctx)
+        .map_err(|e| format!("unable to connect to block source: {}", e))
 }
 
 fn parse_blocks_heights_spec(
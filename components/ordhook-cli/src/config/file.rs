@@ -4,12 +4,17 @@ use ordhook::chainhook_sdk::types::{
     BitcoinBlockSignaling, BitcoinNetwork, StacksNetwork, StacksNodeConfig,
 };
 use ordhook::config::{
-    Config, LogConfig, PredicatesApi, PredicatesApiConfig, ResourcesConfig, SnapshotConfig,
-    StorageConfig, DEFAULT_BITCOIND_RPC_THREADS, DEFAULT_BITCOIND_RPC_TIMEOUT,
-    DEFAULT_CONTROL_PORT, DEFAULT_MEMORY_AVAILABLE, DEFAULT_ULIMIT,
+    BlockSourceConfig, BlocksCompression, CompactionStyle, Config, InscriptionsIndexLayout,
+    LogConfig, NetworkParams, PredicatesApi, PredicatesApiConfig, ResourcesConfig, RocksDbConfig,
+    SnapshotConfig, SnapshotDownloadConfig, StorageConfig, DEFAULT_BITCOIND_RPC_THREADS,
+    DEFAULT_BITCOIND_RPC_TIMEOUT, DEFAULT_CONTROL_PORT, DEFAULT_MEMORY_AVAILABLE,
+    DEFAULT_ROCKSDB_BLOCK_CACHE_SIZE_MB, DEFAULT_ROCKSDB_MAX_BACKGROUND_JOBS,
+    DEFAULT_ROCKSDB_MAX_WRITE_BUFFER_NUMBER, DEFAULT_ROCKSDB_WRITE_BUFFER_SIZE_MB, DEFAULT_ULIMIT,
+    DEFAULT_UNDO_JOURNAL_DEPTH, DEFAULT_ZSTD_DICTIONARY_SIZE_KB, DEFAULT_ZSTD_LEVEL,
 };
 use std::fs::File;
 use std::io::{BufReader, Read};
+use std::sync::Arc;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ConfigFile {
@@ -51,15 +56,140 @@ impl ConfigFile {
 
         let snapshot = match config_file.snapshot {
             Some(bootstrap) => match bootstrap.download_url {
-                Some(ref url) => SnapshotConfig::Download(url.to_string()),
+                Some(ref url) => SnapshotConfig::Download(SnapshotDownloadConfig {
+                    url: url.to_string(),
+                    allow_missing_manifest: bootstrap.allow_missing_manifest.unwrap_or(false),
+                }),
                 None => SnapshotConfig::Build,
             },
             None => SnapshotConfig::Build,
         };
 
+        let inscriptions_index = match config_file.storage.inscriptions_index.as_deref() {
+            None | Some("sqlite") => InscriptionsIndexLayout::Sqlite,
+            Some("rocksdb") => InscriptionsIndexLayout::RocksDb,
+            #[cfg(feature = "postgres")]
+            Some("postgres") => match config_file.storage.postgres_url {
+                Some(ref database_url) => InscriptionsIndexLayout::Postgres(database_url.clone()),
+                None => {
+                    return Err(
+                        "storage.postgres_url is required with storage.inscriptions_index = \"postgres\"".to_string(),
+                    )
+                }
+            },
+            #[cfg(not(feature = "postgres"))]
+            Some("postgres") => {
+                return Err(
+                    "storage.inscriptions_index postgres requires ordhook to be built with the postgres feature".to_string(),
+                )
+            }
+            Some(layout) => {
+                return Err(format!(
+                    "storage.inscriptions_index {layout} not supported (expected sqlite, rocksdb or postgres)"
+                ))
+            }
+        };
+
+        let rocksdb = config_file.storage.rocksdb.unwrap_or_default();
+        let blocks_compression = match rocksdb.blocks_compression.as_deref() {
+            None | Some("snappy") => BlocksCompression::Snappy,
+            Some("zstd") => BlocksCompression::Zstd {
+                level: rocksdb.zstd_level.unwrap_or(DEFAULT_ZSTD_LEVEL),
+                dictionary_size_kb: rocksdb
+                    .zstd_dictionary_size_kb
+                    .unwrap_or(DEFAULT_ZSTD_DICTIONARY_SIZE_KB),
+            },
+            Some(compression) => {
+                return Err(format!(
+                    "storage.rocksdb.blocks_compression {compression} not supported (expected snappy or zstd)"
+                ))
+            }
+        };
+        let compaction_style = match rocksdb.compaction_style.as_deref() {
+            None | Some("level") => CompactionStyle::Level,
+            Some("universal") => CompactionStyle::Universal,
+            Some(style) => {
+                return Err(format!(
+                    "storage.rocksdb.compaction_style {style} not supported (expected level or universal)"
+                ))
+            }
+        };
+        let rocksdb = RocksDbConfig {
+            blocks_compression,
+            block_cache_size_mb: rocksdb
+                .block_cache_size_mb
+                .unwrap_or(DEFAULT_ROCKSDB_BLOCK_CACHE_SIZE_MB),
+            write_buffer_size_mb: rocksdb
+                .write_buffer_size_mb
+                .unwrap_or(DEFAULT_ROCKSDB_WRITE_BUFFER_SIZE_MB),
+            max_write_buffer_number: rocksdb
+                .max_write_buffer_number
+                .unwrap_or(DEFAULT_ROCKSDB_MAX_WRITE_BUFFER_NUMBER),
+            compaction_style,
+            bloom_filter_bits_per_key: rocksdb.bloom_filter_bits_per_key.unwrap_or(0),
+            max_background_jobs: rocksdb
+                .max_background_jobs
+                .unwrap_or(DEFAULT_ROCKSDB_MAX_BACKGROUND_JOBS),
+        };
+
+        let block_source =
+            match (
+                &config_file.network.bitcoind_blocks_dir,
+                &config_file.network.esplora_url,
+            ) {
+                (None, None) => BlockSourceConfig::BitcoindRpc,
+                (Some(blocks_dir), None) => BlockSourceConfig::BitcoindBlocksDir(blocks_dir.into()),
+                (None, Some(esplora_url)) => BlockSourceConfig::Esplora(esplora_url.clone()),
+                (Some(_), Some(_)) => return Err(
+                    "network.bitcoind_blocks_dir and network.esplora_url are mutually exclusive"
+                        .to_string(),
+                ),
+            };
+
+        // bitcoind RPC is only optional when blocks are served (and streamed) by Esplora
+        let rpc_field = |field: &Option<String>, name: &str| match field {
+            Some(value) => Ok(value.clone()),
+            None if config_file.network.esplora_url.is_some() => Ok(String::new()),
+            None => Err(format!(
+                "network.{name} is required unless network.esplora_url is set"
+            )),
+        };
+        let bitcoind_rpc_url =
+            rpc_field(&config_file.network.bitcoind_rpc_url, "bitcoind_rpc_url")?;
+        let bitcoind_rpc_username = rpc_field(
+            &config_file.network.bitcoind_rpc_username,
+            "bitcoind_rpc_username",
+        )?;
+        let bitcoind_rpc_password = rpc_field(
+            &config_file.network.bitcoind_rpc_password,
+            "bitcoind_rpc_password",
+        )?;
+
+        let mut network_params = NetworkParams::for_network(&bitcoin_network);
+        if let Some(ref params) = config_file.network.params {
+            if let Some(first_inscription_height) = params.first_inscription_height {
+                network_params.first_inscription_height = first_inscription_height;
+            }
+            if let Some(jubilee_height) = params.jubilee_height {
+                network_params.jubilee_height = jubilee_height;
+            }
+            if let Some(ref genesis_block_hash) = params.genesis_block_hash {
+                network_params.genesis_block_hash = genesis_block_hash.to_lowercase();
+            }
+            if let Some(ref address_hrp) = params.address_hrp {
+                network_params.address_hrp = address_hrp.to_lowercase();
+            }
+        }
+
         let config = Config {
             storage: StorageConfig {
                 working_dir: config_file.storage.working_dir.unwrap_or("ordhook".into()),
+                inscriptions_index,
+                undo_journal_depth: config_file
+                    .storage
+                    .undo_journal_depth
+                    .unwrap_or(DEFAULT_UNDO_JOURNAL_DEPTH),
+                traversals_cache_size_mb: config_file.storage.traversals_cache_size_mb.unwrap_or(0),
             },
             http_api: match config_file.http_api {
                 None => PredicatesApi::Off,
@@ -94,11 +224,13 @@ impl ConfigFile {
                     .resources
                     .expected_observers_count
                     .unwrap_or(1),
+                rocksdb,
+                memory_in_use: Arc::default(),
             },
             network: IndexerConfig {
-                bitcoind_rpc_url: config_file.network.bitcoind_rpc_url.to_string(),
-                bitcoind_rpc_username: config_file.network.bitcoind_rpc_username.to_string(),
-                bitcoind_rpc_password: config_file.network.bitcoind_rpc_password.to_string(),
+                bitcoind_rpc_url,
+                bitcoind_rpc_username,
+                bitcoind_rpc_password,
                 bitcoin_block_signaling: match config_file.network.bitcoind_zmq_url {
                     Some(ref zmq_url) => BitcoinBlockSignaling::ZeroMQ(zmq_url.clone()),
                     None => BitcoinBlockSignaling::Stacks(StacksNodeConfig::default_localhost(
@@ -111,6 +243,8 @@ impl ConfigFile {
                 stacks_network,
                 bitcoin_network,
             },
+            network_params,
+            block_source,
             logs: LogConfig {
                 ordinals_internals: config_file
                     .logs
@@ -153,6 +287,24 @@ pub struct LogConfigFile {
 #[derive(Deserialize, Debug, Clone)]
 pub struct StorageConfigFile {
     pub working_dir: Option<String>,
+    pub inscriptions_index: Option<String>,
+    pub postgres_url: Option<String>,
+    pub undo_journal_depth: Option<u64>,
+    pub traversals_cache_size_mb: Option<u64>,
+    pub rocksdb: Option<RocksDbConfigFile>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RocksDbConfigFile {
+    pub blocks_compression: Option<String>,
+    pub zstd_level: Option<i32>,
+    pub zstd_dictionary_size_kb: Option<u32>,
+    pub block_cache_size_mb: Option<usize>,
+    pub write_buffer_size_mb: Option<usize>,
+    pub max_write_buffer_number: Option<i32>,
+    pub compaction_style: Option<String>,
+    pub bloom_filter_bits_per_key: Option<u32>,
+    pub max_background_jobs: Option<i32>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -166,6 +318,7 @@ pub struct PredicatesApiConfigFile {
 #[derive(Deserialize, Debug, Clone)]
 pub struct SnapshotConfigFile {
     pub download_url: Option<String>,
+    pub allow_missing_manifest: Option<bool>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -181,10 +334,21 @@ pub struct ResourcesConfigFile {
 #[derive(Deserialize, Debug, Clone)]
 pub struct NetworkConfigFile {
     pub mode: String,
-    pub bitcoind_rpc_url: String,
-    pub bitcoind_rpc_username: String,
-    pub bitcoind_rpc_password: String,
+    pub bitcoind_rpc_url: Option<String>,
+    pub bitcoind_rpc_username: Option<String>,
+    pub bitcoind_rpc_password: Option<String>,
     pub bitcoind_zmq_url: Option<String>,
+    pub bitcoind_blocks_dir: Option<String>,
+    pub esplora_url: Option<String>,
     pub stacks_node_rpc_url: Option<String>,
     pub stacks_events_ingestion_port: Option<u16>,
+    pub params: Option<NetworkParamsConfigFile>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct NetworkParamsConfigFile {
+    pub first_inscription_height: Option<u64>,
+    pub jubilee_height: Option<u64>,
+    pub genesis_block_hash: Option<String>,
+    pub address_hrp: Option<String>,
 }
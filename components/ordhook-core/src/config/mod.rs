@@ -1,10 +1,15 @@
 use crate::core::OrdhookConfig;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::bech32::{self, u5, ToBase32, Variant};
+use chainhook_sdk::bitcoincore_rpc::bitcoin::blockdata::constants::genesis_block;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{Address, Network, Script};
 pub use chainhook_sdk::indexer::IndexerConfig;
 use chainhook_sdk::observer::EventObserverConfig;
 use chainhook_sdk::types::{
     BitcoinBlockSignaling, BitcoinNetwork, StacksNetwork, StacksNodeConfig,
 };
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 const DEFAULT_MAINNET_ORDINALS_SQLITE_ARCHIVE: &str =
     "https://archive.hiro.so/mainnet/ordhook/mainnet-ordhook-sqlite-latest";
@@ -15,6 +20,13 @@ pub const DEFAULT_ULIMIT: usize = 2048;
 pub const DEFAULT_MEMORY_AVAILABLE: usize = 8;
 pub const DEFAULT_BITCOIND_RPC_THREADS: usize = 4;
 pub const DEFAULT_BITCOIND_RPC_TIMEOUT: u32 = 15;
+pub const DEFAULT_UNDO_JOURNAL_DEPTH: u64 = 12;
+pub const DEFAULT_ZSTD_LEVEL: i32 = 3;
+pub const DEFAULT_ZSTD_DICTIONARY_SIZE_KB: u32 = 64;
+pub const DEFAULT_ROCKSDB_BLOCK_CACHE_SIZE_MB: usize = 32;
+pub const DEFAULT_ROCKSDB_WRITE_BUFFER_SIZE_MB: usize = 64;
+pub const DEFAULT_ROCKSDB_MAX_WRITE_BUFFER_NUMBER: i32 = 2;
+pub const DEFAULT_ROCKSDB_MAX_BACKGROUND_JOBS: i32 = 2;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -22,6 +34,8 @@ pub struct Config {
     pub http_api: PredicatesApi,
     pub resources: ResourcesConfig,
     pub network: IndexerConfig,
+    pub network_params: NetworkParams,
+    pub block_source: BlockSourceConfig,
     pub snapshot: SnapshotConfig,
     pub logs: LogConfig,
 }
@@ -35,6 +49,105 @@ pub struct LogConfig {
 #[derive(Clone, Debug)]
 pub struct StorageConfig {
     pub working_dir: String,
+    pub inscriptions_index: InscriptionsIndexLayout,
+    /// Number of recent blocks for which an undo journal is kept, 0 to disable.
+    pub undo_journal_depth: u64,
+    /// Size cap of the traversals cache persisted in hord.rocksdb across restarts, in MB,
+    /// 0 to disable.
+    pub traversals_cache_size_mb: u64,
+}
+
+/// Where the inscriptions index (inscriptions, locations, sequence metadata) is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InscriptionsIndexLayout {
+    /// hord.sqlite, next to the blocks stored in hord.rocksdb.
+    Sqlite,
+    /// Column families of hord.rocksdb: the index and the blocks are written together.
+    RocksDb,
+    /// Tables of a PostgreSQL database (connection URL), shared with other readers such as
+    /// the API tier. Blocks remain in hord.rocksdb.
+    #[cfg(feature = "postgres")]
+    Postgres(String),
+}
+
+/// Where the blocks pipelined during syncs are fetched from. Blocks mined once the service is
+/// running are always received from the chainhook observer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockSourceConfig {
+    /// `getblockhash` / `getblock` requests sent to `network.bitcoind_rpc_url`.
+    BitcoindRpc,
+    /// bitcoind's `blocks` directory: blk*.dat files, located with the LevelDB block index.
+    BitcoindBlocksDir(PathBuf),
+    /// Base URL of an Esplora (or Electrs) REST API, e.g. `https://blockstream.info/api`.
+    Esplora(String),
+}
+
+/// Chain parameters of the network indexed. Defaults to the parameters of
+/// `network.bitcoin_network`, and can be overridden to index a custom signet or a new testnet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NetworkParams {
+    pub first_inscription_height: u64,
+    /// Height from which inscriptions are numbered by their jubilee number.
+    pub jubilee_height: u64,
+    /// Hash of the genesis block, checked against the block source on startup.
+    pub genesis_block_hash: String,
+    /// Human-readable part of the segwit addresses (`bc`, `tb`, `bcrt`).
+    pub address_hrp: String,
+}
+
+/// Network of the `bitcoin` crate matching `bitcoin_network`.
+pub fn network_from_bitcoin_network(bitcoin_network: &BitcoinNetwork) -> Network {
+    match bitcoin_network {
+        BitcoinNetwork::Mainnet => Network::Bitcoin,
+        BitcoinNetwork::Regtest => Network::Regtest,
+        BitcoinNetwork::Testnet => Network::Testnet,
+        BitcoinNetwork::Signet => Network::Signet,
+    }
+}
+
+impl NetworkParams {
+    pub fn for_network(bitcoin_network: &BitcoinNetwork) -> NetworkParams {
+        let (first_inscription_height, jubilee_height, address_hrp) = match bitcoin_network {
+            BitcoinNetwork::Mainnet => (767430, 824544, "bc"),
+            BitcoinNetwork::Regtest => (1, 110, "bcrt"),
+            BitcoinNetwork::Testnet => (2413343, 2544192, "tb"),
+            BitcoinNetwork::Signet => (112402, 175392, "tb"),
+        };
+        let network = network_from_bitcoin_network(bitcoin_network);
+        NetworkParams {
+            first_inscription_height,
+            jubilee_height,
+            genesis_block_hash: genesis_block(network).block_hash().to_string(),
+            address_hrp: address_hrp.to_string(),
+        }
+    }
+
+    /// Address paying to `script`. Segwit addresses are encoded with `address_hrp`, legacy
+    /// addresses with the mainnet prefixes when `address_hrp` is `bc`, the testnet ones otherwise.
+    pub fn address_from_script(&self, script: &Script) -> Result<String, String> {
+        let network = match self.address_hrp.as_str() {
+            "bc" => Network::Bitcoin,
+            "bcrt" => Network::Regtest,
+            "tb" => Network::Testnet,
+            _ => match script.witness_version() {
+                Some(version) => {
+                    let mut data = vec![u5::try_from_u8(version.to_num())
+                        .map_err(|e| format!("invalid witness version: {e}"))?];
+                    data.extend(script.as_bytes()[2..].to_base32());
+                    let variant = match version.to_num() {
+                        0 => Variant::Bech32,
+                        _ => Variant::Bech32m,
+                    };
+                    return bech32::encode(&self.address_hrp, data, variant)
+                        .map_err(|e| format!("unable to encode address: {e}"));
+                }
+                None => Network::Testnet,
+            },
+        };
+        Address::from_script(script, network)
+            .map(|address| address.to_string())
+            .map_err(|e| e.to_string())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -52,7 +165,15 @@ pub struct PredicatesApiConfig {
 #[derive(Clone, Debug)]
 pub enum SnapshotConfig {
     Build,
-    Download(String),
+    Download(SnapshotDownloadConfig),
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapshotDownloadConfig {
+    pub url: String,
+    /// Install archives that don't include a manifest, whose network and chain tip can
+    /// not be verified.
+    pub allow_missing_manifest: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -73,6 +194,124 @@ pub struct ResourcesConfig {
     pub bitcoind_rpc_threads: usize,
     pub bitcoind_rpc_timeout: u32,
     pub expected_observers_count: usize,
+    #[serde(default)]
+    pub rocksdb: RocksDbConfig,
+    /// Bytes of the memory budget in use, shared by the clones of the config.
+    #[serde(skip)]
+    pub memory_in_use: Arc<AtomicU64>,
+}
+
+/// Memory shared by the traversals caches of the processors and of the sidecar, and by the
+/// blocks queued by the pipeline.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget {
+    limit: u64,
+    in_use: Arc<AtomicU64>,
+}
+
+impl MemoryBudget {
+    /// Budget that is not shared with other consumers.
+    pub fn new(limit: u64) -> MemoryBudget {
+        MemoryBudget {
+            limit,
+            in_use: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    pub fn in_use(&self) -> u64 {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    pub fn available(&self) -> u64 {
+        self.limit.saturating_sub(self.in_use())
+    }
+
+    /// Account for `bytes`, returning the bytes in use once allocated.
+    pub fn allocate(&self, bytes: u64) -> u64 {
+        self.in_use.fetch_add(bytes, Ordering::Relaxed) + bytes
+    }
+
+    pub fn release(&self, bytes: u64) {
+        let _ = self
+            .in_use
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |in_use| {
+                Some(in_use.saturating_sub(bytes))
+            });
+    }
+
+    /// Account for `bytes` until the reservation is dropped.
+    pub fn reserve(&self, bytes: u64) -> MemoryReservation {
+        self.allocate(bytes);
+        MemoryReservation {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+}
+
+pub struct MemoryReservation {
+    budget: MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.release(self.bytes);
+    }
+}
+
+/// Options of hord.rocksdb. Block caches and write buffers are allocated per connection and
+/// column family.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct RocksDbConfig {
+    pub blocks_compression: BlocksCompression,
+    /// Size of the LRU cache of uncompressed blocks, in MB, shared by the connections of the process.
+    pub block_cache_size_mb: usize,
+    /// Size of a memtable, in MB.
+    pub write_buffer_size_mb: usize,
+    pub max_write_buffer_number: i32,
+    pub compaction_style: CompactionStyle,
+    /// Bits per key of the bloom filters, 0 to disable them.
+    pub bloom_filter_bits_per_key: u32,
+    pub max_background_jobs: i32,
+}
+
+impl Default for RocksDbConfig {
+    fn default() -> Self {
+        RocksDbConfig {
+            blocks_compression: BlocksCompression::default(),
+            block_cache_size_mb: DEFAULT_ROCKSDB_BLOCK_CACHE_SIZE_MB,
+            write_buffer_size_mb: DEFAULT_ROCKSDB_WRITE_BUFFER_SIZE_MB,
+            max_write_buffer_number: DEFAULT_ROCKSDB_MAX_WRITE_BUFFER_NUMBER,
+            compaction_style: CompactionStyle::default(),
+            bloom_filter_bits_per_key: 0,
+            max_background_jobs: DEFAULT_ROCKSDB_MAX_BACKGROUND_JOBS,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum CompactionStyle {
+    /// Lower space and read amplification, suited to SSDs.
+    #[default]
+    Level,
+    /// Lower write amplification, at the cost of space: suited to network disks.
+    Universal,
+}
+
+/// Compression of the blocks column family. Compressed files are self-describing: changing the
+/// compression only applies to the files written afterwards (see `ordhook db migrate --blocks`).
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum BlocksCompression {
+    #[default]
+    Snappy,
+    /// zstd, with a dictionary of `dictionary_size_kb` (0 to disable) trained on the blocks
+    /// being compacted.
+    Zstd { level: i32, dictionary_size_kb: u32 },
 }
 
 impl ResourcesConfig {
@@ -82,6 +321,15 @@ impl ResourcesConfig {
         // handling the "reduce" step.
         self.cpu_core_available.saturating_sub(2).max(1)
     }
+
+    /// Memory budget shared by the traversals caches and the pipeline queue: half of
+    /// `memory_available`, the rest being left to RocksDB and to the blocks being processed.
+    pub fn get_memory_budget(&self) -> MemoryBudget {
+        MemoryBudget {
+            limit: (self.memory_available as u64) * 1024 * 1024 * 1024 / 2,
+            in_use: self.memory_in_use.clone(),
+        }
+    }
 }
 
 impl Config {
@@ -92,17 +340,18 @@ impl Config {
         }
     }
 
+    /// Esplora-only deployments leave the bitcoind RPC settings empty.
+    pub fn is_bitcoind_rpc_configured(&self) -> bool {
+        !self.network.bitcoind_rpc_url.is_empty()
+    }
+
     pub fn get_ordhook_config(&self) -> OrdhookConfig {
         OrdhookConfig {
             resources: self.resources.clone(),
             db_path: self.expected_cache_path(),
-            first_inscription_height: match self.network.bitcoin_network {
-                BitcoinNetwork::Mainnet => 767430,
-                BitcoinNetwork::Regtest => 1,
-                BitcoinNetwork::Testnet => 2413343,
-                BitcoinNetwork::Signet => 112402,
-            },
+            network_params: self.network_params.clone(),
             logs: self.logs.clone(),
+            undo_journal_depth: self.storage.undo_journal_depth,
         }
     }
 
@@ -143,10 +392,26 @@ impl Config {
         destination_path
     }
 
+    pub fn should_allow_snapshot_without_manifest(&self) -> bool {
+        match &self.snapshot {
+            SnapshotConfig::Build => false,
+            SnapshotConfig::Download(config) => config.allow_missing_manifest,
+        }
+    }
+
+    pub fn check_sqlite_inscriptions_index(&self, operation: &str) -> Result<(), String> {
+        if self.storage.inscriptions_index != InscriptionsIndexLayout::Sqlite {
+            return Err(format!(
+                "{operation} is only supported with the sqlite inscriptions index"
+            ));
+        }
+        Ok(())
+    }
+
     fn expected_remote_ordinals_sqlite_base_url(&self) -> &str {
         match &self.snapshot {
             SnapshotConfig::Build => unreachable!(),
-            SnapshotConfig::Download(url) => &url,
+            SnapshotConfig::Download(config) => &config.url,
         }
     }
 
@@ -162,6 +427,9 @@ impl Config {
         Config {
             storage: StorageConfig {
                 working_dir: default_cache_path(),
+                inscriptions_index: InscriptionsIndexLayout::Sqlite,
+                undo_journal_depth: DEFAULT_UNDO_JOURNAL_DEPTH,
+                traversals_cache_size_mb: 0,
             },
             http_api: PredicatesApi::Off,
             snapshot: SnapshotConfig::Build,
@@ -172,6 +440,8 @@ impl Config {
                 bitcoind_rpc_threads: DEFAULT_BITCOIND_RPC_THREADS,
                 bitcoind_rpc_timeout: DEFAULT_BITCOIND_RPC_TIMEOUT,
                 expected_observers_count: 1,
+                rocksdb: RocksDbConfig::default(),
+                memory_in_use: Arc::default(),
             },
             network: IndexerConfig {
                 bitcoind_rpc_url: "http://0.0.0.0:18443".into(),
@@ -183,6 +453,8 @@ impl Config {
                 stacks_network: StacksNetwork::Devnet,
                 bitcoin_network: BitcoinNetwork::Regtest,
             },
+            network_params: NetworkParams::for_network(&BitcoinNetwork::Regtest),
+            block_source: BlockSourceConfig::BitcoindRpc,
             logs: LogConfig {
                 ordinals_internals: true,
                 chainhook_internals: false,
@@ -194,6 +466,9 @@ impl Config {
         Config {
             storage: StorageConfig {
                 working_dir: default_cache_path(),
+                inscriptions_index: InscriptionsIndexLayout::Sqlite,
+                undo_journal_depth: DEFAULT_UNDO_JOURNAL_DEPTH,
+                traversals_cache_size_mb: 0,
             },
             http_api: PredicatesApi::Off,
             snapshot: SnapshotConfig::Build,
@@ -204,6 +479,8 @@ impl Config {
                 bitcoind_rpc_threads: DEFAULT_BITCOIND_RPC_THREADS,
                 bitcoind_rpc_timeout: DEFAULT_BITCOIND_RPC_TIMEOUT,
                 expected_observers_count: 1,
+                rocksdb: RocksDbConfig::default(),
+                memory_in_use: Arc::default(),
             },
             network: IndexerConfig {
                 bitcoind_rpc_url: "http://0.0.0.0:18332".into(),
@@ -215,6 +492,8 @@ impl Config {
                 stacks_network: StacksNetwork::Testnet,
                 bitcoin_network: BitcoinNetwork::Testnet,
             },
+            network_params: NetworkParams::for_network(&BitcoinNetwork::Testnet),
+            block_source: BlockSourceConfig::BitcoindRpc,
             logs: LogConfig {
                 ordinals_internals: true,
                 chainhook_internals: false,
@@ -226,9 +505,15 @@ impl Config {
         Config {
             storage: StorageConfig {
                 working_dir: default_cache_path(),
+                inscriptions_index: InscriptionsIndexLayout::Sqlite,
+                undo_journal_depth: DEFAULT_UNDO_JOURNAL_DEPTH,
+                traversals_cache_size_mb: 0,
             },
             http_api: PredicatesApi::Off,
-            snapshot: SnapshotConfig::Download(DEFAULT_MAINNET_ORDINALS_SQLITE_ARCHIVE.to_string()),
+            snapshot: SnapshotConfig::Download(SnapshotDownloadConfig {
+                url: DEFAULT_MAINNET_ORDINALS_SQLITE_ARCHIVE.to_string(),
+                allow_missing_manifest: false,
+            }),
             resources: ResourcesConfig {
                 cpu_core_available: num_cpus::get(),
                 memory_available: DEFAULT_MEMORY_AVAILABLE,
@@ -236,6 +521,8 @@ impl Config {
                 bitcoind_rpc_threads: DEFAULT_BITCOIND_RPC_THREADS,
                 bitcoind_rpc_timeout: DEFAULT_BITCOIND_RPC_TIMEOUT,
                 expected_observers_count: 1,
+                rocksdb: RocksDbConfig::default(),
+                memory_in_use: Arc::default(),
             },
             network: IndexerConfig {
                 bitcoind_rpc_url: "http://0.0.0.0:8332".into(),
@@ -247,6 +534,8 @@ impl Config {
                 stacks_network: StacksNetwork::Mainnet,
                 bitcoin_network: BitcoinNetwork::Mainnet,
             },
+            network_params: NetworkParams::for_network(&BitcoinNetwork::Mainnet),
+            block_source: BlockSourceConfig::BitcoindRpc,
             logs: LogConfig {
                 ordinals_internals: true,
                 chainhook_internals: false,
@@ -1,53 +1,103 @@
 pub mod pipeline;
 pub mod protocol;
+pub mod sync_planner;
 
-use dashmap::DashMap;
-use fxhash::{FxBuildHasher, FxHasher};
-use std::hash::BuildHasherDefault;
-use std::ops::Div;
 use std::path::PathBuf;
 
-use chainhook_sdk::{
-    bitcoincore_rpc::{Auth, Client, RpcApi},
-    utils::Context,
-};
+use chainhook_sdk::utils::Context;
+
+use rocksdb::DB;
 
 use crate::{
-    config::{Config, LogConfig, ResourcesConfig},
-    db::{find_pinned_block_bytes_at_block_height, open_ordhook_db_conn_rocks_db_loop},
+    config::{
+        Config, InscriptionsIndexLayout, LogConfig, MemoryBudget, NetworkParams, ResourcesConfig,
+    },
+    core::pipeline::block_source::BlockSource,
+    core::sync_planner::{SyncBatch, SyncPlanner},
+    db::{
+        open_ordhook_db_conn_rocks_db_loop, open_readonly_ordhook_db_conn_rocks_db,
+        store::{
+            rocksdb::{create_inscriptions_column_families, RocksDbInscriptionStore},
+            ConfiguredInscriptionStore, InscriptionStore,
+        },
+        sync_checkpoint::{find_sync_checkpoint, write_sync_checkpoint, SyncCheckpoint},
+        traversals_cache::{load_traversals_cache, save_traversals_cache, TraversalsCache},
+    },
 };
 
+#[cfg(feature = "postgres")]
+use crate::db::store::postgres::PostgresInscriptionStore;
 use crate::db::{
-    find_last_block_inserted, find_latest_inscription_block_height, initialize_ordhook_db,
-    open_readonly_ordhook_db_conn,
+    delete_data_in_ordhook_db, find_last_block_inserted, find_latest_inscription_block_height,
+    initialize_ordhook_db, open_readonly_ordhook_db_conn, open_readwrite_ordhook_dbs,
 };
 
-use crate::db::TransactionBytesCursor;
-
 #[derive(Clone, Debug)]
 pub struct OrdhookConfig {
     pub resources: ResourcesConfig,
     pub db_path: PathBuf,
-    pub first_inscription_height: u64,
+    pub network_params: NetworkParams,
     pub logs: LogConfig,
+    pub undo_journal_depth: u64,
 }
 
-pub fn new_traversals_cache(
-) -> DashMap<(u32, [u8; 8]), (Vec<([u8; 8], u32, u16, u64)>, Vec<u64>), BuildHasherDefault<FxHasher>>
-{
-    let hasher = FxBuildHasher::default();
-    DashMap::with_hasher(hasher)
-}
-
-pub fn new_traversals_lazy_cache(
-    cache_size: usize,
-) -> DashMap<(u32, [u8; 8]), TransactionBytesCursor, BuildHasherDefault<FxHasher>> {
-    let hasher = FxBuildHasher::default();
-    DashMap::with_capacity_and_hasher(
-        ((cache_size.saturating_sub(500)) * 1000 * 1000)
-            .div(TransactionBytesCursor::get_average_bytes_size()),
-        hasher,
+pub fn new_traversals_lazy_cache(memory_budget: MemoryBudget) -> TraversalsCache {
+    TraversalsCache::new(memory_budget)
+}
+
+/// Warm up `traversals_cache` with the entries persisted by `persist_traversals_cache`, when
+/// `storage.traversals_cache_size_mb` is enabled.
+pub fn warm_up_traversals_cache(
+    config: &Config,
+    traversals_cache: &TraversalsCache,
+    ctx: &Context,
+) {
+    if config.storage.traversals_cache_size_mb == 0 {
+        return;
+    }
+    let res = open_readonly_ordhook_db_conn_rocks_db(
+        &config.expected_cache_path(),
+        &config.resources,
+        ctx,
     )
+    .and_then(|blocks_db| load_traversals_cache(traversals_cache, &blocks_db, ctx));
+    match res {
+        Ok(loaded) => {
+            ctx.try_log(|logger| info!(logger, "Traversals cache warmed up with {loaded} entries"))
+        }
+        Err(e) => ctx.try_log(|logger| warn!(logger, "Unable to warm up traversals cache: {e}")),
+    }
+}
+
+/// Persist `traversals_cache` in hord.rocksdb, within the `storage.traversals_cache_size_mb` cap.
+pub fn persist_traversals_cache(
+    config: &Config,
+    traversals_cache: &TraversalsCache,
+    ctx: &Context,
+) {
+    if config.storage.traversals_cache_size_mb == 0 || traversals_cache.is_empty() {
+        return;
+    }
+    let mut blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+        true,
+        &config.expected_cache_path(),
+        &config.resources,
+        ctx,
+    );
+    match save_traversals_cache(
+        traversals_cache,
+        config.storage.traversals_cache_size_mb * 1024 * 1024,
+        &mut blocks_db_rw,
+        ctx,
+    ) {
+        Ok((saved, evicted)) => ctx.try_log(|logger| {
+            info!(
+                logger,
+                "Traversals cache persisted ({saved} entries saved, {evicted} entries evicted)"
+            )
+        }),
+        Err(e) => ctx.try_log(|logger| warn!(logger, "Unable to persist traversals cache: {e}")),
+    }
 }
 
 #[derive(PartialEq, Debug)]
@@ -93,104 +143,286 @@ pub fn compute_next_satpoint_data(
     SatPosition::Output((output_index, (offset_cross_inputs - offset_intra_outputs)))
 }
 
+fn find_latest_indexed_block_height(
+    config: &Config,
+    blocks_db: &DB,
+    ctx: &Context,
+) -> Result<Option<u64>, String> {
+    match config.storage.inscriptions_index {
+        InscriptionsIndexLayout::Sqlite => {
+            let inscriptions_db_conn =
+                open_readonly_ordhook_db_conn(&config.expected_cache_path(), &ctx)?;
+            find_latest_inscription_block_height(&inscriptions_db_conn, ctx)
+        }
+        InscriptionsIndexLayout::RocksDb => match RocksDbInscriptionStore::new(blocks_db) {
+            Ok(inscriptions_store) => {
+                Ok(inscriptions_store.find_latest_inscription_block_height(ctx))
+            }
+            // Column families not created yet: nothing indexed
+            Err(_) => Ok(None),
+        },
+        #[cfg(feature = "postgres")]
+        InscriptionsIndexLayout::Postgres(ref database_url) => {
+            let inscriptions_store = PostgresInscriptionStore::connect(database_url)?;
+            Ok(inscriptions_store.find_latest_inscription_block_height(ctx))
+        }
+    }
+}
+
+/// Sync checkpoint of hord.rocksdb. Databases synced before checkpoints were introduced get one
+/// initialized from the tips of the stores.
+fn get_sync_checkpoint(
+    config: &Config,
+    blocks_db_rw: &DB,
+    ctx: &Context,
+) -> Result<SyncCheckpoint, String> {
+    if let Some(checkpoint) = find_sync_checkpoint(blocks_db_rw) {
+        return Ok(checkpoint);
+    }
+    let last_block_inserted = match find_last_block_inserted(blocks_db_rw) {
+        0 => None,
+        block_height => Some(block_height as u64),
+    };
+    let checkpoint = SyncCheckpoint {
+        downloaded: last_block_inserted,
+        archived: last_block_inserted,
+        sequenced: find_latest_indexed_block_height(config, blocks_db_rw, ctx)?,
+    };
+    ctx.try_log(|logger| {
+        info!(
+            logger,
+            "Initializing sync checkpoint (archived: {:?}, sequenced: {:?})",
+            checkpoint.archived,
+            checkpoint.sequenced
+        )
+    });
+    write_sync_checkpoint(&checkpoint, blocks_db_rw, ctx);
+    Ok(checkpoint)
+}
+
 pub fn should_sync_rocks_db(config: &Config, ctx: &Context) -> Result<Option<(u64, u64)>, String> {
     let blocks_db = open_ordhook_db_conn_rocks_db_loop(
         true,
         &config.expected_cache_path(),
-        config.resources.ulimit,
-        config.resources.memory_available,
+        &config.resources,
         &ctx,
     );
-    let inscriptions_db_conn = open_readonly_ordhook_db_conn(&config.expected_cache_path(), &ctx)?;
-    let last_compressed_block = find_last_block_inserted(&blocks_db) as u64;
-    let last_indexed_block = match find_latest_inscription_block_height(&inscriptions_db_conn, ctx)?
-    {
-        Some(last_indexed_block) => last_indexed_block,
-        None => 0,
-    };
+    let checkpoint = get_sync_checkpoint(config, &blocks_db, ctx)?;
 
-    let res = if last_compressed_block < last_indexed_block {
-        Some((last_compressed_block, last_indexed_block))
-    } else {
-        None
+    let res = match checkpoint.sequenced {
+        Some(last_sequenced_block) if checkpoint.archived < Some(last_sequenced_block) => {
+            let start_block = checkpoint
+                .archived
+                .map_or(0, |block_height| block_height + 1);
+            Some((start_block, last_sequenced_block))
+        }
+        _ => None,
     };
     Ok(res)
 }
 
 pub fn should_sync_ordhook_db(
     config: &Config,
+    block_source: &dyn BlockSource,
+    sync_planner: &SyncPlanner,
     ctx: &Context,
-) -> Result<Option<(u64, u64, usize)>, String> {
-    let auth = Auth::UserPass(
-        config.network.bitcoind_rpc_username.clone(),
-        config.network.bitcoind_rpc_password.clone(),
+) -> Result<Option<SyncBatch>, String> {
+    let mut blocks_db = open_ordhook_db_conn_rocks_db_loop(
+        true,
+        &config.expected_cache_path(),
+        &config.resources,
+        &ctx,
     );
 
-    let bitcoin_rpc = match Client::new(&config.network.bitcoind_rpc_url, auth) {
-        Ok(con) => con,
-        Err(message) => {
-            return Err(format!("Bitcoin RPC error: {}", message.to_string()));
-        }
-    };
+    if find_last_block_inserted(&blocks_db) == 0 {
+        let _ = initialize_ordhook_db(&config.expected_cache_path(), &ctx);
+    }
+
+    if config.storage.inscriptions_index == InscriptionsIndexLayout::RocksDb {
+        create_inscriptions_column_families(&mut blocks_db, &config.resources)?;
+    }
+
+    // Archiving and sequencing resume independently, the lowest of them starts the batch
+    let checkpoint = get_sync_checkpoint(config, &blocks_db, ctx)?;
+    let start_block = checkpoint.resume_height(config.network_params.first_inscription_height);
 
+    let chain_tip = block_source.get_chain_tip(ctx)?;
+    Ok(sync_planner.plan_batch(start_block, chain_tip))
+}
+
+/// Blocks of `start_block..=end_block` to be fetched by the pipeline, along with the height from
+/// which they have to be sequenced: the blocks already archived are skipped, and the blocks
+/// already sequenced are only archived.
+pub fn find_blocks_to_download(
+    config: &Config,
+    start_block: u64,
+    end_block: u64,
+    ctx: &Context,
+) -> Result<(Vec<u64>, u64), String> {
     let blocks_db = open_ordhook_db_conn_rocks_db_loop(
         true,
         &config.expected_cache_path(),
-        config.resources.ulimit,
-        config.resources.memory_available,
+        &config.resources,
         &ctx,
     );
-    let mut start_block = find_last_block_inserted(&blocks_db) as u64;
+    let checkpoint = get_sync_checkpoint(config, &blocks_db, ctx)?;
+    let start_sequencing =
+        checkpoint.sequencing_start(config.network_params.first_inscription_height);
+    let blocks = checkpoint.filter_blocks_to_download(
+        (start_block..=end_block).collect(),
+        start_sequencing,
+        &blocks_db,
+    );
+    Ok((blocks, start_sequencing))
+}
 
-    if start_block == 0 {
-        let _ = initialize_ordhook_db(&config.expected_cache_path(), &ctx);
+/// Walk down from `tip_height` until the hash of the indexed block matches the hash of the
+/// canonical block. Returns the lowest orphaned height, if any.
+/// Blocks indexed without their hash being recorded are considered canonical.
+pub fn find_first_orphaned_block_height<F, G>(
+    tip_height: u64,
+    find_indexed_block_hash: F,
+    find_canonical_block_hash: G,
+) -> Result<Option<u64>, String>
+where
+    F: Fn(u64) -> Option<String>,
+    G: Fn(u64) -> Result<String, String>,
+{
+    let mut first_orphaned_block_height = None;
+    let mut block_height = tip_height;
+    while block_height > 0 {
+        let indexed_block_hash = match find_indexed_block_hash(block_height) {
+            Some(block_hash) => block_hash,
+            None => break,
+        };
+        let canonical_block_hash = find_canonical_block_hash(block_height)?;
+        if indexed_block_hash.trim_start_matches("0x")
+            == canonical_block_hash.trim_start_matches("0x")
+        {
+            break;
+        }
+        first_orphaned_block_height = Some(block_height);
+        block_height -= 1;
     }
+    Ok(first_orphaned_block_height)
+}
 
-    let inscriptions_db_conn = open_readonly_ordhook_db_conn(&config.expected_cache_path(), &ctx)?;
+/// Make sure that the block source is serving the chain described by `network_params`.
+pub fn check_network_genesis(
+    config: &Config,
+    block_source: &dyn BlockSource,
+    ctx: &Context,
+) -> Result<(), String> {
+    let genesis_block_hash = block_source.get_block_hash(0, ctx)?;
+    let expected_genesis_block_hash = &config.network_params.genesis_block_hash;
+    if genesis_block_hash.trim_start_matches("0x")
+        != expected_genesis_block_hash.trim_start_matches("0x")
+    {
+        return Err(format!(
+            "block source is serving a chain starting with block {genesis_block_hash}, expected {expected_genesis_block_hash} (network.params.genesis_block_hash)"
+        ));
+    }
+    Ok(())
+}
 
-    match find_latest_inscription_block_height(&inscriptions_db_conn, ctx)? {
-        Some(height) => {
-            if find_pinned_block_bytes_at_block_height(height as u32, 3, &blocks_db, &ctx).is_none()
-            {
-                start_block = start_block.min(height);
-            } else {
-                start_block = height;
-            }
-            start_block += 1;
-        }
-        None => {
-            start_block = start_block.min(config.get_ordhook_config().first_inscription_height);
-        }
-    };
+/// Compare the hashes of the last indexed blocks with the block source, and delete the blocks
+/// that were orphaned by a re-org happening while ordhook was not running.
+/// Returns the range of blocks deleted. Fails when the block source is behind ordhook, the
+/// blocks above its tip can't be checked.
+pub fn rollback_orphaned_blocks(
+    config: &Config,
+    block_source: &dyn BlockSource,
+    ctx: &Context,
+) -> Result<Option<(u64, u64)>, String> {
+    let chain_tip = block_source.get_chain_tip(ctx)?;
 
-    let end_block = match bitcoin_rpc.get_blockchain_info() {
-        Ok(result) => result.blocks,
-        Err(e) => {
-            return Err(format!(
-                "unable to retrieve Bitcoin chain tip ({})",
-                e.to_string()
-            ));
-        }
-    };
+    let (blocks_db_rw, inscriptions_db_conn_rw) =
+        open_readwrite_ordhook_dbs(&config.expected_cache_path(), &config.resources, &ctx)?;
+    let last_block_inserted = find_last_block_inserted(&blocks_db_rw) as u64;
+    if last_block_inserted > chain_tip {
+        return Err(format!(
+            "block source is behind ordhook (#{chain_tip} < #{last_block_inserted}): blocks above #{chain_tip} can't be checked for re-orgs, wait for the block source to catch up"
+        ));
+    }
+    // Block hashes are only recorded for the blocks sequenced: the blocks only archived above
+    // the sequencing tip have no hash to be compared with
+    let last_block_sequenced = get_sync_checkpoint(config, &blocks_db_rw, ctx)?
+        .sequenced
+        .map_or(last_block_inserted, |block_height| {
+            block_height.min(last_block_inserted)
+        });
 
-    // TODO: Gracefully handle Regtest, Testnet and Signet
-    let (mut end_block, speed) = if start_block < 200_000 {
-        (end_block.min(200_000), 10_000)
-    } else if start_block < 550_000 {
-        (end_block.min(550_000), 1_000)
-    } else {
-        (end_block, 100)
+    let inscriptions_store = match ConfiguredInscriptionStore::open(
+        &config.storage.inscriptions_index,
+        &blocks_db_rw,
+        &inscriptions_db_conn_rw,
+    ) {
+        Ok(inscriptions_store) => inscriptions_store,
+        // Column families not created yet: nothing indexed
+        Err(_) if config.storage.inscriptions_index == InscriptionsIndexLayout::RocksDb => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e),
     };
+    let first_orphaned_block_height = find_first_orphaned_block_height(
+        last_block_sequenced,
+        |block_height| {
+            inscriptions_store
+                .as_store()
+                .find_block_hash_at_block_height(block_height, ctx)
+        },
+        |block_height| block_source.get_block_hash(block_height, ctx),
+    )?;
 
-    if start_block < 767430 && end_block > 767430 {
-        end_block = 767430;
+    match first_orphaned_block_height {
+        Some(start_block) => {
+            ctx.try_log(|logger| {
+                warn!(
+                    logger,
+                    "Blocks #{start_block} to #{last_block_inserted} were orphaned while ordhook was down, rolling them back"
+                )
+            });
+            delete_data_in_ordhook_db(
+                start_block,
+                last_block_inserted,
+                &blocks_db_rw,
+                &inscriptions_db_conn_rw,
+                ctx,
+            )?;
+            // Already done for hord.sqlite and hord.rocksdb, needed by the PostgreSQL layout
+            inscriptions_store.as_store().delete_data_in_block_range(
+                start_block,
+                last_block_inserted,
+                ctx,
+            );
+            Ok(Some((start_block, last_block_inserted)))
+        }
+        None => Ok(None),
     }
+}
 
-    if start_block <= end_block {
-        Ok(Some((start_block, end_block, speed)))
-    } else {
+#[test]
+fn test_find_first_orphaned_block_height() {
+    let indexed = |block_height: u64| match block_height {
+        100..=102 => Some(format!("0x{:064x}", block_height)),
+        103..=104 => Some(format!("0x{:064x}", block_height + 1_000)),
+        _ => None,
+    };
+    let canonical = |block_height: u64| Ok(format!("{:064x}", block_height));
+    assert_eq!(
+        find_first_orphaned_block_height(104, indexed, canonical),
+        Ok(Some(103))
+    );
+    assert_eq!(
+        find_first_orphaned_block_height(102, indexed, canonical),
         Ok(None)
-    }
+    );
+    // Hashes not recorded
+    assert_eq!(
+        find_first_orphaned_block_height(99, indexed, canonical),
+        Ok(None)
+    );
+    assert!(find_first_orphaned_block_height(104, indexed, |_| Err("unreachable".into())).is_err());
 }
 
 #[test]
@@ -0,0 +1,809 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chainhook_sdk::bitcoincore_rpc::bitcoin::consensus::deserialize;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::hashes::Hash;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{Block, ScriptBuf, Txid};
+use chainhook_sdk::utils::Context;
+use futures::future::BoxFuture;
+use rocksdb::{Direction, IteratorMode, WriteBatch, DB};
+
+use crate::config::{Config, NetworkParams};
+
+use super::{build_getblock_response, BlockSource};
+
+// Flags of `nStatus`, in bitcoind's block index
+const BLOCK_VALID_MASK: u64 = 7;
+const BLOCK_VALID_SCRIPTS: u64 = 5;
+const BLOCK_HAVE_DATA: u64 = 8;
+const BLOCK_HAVE_UNDO: u64 = 16;
+const BLOCK_FAILED_MASK: u64 = 32 | 64;
+
+/// Number of blocks for which the outputs spent are kept, so that the tracked UTXO set can be
+/// rewound when a sync is resumed below the last block tracked.
+const UTXO_UNDO_DEPTH: u64 = 1_000;
+/// Maximum number of blocks read ahead of the requests, when requests are served out of order.
+const MAX_BLOCKS_READ_AHEAD: usize = 1_024;
+
+const UTXO_KEY_LEN: usize = 37;
+const UTXO_VALUE_LEN: usize = 12;
+const UTXO_TIP_KEY: &[u8] = b"metadata::tip";
+
+/// Block of the active chain, as recorded in bitcoind's block index (`blocks/index`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockIndexEntry {
+    pub hash: [u8; 32],
+    pub previous_hash: [u8; 32],
+    pub height: u64,
+    pub status: u64,
+    pub time: u32,
+    /// Number of the blk*.dat file storing the block.
+    pub file: u64,
+    /// Offset of the block in the blk*.dat file, right after its magic and size.
+    pub data_pos: u64,
+}
+
+/// Blocks read from the `blocks` directory of a local bitcoind (blk*.dat files, located through
+/// the LevelDB block index), for cold syncs without any JSON-RPC overhead.
+///
+/// Prevouts are not part of the blocks stored by bitcoind: the values of the outputs not spent yet
+/// are tracked in `<working_dir>/blocks_dir_utxos.rocksdb`, which requires blocks to be read from
+/// the genesis block. The tracked set is persisted, so that subsequent syncs are only reading the
+/// new blocks. The block index can be compacted by a running bitcoind: it should preferably be
+/// stopped during the sync.
+pub struct BitcoindBlocksDirSource {
+    tip_height: u64,
+    reader: Arc<Mutex<BlocksDirReader>>,
+}
+
+impl BitcoindBlocksDirSource {
+    pub fn new(
+        blocks_dir: &PathBuf,
+        config: &Config,
+        ctx: &Context,
+    ) -> Result<BitcoindBlocksDirSource, String> {
+        let chain = read_active_chain(blocks_dir)?;
+        let tip_height = chain.len() as u64 - 1;
+        ctx.try_log(|logger| {
+            info!(
+                logger,
+                "Block index loaded from {}, chain tip at #{tip_height}",
+                blocks_dir.display()
+            )
+        });
+        // Since v28, bitcoind is obfuscating blk*.dat files with the key stored in xor.dat
+        let xor_key = match std::fs::read(blocks_dir.join("xor.dat")) {
+            Ok(key) if key.iter().any(|b| *b != 0) => key,
+            _ => vec![],
+        };
+        let reader = BlocksDirReader {
+            chain,
+            block_files: BlockFilesReader {
+                blocks_dir: blocks_dir.clone(),
+                xor_key,
+                open_file: None,
+            },
+            utxos: None,
+            utxos_path: config
+                .expected_cache_path()
+                .join("blocks_dir_utxos.rocksdb"),
+            ulimit: config.resources.ulimit,
+            network_params: config.network_params.clone(),
+            first_requested_height: None,
+            read_ahead: BTreeMap::new(),
+        };
+        Ok(BitcoindBlocksDirSource {
+            tip_height,
+            reader: Arc::new(Mutex::new(reader)),
+        })
+    }
+}
+
+impl BlockSource for BitcoindBlocksDirSource {
+    fn get_chain_tip(&self, _ctx: &Context) -> Result<u64, String> {
+        Ok(self.tip_height)
+    }
+
+    fn get_block_hash(&self, block_height: u64, _ctx: &Context) -> Result<String, String> {
+        let reader = self
+            .reader
+            .lock()
+            .map_err(|_| "blocks dir reader poisoned".to_string())?;
+        match reader.chain.get(block_height as usize) {
+            Some(entry) => Ok(format_block_hash(&entry.hash)),
+            None => Err(format!("block #{block_height} not found in block index")),
+        }
+    }
+
+    fn fetch_block(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        let reader = self.reader.clone();
+        let ctx = ctx.clone();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || match reader.lock() {
+                Ok(mut reader) => reader.fetch_block(block_height, &ctx),
+                Err(_) => Err("blocks dir reader poisoned".to_string()),
+            })
+            .await
+            .map_err(|e| format!("unable to read block #{block_height}: {e}"))?
+        })
+    }
+
+    fn get_output_script(
+        &self,
+        txid: &Txid,
+        _vout: u32,
+        _ctx: &Context,
+    ) -> Result<ScriptBuf, String> {
+        Err(format!(
+            "transaction {txid} can't be looked up in blk*.dat files, which are not indexed by txid"
+        ))
+    }
+}
+
+/// Decode a varint, as serialized by bitcoind (MSB base-128, with an offset on each byte).
+fn read_varint(bytes: &[u8], cursor: &mut usize) -> Result<u64, String> {
+    let mut n: u64 = 0;
+    loop {
+        let byte = *bytes
+            .get(*cursor)
+            .ok_or("unexpected end of varint".to_string())?;
+        *cursor += 1;
+        if n > (u64::MAX >> 7) {
+            return Err("varint overflow".to_string());
+        }
+        n = (n << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+        n += 1;
+    }
+}
+
+/// Decode a `CDiskBlockIndex`, value of the `b<hash>` keys of the block index.
+pub fn parse_block_index_record(hash: [u8; 32], record: &[u8]) -> Result<BlockIndexEntry, String> {
+    let mut cursor = 0;
+    let _client_version = read_varint(record, &mut cursor)?;
+    let height = read_varint(record, &mut cursor)?;
+    let status = read_varint(record, &mut cursor)?;
+    let _tx_count = read_varint(record, &mut cursor)?;
+    let file = match status & (BLOCK_HAVE_DATA | BLOCK_HAVE_UNDO) {
+        0 => 0,
+        _ => read_varint(record, &mut cursor)?,
+    };
+    let data_pos = match status & BLOCK_HAVE_DATA {
+        0 => 0,
+        _ => read_varint(record, &mut cursor)?,
+    };
+    if status & BLOCK_HAVE_UNDO != 0 {
+        let _undo_pos = read_varint(record, &mut cursor)?;
+    }
+    // Header: version (4 bytes), previous block hash (32), merkle root (32), time (4), bits (4), nonce (4)
+    let header = record
+        .get(cursor..cursor + 80)
+        .ok_or("block index record truncated".to_string())?;
+    let mut previous_hash = [0u8; 32];
+    previous_hash.copy_from_slice(&header[4..36]);
+    let time = u32::from_le_bytes([header[68], header[69], header[70], header[71]]);
+    Ok(BlockIndexEntry {
+        hash,
+        previous_hash,
+        height,
+        status,
+        time,
+        file,
+        data_pos,
+    })
+}
+
+fn format_block_hash(hash: &[u8; 32]) -> String {
+    let mut bytes = *hash;
+    bytes.reverse();
+    hex::encode(bytes)
+}
+
+/// Blocks of the active chain, indexed by height: the highest block fully validated is walked
+/// back to the genesis block.
+fn read_active_chain(blocks_dir: &PathBuf) -> Result<Vec<BlockIndexEntry>, String> {
+    let index_path = blocks_dir.join("index");
+    let mut opts = rocksdb::Options::default();
+    opts.create_if_missing(false);
+    let index = DB::open_for_read_only(&opts, &index_path, false).map_err(|e| {
+        format!(
+            "unable to open block index {}: {}",
+            index_path.display(),
+            e.to_string()
+        )
+    })?;
+
+    let mut entries = HashMap::new();
+    let mut tip: Option<(u64, [u8; 32])> = None;
+    for item in index.iterator(IteratorMode::From(b"b", Direction::Forward)) {
+        let (key, value) = item.map_err(|e| format!("unable to read block index: {e}"))?;
+        if key.first() != Some(&b'b') {
+            break;
+        }
+        if key.len() != 33 {
+            continue;
+        }
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&key[1..]);
+        let entry = parse_block_index_record(hash, &value)?;
+        if entry.status & BLOCK_HAVE_DATA == 0
+            || entry.status & BLOCK_FAILED_MASK != 0
+            || entry.status & BLOCK_VALID_MASK < BLOCK_VALID_SCRIPTS
+        {
+            continue;
+        }
+        if tip.map_or(true, |(height, _)| entry.height > height) {
+            tip = Some((entry.height, hash));
+        }
+        entries.insert(hash, entry);
+    }
+
+    let Some((tip_height, mut hash)) = tip else {
+        return Err(format!("no block found in {}", index_path.display()));
+    };
+    let mut chain = Vec::with_capacity(tip_height as usize + 1);
+    loop {
+        let entry = entries.remove(&hash).ok_or(format!(
+            "block {} not available in {} (pruned node?)",
+            format_block_hash(&hash),
+            blocks_dir.display()
+        ))?;
+        let (height, previous_hash) = (entry.height, entry.previous_hash);
+        chain.push(entry);
+        if height == 0 {
+            break;
+        }
+        hash = previous_hash;
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+struct BlockFilesReader {
+    blocks_dir: PathBuf,
+    xor_key: Vec<u8>,
+    open_file: Option<(u64, File)>,
+}
+
+impl BlockFilesReader {
+    fn read_block(&mut self, entry: &BlockIndexEntry) -> Result<Block, String> {
+        // Blocks are preceded by their size
+        let size = self.read_at(entry.file, entry.data_pos - 4, 4)?;
+        let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+        let bytes = self.read_at(entry.file, entry.data_pos, size)?;
+        let block: Block = deserialize(&bytes)
+            .map_err(|e| format!("unable to decode block #{}: {e}", entry.height))?;
+        if block.block_hash().to_byte_array() != entry.hash {
+            return Err(format!(
+                "block #{} read from blk{:05}.dat does not match the block index",
+                entry.height, entry.file
+            ));
+        }
+        Ok(block)
+    }
+
+    fn read_at(&mut self, file_number: u64, offset: u64, len: usize) -> Result<Vec<u8>, String> {
+        if self.open_file.as_ref().map(|(n, _)| *n) != Some(file_number) {
+            let path = self.blocks_dir.join(format!("blk{:05}.dat", file_number));
+            let file =
+                File::open(&path).map_err(|e| format!("unable to open {}: {e}", path.display()))?;
+            self.open_file = Some((file_number, file));
+        }
+        let (_, file) = self.open_file.as_mut().expect("blk file not opened");
+        let mut bytes = vec![0u8; len];
+        file.seek(SeekFrom::Start(offset))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(|e| format!("unable to read blk{:05}.dat: {e}", file_number))?;
+        if !self.xor_key.is_empty() {
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte ^= self.xor_key[(offset as usize + i) % self.xor_key.len()];
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+fn utxo_key(txid: &Txid, vout: u32) -> Vec<u8> {
+    let mut key = Vec::with_capacity(UTXO_KEY_LEN);
+    key.push(b'u');
+    key.extend_from_slice(&txid.to_byte_array());
+    key.extend_from_slice(&vout.to_be_bytes());
+    key
+}
+
+fn utxo_value(value: u64, block_height: u64) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(UTXO_VALUE_LEN);
+    bytes.extend_from_slice(&value.to_be_bytes());
+    bytes.extend_from_slice(&(block_height as u32).to_be_bytes());
+    bytes
+}
+
+/// (value, block_height) of an output.
+fn decode_utxo_value(bytes: &[u8]) -> (u64, u64) {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[0..8]);
+    let mut block_height = [0u8; 4];
+    block_height.copy_from_slice(&bytes[8..12]);
+    (
+        u64::from_be_bytes(value),
+        u32::from_be_bytes(block_height) as u64,
+    )
+}
+
+fn undo_key(block_height: u64) -> Vec<u8> {
+    let mut key = vec![b'd'];
+    key.extend_from_slice(&block_height.to_be_bytes());
+    key
+}
+
+/// Outputs not spent yet, with the block tracked last and the outputs spent by the last
+/// `UTXO_UNDO_DEPTH` blocks.
+struct UtxoTracker {
+    db: DB,
+}
+
+impl UtxoTracker {
+    fn open(path: &PathBuf, ulimit: usize) -> Result<UtxoTracker, String> {
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        opts.set_max_open_files(ulimit as i32);
+        let db = DB::open(&opts, path)
+            .map_err(|e| format!("unable to open {}: {}", path.display(), e.to_string()))?;
+        Ok(UtxoTracker { db })
+    }
+
+    fn destroy(path: &PathBuf) -> Result<(), String> {
+        DB::destroy(&rocksdb::Options::default(), path)
+            .map_err(|e| format!("unable to destroy {}: {}", path.display(), e.to_string()))
+    }
+
+    fn tip(&self) -> Result<Option<(u64, [u8; 32])>, String> {
+        match self.db.get(UTXO_TIP_KEY) {
+            Ok(Some(bytes)) => {
+                let mut block_height = [0u8; 8];
+                block_height.copy_from_slice(&bytes[0..8]);
+                let mut hash = [0u8; 32];
+                hash.copy_from_slice(&bytes[8..40]);
+                Ok(Some((u64::from_be_bytes(block_height), hash)))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => Err(format!("unable to read tracked tip: {}", e.to_string())),
+        }
+    }
+
+    fn set_tip(batch: &mut WriteBatch, tip: Option<(u64, [u8; 32])>) {
+        match tip {
+            Some((block_height, hash)) => {
+                let mut bytes = block_height.to_be_bytes().to_vec();
+                bytes.extend_from_slice(&hash);
+                batch.put(UTXO_TIP_KEY, bytes);
+            }
+            None => batch.delete(UTXO_TIP_KEY),
+        }
+    }
+
+    /// Spend the inputs and create the outputs of `block`.
+    /// Returns the (value, block_height) of the prevouts, for each transaction.
+    fn apply_block(
+        &self,
+        block_height: u64,
+        block: &Block,
+    ) -> Result<Vec<Vec<(u64, u64)>>, String> {
+        let mut batch = WriteBatch::default();
+        let mut created: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut spent = vec![];
+        let mut prevouts = vec![];
+        for (tx_index, tx) in block.txdata.iter().enumerate() {
+            let mut tx_prevouts = vec![];
+            if tx_index > 0 {
+                for input in tx.input.iter() {
+                    let outpoint = &input.previous_output;
+                    let key = utxo_key(&outpoint.txid, outpoint.vout);
+                    let value = match created.remove(&key) {
+                        // Outputs created and spent in the same block are never written
+                        Some(value) => value,
+                        None => {
+                            let value = self
+                                .db
+                                .get(&key)
+                                .map_err(|e| format!("unable to read utxo: {}", e.to_string()))?
+                                .ok_or(format!(
+                                    "output {}:{} spent in block #{block_height} not found",
+                                    outpoint.txid, outpoint.vout
+                                ))?;
+                            batch.delete(&key);
+                            spent.extend_from_slice(&key);
+                            spent.extend_from_slice(&value);
+                            value
+                        }
+                    };
+                    tx_prevouts.push(decode_utxo_value(&value));
+                }
+            }
+            prevouts.push(tx_prevouts);
+            let txid = tx.txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                if output.script_pubkey.is_op_return() {
+                    continue;
+                }
+                created.insert(
+                    utxo_key(&txid, vout as u32),
+                    utxo_value(output.value, block_height),
+                );
+            }
+        }
+        for (key, value) in created.into_iter() {
+            batch.put(key, value);
+        }
+        batch.put(undo_key(block_height), spent);
+        if block_height >= UTXO_UNDO_DEPTH {
+            batch.delete(undo_key(block_height - UTXO_UNDO_DEPTH));
+        }
+        Self::set_tip(
+            &mut batch,
+            Some((block_height, block.block_hash().to_byte_array())),
+        );
+        self.db
+            .write(batch)
+            .map_err(|e| format!("unable to write utxos: {}", e.to_string()))?;
+        Ok(prevouts)
+    }
+
+    /// Revert `apply_block`, `previous_tip` being the block preceding `block`.
+    fn rewind_block(
+        &self,
+        block_height: u64,
+        block: &Block,
+        previous_tip: Option<(u64, [u8; 32])>,
+    ) -> Result<(), String> {
+        let spent = self
+            .db
+            .get(undo_key(block_height))
+            .map_err(|e| format!("unable to read undo data: {}", e.to_string()))?
+            .ok_or(format!("undo data of block #{block_height} not found"))?;
+        let mut batch = WriteBatch::default();
+        for tx in block.txdata.iter() {
+            let txid = tx.txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                if !output.script_pubkey.is_op_return() {
+                    batch.delete(utxo_key(&txid, vout as u32));
+                }
+            }
+        }
+        for entry in spent.chunks(UTXO_KEY_LEN + UTXO_VALUE_LEN) {
+            batch.put(&entry[..UTXO_KEY_LEN], &entry[UTXO_KEY_LEN..]);
+        }
+        batch.delete(undo_key(block_height));
+        Self::set_tip(&mut batch, previous_tip);
+        self.db
+            .write(batch)
+            .map_err(|e| format!("unable to write utxos: {}", e.to_string()))
+    }
+}
+
+struct BlocksDirReader {
+    chain: Vec<BlockIndexEntry>,
+    block_files: BlockFilesReader,
+    /// Opened on the first block fetched: sources only used for their chain tip are not locking it.
+    utxos: Option<UtxoTracker>,
+    utxos_path: PathBuf,
+    ulimit: usize,
+    network_params: NetworkParams,
+    first_requested_height: Option<u64>,
+    read_ahead: BTreeMap<u64, Vec<u8>>,
+}
+
+impl BlocksDirReader {
+    fn fetch_block(&mut self, block_height: u64, ctx: &Context) -> Result<Vec<u8>, String> {
+        if let Some(bytes) = self.read_ahead.remove(&block_height) {
+            return Ok(bytes);
+        }
+        if block_height >= self.chain.len() as u64 {
+            return Err(format!(
+                "block #{block_height} not available in {}",
+                self.block_files.blocks_dir.display()
+            ));
+        }
+        let first_requested_height = self
+            .first_requested_height
+            .map_or(block_height, |height| height.min(block_height));
+        self.first_requested_height = Some(first_requested_height);
+
+        let mut next_height = self.prepare_utxos(block_height, ctx)?;
+        let utxos = self.utxos.as_ref().expect("utxos not opened");
+        loop {
+            let entry = &self.chain[next_height as usize];
+            let block = self.block_files.read_block(entry)?;
+            let prevouts = utxos.apply_block(next_height, &block)?;
+            let bytes = build_getblock_response(
+                &block,
+                next_height,
+                self.median_time_past(next_height),
+                &prevouts,
+                &self.network_params,
+            )?;
+            if next_height == block_height {
+                return Ok(bytes);
+            }
+            if next_height >= first_requested_height {
+                self.read_ahead.insert(next_height, bytes);
+                if self.read_ahead.len() > MAX_BLOCKS_READ_AHEAD {
+                    self.read_ahead.pop_first();
+                }
+            } else if next_height % 10_000 == 0 {
+                ctx.try_log(|logger| {
+                    info!(
+                        logger,
+                        "Tracking outputs of blk*.dat files: block #{next_height}"
+                    )
+                });
+            }
+            next_height += 1;
+        }
+    }
+
+    /// Open the tracked UTXO set, and bring it right before `block_height`, rewinding it or
+    /// resetting it when needed. Returns the height of the next block to track.
+    fn prepare_utxos(&mut self, block_height: u64, ctx: &Context) -> Result<u64, String> {
+        if self.utxos.is_none() {
+            self.utxos = Some(UtxoTracker::open(&self.utxos_path, self.ulimit)?);
+        }
+        let utxos = self.utxos.as_ref().expect("utxos not opened");
+        let mut tip = utxos.tip()?;
+
+        let tip_is_canonical = match tip {
+            Some((height, hash)) => self
+                .chain
+                .get(height as usize)
+                .map_or(false, |entry| entry.hash == hash),
+            None => true,
+        };
+        let rewind_depth = match tip {
+            Some((height, _)) if height >= block_height => height - block_height + 1,
+            _ => 0,
+        };
+        if !tip_is_canonical || rewind_depth > UTXO_UNDO_DEPTH {
+            ctx.try_log(|logger| {
+                warn!(
+                    logger,
+                    "Outputs tracked can't be rewound to block #{block_height}, tracking from genesis"
+                )
+            });
+            self.utxos = None;
+            UtxoTracker::destroy(&self.utxos_path)?;
+            self.utxos = Some(UtxoTracker::open(&self.utxos_path, self.ulimit)?);
+            return Ok(0);
+        }
+
+        while let Some((height, _)) = tip {
+            if height < block_height {
+                break;
+            }
+            let block = self.block_files.read_block(&self.chain[height as usize])?;
+            let previous_tip = match height {
+                0 => None,
+                _ => Some((height - 1, self.chain[height as usize - 1].hash)),
+            };
+            utxos.rewind_block(height, &block, previous_tip)?;
+            tip = previous_tip;
+        }
+        Ok(tip.map_or(0, |(height, _)| height + 1))
+    }
+
+    fn median_time_past(&self, block_height: u64) -> u32 {
+        let start = block_height.saturating_sub(10) as usize;
+        let mut times = self.chain[start..=block_height as usize]
+            .iter()
+            .map(|entry| entry.time)
+            .collect::<Vec<_>>();
+        times.sort();
+        times[times.len() / 2]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use chainhook_sdk::bitcoincore_rpc::bitcoin::consensus::serialize;
+    use chainhook_sdk::bitcoincore_rpc::bitcoin::hashes::Hash;
+    use chainhook_sdk::bitcoincore_rpc::bitcoin::OutPoint;
+    use chainhook_sdk::indexer::bitcoin::parse_downloaded_block;
+    use chainhook_sdk::utils::Context;
+    use serde_json::Value;
+
+    use super::{parse_block_index_record, read_varint, BitcoindBlocksDirSource};
+    use crate::config::Config;
+    use crate::core::pipeline::block_source::BlockSource;
+    use crate::core::pipeline::download_and_pipeline_blocks;
+    use crate::db::BlockBytesCursor;
+    use crate::testing::TempDir;
+    use crate::tests::blocks::{craft_chain, craft_coinbase, craft_transaction};
+
+    fn write_varint(mut n: u64, bytes: &mut Vec<u8>) {
+        let mut encoded = vec![(n & 0x7f) as u8];
+        while n > 0x7f {
+            n = (n >> 7) - 1;
+            encoded.push((n & 0x7f) as u8 | 0x80);
+        }
+        encoded.reverse();
+        bytes.extend(encoded);
+    }
+
+    #[test]
+    fn test_read_varint() {
+        for n in [
+            0,
+            1,
+            127,
+            128,
+            255,
+            16_511,
+            16_512,
+            767_430,
+            u32::MAX as u64,
+        ] {
+            let mut bytes = vec![];
+            write_varint(n, &mut bytes);
+            let mut cursor = 0;
+            assert_eq!(read_varint(&bytes, &mut cursor), Ok(n));
+            assert_eq!(cursor, bytes.len());
+        }
+        assert_eq!(read_varint(&[0x80, 0x00], &mut 0), Ok(128));
+        assert!(read_varint(&[0x80], &mut 0).is_err());
+    }
+
+    #[test]
+    fn test_read_blocks_dir() {
+        let ctx = Context::empty();
+        let working_dir = TempDir::new("blocks-dir");
+        let blocks_dir = working_dir.join("blocks");
+        std::fs::create_dir_all(&blocks_dir).unwrap();
+
+        let spent = craft_coinbase(0);
+        let blocks = craft_chain(vec![
+            vec![spent.clone()],
+            vec![
+                craft_coinbase(1),
+                craft_transaction(
+                    vec![OutPoint::new(spent.txid(), 0)],
+                    vec![10_000, 4_999_989_000],
+                ),
+            ],
+        ]);
+
+        // blk00000.dat, indexed by a block index
+        let mut blk_file = std::fs::File::create(blocks_dir.join("blk00000.dat")).unwrap();
+        let index = rocksdb::DB::open_default(blocks_dir.join("index")).unwrap();
+        let mut data_pos = 0;
+        for (height, block) in blocks.iter().enumerate() {
+            let bytes = serialize(block);
+            blk_file.write_all(&[0xfa, 0xbf, 0xb5, 0xda]).unwrap();
+            blk_file
+                .write_all(&(bytes.len() as u32).to_le_bytes())
+                .unwrap();
+            blk_file.write_all(&bytes).unwrap();
+            data_pos += 8;
+
+            let mut record = vec![];
+            write_varint(259_900, &mut record);
+            write_varint(height as u64, &mut record);
+            write_varint(5 | 8 | 16, &mut record);
+            write_varint(block.txdata.len() as u64, &mut record);
+            write_varint(0, &mut record);
+            write_varint(data_pos, &mut record);
+            write_varint(0, &mut record);
+            record.extend(serialize(&block.header));
+            let hash = block.block_hash().to_byte_array();
+            let entry = parse_block_index_record(hash, &record).unwrap();
+            assert_eq!((entry.height, entry.data_pos), (height as u64, data_pos));
+
+            let mut key = vec![b'b'];
+            key.extend_from_slice(&hash);
+            index.put(key, record).unwrap();
+            data_pos += bytes.len() as u64;
+        }
+        drop(index);
+        drop(blk_file);
+
+        let mut config = Config::devnet_default();
+        config.storage.working_dir = format!("{}", working_dir.path().display());
+        let source = BitcoindBlocksDirSource::new(&blocks_dir, &config, &ctx).unwrap();
+        assert_eq!(source.get_chain_tip(&ctx), Ok(1));
+
+        let bytes = hiro_system_kit::nestable_block_on(source.fetch_block(1, &ctx)).unwrap();
+        let response: Value = serde_json::from_slice(&bytes).unwrap();
+        let spending_tx = &response["result"]["tx"][1];
+        assert_eq!(spending_tx["vin"][0]["txid"], spent.txid().to_string());
+        assert_eq!(spending_tx["vin"][0]["prevout"]["value"], 50.0);
+        assert_eq!(spending_tx["vin"][0]["prevout"]["height"], 0);
+        let block = parse_downloaded_block(bytes.clone()).unwrap();
+        assert_eq!(block.height, 1);
+        BlockBytesCursor::from_full_block(&block).unwrap();
+
+        // Resuming below the tip tracked rewinds the outputs tracked
+        hiro_system_kit::nestable_block_on(source.fetch_block(0, &ctx)).unwrap();
+        assert_eq!(
+            hiro_system_kit::nestable_block_on(source.fetch_block(1, &ctx)),
+            Ok(bytes)
+        );
+        assert!(hiro_system_kit::nestable_block_on(source.fetch_block(2, &ctx)).is_err());
+    }
+
+    /// Regtest chain of 102 blocks, block #102 spending the coinbase of block #1, stored the way
+    /// bitcoind stores it: blk00000.dat, and a LevelDB block index (`.ldb` table, MANIFEST, empty
+    /// log) including the `f` and `l` records of the block files.
+    #[test]
+    fn test_read_bitcoind_blocks_dir_fixture() {
+        let ctx = Context::empty();
+        let working_dir = TempDir::new("blocks-dir");
+        let blocks_dir = working_dir.join("blocks");
+        let fixture_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/core/pipeline/block_source/fixtures/regtest_blocks");
+        // Opening the block index writes to its directory
+        std::fs::create_dir_all(blocks_dir.join("index")).unwrap();
+        std::fs::copy(
+            fixture_dir.join("blk00000.dat"),
+            blocks_dir.join("blk00000.dat"),
+        )
+        .unwrap();
+        for entry in std::fs::read_dir(fixture_dir.join("index")).unwrap() {
+            let path = entry.unwrap().path();
+            std::fs::copy(
+                &path,
+                blocks_dir.join("index").join(path.file_name().unwrap()),
+            )
+            .unwrap();
+        }
+
+        let mut config = Config::devnet_default();
+        config.storage.working_dir = format!("{}", working_dir.path().display());
+        let source = BitcoindBlocksDirSource::new(&blocks_dir, &config, &ctx).unwrap();
+        assert_eq!(source.get_chain_tip(&ctx), Ok(102));
+        assert_eq!(
+            source.get_block_hash(0, &ctx),
+            Ok(config.network_params.genesis_block_hash.clone())
+        );
+        assert_eq!(
+            source.get_block_hash(102, &ctx),
+            Ok("2a9819459cbf8332aa38deec5de89fee16656e66450c4ab0a16a1b9de99ccbf2".to_string())
+        );
+
+        let bytes = hiro_system_kit::nestable_block_on(source.fetch_block(102, &ctx)).unwrap();
+        let response: Value = serde_json::from_slice(&bytes).unwrap();
+        let spending_tx = &response["result"]["tx"][1];
+        assert_eq!(
+            spending_tx["vin"][0]["txid"],
+            "b7892f76d1b3b327b8a0351123c5747c2b706384a45af28deac7b06edb506127"
+        );
+        assert_eq!(spending_tx["vin"][0]["prevout"]["value"], 50.0);
+        assert_eq!(spending_tx["vin"][0]["prevout"]["height"], 1);
+        let block = parse_downloaded_block(bytes).unwrap();
+        BlockBytesCursor::from_full_block(&block).unwrap();
+
+        // Blocks missing from the source are failing the pipeline instead of panicking
+        assert!(
+            hiro_system_kit::nestable_block_on(download_and_pipeline_blocks(
+                &config,
+                &source,
+                vec![103],
+                103,
+                None,
+                10,
+                None,
+                &ctx,
+            ))
+            .is_err()
+        );
+    }
+}
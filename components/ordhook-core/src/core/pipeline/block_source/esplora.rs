@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chainhook_sdk::bitcoincore_rpc::bitcoin::consensus::deserialize;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{Block, ScriptBuf, Txid};
+use chainhook_sdk::utils::Context;
+use futures::future::BoxFuture;
+use futures::{stream, StreamExt};
+use reqwest::StatusCode;
+use serde_json::Value;
+
+use crate::config::{Config, NetworkParams};
+
+use super::{build_getblock_response, BlockSource};
+
+/// Number of transactions kept in memory for resolving the prevouts of the blocks following.
+const TRANSACTIONS_CACHE_CAPACITY: usize = 500_000;
+const MAX_CONCURRENT_TRANSACTION_REQUESTS: usize = 16;
+
+enum RequestError {
+    /// Network errors, server errors and rate limiting: the request is retried.
+    Transient(String),
+    Fatal(String),
+}
+
+impl RequestError {
+    fn into_message(self) -> String {
+        match self {
+            RequestError::Transient(message) | RequestError::Fatal(message) => message,
+        }
+    }
+}
+
+/// Block height and outputs values of a transaction.
+type TransactionOutputs = (u64, Vec<u64>);
+
+/// Blocks downloaded from an Esplora (or Electrs) REST API, for deployments without access to an
+/// authenticated bitcoind RPC.
+///
+/// Esplora is serving raw blocks without their prevouts: the values and heights of the outputs
+/// spent are resolved with `GET /tx/:txid`, the transactions of the blocks recently fetched being
+/// kept in memory to spare most of these requests.
+#[derive(Clone)]
+pub struct EsploraSource {
+    base_url: String,
+    http_client: reqwest::Client,
+    network_params: NetworkParams,
+    transactions_cache: Arc<Mutex<HashMap<Txid, TransactionOutputs>>>,
+}
+
+impl EsploraSource {
+    pub fn new(base_url: &str, config: &Config) -> Result<EsploraSource, String> {
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(
+                config.resources.bitcoind_rpc_timeout.into(),
+            ))
+            .build()
+            .map_err(|e| format!("unable to build http client: {}", e.to_string()))?;
+        Ok(EsploraSource {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http_client,
+            network_params: config.network_params.clone(),
+            transactions_cache: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    async fn get_once(&self, path: &str) -> Result<Vec<u8>, RequestError> {
+        let url = format!("{}/{}", self.base_url, path);
+        let response = match self.http_client.get(&url).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                return Err(RequestError::Transient(format!(
+                    "GET {url} failed: {}",
+                    e.to_string()
+                )))
+            }
+        };
+        let status = response.status();
+        if !status.is_success() {
+            let message = format!("GET {url} failed ({status})");
+            return match status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                true => Err(RequestError::Fatal(message)),
+                false => Err(RequestError::Transient(message)),
+            };
+        }
+        match response.bytes().await {
+            Ok(bytes) => Ok(bytes.to_vec()),
+            Err(e) => Err(RequestError::Transient(format!(
+                "GET {url} failed: {}",
+                e.to_string()
+            ))),
+        }
+    }
+
+    async fn get(&self, path: &str, ctx: &Context) -> Result<Vec<u8>, String> {
+        let mut retries = 0;
+        loop {
+            match self.get_once(path).await {
+                Ok(bytes) => return Ok(bytes),
+                Err(RequestError::Fatal(e)) => return Err(e),
+                Err(RequestError::Transient(e)) => {
+                    retries += 1;
+                    ctx.try_log(|logger| warn!(logger, "{e}, retrying (attempt #{retries})"));
+                    tokio::time::sleep(Duration::from_millis(500 * retries.min(20))).await;
+                }
+            }
+        }
+    }
+
+    async fn get_json(&self, path: &str, ctx: &Context) -> Result<Value, String> {
+        let bytes = self.get(path, ctx).await?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| format!("unable to parse response of {path}: {}", e.to_string()))
+    }
+
+    async fn download_transaction_outputs(
+        &self,
+        txid: Txid,
+        ctx: &Context,
+    ) -> Result<(Txid, TransactionOutputs), String> {
+        let tx = self.get_json(&format!("tx/{txid}"), ctx).await?;
+        let block_height = tx["status"]["block_height"]
+            .as_u64()
+            .ok_or(format!("transaction {txid} not confirmed"))?;
+        let values = tx["vout"]
+            .as_array()
+            .ok_or(format!("outputs of transaction {txid} missing"))?
+            .iter()
+            .map(|output| {
+                output["value"]
+                    .as_u64()
+                    .ok_or(format!("output value of transaction {txid} missing"))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok((txid, (block_height, values)))
+    }
+
+    async fn download_block(&self, block_height: u64, ctx: &Context) -> Result<Vec<u8>, String> {
+        let block_hash = self
+            .get(&format!("block-height/{block_height}"), ctx)
+            .await?;
+        let block_hash = String::from_utf8_lossy(&block_hash).trim().to_string();
+        let block_info = self.get_json(&format!("block/{block_hash}"), ctx).await?;
+        let median_time = block_info["mediantime"]
+            .as_u64()
+            .ok_or(format!("median time of block #{block_height} missing"))?;
+        let raw_block = self.get(&format!("block/{block_hash}/raw"), ctx).await?;
+        let block: Block = deserialize(&raw_block)
+            .map_err(|e| format!("unable to decode block #{block_height}: {e}"))?;
+        if block.block_hash().to_string() != block_hash {
+            return Err(format!(
+                "block #{block_height} downloaded does not match {block_hash}"
+            ));
+        }
+
+        let mut transactions: HashMap<Txid, TransactionOutputs> = block
+            .txdata
+            .iter()
+            .map(|tx| {
+                let values = tx.output.iter().map(|output| output.value).collect();
+                (tx.txid(), (block_height, values))
+            })
+            .collect();
+        let mut missing_txids = HashSet::new();
+        {
+            let cache = self
+                .transactions_cache
+                .lock()
+                .map_err(|_| "transactions cache poisoned".to_string())?;
+            for tx in block.txdata.iter().skip(1) {
+                for input in tx.input.iter() {
+                    let txid = input.previous_output.txid;
+                    if transactions.contains_key(&txid) {
+                        continue;
+                    }
+                    match cache.get(&txid) {
+                        Some(outputs) => {
+                            transactions.insert(txid, outputs.clone());
+                        }
+                        None => {
+                            missing_txids.insert(txid);
+                        }
+                    }
+                }
+            }
+        }
+        let downloaded = stream::iter(
+            missing_txids
+                .into_iter()
+                .map(|txid| self.download_transaction_outputs(txid, ctx)),
+        )
+        .buffer_unordered(MAX_CONCURRENT_TRANSACTION_REQUESTS)
+        .collect::<Vec<_>>()
+        .await;
+        for res in downloaded.into_iter() {
+            let (txid, outputs) = res?;
+            transactions.insert(txid, outputs);
+        }
+
+        let mut prevouts = vec![vec![]];
+        for tx in block.txdata.iter().skip(1) {
+            let mut tx_prevouts = vec![];
+            for input in tx.input.iter() {
+                let outpoint = &input.previous_output;
+                let (prevout_height, values) = &transactions[&outpoint.txid];
+                let value = values.get(outpoint.vout as usize).ok_or(format!(
+                    "output {}:{} spent in block #{block_height} not found",
+                    outpoint.txid, outpoint.vout
+                ))?;
+                tx_prevouts.push((*value, *prevout_height));
+            }
+            prevouts.push(tx_prevouts);
+        }
+
+        // The transactions of this block are likely to be spent by the blocks following
+        {
+            let mut cache = self
+                .transactions_cache
+                .lock()
+                .map_err(|_| "transactions cache poisoned".to_string())?;
+            if cache.len() > TRANSACTIONS_CACHE_CAPACITY {
+                cache.clear();
+            }
+            for tx in block.txdata.iter() {
+                let txid = tx.txid();
+                if let Some(outputs) = transactions.remove(&txid) {
+                    cache.insert(txid, outputs);
+                }
+            }
+        }
+
+        build_getblock_response(
+            &block,
+            block_height,
+            median_time as u32,
+            &prevouts,
+            &self.network_params,
+        )
+    }
+}
+
+impl BlockSource for EsploraSource {
+    fn get_chain_tip(&self, _ctx: &Context) -> Result<u64, String> {
+        let bytes = hiro_system_kit::nestable_block_on(self.get_once("blocks/tip/height"))
+            .map_err(|e| e.into_message())?;
+        String::from_utf8_lossy(&bytes)
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("unable to parse chain tip: {}", e.to_string()))
+    }
+
+    fn get_block_hash(&self, block_height: u64, _ctx: &Context) -> Result<String, String> {
+        let bytes = hiro_system_kit::nestable_block_on(
+            self.get_once(&format!("block-height/{block_height}")),
+        )
+        .map_err(|e| e.into_message())?;
+        Ok(String::from_utf8_lossy(&bytes).trim().to_string())
+    }
+
+    fn fetch_block(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        let source = self.clone();
+        let ctx = ctx.clone();
+        Box::pin(async move { source.download_block(block_height, &ctx).await })
+    }
+
+    fn get_output_script(
+        &self,
+        txid: &Txid,
+        vout: u32,
+        _ctx: &Context,
+    ) -> Result<ScriptBuf, String> {
+        let bytes = hiro_system_kit::nestable_block_on(self.get_once(&format!("tx/{txid}")))
+            .map_err(|e| e.into_message())?;
+        let tx: Value = serde_json::from_slice(&bytes)
+            .map_err(|e| format!("unable to parse transaction {txid}: {}", e.to_string()))?;
+        let script = tx["vout"][vout as usize]["scriptpubkey"]
+            .as_str()
+            .ok_or(format!("output {vout} missing in transaction {txid}"))?;
+        hex::decode(script)
+            .map(ScriptBuf::from)
+            .map_err(|e| format!("unable to decode script of {txid}:{vout}: {e}"))
+    }
+}
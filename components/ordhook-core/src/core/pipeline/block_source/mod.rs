@@ -0,0 +1,229 @@
+pub mod blocks_dir;
+pub mod esplora;
+
+use std::sync::Arc;
+
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{Block, Script, ScriptBuf, Txid};
+use chainhook_sdk::bitcoincore_rpc::{Auth, Client, RpcApi};
+use chainhook_sdk::indexer::bitcoin::{build_http_client, try_download_block_bytes_with_retry};
+use chainhook_sdk::observer::BitcoinConfig;
+use chainhook_sdk::utils::Context;
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+
+use crate::config::{BlockSourceConfig, Config, NetworkParams};
+
+use self::blocks_dir::BitcoindBlocksDirSource;
+use self::esplora::EsploraSource;
+
+/// Source of the blocks pipelined by `download_and_pipeline_blocks`.
+///
+/// Blocks are returned as the bytes of a `getblock <hash> 3` JSON-RPC response (prevouts included),
+/// so that all the sources are going through `parse_downloaded_block` and
+/// `BlockBytesCursor::from_full_block`.
+pub trait BlockSource: Send + Sync {
+    /// Height of the chain tip known by the source.
+    fn get_chain_tip(&self, ctx: &Context) -> Result<u64, String>;
+
+    /// Hash of the canonical block at `block_height`.
+    fn get_block_hash(&self, block_height: u64, ctx: &Context) -> Result<String, String>;
+
+    /// Fetch the block at `block_height`. Transient failures are retried by the source.
+    fn fetch_block(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> BoxFuture<'static, Result<Vec<u8>, String>>;
+
+    /// Script of the output `vout` of the transaction `txid`, used for resolving owners.
+    fn get_output_script(&self, txid: &Txid, vout: u32, ctx: &Context)
+        -> Result<ScriptBuf, String>;
+}
+
+pub fn build_block_source(config: &Config, ctx: &Context) -> Result<Arc<dyn BlockSource>, String> {
+    let block_source: Arc<dyn BlockSource> = match config.block_source {
+        BlockSourceConfig::BitcoindRpc => Arc::new(BitcoindRpcSource::new(config)),
+        BlockSourceConfig::BitcoindBlocksDir(ref blocks_dir) => {
+            Arc::new(BitcoindBlocksDirSource::new(blocks_dir, config, ctx)?)
+        }
+        BlockSourceConfig::Esplora(ref base_url) => Arc::new(EsploraSource::new(base_url, config)?),
+    };
+    Ok(block_source)
+}
+
+fn script_pubkey_json(script: &Script, network_params: Option<&NetworkParams>) -> Value {
+    let mut script_pubkey = json!({
+        "asm": script.to_asm_string(),
+        "hex": hex::encode(script.as_bytes()),
+    });
+    if let Some(Ok(address)) = network_params.map(|params| params.address_from_script(script)) {
+        script_pubkey["address"] = json!(address);
+    }
+    script_pubkey
+}
+
+/// Format a block the way `getblock <hash> 3` does, wrapped in a JSON-RPC response, for sources
+/// reading raw blocks. `prevouts` are the (value, block_height) of the outputs spent by each
+/// transaction (none for the coinbase). Addresses are only derived from the first inscription
+/// height, the blocks prior to it being only compacted.
+pub fn build_getblock_response(
+    block: &Block,
+    block_height: u64,
+    median_time: u32,
+    prevouts: &[Vec<(u64, u64)>],
+    network_params: &NetworkParams,
+) -> Result<Vec<u8>, String> {
+    let btc = |sats: u64| sats as f64 / 100_000_000.0;
+    let address_params =
+        Some(network_params).filter(|params| block_height >= params.first_inscription_height);
+    let mut transactions = vec![];
+    for (tx_index, (tx, tx_prevouts)) in block.txdata.iter().zip(prevouts).enumerate() {
+        let mut vin = vec![];
+        for (input_index, input) in tx.input.iter().enumerate() {
+            let mut json_input = if tx_index == 0 {
+                json!({ "coinbase": hex::encode(input.script_sig.as_bytes()) })
+            } else {
+                let (value, prevout_height) = tx_prevouts[input_index];
+                json!({
+                    "txid": input.previous_output.txid.to_string(),
+                    "vout": input.previous_output.vout,
+                    "scriptSig": {
+                        "asm": input.script_sig.to_asm_string(),
+                        "hex": hex::encode(input.script_sig.as_bytes()),
+                    },
+                    "prevout": {
+                        "height": prevout_height,
+                        "value": btc(value),
+                    },
+                })
+            };
+            if !input.witness.is_empty() {
+                json_input["txinwitness"] =
+                    json!(input.witness.iter().map(hex::encode).collect::<Vec<_>>());
+            }
+            json_input["sequence"] = json!(input.sequence.0);
+            vin.push(json_input);
+        }
+        let vout = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(n, output)| {
+                json!({
+                    "value": btc(output.value),
+                    "n": n,
+                    "scriptPubKey": script_pubkey_json(&output.script_pubkey, address_params),
+                })
+            })
+            .collect::<Vec<_>>();
+        transactions.push(json!({
+            "txid": tx.txid().to_string(),
+            "hash": tx.wtxid().to_string(),
+            "version": tx.version,
+            "locktime": tx.lock_time.to_consensus_u32(),
+            "vin": vin,
+            "vout": vout,
+        }));
+    }
+
+    let mut result = json!({
+        "hash": block.block_hash().to_string(),
+        "height": block_height,
+        "version": block.header.version.to_consensus(),
+        "merkleroot": block.header.merkle_root.to_string(),
+        "time": block.header.time,
+        "mediantime": median_time,
+        "nonce": block.header.nonce,
+        "bits": format!("{:08x}", block.header.bits.to_consensus()),
+        "nTx": transactions.len(),
+        "tx": transactions,
+    });
+    if block_height > 0 {
+        result["previousblockhash"] = json!(block.header.prev_blockhash.to_string());
+    }
+    serde_json::to_vec(&json!({ "result": result, "error": null, "id": block_height }))
+        .map_err(|e| format!("unable to serialize block #{block_height}: {e}"))
+}
+
+/// Blocks downloaded from bitcoind, through JSON-RPC.
+pub struct BitcoindRpcSource {
+    bitcoin_config: BitcoinConfig,
+    http_client: reqwest::Client,
+}
+
+impl BitcoindRpcSource {
+    pub fn new(config: &Config) -> BitcoindRpcSource {
+        BitcoindRpcSource {
+            bitcoin_config: BitcoinConfig {
+                username: config.network.bitcoind_rpc_username.clone(),
+                password: config.network.bitcoind_rpc_password.clone(),
+                rpc_url: config.network.bitcoind_rpc_url.clone(),
+                network: config.network.bitcoin_network.clone(),
+                bitcoin_block_signaling: config.network.bitcoin_block_signaling.clone(),
+            },
+            http_client: build_http_client(),
+        }
+    }
+
+    fn rpc_client(&self) -> Result<Client, String> {
+        let auth = Auth::UserPass(
+            self.bitcoin_config.username.clone(),
+            self.bitcoin_config.password.clone(),
+        );
+        match Client::new(&self.bitcoin_config.rpc_url, auth) {
+            Ok(con) => Ok(con),
+            Err(message) => Err(format!("Bitcoin RPC error: {}", message.to_string())),
+        }
+    }
+}
+
+impl BlockSource for BitcoindRpcSource {
+    fn get_chain_tip(&self, _ctx: &Context) -> Result<u64, String> {
+        match self.rpc_client()?.get_blockchain_info() {
+            Ok(result) => Ok(result.blocks),
+            Err(e) => Err(format!(
+                "unable to retrieve Bitcoin chain tip ({})",
+                e.to_string()
+            )),
+        }
+    }
+
+    fn get_block_hash(&self, block_height: u64, _ctx: &Context) -> Result<String, String> {
+        match self.rpc_client()?.get_block_hash(block_height) {
+            Ok(block_hash) => Ok(block_hash.to_string()),
+            Err(e) => Err(format!(
+                "unable to retrieve block hash #{block_height} ({})",
+                e.to_string()
+            )),
+        }
+    }
+
+    fn fetch_block(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        Box::pin(try_download_block_bytes_with_retry(
+            self.http_client.clone(),
+            block_height,
+            self.bitcoin_config.clone(),
+            ctx.clone(),
+        ))
+    }
+
+    fn get_output_script(
+        &self,
+        txid: &Txid,
+        vout: u32,
+        _ctx: &Context,
+    ) -> Result<ScriptBuf, String> {
+        let tx = self
+            .rpc_client()?
+            .get_raw_transaction(txid, None)
+            .map_err(|e| format!("unable to retrieve transaction {txid} ({})", e.to_string()))?;
+        tx.output
+            .get(vout as usize)
+            .map(|output| output.script_pubkey.clone())
+            .ok_or(format!("output {vout} missing in transaction {txid}"))
+    }
+}
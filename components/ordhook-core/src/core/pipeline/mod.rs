@@ -1,20 +1,22 @@
+pub mod block_source;
 pub mod processors;
 
-use chainhook_sdk::observer::BitcoinConfig;
 use chainhook_sdk::types::BitcoinBlockData;
 use chainhook_sdk::utils::Context;
-use crossbeam_channel::bounded;
+use crossbeam_channel::{bounded, RecvTimeoutError};
 use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread::{sleep, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::task::JoinSet;
 
 use crate::config::Config;
 use crate::db::BlockBytesCursor;
 
-use chainhook_sdk::indexer::bitcoin::{
-    build_http_client, parse_downloaded_block, try_download_block_bytes_with_retry,
-};
+use chainhook_sdk::indexer::bitcoin::parse_downloaded_block;
+
+use self::block_source::BlockSource;
 
 use super::protocol::inscription_parsing::parse_inscriptions_and_standardize_block;
 
@@ -34,38 +36,134 @@ pub struct PostProcessorController {
     pub thread_handle: JoinHandle<()>,
 }
 
+/// Time left to a post processor to commit the blocks it received, once a shutdown is requested.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Raised when a shutdown is requested: `download_and_pipeline_blocks` stops issuing new block
+/// requests, drains the blocks in flight and terminates its post processor.
+#[derive(Clone, Debug)]
+pub struct ShutdownSignal {
+    requested: Arc<AtomicBool>,
+    pub timeout: Duration,
+}
+
+impl ShutdownSignal {
+    pub fn new(timeout: Duration) -> ShutdownSignal {
+        ShutdownSignal {
+            requested: Arc::new(AtomicBool::new(false)),
+            timeout,
+        }
+    }
+
+    pub fn trigger(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// Wait for `post_processor` to terminate. Once a shutdown is requested, it is given
+/// `shutdown.timeout` to do so. Returns false if it timed out.
+fn wait_for_post_processor_termination(
+    post_processor: &PostProcessorController,
+    shutdown: Option<&ShutdownSignal>,
+    ctx: &Context,
+) -> bool {
+    let mut deadline = None;
+    loop {
+        match post_processor
+            .events_rx
+            .recv_timeout(Duration::from_millis(500))
+        {
+            Ok(PostProcessorEvent::Terminated | PostProcessorEvent::Expired) => return true,
+            Err(RecvTimeoutError::Disconnected) => return true,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        let Some(shutdown) = shutdown.filter(|shutdown| shutdown.is_triggered()) else {
+            continue;
+        };
+        let deadline = deadline.get_or_insert(Instant::now() + shutdown.timeout);
+        if Instant::now() > *deadline {
+            ctx.try_log(|logger| {
+                warn!(
+                    logger,
+                    "Post processor not terminated after {}s, giving up",
+                    shutdown.timeout.as_secs()
+                )
+            });
+            return false;
+        }
+    }
+}
+
+/// Measurements of a sequence of blocks processed by `download_and_pipeline_blocks`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PipelineStats {
+    pub blocks_processed: u64,
+    pub bytes_downloaded: u64,
+    /// Highest number of blocks waiting in the pipeline for the post processor.
+    pub peak_queued_blocks: u64,
+    pub elapsed: Duration,
+}
+
+impl PipelineStats {
+    pub fn average_block_bytes(&self) -> Option<u64> {
+        match self.blocks_processed {
+            0 => None,
+            blocks => Some(self.bytes_downloaded / blocks),
+        }
+    }
+
+    pub fn blocks_per_second(&self) -> Option<f64> {
+        match (self.blocks_processed, self.elapsed.as_secs_f64()) {
+            (0, _) => None,
+            (_, elapsed) if elapsed <= 0.0 => None,
+            (blocks, elapsed) => Some(blocks as f64 / elapsed),
+        }
+    }
+
+    /// Memory used by the blocks queued at the peak of the batch.
+    pub fn peak_queue_bytes(&self) -> u64 {
+        self.peak_queued_blocks * self.average_block_bytes().unwrap_or(0)
+    }
+}
+
 pub async fn download_and_pipeline_blocks(
     config: &Config,
+    block_source: &dyn BlockSource,
     blocks: Vec<u64>,
     start_sequencing_blocks_at_height: u64,
     blocks_post_processor: Option<&PostProcessorController>,
     speed: usize,
+    shutdown: Option<&ShutdownSignal>,
     ctx: &Context,
-) -> Result<(), String> {
+) -> Result<PipelineStats, String> {
     // let guard = pprof::ProfilerGuardBuilder::default()
     //     .frequency(20)
     //     .blocklist(&["libc", "libgcc", "pthread", "vdso"])
     //     .build()
     //     .unwrap();
 
-    let bitcoin_config = BitcoinConfig {
-        username: config.network.bitcoind_rpc_username.clone(),
-        password: config.network.bitcoind_rpc_password.clone(),
-        rpc_url: config.network.bitcoind_rpc_url.clone(),
-        network: config.network.bitcoin_network.clone(),
-        bitcoin_block_signaling: config.network.bitcoin_block_signaling.clone(),
-    };
+    let started_at = Instant::now();
+
+    // Every block of the range is already archived and sequenced
+    if blocks.is_empty() {
+        if let Some(post_processor) = blocks_post_processor {
+            let _ = post_processor
+                .commands_tx
+                .send(PostProcessorCommand::Terminate);
+            wait_for_post_processor_termination(post_processor, shutdown, ctx);
+        }
+        return Ok(PipelineStats::default());
+    }
 
     let ordhook_config = config.get_ordhook_config();
 
     let number_of_blocks_to_process = blocks.len() as u64;
 
     let (block_compressed_tx, block_compressed_rx) = crossbeam_channel::bounded(speed);
-    let http_client = build_http_client();
-
-    let moved_config = bitcoin_config.clone();
-    let moved_ctx = ctx.clone();
-    let moved_http_client = http_client.clone();
 
     let mut set = JoinSet::new();
 
@@ -90,22 +188,14 @@ pub async fn download_and_pipeline_blocks(
 
     for _ in 0..ordhook_config.resources.bitcoind_rpc_threads {
         if let Some(block_height) = block_heights.pop_front() {
-            let config = moved_config.clone();
-            let ctx = moved_ctx.clone();
-            let http_client = moved_http_client.clone();
             // We interleave the initial requests to avoid DDOSing bitcoind from the get go.
             sleep(Duration::from_millis(500));
-            set.spawn(try_download_block_bytes_with_retry(
-                http_client,
-                block_height,
-                config,
-                ctx,
-            ));
+            set.spawn(block_source.fetch_block(block_height, ctx));
         }
     }
 
     let moved_ctx: Context = ctx.clone();
-    let moved_bitcoin_network = bitcoin_config.network.clone();
+    let moved_bitcoin_network = config.network.bitcoin_network.clone();
 
     let mut tx_thread_pool = vec![];
     let mut rx_thread_pool = vec![];
@@ -165,7 +255,9 @@ pub async fn download_and_pipeline_blocks(
             let mut inbox = HashMap::new();
             let mut inbox_cursor = start_sequencing_blocks_at_height.max(start_block);
             let mut blocks_processed = 0;
+            let mut peak_queued_blocks = 0;
             let mut stop_runloop = false;
+            let mut end_of_stream = false;
 
             loop {
                 if stop_runloop {
@@ -182,6 +274,7 @@ pub async fn download_and_pipeline_blocks(
                 }
 
                 // Dequeue all the blocks available
+                peak_queued_blocks = peak_queued_blocks.max(block_compressed_rx.len() as u64);
                 let mut new_blocks = vec![];
                 while let Ok(message) = block_compressed_rx.try_recv() {
                     match message {
@@ -193,12 +286,15 @@ pub async fn download_and_pipeline_blocks(
                             }
                         }
                         None => {
+                            end_of_stream = true;
                             break;
                         }
                     }
                 }
 
-                if blocks_processed == number_of_blocks_to_process {
+                // All the blocks fetched were received: the pipeline was either fully
+                // processed, or interrupted by a shutdown
+                if blocks_processed == number_of_blocks_to_process || end_of_stream {
                     stop_runloop = true;
                 }
 
@@ -256,15 +352,29 @@ pub async fn download_and_pipeline_blocks(
                     stop_runloop = true;
                 }
             }
-            ()
+            (peak_queued_blocks, blocks_processed)
         })
         .expect("unable to spawn thread");
 
     let mut round_robin_worker_thread_index = 0;
+    let mut bytes_downloaded = 0;
+    let mut fetch_error = None;
     while let Some(res) = set.join_next().await {
-        let block = res
-            .expect("unable to retrieve block")
-            .expect("unable to deserialize block");
+        // Blocks fetched so far are still processed, the pipeline is terminated as usual
+        let block = match res {
+            Ok(Ok(block)) => block,
+            Ok(Err(e)) => {
+                fetch_error = Some(e);
+                set.abort_all();
+                break;
+            }
+            Err(e) => {
+                fetch_error = Some(format!("unable to retrieve block: {e}"));
+                set.abort_all();
+                break;
+            }
+        };
+        bytes_downloaded += block.len() as u64;
 
         loop {
             let res = tx_thread_pool[round_robin_worker_thread_index].send(Some(block.clone()));
@@ -276,16 +386,19 @@ pub async fn download_and_pipeline_blocks(
             sleep(Duration::from_millis(500));
         }
 
+        if shutdown.map_or(false, |shutdown| shutdown.is_triggered()) && !block_heights.is_empty() {
+            ctx.try_log(|logger| {
+                info!(
+                    logger,
+                    "Shutdown requested, draining {} blocks in flight",
+                    set.len()
+                )
+            });
+            block_heights.clear();
+        }
+
         if let Some(block_height) = block_heights.pop_front() {
-            let config = moved_config.clone();
-            let ctx = ctx.clone();
-            let http_client = moved_http_client.clone();
-            set.spawn(try_download_block_bytes_with_retry(
-                http_client,
-                block_height,
-                config,
-                ctx,
-            ));
+            set.spawn(block_source.fetch_block(block_height, ctx));
         }
     }
 
@@ -308,21 +421,31 @@ pub async fn download_and_pipeline_blocks(
 
     ctx.try_log(|logger| debug!(logger, "Pipeline successfully terminated"));
 
-    if let Some(post_processor) = blocks_post_processor {
-        loop {
-            if let Ok(signal) = post_processor.events_rx.recv() {
-                match signal {
-                    PostProcessorEvent::Terminated | PostProcessorEvent::Expired => break,
-                }
-            }
-        }
-    }
-
+    // Every block fetched was compressed: let the dispatcher terminate the post processor
     let _ = block_compressed_tx.send(None);
 
-    let _ = storage_thread.join();
+    let post_processor_terminated = match blocks_post_processor {
+        Some(post_processor) => wait_for_post_processor_termination(post_processor, shutdown, ctx),
+        None => true,
+    };
+
+    // The dispatcher can be blocked by a post processor which did not terminate in time
+    let (peak_queued_blocks, blocks_processed) = match post_processor_terminated {
+        true => storage_thread.join().unwrap_or((0, 0)),
+        false => (0, 0),
+    };
     let _ = set.shutdown();
 
+    if let Some(e) = fetch_error {
+        ctx.try_log(|logger| {
+            error!(
+                logger,
+                "Pipeline interrupted after {blocks_processed} blocks: {e}"
+            )
+        });
+        return Err(e);
+    }
+
     ctx.try_log(|logger| {
         info!(
             logger,
@@ -345,5 +468,10 @@ pub async fn download_and_pipeline_blocks(
     //     }
     // }
 
-    Ok(())
+    Ok(PipelineStats {
+        blocks_processed,
+        bytes_downloaded,
+        peak_queued_blocks,
+        elapsed: started_at.elapsed(),
+    })
 }
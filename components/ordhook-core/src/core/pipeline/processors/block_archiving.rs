@@ -9,7 +9,10 @@ use std::{
 use crate::{
     config::Config,
     core::pipeline::{PostProcessorCommand, PostProcessorController, PostProcessorEvent},
-    db::{insert_entry_in_blocks, open_ordhook_db_conn_rocks_db_loop},
+    db::{
+        insert_entry_in_blocks, open_ordhook_db_conn_rocks_db_loop,
+        sync_checkpoint::update_sync_checkpoint,
+    },
 };
 
 pub fn start_block_archiving_processor(
@@ -28,8 +31,7 @@ pub fn start_block_archiving_processor(
             let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
                 true,
                 &config.expected_cache_path(),
-                config.resources.ulimit,
-                config.resources.memory_available,
+                &config.resources,
                 &ctx,
             );
             let mut processed_blocks = 0;
@@ -40,6 +42,8 @@ pub fn start_block_archiving_processor(
                         (compacted_blocks, blocks)
                     }
                     Ok(PostProcessorCommand::Terminate) => {
+                        // Archived blocks have to be on disk once the termination is acknowledged
+                        flush_blocks_db(&blocks_db_rw, &ctx);
                         let _ = events_tx.send(PostProcessorEvent::Terminated);
                         break;
                     }
@@ -49,6 +53,7 @@ pub fn start_block_archiving_processor(
                             continue;
                         }
                         _ => {
+                            flush_blocks_db(&blocks_db_rw, &ctx);
                             break;
                         }
                     },
@@ -60,12 +65,6 @@ pub fn start_block_archiving_processor(
                     let _ = blocks_db_rw.flush_wal(true);
                 }
             }
-
-            if let Err(e) = blocks_db_rw.flush() {
-                ctx.try_log(|logger| {
-                    error!(logger, "{}", e.to_string());
-                });
-            }
         })
         .expect("unable to spawn thread");
 
@@ -76,6 +75,14 @@ pub fn start_block_archiving_processor(
     }
 }
 
+fn flush_blocks_db(blocks_db_rw: &DB, ctx: &Context) {
+    if let Err(e) = blocks_db_rw.flush() {
+        ctx.try_log(|logger| {
+            error!(logger, "{}", e.to_string());
+        });
+    }
+}
+
 pub fn store_compacted_blocks(
     mut compacted_blocks: Vec<(u64, Vec<u8>)>,
     update_tip: bool,
@@ -83,6 +90,10 @@ pub fn store_compacted_blocks(
     ctx: &Context,
 ) {
     compacted_blocks.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let block_heights = compacted_blocks
+        .iter()
+        .map(|(block_height, _)| *block_height)
+        .collect::<Vec<_>>();
 
     for (block_height, compacted_block) in compacted_blocks.into_iter() {
         insert_entry_in_blocks(
@@ -97,6 +108,12 @@ pub fn store_compacted_blocks(
         });
     }
 
+    if update_tip {
+        update_sync_checkpoint(blocks_db_rw, ctx, |checkpoint| {
+            checkpoint.record_archived_blocks(&block_heights, blocks_db_rw)
+        });
+    }
+
     if let Err(e) = blocks_db_rw.flush() {
         ctx.try_log(|logger| {
             error!(logger, "{}", e.to_string());
@@ -10,12 +10,9 @@ use chainhook_sdk::{
     utils::Context,
 };
 use crossbeam_channel::{Sender, TryRecvError};
-use rusqlite::Transaction;
+use rocksdb::DB;
 
-use dashmap::DashMap;
-use fxhash::FxHasher;
 use rusqlite::Connection;
-use std::hash::BuildHasherDefault;
 
 use crate::{
     core::{
@@ -30,20 +27,28 @@ use crate::{
             },
             inscription_tracking::augment_block_with_ordinals_transfer_data,
         },
-        OrdhookConfig,
+        warm_up_traversals_cache, OrdhookConfig,
     },
     db::{
-        get_any_entry_in_ordinal_activities, open_ordhook_db_conn_rocks_db_loop,
-        open_readonly_ordhook_db_conn,
+        get_any_entry_in_ordinal_activities,
+        journal::{build_block_undo_journal, record_block_undo_journal},
+        open_ordhook_db_conn_rocks_db_loop, open_readonly_ordhook_db_conn,
+        store::{
+            rocksdb::{create_inscriptions_column_families, RocksDbInscriptionStore},
+            InscriptionStore, SqliteInscriptionStore,
+        },
+        sync_checkpoint::update_sync_checkpoint,
     },
 };
 
-use crate::db::{TransactionBytesCursor, TraversalResult};
+#[cfg(feature = "postgres")]
+use crate::db::store::postgres::PostgresInscriptionStore;
+use crate::db::{traversals_cache::TraversalsCache, TraversalResult};
 
 use crate::{
-    config::Config,
+    config::{Config, InscriptionsIndexLayout},
     core::{
-        new_traversals_lazy_cache,
+        new_traversals_lazy_cache, persist_traversals_cache,
         pipeline::{PostProcessorCommand, PostProcessorController, PostProcessorEvent},
     },
     db::open_readwrite_ordhook_db_conn,
@@ -61,7 +66,10 @@ pub fn start_inscription_indexing_processor(
     let ctx = ctx.clone();
     let handle: JoinHandle<()> = hiro_system_kit::thread_named("Inscription indexing runloop")
         .spawn(move || {
-            let cache_l2 = Arc::new(new_traversals_lazy_cache(2048));
+            let cache_l2 = Arc::new(new_traversals_lazy_cache(
+                config.resources.get_memory_budget(),
+            ));
+            warm_up_traversals_cache(&config, &cache_l2, &ctx);
             let garbage_collect_every_n_blocks = 100;
             let mut garbage_collect_nth_block = 0;
 
@@ -72,7 +80,23 @@ pub fn start_inscription_indexing_processor(
 
             let inscriptions_db_conn =
                 open_readonly_ordhook_db_conn(&config.expected_cache_path(), &ctx).unwrap();
-            let mut sequence_cursor = SequenceCursor::new(&inscriptions_db_conn);
+            let inscriptions_store = SqliteInscriptionStore::new(&inscriptions_db_conn);
+            let mut sequence_cursor = SequenceCursor::new(&inscriptions_store);
+
+            #[cfg(feature = "postgres")]
+            let postgres_store = match config.storage.inscriptions_index {
+                InscriptionsIndexLayout::Postgres(ref database_url) => {
+                    match PostgresInscriptionStore::connect(database_url) {
+                        Ok(inscriptions_store) => Some(inscriptions_store),
+                        Err(e) => {
+                            ctx.try_log(|logger| error!(logger, "{e}"));
+                            let _ = events_tx.send(PostProcessorEvent::Terminated);
+                            return;
+                        }
+                    }
+                }
+                _ => None,
+            };
 
             loop {
                 let (compacted_blocks, mut blocks) = match commands_rx.try_recv() {
@@ -81,6 +105,7 @@ pub fn start_inscription_indexing_processor(
                         (compacted_blocks, blocks)
                     }
                     Ok(PostProcessorCommand::Terminate) => {
+                        persist_traversals_cache(&config, &cache_l2, &ctx);
                         let _ = events_tx.send(PostProcessorEvent::Terminated);
                         break;
                     }
@@ -91,6 +116,7 @@ pub fn start_inscription_indexing_processor(
                                 ctx.try_log(|logger| {
                                     info!(logger, "Block processor reached expiration")
                                 });
+                                persist_traversals_cache(&config, &cache_l2, &ctx);
                                 let _ = events_tx.send(PostProcessorEvent::Expired);
                                 break;
                             }
@@ -103,47 +129,147 @@ pub fn start_inscription_indexing_processor(
                     },
                 };
 
-                {
-                    let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
-                        true,
-                        &config.expected_cache_path(),
-                        config.resources.ulimit,
-                        config.resources.memory_available,
-                        &ctx,
-                    );
-                    store_compacted_blocks(
-                        compacted_blocks,
-                        true,
-                        &blocks_db_rw,
-                        &Context::empty(),
-                    );
-                }
+                match config.storage.inscriptions_index {
+                    InscriptionsIndexLayout::RocksDb => {
+                        let mut blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                            true,
+                            &config.expected_cache_path(),
+                            &config.resources,
+                            &ctx,
+                        );
+                        if let Err(e) = create_inscriptions_column_families(
+                            &mut blocks_db_rw,
+                            &config.resources,
+                        ) {
+                            ctx.try_log(|logger| error!(logger, "{e}"));
+                            let _ = events_tx.send(PostProcessorEvent::Terminated);
+                            break;
+                        }
+                        store_compacted_blocks(
+                            compacted_blocks,
+                            true,
+                            &blocks_db_rw,
+                            &Context::empty(),
+                        );
+
+                        if blocks.is_empty() {
+                            continue;
+                        }
 
-                // Early return
-                if blocks.is_empty() {
-                    continue;
-                }
+                        ctx.try_log(|logger| info!(logger, "Processing {} blocks", blocks.len()));
+                        let inscriptions_store = RocksDbInscriptionStore::new(&blocks_db_rw)
+                            .expect("unable to use hord.rocksdb column families");
+                        blocks = process_blocks_in_rocks_db(
+                            &mut blocks,
+                            &cache_l2,
+                            &inscriptions_store,
+                            &ordhook_config,
+                            &post_processor,
+                            &ctx,
+                        );
+                        record_sequenced_blocks(&blocks, &blocks_db_rw, &ctx);
+                    }
+                    #[cfg(feature = "postgres")]
+                    InscriptionsIndexLayout::Postgres(_) => {
+                        {
+                            let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                                true,
+                                &config.expected_cache_path(),
+                                &config.resources,
+                                &ctx,
+                            );
+                            store_compacted_blocks(
+                                compacted_blocks,
+                                true,
+                                &blocks_db_rw,
+                                &Context::empty(),
+                            );
+                        }
 
-                ctx.try_log(|logger| info!(logger, "Processing {} blocks", blocks.len()));
-                blocks = process_blocks(
-                    &mut blocks,
-                    &mut sequence_cursor,
-                    &cache_l2,
-                    &mut inscriptions_db_conn_rw,
-                    &ordhook_config,
-                    &post_processor,
-                    &ctx,
-                );
+                        if blocks.is_empty() {
+                            continue;
+                        }
+
+                        ctx.try_log(|logger| info!(logger, "Processing {} blocks", blocks.len()));
+                        let inscriptions_store = postgres_store
+                            .as_ref()
+                            .expect("postgres store not connected");
+                        blocks = process_blocks_in_postgres(
+                            &mut blocks,
+                            &cache_l2,
+                            inscriptions_store,
+                            &ordhook_config,
+                            &post_processor,
+                            &ctx,
+                        );
+                        let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                            true,
+                            &config.expected_cache_path(),
+                            &config.resources,
+                            &ctx,
+                        );
+                        record_sequenced_blocks(&blocks, &blocks_db_rw, &ctx);
+                    }
+                    InscriptionsIndexLayout::Sqlite => {
+                        {
+                            let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                                true,
+                                &config.expected_cache_path(),
+                                &config.resources,
+                                &ctx,
+                            );
+                            store_compacted_blocks(
+                                compacted_blocks,
+                                true,
+                                &blocks_db_rw,
+                                &Context::empty(),
+                            );
+                        }
+
+                        // Early return
+                        if blocks.is_empty() {
+                            continue;
+                        }
+
+                        ctx.try_log(|logger| info!(logger, "Processing {} blocks", blocks.len()));
+                        blocks = process_blocks(
+                            &mut blocks,
+                            &mut sequence_cursor,
+                            &cache_l2,
+                            &mut inscriptions_db_conn_rw,
+                            &ordhook_config,
+                            &post_processor,
+                            &ctx,
+                        );
+                        let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                            true,
+                            &config.expected_cache_path(),
+                            &config.resources,
+                            &ctx,
+                        );
+                        record_sequenced_blocks(&blocks, &blocks_db_rw, &ctx);
+                    }
+                }
 
                 garbage_collect_nth_block += blocks.len();
                 if garbage_collect_nth_block > garbage_collect_every_n_blocks {
                     ctx.try_log(|logger| info!(logger, "Performing garbage collecting"));
 
-                    // Clear L2 cache on a regular basis
+                    // L2 cache is bounded by its memory budget, evictions are only reported
+                    let stats = cache_l2.stats();
                     ctx.try_log(|logger| {
-                        info!(logger, "Clearing cache L2 ({} entries)", cache_l2.len())
+                        info!(
+                            logger,
+                            "Cache L2: {} entries, {}MB used out of {}MB, {} hits, {} misses, {} evictions",
+                            stats.entries,
+                            stats.size / (1024 * 1024),
+                            stats.memory_budget / (1024 * 1024),
+                            stats.hits,
+                            stats.misses,
+                            stats.evictions
+                        )
                     });
-                    cache_l2.clear();
+                    persist_traversals_cache(&config, &cache_l2, &ctx);
 
                     // Recreate sqlite db connection on a regular basis
                     inscriptions_db_conn_rw =
@@ -163,10 +289,21 @@ pub fn start_inscription_indexing_processor(
     }
 }
 
+/// Advance the sync checkpoint past the blocks processed, so that an interrupted sync
+/// resumes sequencing after them.
+fn record_sequenced_blocks(blocks: &[BitcoinBlockData], blocks_db_rw: &DB, ctx: &Context) {
+    let Some(block_height) = blocks.iter().map(|b| b.block_identifier.index).max() else {
+        return;
+    };
+    update_sync_checkpoint(blocks_db_rw, ctx, |checkpoint| {
+        checkpoint.record_sequenced_block(block_height)
+    });
+}
+
 pub fn process_blocks(
     next_blocks: &mut Vec<BitcoinBlockData>,
     sequence_cursor: &mut SequenceCursor,
-    cache_l2: &Arc<DashMap<(u32, [u8; 8]), TransactionBytesCursor, BuildHasherDefault<FxHasher>>>,
+    cache_l2: &Arc<TraversalsCache>,
     inscriptions_db_conn_rw: &mut Connection,
     ordhook_config: &OrdhookConfig,
     post_processor: &Option<Sender<BitcoinBlockData>>,
@@ -190,16 +327,31 @@ pub fn process_blocks(
             ctx,
         );
 
+        let inscriptions_store = SqliteInscriptionStore::new(&inscriptions_db_tx);
+        let undo_journal = build_block_undo_journal(
+            &block.block_identifier,
+            ordhook_config.undo_journal_depth,
+            &inscriptions_store,
+            ctx,
+        );
         let _ = process_block(
             &mut block,
             &next_blocks,
             sequence_cursor,
             &mut cache_l1,
             cache_l2,
-            &inscriptions_db_tx,
+            &inscriptions_store,
             ordhook_config,
             ctx,
         );
+        if let Some(ref undo_journal) = undo_journal {
+            record_block_undo_journal(
+                undo_journal,
+                ordhook_config.undo_journal_depth,
+                &inscriptions_store,
+                ctx,
+            );
+        }
 
         let inscriptions_revealed = get_inscriptions_revealed_in_block(&block)
             .iter()
@@ -258,13 +410,218 @@ pub fn process_blocks(
     updated_blocks
 }
 
+/// Same as `process_blocks`, for the all-RocksDB layout: the updates of each block are
+/// committed in one `WriteBatch`, or dropped if activities are already present at its height.
+pub fn process_blocks_in_rocks_db(
+    next_blocks: &mut Vec<BitcoinBlockData>,
+    cache_l2: &Arc<TraversalsCache>,
+    inscriptions_store: &RocksDbInscriptionStore,
+    ordhook_config: &OrdhookConfig,
+    post_processor: &Option<Sender<BitcoinBlockData>>,
+    ctx: &Context,
+) -> Vec<BitcoinBlockData> {
+    let mut cache_l1 = BTreeMap::new();
+    let mut sequence_cursor = SequenceCursor::new(inscriptions_store);
+
+    let mut updated_blocks = vec![];
+
+    for _cursor in 0..next_blocks.len() {
+        let mut block = next_blocks.remove(0);
+        let block_height = block.block_identifier.index;
+
+        let any_existing_activity = inscriptions_store.any_entry_at_block_height(block_height, ctx);
+
+        let undo_journal = build_block_undo_journal(
+            &block.block_identifier,
+            ordhook_config.undo_journal_depth,
+            inscriptions_store,
+            ctx,
+        );
+        let _ = process_block(
+            &mut block,
+            &next_blocks,
+            &mut sequence_cursor,
+            &mut cache_l1,
+            cache_l2,
+            inscriptions_store,
+            ordhook_config,
+            ctx,
+        );
+        if let Some(ref undo_journal) = undo_journal {
+            record_block_undo_journal(
+                undo_journal,
+                ordhook_config.undo_journal_depth,
+                inscriptions_store,
+                ctx,
+            );
+        }
+
+        let inscriptions_revealed = get_inscriptions_revealed_in_block(&block)
+            .iter()
+            .map(|d| d.get_inscription_number().to_string())
+            .collect::<Vec<String>>();
+
+        let inscriptions_transferred = get_inscriptions_transferred_in_block(&block).len();
+
+        ctx.try_log(|logger| {
+            info!(
+                logger,
+                "Block #{} processed, revealed {} inscriptions [{}] and {inscriptions_transferred} transfers",
+                block_height,
+                inscriptions_revealed.len(),
+                inscriptions_revealed.join(", ")
+            )
+        });
+
+        if any_existing_activity {
+            ctx.try_log(|logger| {
+                error!(
+                    logger,
+                    "Dropping updates for block #{}, activities present in database", block_height,
+                )
+            });
+            inscriptions_store.discard();
+        } else {
+            inscriptions_store.commit(block_height, None, ctx);
+        }
+
+        if let Some(post_processor_tx) = post_processor {
+            let _ = post_processor_tx.send(block.clone());
+        }
+        updated_blocks.push(block);
+    }
+    updated_blocks
+}
+
+/// Attempts at committing the updates of a block to PostgreSQL, before giving up on the batch.
+#[cfg(feature = "postgres")]
+const MAX_POSTGRES_BLOCK_ATTEMPTS: usize = 3;
+
+/// Same as `process_blocks`, for the PostgreSQL layout: the updates of each block are
+/// committed in one transaction, or rolled back if activities are already present at its height.
+/// Blocks whose transaction fails are processed again. After `MAX_POSTGRES_BLOCK_ATTEMPTS`, the
+/// remaining blocks of the batch are left to the next sync.
+#[cfg(feature = "postgres")]
+pub fn process_blocks_in_postgres(
+    next_blocks: &mut Vec<BitcoinBlockData>,
+    cache_l2: &Arc<TraversalsCache>,
+    inscriptions_store: &PostgresInscriptionStore,
+    ordhook_config: &OrdhookConfig,
+    post_processor: &Option<Sender<BitcoinBlockData>>,
+    ctx: &Context,
+) -> Vec<BitcoinBlockData> {
+    let mut cache_l1 = BTreeMap::new();
+    let mut sequence_cursor = SequenceCursor::new(inscriptions_store);
+
+    let mut updated_blocks = vec![];
+
+    for _cursor in 0..next_blocks.len() {
+        let pending_block = next_blocks.remove(0);
+        let block_height = pending_block.block_identifier.index;
+
+        let mut attempts = 0;
+        let processed_block = loop {
+            let mut block = pending_block.clone();
+            inscriptions_store.begin(ctx);
+            let any_existing_activity =
+                inscriptions_store.any_entry_at_block_height(block_height, ctx);
+
+            let undo_journal = build_block_undo_journal(
+                &block.block_identifier,
+                ordhook_config.undo_journal_depth,
+                inscriptions_store,
+                ctx,
+            );
+            let _ = process_block(
+                &mut block,
+                &next_blocks,
+                &mut sequence_cursor,
+                &mut cache_l1,
+                cache_l2,
+                inscriptions_store,
+                ordhook_config,
+                ctx,
+            );
+            if let Some(ref undo_journal) = undo_journal {
+                record_block_undo_journal(
+                    undo_journal,
+                    ordhook_config.undo_journal_depth,
+                    inscriptions_store,
+                    ctx,
+                );
+            }
+
+            if any_existing_activity {
+                ctx.try_log(|logger| {
+                    error!(
+                        logger,
+                        "Dropping updates for block #{}, activities present in database",
+                        block_height,
+                    )
+                });
+                inscriptions_store.rollback(ctx);
+                break Some(block);
+            }
+            match inscriptions_store.commit(ctx) {
+                Ok(()) => break Some(block),
+                Err(e) => {
+                    attempts += 1;
+                    // Numbers picked during the failed attempt were not persisted
+                    sequence_cursor.reset();
+                    ctx.try_log(|logger| {
+                        warn!(
+                            logger,
+                            "Unable to index block #{block_height} (attempt {attempts}/{MAX_POSTGRES_BLOCK_ATTEMPTS}): {e}"
+                        )
+                    });
+                    if attempts >= MAX_POSTGRES_BLOCK_ATTEMPTS {
+                        break None;
+                    }
+                }
+            }
+        };
+        let Some(block) = processed_block else {
+            ctx.try_log(|logger| {
+                error!(
+                    logger,
+                    "Giving up on block #{block_height}, it will be indexed again by the next sync"
+                )
+            });
+            break;
+        };
+
+        let inscriptions_revealed = get_inscriptions_revealed_in_block(&block)
+            .iter()
+            .map(|d| d.get_inscription_number().to_string())
+            .collect::<Vec<String>>();
+
+        let inscriptions_transferred = get_inscriptions_transferred_in_block(&block).len();
+
+        ctx.try_log(|logger| {
+            info!(
+                logger,
+                "Block #{} processed, revealed {} inscriptions [{}] and {inscriptions_transferred} transfers",
+                block_height,
+                inscriptions_revealed.len(),
+                inscriptions_revealed.join(", ")
+            )
+        });
+
+        if let Some(post_processor_tx) = post_processor {
+            let _ = post_processor_tx.send(block.clone());
+        }
+        updated_blocks.push(block);
+    }
+    updated_blocks
+}
+
 pub fn process_block(
     block: &mut BitcoinBlockData,
     next_blocks: &Vec<BitcoinBlockData>,
     sequence_cursor: &mut SequenceCursor,
     cache_l1: &mut BTreeMap<(TransactionIdentifier, usize, u64), TraversalResult>,
-    cache_l2: &Arc<DashMap<(u32, [u8; 8]), TransactionBytesCursor, BuildHasherDefault<FxHasher>>>,
-    inscriptions_db_tx: &Transaction,
+    cache_l2: &Arc<TraversalsCache>,
+    inscriptions_store: &dyn InscriptionStore,
     ordhook_config: &OrdhookConfig,
     ctx: &Context,
 ) -> Result<(), String> {
@@ -273,7 +630,7 @@ pub fn process_block(
         &next_blocks,
         cache_l1,
         cache_l2,
-        inscriptions_db_tx,
+        inscriptions_store,
         &ordhook_config,
         ctx,
     )?;
@@ -290,13 +647,22 @@ pub fn process_block(
             block,
             sequence_cursor,
             cache_l1,
-            &inscriptions_db_tx,
+            inscriptions_store,
+            &ordhook_config.network_params,
             &inner_ctx,
         );
     }
 
     // Handle transfers
-    let _ = augment_block_with_ordinals_transfer_data(block, inscriptions_db_tx, true, &inner_ctx);
+    let _ = augment_block_with_ordinals_transfer_data(
+        block,
+        inscriptions_store,
+        true,
+        &ordhook_config.network_params,
+        &inner_ctx,
+    );
+
+    inscriptions_store.insert_block_hash(&block.block_identifier, ctx);
 
     Ok(())
 }
@@ -17,7 +17,7 @@ use crate::{
     },
     db::{
         insert_new_inscriptions_from_block_in_locations, open_readwrite_ordhook_db_conn,
-        remove_entries_from_locations_at_block_height,
+        remove_entries_from_locations_at_block_height, store::SqliteInscriptionStore,
     },
 };
 
@@ -72,8 +72,9 @@ pub fn start_transfers_recomputing_processor(
                 for block in blocks.iter_mut() {
                     consolidate_block_with_pre_computed_ordinals_data(
                         block,
-                        &inscriptions_db_tx,
+                        &SqliteInscriptionStore::new(&inscriptions_db_tx),
                         false,
+                        &config.network_params,
                         &ctx,
                     );
 
@@ -91,8 +92,9 @@ pub fn start_transfers_recomputing_processor(
 
                     augment_block_with_ordinals_transfer_data(
                         block,
-                        &inscriptions_db_tx,
+                        &SqliteInscriptionStore::new(&inscriptions_db_tx),
                         true,
+                        &config.network_params,
                         &ctx,
                     );
 
@@ -1,30 +1,24 @@
 use std::{
     collections::{BTreeMap, HashMap, VecDeque},
-    hash::BuildHasherDefault,
     sync::Arc,
 };
 
 use chainhook_sdk::{
-    bitcoincore_rpc_json::bitcoin::Network,
     types::{
-        BitcoinBlockData, BitcoinNetwork, BitcoinTransactionData, BlockIdentifier,
-        OrdinalInscriptionCurseType, OrdinalInscriptionNumber,
-        OrdinalInscriptionTransferDestination, OrdinalOperation, TransactionIdentifier,
+        BitcoinBlockData, BitcoinTransactionData, BlockIdentifier, OrdinalInscriptionCurseType,
+        OrdinalInscriptionNumber, OrdinalInscriptionTransferDestination, OrdinalOperation,
+        TransactionIdentifier,
     },
     utils::Context,
 };
 use crossbeam_channel::unbounded;
-use dashmap::DashMap;
-use fxhash::FxHasher;
-use rusqlite::{Connection, Transaction};
 
 use crate::{
+    config::NetworkParams,
     core::OrdhookConfig,
     db::{
-        find_blessed_inscription_with_ordinal_number, find_nth_classic_neg_number_at_block_height,
-        find_nth_classic_pos_number_at_block_height, find_nth_jubilee_number_at_block_height,
-        format_inscription_id, update_inscriptions_with_block, update_sequence_metadata_with_block,
-        TransactionBytesCursor, TraversalResult,
+        format_inscription_id, store::InscriptionStore, traversals_cache::TraversalsCache,
+        TraversalResult,
     },
     ord::height::Height,
 };
@@ -33,14 +27,12 @@ use rand::seq::SliceRandom;
 use rand::thread_rng;
 use std::sync::mpsc::channel;
 
-use crate::db::find_all_inscriptions_in_block;
-
 use super::{
     inscription_parsing::get_inscriptions_revealed_in_block,
     inscription_tracking::{
         augment_transaction_with_ordinals_transfers_data, compute_satpoint_post_transfer,
     },
-    satoshi_numbering::compute_satoshi_number,
+    satoshi_numbering::{compute_satoshi_numbers, TraversalRequest},
 };
 
 /// Parallelize the computation of ordinals numbers for inscriptions present in a block.
@@ -51,8 +43,8 @@ use super::{
 /// 2) Create a threadpool, by spawning as many threads as specified by the config to process the batch ordinals to
 /// retrieve
 /// 3) Consume eventual entries in cache L1
-/// 4) Inject the ordinals to compute (random order) in a priority queue
-/// via the command line).
+/// 4) Inject the ordinals to compute (random order) in a priority queue, as one batch per thread:
+/// the traversals of a batch are advanced in lockstep (see `compute_satoshi_numbers`).
 /// 5) Keep injecting ordinals from next blocks (if any) as long as the ordinals from the current block are not all
 /// computed and augment the cache L1 for future blocks.
 ///
@@ -70,8 +62,8 @@ pub fn parallelize_inscription_data_computations(
     block: &BitcoinBlockData,
     next_blocks: &Vec<BitcoinBlockData>,
     cache_l1: &mut BTreeMap<(TransactionIdentifier, usize, u64), TraversalResult>,
-    cache_l2: &Arc<DashMap<(u32, [u8; 8]), TransactionBytesCursor, BuildHasherDefault<FxHasher>>>,
-    inscriptions_db_tx: &Transaction,
+    cache_l2: &Arc<TraversalsCache>,
+    inscriptions_store: &dyn InscriptionStore,
     ordhook_config: &OrdhookConfig,
     ctx: &Context,
 ) -> Result<bool, String> {
@@ -89,7 +81,7 @@ pub fn parallelize_inscription_data_computations(
     });
 
     let (mut transactions_ids, l1_cache_hits) =
-        get_transactions_to_process(block, cache_l1, inscriptions_db_tx, ctx);
+        get_transactions_to_process(block, cache_l1, inscriptions_store, ctx);
 
     let has_transactions_to_process = !transactions_ids.is_empty() || !l1_cache_hits.is_empty();
 
@@ -113,35 +105,31 @@ pub fn parallelize_inscription_data_computations(
         let moved_traversal_tx = traversal_tx.clone();
         let moved_ctx = inner_ctx.clone();
         let moved_ordhook_db_path = ordhook_config.db_path.clone();
-        let ulimit = ordhook_config.resources.ulimit;
-        let memory_available = ordhook_config.resources.memory_available;
+        let resources = ordhook_config.resources.clone();
 
         let local_cache = cache_l2.clone();
 
         let handle = hiro_system_kit::thread_named("Worker")
             .spawn(move || {
-                while let Ok(Some((
-                    transaction_id,
-                    block_identifier,
-                    input_index,
-                    inscription_pointer,
-                    prioritary,
-                ))) = rx.recv()
-                {
-                    let traversal: Result<(TraversalResult, u64, _), String> =
-                        compute_satoshi_number(
-                            &moved_ordhook_db_path,
-                            &block_identifier,
-                            &transaction_id,
-                            input_index,
-                            inscription_pointer,
-                            &local_cache,
-                            ulimit,
-                            memory_available,
-                            false,
-                            &moved_ctx,
-                        );
-                    let _ = moved_traversal_tx.send((traversal, prioritary, thread_index));
+                while let Ok(Some((requests, prioritary))) = rx.recv() {
+                    let blocks_db = open_ordhook_db_conn_rocks_db_loop(
+                        false,
+                        &moved_ordhook_db_path,
+                        &resources,
+                        &moved_ctx,
+                    );
+                    let traversals =
+                        compute_satoshi_numbers(&blocks_db, &requests, &local_cache, &moved_ctx);
+                    let batch_len = traversals.len();
+                    for (i, traversal) in traversals.into_iter().enumerate() {
+                        // The worker is available once the last traversal of its batch is sent
+                        let _ = moved_traversal_tx.send((
+                            traversal,
+                            prioritary,
+                            thread_index,
+                            i + 1 == batch_len,
+                        ));
+                    }
                 }
             })
             .expect("unable to spawn thread");
@@ -157,6 +145,7 @@ pub fn parallelize_inscription_data_computations(
                 Ok((entry.clone(), key.2, vec![])),
                 true,
                 round_robin_thread_index,
+                false,
             ));
             round_robin_thread_index = (round_robin_thread_index + 1) % thread_pool_capacity;
         }
@@ -182,30 +171,25 @@ pub fn parallelize_inscription_data_computations(
 
     let mut rng = thread_rng();
     transactions_ids.shuffle(&mut rng);
-    let mut priority_queue = VecDeque::new();
+    let mut priority_queue = into_traversal_batches(
+        &block.block_identifier,
+        transactions_ids,
+        thread_pool_capacity,
+    );
     let mut warmup_queue = VecDeque::new();
 
-    for (transaction_id, input_index, inscription_pointer) in transactions_ids.into_iter() {
-        priority_queue.push_back((
-            transaction_id,
-            block.block_identifier.clone(),
-            input_index,
-            inscription_pointer,
-            true,
-        ));
-    }
-
-    // Feed each worker from the thread pool with 2 workitems each
-    for thread_index in 0..thread_pool_capacity {
-        let _ = tx_thread_pool[thread_index].send(priority_queue.pop_front());
-    }
+    // Feed each worker from the thread pool with a batch
     for thread_index in 0..thread_pool_capacity {
-        let _ = tx_thread_pool[thread_index].send(priority_queue.pop_front());
+        if let Some(batch) = priority_queue.pop_front() {
+            let _ = tx_thread_pool[thread_index].send(Some((batch, true)));
+        }
     }
 
     let mut next_block_iter = next_blocks.iter();
     let mut traversals_received = 0;
-    while let Ok((traversal_result, prioritary, thread_index)) = traversal_rx.recv() {
+    while let Ok((traversal_result, prioritary, thread_index, batch_completed)) =
+        traversal_rx.recv()
+    {
         if prioritary {
             traversals_received += 1;
         }
@@ -243,15 +227,19 @@ pub fn parallelize_inscription_data_computations(
             break;
         }
 
-        if let Some(w) = priority_queue.pop_front() {
-            let _ = tx_thread_pool[thread_index].send(Some(w));
+        if !batch_completed {
+            continue;
+        }
+
+        if let Some(batch) = priority_queue.pop_front() {
+            let _ = tx_thread_pool[thread_index].send(Some((batch, true)));
         } else {
-            if let Some(w) = warmup_queue.pop_front() {
-                let _ = tx_thread_pool[thread_index].send(Some(w));
+            if let Some(batch) = warmup_queue.pop_front() {
+                let _ = tx_thread_pool[thread_index].send(Some((batch, false)));
             } else {
                 if let Some(next_block) = next_block_iter.next() {
                     let (mut transactions_ids, _) =
-                        get_transactions_to_process(next_block, cache_l1, inscriptions_db_tx, ctx);
+                        get_transactions_to_process(next_block, cache_l1, inscriptions_store, ctx);
 
                     inner_ctx.try_log(|logger| {
                         info!(
@@ -263,18 +251,14 @@ pub fn parallelize_inscription_data_computations(
                     });
 
                     transactions_ids.shuffle(&mut rng);
-                    for (transaction_id, input_index, inscription_pointer) in
-                        transactions_ids.into_iter()
-                    {
-                        warmup_queue.push_back((
-                            transaction_id,
-                            next_block.block_identifier.clone(),
-                            input_index,
-                            inscription_pointer,
-                            false,
-                        ));
+                    warmup_queue = into_traversal_batches(
+                        &next_block.block_identifier,
+                        transactions_ids,
+                        thread_pool_capacity,
+                    );
+                    if let Some(batch) = warmup_queue.pop_front() {
+                        let _ = tx_thread_pool[thread_index].send(Some((batch, false)));
                     }
-                    let _ = tx_thread_pool[thread_index].send(warmup_queue.pop_front());
                 }
             }
         }
@@ -287,32 +271,31 @@ pub fn parallelize_inscription_data_computations(
     });
 
     // Collect eventual results for incoming blocks
-    for tx in tx_thread_pool.iter() {
-        // Empty the queue
-        if let Ok((traversal_result, _prioritary, thread_index)) = traversal_rx.try_recv() {
-            if let Ok((traversal, inscription_pointer, _)) = traversal_result {
-                inner_ctx.try_log(|logger| {
-                    info!(
-                        logger,
-                        "Completed ordinal number retrieval for Satpoint {}:{}:{} (block: #{}:{}, transfers: {}, pre-retrieval, thread: {thread_index})",
-                        traversal.transaction_identifier_inscription.hash,
-                        traversal.inscription_input_index,
-                        inscription_pointer,
-                        traversal.get_ordinal_coinbase_height(),
-                        traversal.get_ordinal_coinbase_offset(),
-                        traversal.transfers
-                        )
-                    });
-                cache_l1.insert(
-                    (
-                        traversal.transaction_identifier_inscription.clone(),
-                        traversal.inscription_input_index,
-                        inscription_pointer,
-                    ),
-                    traversal,
-                );
-            }
+    while let Ok((traversal_result, _prioritary, thread_index, _)) = traversal_rx.try_recv() {
+        if let Ok((traversal, inscription_pointer, _)) = traversal_result {
+            inner_ctx.try_log(|logger| {
+                info!(
+                    logger,
+                    "Completed ordinal number retrieval for Satpoint {}:{}:{} (block: #{}:{}, transfers: {}, pre-retrieval, thread: {thread_index})",
+                    traversal.transaction_identifier_inscription.hash,
+                    traversal.inscription_input_index,
+                    inscription_pointer,
+                    traversal.get_ordinal_coinbase_height(),
+                    traversal.get_ordinal_coinbase_offset(),
+                    traversal.transfers
+                    )
+                });
+            cache_l1.insert(
+                (
+                    traversal.transaction_identifier_inscription.clone(),
+                    traversal.inscription_input_index,
+                    inscription_pointer,
+                ),
+                traversal,
+            );
         }
+    }
+    for tx in tx_thread_pool.iter() {
         let _ = tx.send(None);
     }
 
@@ -336,6 +319,32 @@ pub fn parallelize_inscription_data_computations(
     Ok(has_transactions_to_process)
 }
 
+/// Split the traversals to perform for the inscriptions of a block in `batches_count` batches.
+fn into_traversal_batches(
+    block_identifier: &BlockIdentifier,
+    transactions_ids: Vec<(TransactionIdentifier, usize, u64)>,
+    batches_count: usize,
+) -> VecDeque<Vec<TraversalRequest>> {
+    let batch_size = ((transactions_ids.len() + batches_count - 1) / batches_count).max(1);
+    let requests = transactions_ids
+        .into_iter()
+        .map(
+            |(transaction_identifier, inscription_input_index, inscription_pointer)| {
+                TraversalRequest {
+                    block_identifier: block_identifier.clone(),
+                    transaction_identifier,
+                    inscription_input_index,
+                    inscription_pointer,
+                }
+            },
+        )
+        .collect::<Vec<_>>();
+    requests
+        .chunks(batch_size)
+        .map(|batch| batch.to_vec())
+        .collect()
+}
+
 /// Given a block, a cache L1, and a readonly DB connection, returns a tuple with the transactions that must be included
 /// for ordinals computation and the list of transactions where we have a cache hit.
 ///
@@ -353,7 +362,7 @@ pub fn parallelize_inscription_data_computations(
 fn get_transactions_to_process(
     block: &BitcoinBlockData,
     cache_l1: &mut BTreeMap<(TransactionIdentifier, usize, u64), TraversalResult>,
-    inscriptions_db_tx: &Transaction,
+    inscriptions_store: &dyn InscriptionStore,
     ctx: &Context,
 ) -> (
     Vec<(TransactionIdentifier, usize, u64)>,
@@ -363,7 +372,7 @@ fn get_transactions_to_process(
     let mut l1_cache_hits = vec![];
 
     let known_transactions =
-        find_all_inscriptions_in_block(&block.block_identifier.index, inscriptions_db_tx, ctx);
+        inscriptions_store.find_all_inscriptions_in_block(&block.block_identifier.index, ctx);
 
     for tx in block.transactions.iter().skip(1) {
         // Have a new inscription been revealed, if so, are looking at a re-inscription
@@ -410,17 +419,17 @@ pub struct SequenceCursor<'a> {
     pos_cursor: Option<i64>,
     neg_cursor: Option<i64>,
     jubilee_cursor: Option<i64>,
-    inscriptions_db_conn: &'a Connection,
+    inscriptions_store: &'a dyn InscriptionStore,
     current_block_height: u64,
 }
 
 impl<'a> SequenceCursor<'a> {
-    pub fn new(inscriptions_db_conn: &'a Connection) -> SequenceCursor<'a> {
+    pub fn new(inscriptions_store: &'a dyn InscriptionStore) -> SequenceCursor<'a> {
         SequenceCursor {
             jubilee_cursor: None,
             pos_cursor: None,
             neg_cursor: None,
-            inscriptions_db_conn,
+            inscriptions_store,
             current_block_height: 0,
         }
     }
@@ -436,7 +445,7 @@ impl<'a> SequenceCursor<'a> {
         &mut self,
         cursed: bool,
         block_height: u64,
-        network: &Network,
+        network_params: &NetworkParams,
         ctx: &Context,
     ) -> OrdinalInscriptionNumber {
         if block_height < self.current_block_height {
@@ -448,14 +457,7 @@ impl<'a> SequenceCursor<'a> {
             true => self.pick_next_neg_classic(ctx),
             false => self.pick_next_pos_classic(ctx),
         };
-        let jubilee_height = match network {
-            Network::Bitcoin => 824544,
-            Network::Regtest => 110,
-            Network::Signet => 175392,
-            Network::Testnet => 2544192,
-            _ => unreachable!(),
-        };
-        let jubilee = if block_height >= jubilee_height {
+        let jubilee = if block_height >= network_params.jubilee_height {
             self.pick_next_jubilee_number(ctx)
         } else {
             classic
@@ -466,11 +468,10 @@ impl<'a> SequenceCursor<'a> {
     fn pick_next_pos_classic(&mut self, ctx: &Context) -> i64 {
         match self.pos_cursor {
             None => {
-                match find_nth_classic_pos_number_at_block_height(
-                    &self.current_block_height,
-                    &self.inscriptions_db_conn,
-                    &ctx,
-                ) {
+                match self
+                    .inscriptions_store
+                    .find_nth_classic_pos_number_at_block_height(&self.current_block_height, &ctx)
+                {
                     Some(inscription_number) => {
                         self.pos_cursor = Some(inscription_number);
                         inscription_number + 1
@@ -485,11 +486,10 @@ impl<'a> SequenceCursor<'a> {
     fn pick_next_jubilee_number(&mut self, ctx: &Context) -> i64 {
         match self.jubilee_cursor {
             None => {
-                match find_nth_jubilee_number_at_block_height(
-                    &self.current_block_height,
-                    &self.inscriptions_db_conn,
-                    &ctx,
-                ) {
+                match self
+                    .inscriptions_store
+                    .find_nth_jubilee_number_at_block_height(&self.current_block_height, &ctx)
+                {
                     Some(inscription_number) => {
                         self.jubilee_cursor = Some(inscription_number);
                         inscription_number + 1
@@ -504,11 +504,10 @@ impl<'a> SequenceCursor<'a> {
     fn pick_next_neg_classic(&mut self, ctx: &Context) -> i64 {
         match self.neg_cursor {
             None => {
-                match find_nth_classic_neg_number_at_block_height(
-                    &self.current_block_height,
-                    &self.inscriptions_db_conn,
-                    &ctx,
-                ) {
+                match self
+                    .inscriptions_store
+                    .find_nth_classic_neg_number_at_block_height(&self.current_block_height, &ctx)
+                {
                     Some(inscription_number) => {
                         self.neg_cursor = Some(inscription_number);
                         inscription_number - 1
@@ -536,25 +535,24 @@ impl<'a> SequenceCursor<'a> {
 /// Given a `BitcoinBlockData` that have been augmented with the functions `parse_inscriptions_in_raw_tx`, `parse_inscriptions_in_standardized_tx`
 /// or `parse_inscriptions_and_standardize_block`, mutate the ordinals drafted informations with actual, consensus data.
 ///
-/// This function will write the updated informations to the Sqlite transaction (`inscriptions` and `locations` tables),
+/// This function will write the updated informations to the inscription store (`inscriptions` and `locations` tables),
 /// but is leaving the responsibility to the caller to commit the transaction.
 ///
 pub fn augment_block_with_ordinals_inscriptions_data_and_write_to_db_tx(
     block: &mut BitcoinBlockData,
     sequence_cursor: &mut SequenceCursor,
     inscriptions_data: &mut BTreeMap<(TransactionIdentifier, usize, u64), TraversalResult>,
-    inscriptions_db_tx: &Transaction,
+    inscriptions_store: &dyn InscriptionStore,
+    network_params: &NetworkParams,
     ctx: &Context,
 ) -> bool {
     // Handle re-inscriptions
     let mut reinscriptions_data = HashMap::new();
     for (_, inscription_data) in inscriptions_data.iter() {
         if inscription_data.ordinal_number != 0 {
-            if let Some(inscription_id) = find_blessed_inscription_with_ordinal_number(
-                &inscription_data.ordinal_number,
-                inscriptions_db_tx,
-                ctx,
-            ) {
+            if let Some(inscription_id) = inscriptions_store
+                .find_blessed_inscription_with_ordinal_number(&inscription_data.ordinal_number, ctx)
+            {
                 reinscriptions_data.insert(inscription_data.ordinal_number, inscription_id);
             }
         }
@@ -565,12 +563,13 @@ pub fn augment_block_with_ordinals_inscriptions_data_and_write_to_db_tx(
         sequence_cursor,
         inscriptions_data,
         &mut reinscriptions_data,
+        network_params,
         &ctx,
     );
 
     // Store inscriptions
-    update_inscriptions_with_block(block, inscriptions_db_tx, ctx);
-    update_sequence_metadata_with_block(block, inscriptions_db_tx, ctx);
+    inscriptions_store.update_inscriptions_with_block(block, ctx);
+    inscriptions_store.update_sequence_metadata_with_block(block, ctx);
     any_events
 }
 
@@ -587,18 +586,13 @@ pub fn augment_block_with_ordinals_inscriptions_data(
     sequence_cursor: &mut SequenceCursor,
     inscriptions_data: &mut BTreeMap<(TransactionIdentifier, usize, u64), TraversalResult>,
     reinscriptions_data: &mut HashMap<u64, String>,
+    network_params: &NetworkParams,
     ctx: &Context,
 ) -> bool {
     // Handle sat oveflows
     let mut sats_overflows = VecDeque::new();
     let mut any_event = false;
 
-    let network = match block.metadata.network {
-        BitcoinNetwork::Mainnet => Network::Bitcoin,
-        BitcoinNetwork::Regtest => Network::Regtest,
-        BitcoinNetwork::Testnet => Network::Testnet,
-        BitcoinNetwork::Signet => Network::Signet,
-    };
     let coinbase_subsidy = Height(block.block_identifier.index).subsidy();
     let coinbase_txid = &block.transactions[0].transaction_identifier.clone();
     let mut cumulated_fees = 0u64;
@@ -609,7 +603,7 @@ pub fn augment_block_with_ordinals_inscriptions_data(
             tx_index,
             &block.block_identifier,
             sequence_cursor,
-            &network,
+            network_params,
             inscriptions_data,
             coinbase_txid,
             coinbase_subsidy,
@@ -629,7 +623,7 @@ pub fn augment_block_with_ordinals_inscriptions_data(
         };
         let is_curse = inscription_data.curse_type.is_some();
         let inscription_number =
-            sequence_cursor.pick_next(is_curse, block.block_identifier.index, &network, &ctx);
+            sequence_cursor.pick_next(is_curse, block.block_identifier.index, network_params, &ctx);
         inscription_data.inscription_number = inscription_number;
 
         if is_curse {
@@ -664,7 +658,7 @@ fn augment_transaction_with_ordinals_inscriptions_data(
     tx_index: usize,
     block_identifier: &BlockIdentifier,
     sequence_cursor: &mut SequenceCursor,
-    network: &Network,
+    network_params: &NetworkParams,
     inscriptions_data: &mut BTreeMap<(TransactionIdentifier, usize, u64), TraversalResult>,
     coinbase_txid: &TransactionIdentifier,
     coinbase_subsidy: u64,
@@ -707,7 +701,7 @@ fn augment_transaction_with_ordinals_inscriptions_data(
 
         // Do we need to curse the inscription?
         let mut inscription_number =
-            sequence_cursor.pick_next(is_cursed, block_identifier.index, network, ctx);
+            sequence_cursor.pick_next(is_cursed, block_identifier.index, network_params, ctx);
         let mut curse_type_override = None;
         if !is_cursed {
             // Is this inscription re-inscribing an existing blessed inscription?
@@ -725,8 +719,12 @@ fn augment_transaction_with_ordinals_inscriptions_data(
                 });
 
                 is_cursed = true;
-                inscription_number =
-                    sequence_cursor.pick_next(is_cursed, block_identifier.index, network, ctx);
+                inscription_number = sequence_cursor.pick_next(
+                    is_cursed,
+                    block_identifier.index,
+                    network_params,
+                    ctx,
+                );
                 curse_type_override = Some(OrdinalInscriptionCurseType::Reinscription)
             }
         };
@@ -748,7 +746,7 @@ fn augment_transaction_with_ordinals_inscriptions_data(
             &&*tx,
             traversal.inscription_input_index,
             inscription.inscription_pointer,
-            network,
+            network_params,
             coinbase_txid,
             coinbase_subsidy,
             cumulated_fees,
@@ -763,7 +761,7 @@ fn augment_transaction_with_ordinals_inscriptions_data(
             OrdinalInscriptionTransferDestination::SpentInFees => {
                 // Inscriptions are assigned inscription numbers starting at zero, first by the
                 // order reveal transactions appear in blocks, and the order that reveal envelopes
-                // appear in those transactions.                
+                // appear in those transactions.
                 // Due to a historical bug in `ord` which cannot be fixed without changing a great
                 // many inscription numbers, inscriptions which are revealed and then immediately
                 // spent to fees are numbered as if they appear last in the block in which they
@@ -817,7 +815,7 @@ fn consolidate_transaction_with_pre_computed_inscription_data(
     coinbase_txid: &TransactionIdentifier,
     coinbase_subsidy: u64,
     cumulated_fees: &mut u64,
-    network: &Network,
+    network_params: &NetworkParams,
     inscriptions_data: &mut BTreeMap<String, TraversalResult>,
     ctx: &Context,
 ) {
@@ -852,7 +850,7 @@ fn consolidate_transaction_with_pre_computed_inscription_data(
             tx,
             traversal.inscription_input_index,
             inscription.inscription_pointer,
-            network,
+            network_params,
             coinbase_txid,
             coinbase_subsidy,
             cumulated_fees,
@@ -883,24 +881,18 @@ fn consolidate_transaction_with_pre_computed_inscription_data(
 /// Some informations are being lost (curse_type).
 pub fn consolidate_block_with_pre_computed_ordinals_data(
     block: &mut BitcoinBlockData,
-    inscriptions_db_tx: &Transaction,
+    inscriptions_store: &dyn InscriptionStore,
     include_transfers: bool,
+    network_params: &NetworkParams,
     ctx: &Context,
 ) {
-    let network = match block.metadata.network {
-        BitcoinNetwork::Mainnet => Network::Bitcoin,
-        BitcoinNetwork::Regtest => Network::Regtest,
-        BitcoinNetwork::Testnet => Network::Testnet,
-        BitcoinNetwork::Signet => Network::Signet,
-    };
-
     let coinbase_subsidy = Height(block.block_identifier.index).subsidy();
     let coinbase_txid = &block.transactions[0].transaction_identifier.clone();
     let mut cumulated_fees = 0;
     let expected_inscriptions_count = get_inscriptions_revealed_in_block(&block).len();
     let mut inscriptions_data = loop {
         let results =
-            find_all_inscriptions_in_block(&block.block_identifier.index, inscriptions_db_tx, ctx);
+            inscriptions_store.find_all_inscriptions_in_block(&block.block_identifier.index, ctx);
         // TODO: investigate, sporadically the set returned is empty, and requires a retry.
         if results.len() != expected_inscriptions_count {
             ctx.try_log(|logger| {
@@ -922,7 +914,7 @@ pub fn consolidate_block_with_pre_computed_ordinals_data(
             coinbase_txid,
             coinbase_subsidy,
             &mut cumulated_fees,
-            &network,
+            network_params,
             &mut inscriptions_data,
             ctx,
         );
@@ -932,11 +924,11 @@ pub fn consolidate_block_with_pre_computed_ordinals_data(
             let _ = augment_transaction_with_ordinals_transfers_data(
                 tx,
                 tx_index,
-                &network,
+                network_params,
                 &coinbase_txid,
                 coinbase_subsidy,
                 &mut cumulated_fees,
-                inscriptions_db_tx,
+                inscriptions_store,
                 ctx,
             );
         }
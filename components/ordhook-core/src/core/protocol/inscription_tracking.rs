@@ -1,37 +1,28 @@
 use chainhook_sdk::{
-    bitcoincore_rpc_json::bitcoin::{Address, Network, ScriptBuf},
+    bitcoincore_rpc_json::bitcoin::ScriptBuf,
     types::{
-        BitcoinBlockData, BitcoinNetwork, BitcoinTransactionData, OrdinalInscriptionTransferData,
+        BitcoinBlockData, BitcoinTransactionData, OrdinalInscriptionTransferData,
         OrdinalInscriptionTransferDestination, OrdinalOperation, TransactionIdentifier,
     },
     utils::Context,
 };
 
 use crate::{
+    config::NetworkParams,
     core::{compute_next_satpoint_data, SatPosition},
-    db::{
-        find_inscriptions_at_wached_outpoint, format_outpoint_to_watch,
-        insert_transfer_in_locations_tx,
-    },
+    db::{format_outpoint_to_watch, store::InscriptionStore},
     ord::height::Height,
 };
-use rusqlite::Transaction;
 
 pub fn augment_block_with_ordinals_transfer_data(
     block: &mut BitcoinBlockData,
-    inscriptions_db_tx: &Transaction,
+    inscriptions_store: &dyn InscriptionStore,
     update_db_tx: bool,
+    network_params: &NetworkParams,
     ctx: &Context,
 ) -> bool {
     let mut any_event = false;
 
-    let network = match block.metadata.network {
-        BitcoinNetwork::Mainnet => Network::Bitcoin,
-        BitcoinNetwork::Regtest => Network::Regtest,
-        BitcoinNetwork::Testnet => Network::Testnet,
-        BitcoinNetwork::Signet => Network::Signet,
-    };
-
     let coinbase_subsidy = Height(block.block_identifier.index).subsidy();
     let coinbase_txid = &block.transactions[0].transaction_identifier.clone();
     let mut cumulated_fees = 0;
@@ -39,11 +30,11 @@ pub fn augment_block_with_ordinals_transfer_data(
         let transfers = augment_transaction_with_ordinals_transfers_data(
             tx,
             tx_index,
-            &network,
+            network_params,
             &coinbase_txid,
             coinbase_subsidy,
             &mut cumulated_fees,
-            inscriptions_db_tx,
+            inscriptions_store,
             ctx,
         );
         any_event |= !transfers.is_empty();
@@ -51,10 +42,9 @@ pub fn augment_block_with_ordinals_transfer_data(
         if update_db_tx {
             // Store transfers between each iteration
             for transfer_data in transfers.into_iter() {
-                insert_transfer_in_locations_tx(
+                inscriptions_store.insert_transfer_in_locations(
                     &transfer_data,
                     &block.block_identifier,
-                    &inscriptions_db_tx,
                     &ctx,
                 );
             }
@@ -68,7 +58,7 @@ pub fn compute_satpoint_post_transfer(
     tx: &BitcoinTransactionData,
     input_index: usize,
     inscription_pointer: u64,
-    network: &Network,
+    network_params: &NetworkParams,
     coinbase_txid: &TransactionIdentifier,
     coinbase_subsidy: u64,
     cumulated_fees: &mut u64,
@@ -90,16 +80,13 @@ pub fn compute_satpoint_post_transfer(
                 let outpoint = format_outpoint_to_watch(&tx.transaction_identifier, output_index);
                 let script_pub_key_hex = tx.metadata.outputs[output_index].get_script_pubkey_hex();
                 let updated_address = match ScriptBuf::from_hex(&script_pub_key_hex) {
-                    Ok(script) => match Address::from_script(&script, network.clone()) {
-                        Ok(address) => {
-                            OrdinalInscriptionTransferDestination::Transferred(address.to_string())
-                        }
+                    Ok(script) => match network_params.address_from_script(&script) {
+                        Ok(address) => OrdinalInscriptionTransferDestination::Transferred(address),
                         Err(e) => {
                             ctx.try_log(|logger| {
                                 info!(
                                     logger,
-                                    "unable to retrieve address from {script_pub_key_hex}: {}",
-                                    e.to_string()
+                                    "unable to retrieve address from {script_pub_key_hex}: {e}"
                                 )
                             });
                             OrdinalInscriptionTransferDestination::Burnt(script.to_string())
@@ -148,11 +135,11 @@ pub fn compute_satpoint_post_transfer(
 pub fn augment_transaction_with_ordinals_transfers_data(
     tx: &mut BitcoinTransactionData,
     tx_index: usize,
-    network: &Network,
+    network_params: &NetworkParams,
     coinbase_txid: &TransactionIdentifier,
     coinbase_subsidy: u64,
     cumulated_fees: &mut u64,
-    inscriptions_db_tx: &Transaction,
+    inscriptions_store: &dyn InscriptionStore,
     ctx: &Context,
 ) -> Vec<OrdinalInscriptionTransferData> {
     let mut transfers = vec![];
@@ -164,7 +151,7 @@ pub fn augment_transaction_with_ordinals_transfers_data(
         );
 
         let entries =
-            find_inscriptions_at_wached_outpoint(&outpoint_pre_transfer, &inscriptions_db_tx, ctx);
+            inscriptions_store.find_inscriptions_at_watched_outpoint(&outpoint_pre_transfer, ctx);
         // For each satpoint inscribed retrieved, we need to compute the next
         // outpoint to watch
         for watched_satpoint in entries.into_iter() {
@@ -176,7 +163,7 @@ pub fn augment_transaction_with_ordinals_transfers_data(
                     &&*tx,
                     input_index,
                     0,
-                    network,
+                    network_params,
                     coinbase_txid,
                     coinbase_subsidy,
                     cumulated_fees,
@@ -1,175 +1,268 @@
 use chainhook_sdk::types::{BlockIdentifier, OrdinalInscriptionNumber, TransactionIdentifier};
 use chainhook_sdk::utils::Context;
-use dashmap::DashMap;
-use fxhash::FxHasher;
-use std::hash::BuildHasherDefault;
+use rocksdb::DB;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::db::{
     find_pinned_block_bytes_at_block_height, open_ordhook_db_conn_rocks_db_loop, BlockBytesCursor,
+    TransactionBytesCursor,
 };
 
-use crate::db::{TransactionBytesCursor, TraversalResult};
+use crate::config::ResourcesConfig;
+use crate::db::{traversals_cache::TraversalsCache, TraversalResult};
 use crate::ord::height::Height;
 
-pub fn compute_satoshi_number(
-    blocks_db_dir: &PathBuf,
-    block_identifier: &BlockIdentifier,
-    transaction_identifier: &TransactionIdentifier,
-    inscription_input_index: usize,
-    inscription_pointer: u64,
-    traversals_cache: &Arc<
-        DashMap<(u32, [u8; 8]), TransactionBytesCursor, BuildHasherDefault<FxHasher>>,
-    >,
-    ulimit: usize,
-    memory_available: usize,
-    _back_tracking: bool,
-    ctx: &Context,
-) -> Result<(TraversalResult, u64, Vec<(u32, [u8; 8], usize)>), String> {
-    let mut ordinal_offset = inscription_pointer;
-    let ordinal_block_number = block_identifier.index as u32;
-    let txid = transaction_identifier.get_8_hash_bytes();
-    let mut back_track = vec![];
-    let blocks_db =
-        open_ordhook_db_conn_rocks_db_loop(false, &blocks_db_dir, ulimit, memory_available, &ctx);
-
-    let (mut tx_cursor, mut ordinal_block_number) = match traversals_cache
-        .get(&(block_identifier.index as u32, txid.clone()))
-    {
-        Some(entry) => {
-            let tx = entry.value();
-            (
-                (
-                    tx.inputs[inscription_input_index].txin.clone(),
-                    tx.inputs[inscription_input_index].vout.into(),
-                ),
-                tx.inputs[inscription_input_index].block_height,
-            )
-        }
-        None => loop {
-            match find_pinned_block_bytes_at_block_height(ordinal_block_number, 3, &blocks_db, &ctx)
-            {
-                None => {
-                    return Err(format!("block #{ordinal_block_number} not in database"));
-                }
-                Some(block_bytes) => {
-                    let cursor = BlockBytesCursor::new(&block_bytes.as_ref());
-                    match cursor.find_and_serialize_transaction_with_txid(&txid) {
-                        Some(tx) => {
-                            break (
-                                (
-                                    tx.inputs[inscription_input_index].txin.clone(),
-                                    tx.inputs[inscription_input_index].vout.into(),
-                                ),
-                                tx.inputs[inscription_input_index].block_height,
-                            );
-                        }
-                        None => return Err(format!("txid not in block #{ordinal_block_number}")),
-                    }
-                }
-            }
-        },
-    };
+pub type TraversalOutput = (TraversalResult, u64, Vec<(u32, [u8; 8], usize)>);
+
+/// Satpoint of an inscription, to be traced back to the coinbase that mined its sat.
+#[derive(Clone, Debug)]
+pub struct TraversalRequest {
+    pub block_identifier: BlockIdentifier,
+    pub transaction_identifier: TransactionIdentifier,
+    pub inscription_input_index: usize,
+    pub inscription_pointer: u64,
+}
+
+/// State of a traversal between two hops.
+struct Traversal<'a> {
+    request: &'a TraversalRequest,
+    /// The input spent by the inscription transaction has not been located yet.
+    locating: bool,
+    ordinal_block_number: u32,
+    tx_cursor: ([u8; 8], usize),
+    /// Value of the output tracked by `tx_cursor`, used to tell apart transactions sharing the
+    /// same txid prefix.
+    tx_cursor_value: u64,
+    ordinal_offset: u64,
+    hops: u32,
+    back_track: Vec<(u32, [u8; 8], usize)>,
+}
 
-    let mut hops: u32 = 0;
+enum Hop {
+    /// The block at this height is required to move forward.
+    Fetch(u32),
+    Done(Result<TraversalOutput, String>),
+}
+
+impl<'a> Traversal<'a> {
+    fn new(request: &'a TraversalRequest) -> Traversal<'a> {
+        Traversal {
+            request,
+            locating: true,
+            ordinal_block_number: request.block_identifier.index as u32,
+            tx_cursor: (request.transaction_identifier.get_8_hash_bytes(), 0),
+            tx_cursor_value: 0,
+            ordinal_offset: request.inscription_pointer,
+            hops: 0,
+            back_track: vec![],
+        }
+    }
 
-    loop {
-        hops += 1;
-        if hops as u64 > block_identifier.index {
+    fn locate(&mut self, tx: &TransactionBytesCursor) -> Result<(), String> {
+        let input_index = self.request.inscription_input_index;
+        let Some(input) = tx.inputs.get(input_index) else {
             return Err(format!(
-                "Unable to process transaction {} detected after {hops} iterations. Manual investigation required",
-                transaction_identifier.hash
+                "input #{input_index} not in transaction {}",
+                self.request.transaction_identifier.hash
             ));
+        };
+        self.tx_cursor = (input.txin, input.vout.into());
+        self.tx_cursor_value = input.txin_value;
+        self.ordinal_block_number = input.block_height;
+        self.locating = false;
+        Ok(())
+    }
+
+    /// Move to the input spending the sat tracked in `tx`. Returns false when the sat was not
+    /// found in the inputs of `tx`.
+    fn spend(&mut self, tx: &TransactionBytesCursor, cached: bool) -> bool {
+        let mut sats_out = 0;
+        for (index, output_value) in tx.outputs.iter().enumerate() {
+            if index == self.tx_cursor.1 {
+                break;
+            }
+            sats_out += output_value;
         }
+        sats_out += self.ordinal_offset;
 
-        if let Some(cached_tx) = traversals_cache.get(&(ordinal_block_number, tx_cursor.0)) {
-            let tx = cached_tx.value();
+        let mut sats_in = 0;
+        for input in tx.inputs.iter() {
+            sats_in += input.txin_value;
 
-            let mut next_found_in_cache = false;
-            let mut sats_out = 0;
-            for (index, output_value) in tx.outputs.iter().enumerate() {
-                if index == tx_cursor.1 {
-                    break;
+            if sats_out < sats_in {
+                if !cached {
+                    self.back_track.push((
+                        self.ordinal_block_number,
+                        self.tx_cursor.0,
+                        self.tx_cursor.1,
+                    ));
                 }
-                sats_out += output_value;
+                self.ordinal_offset = sats_out - (sats_in - input.txin_value);
+                self.ordinal_block_number = input.block_height;
+                self.tx_cursor = (input.txin, input.vout as usize);
+                self.tx_cursor_value = input.txin_value;
+                return true;
             }
-            sats_out += ordinal_offset;
+        }
+        false
+    }
+
+    fn originating_from_non_spending_transaction(
+        &mut self,
+        ctx: &Context,
+    ) -> Result<TraversalOutput, String> {
+        ctx.try_log(|logger| {
+            error!(
+                logger,
+                "Transaction {} is originating from a non spending transaction",
+                self.request.transaction_identifier.hash
+            )
+        });
+        Ok((
+            TraversalResult {
+                inscription_number: OrdinalInscriptionNumber::zero(),
+                ordinal_number: 0,
+                transfers: 0,
+                inscription_input_index: self.request.inscription_input_index,
+                transaction_identifier_inscription: self.request.transaction_identifier.clone(),
+            },
+            self.request.inscription_pointer,
+            std::mem::take(&mut self.back_track),
+        ))
+    }
 
-            let mut sats_in = 0;
-            for input in tx.inputs.iter() {
-                sats_in += input.txin_value;
+    fn completed(&mut self) -> Result<TraversalOutput, String> {
+        let height = Height(self.ordinal_block_number.into());
+        let ordinal_number = height.starting_sat().0 + self.ordinal_offset;
+        Ok((
+            TraversalResult {
+                inscription_number: OrdinalInscriptionNumber::zero(),
+                ordinal_number,
+                transfers: self.hops,
+                inscription_input_index: self.request.inscription_input_index,
+                transaction_identifier_inscription: self.request.transaction_identifier.clone(),
+            },
+            self.request.inscription_pointer,
+            std::mem::take(&mut self.back_track),
+        ))
+    }
 
-                if sats_out < sats_in {
-                    ordinal_offset = sats_out - (sats_in - input.txin_value);
-                    ordinal_block_number = input.block_height;
-                    tx_cursor = (input.txin.clone(), input.vout as usize);
-                    next_found_in_cache = true;
-                    break;
+    /// Follow the hops available in `traversals_cache`, until a block has to be fetched.
+    fn advance_with_cache(&mut self, traversals_cache: &TraversalsCache, ctx: &Context) -> Hop {
+        if self.locating {
+            let key = (self.ordinal_block_number, self.tx_cursor.0);
+            match traversals_cache.get(&key) {
+                Some(tx) => {
+                    if let Err(e) = self.locate(&tx) {
+                        return Hop::Done(Err(e));
+                    }
                 }
+                None => return Hop::Fetch(self.ordinal_block_number),
             }
-
-            if next_found_in_cache {
+        }
+        loop {
+            self.hops += 1;
+            if self.hops as u64 > self.request.block_identifier.index {
+                return Hop::Done(Err(format!(
+                    "Unable to process transaction {} detected after {} iterations. Manual investigation required",
+                    self.request.transaction_identifier.hash, self.hops
+                )));
+            }
+            let Some(tx) = traversals_cache.get(&(self.ordinal_block_number, self.tx_cursor.0))
+            else {
+                return Hop::Fetch(self.ordinal_block_number);
+            };
+            if self.spend(&tx, true) {
                 continue;
             }
-
-            if sats_in == 0 {
-                ctx.try_log(|logger| {
-                    error!(
-                        logger,
-                        "Transaction {} is originating from a non spending transaction",
-                        transaction_identifier.hash
-                    )
-                });
-                return Ok((
-                    TraversalResult {
-                        inscription_number: OrdinalInscriptionNumber::zero(),
-                        ordinal_number: 0,
-                        transfers: 0,
-                        inscription_input_index,
-                        transaction_identifier_inscription: transaction_identifier.clone(),
-                    },
-                    inscription_pointer,
-                    back_track,
-                ));
+            if tx.inputs.iter().all(|input| input.txin_value == 0) {
+                return Hop::Done(self.originating_from_non_spending_transaction(ctx));
             }
+            return Hop::Fetch(self.ordinal_block_number);
         }
+    }
 
-        let pinned_block_bytes = {
-            loop {
-                match find_pinned_block_bytes_at_block_height(
-                    ordinal_block_number,
-                    3,
-                    &blocks_db,
-                    &ctx,
-                ) {
-                    Some(block) => break block,
-                    None => {
-                        return Err(format!("block #{ordinal_block_number} not in database (traversing {} / {} in progress)", transaction_identifier.hash, block_identifier.index));
-                    }
-                }
-            }
-        };
-        let block_cursor = BlockBytesCursor::new(pinned_block_bytes.as_ref());
-        let txid = tx_cursor.0;
+    /// Transaction of a block with full txids matching the tracked txid prefix. Transactions
+    /// sharing the prefix are told apart by the value of the tracked output.
+    fn resolve_ancestor(
+        &self,
+        block_cursor: &BlockBytesCursor,
+    ) -> Result<Option<(u16, TransactionBytesCursor)>, String> {
+        let (txid, vout) = self.tx_cursor;
+        let mut candidates = block_cursor.find_and_serialize_transactions_with_txid(&txid);
+        if candidates.len() > 1 {
+            candidates.retain(|(_, tx)| tx.outputs.get(vout) == Some(&self.tx_cursor_value));
+        }
+        match candidates.len() {
+            0 | 1 => Ok(candidates.pop()),
+            _ => Err(format!(
+                "unable to disambiguate tx ancestor {} in block #{} (satpoint {}:{})",
+                hex::encode(txid),
+                self.ordinal_block_number,
+                self.request.transaction_identifier.get_hash_bytes_str(),
+                self.request.inscription_input_index,
+            )),
+        }
+    }
+
+    /// Perform the next hop with the block fetched after `advance_with_cache`. Transactions
+    /// deserialized during a round are kept in `round_transactions`, so that ancestors shared
+    /// by several traversals are only deserialized once. Returns the result of the traversal,
+    /// if completed.
+    fn advance_with_block(
+        &mut self,
+        block_cursor: &BlockBytesCursor,
+        round_transactions: &mut HashMap<(u32, [u8; 8]), TransactionBytesCursor>,
+        traversals_cache: &TraversalsCache,
+        ctx: &Context,
+    ) -> Option<Result<TraversalOutput, String>> {
+        if self.locating {
+            let ordinal_block_number = self.ordinal_block_number;
+            // Blocks with colliding txid prefixes are identifying their transactions with full txids
+            let searched_txid = match block_cursor.has_full_txids() {
+                true => self.request.transaction_identifier.get_hash_bytes(),
+                false => self.tx_cursor.0.to_vec(),
+            };
+            let res = match block_cursor.find_and_serialize_transaction_with_txid(&searched_txid) {
+                Some(tx) => self.locate(&tx),
+                None => Err(format!("txid not in block #{ordinal_block_number}")),
+            };
+            return res.err().map(Err);
+        }
+
+        let txid = self.tx_cursor.0;
         let mut block_cursor_tx_iter = block_cursor.iter_tx();
         let coinbase = block_cursor_tx_iter.next().expect("empty block");
 
+        let ancestor = match block_cursor.has_full_txids() {
+            true => match self.resolve_ancestor(block_cursor) {
+                Ok(ancestor) => ancestor,
+                Err(e) => return Some(Err(e)),
+            },
+            false => None,
+        };
+        let coinbase_reached = match block_cursor.has_full_txids() {
+            true => matches!(ancestor, Some((0, _))),
+            false => coinbase.txid.eq(&txid),
+        };
+
         // evaluate exit condition: did we reach the **final** coinbase transaction
-        if coinbase.txid.eq(&txid) {
+        if coinbase_reached {
             let mut intra_coinbase_output_offset = 0;
             for (index, output_value) in coinbase.outputs.iter().enumerate() {
-                if index == tx_cursor.1 {
+                if index == self.tx_cursor.1 {
                     break;
                 }
                 intra_coinbase_output_offset += output_value;
             }
-            ordinal_offset += intra_coinbase_output_offset;
+            self.ordinal_offset += intra_coinbase_output_offset;
 
-            let subsidy = Height(ordinal_block_number.into()).subsidy();
-            if ordinal_offset < subsidy {
+            let subsidy = Height(self.ordinal_block_number.into()).subsidy();
+            if self.ordinal_offset < subsidy {
                 // Great!
-                break;
+                return Some(self.completed());
             }
 
             // loop over the transaction fees to detect the right range
@@ -187,10 +280,10 @@ pub fn compute_satoshi_number(
                 }
 
                 let fee = total_in - total_out;
-                if accumulated_fees + fee > ordinal_offset {
+                if accumulated_fees + fee > self.ordinal_offset {
                     // We are looking at the right transaction
                     // Retraverse the inputs to select the index to be picked
-                    let offset_within_fee = ordinal_offset - accumulated_fees;
+                    let offset_within_fee = self.ordinal_offset - accumulated_fees;
                     total_out += offset_within_fee;
                     let mut sats_in = 0;
 
@@ -198,9 +291,10 @@ pub fn compute_satoshi_number(
                         sats_in += input.txin_value;
 
                         if sats_in > total_out {
-                            ordinal_offset = total_out - (sats_in - input.txin_value);
-                            ordinal_block_number = input.block_height;
-                            tx_cursor = (input.txin.clone(), input.vout as usize);
+                            self.ordinal_offset = total_out - (sats_in - input.txin_value);
+                            self.ordinal_block_number = input.block_height;
+                            self.tx_cursor = (input.txin, input.vout as usize);
+                            self.tx_cursor_value = input.txin_value;
                             break;
                         }
                     }
@@ -209,83 +303,160 @@ pub fn compute_satoshi_number(
                     accumulated_fees += fee;
                 }
             }
-        } else {
-            // isolate the target transaction
-            let tx_bytes_cursor = match block_cursor.find_and_serialize_transaction_with_txid(&txid)
-            {
-                Some(entry) => entry,
-                None => {
-                    ctx.try_log(|logger| {
-                        error!(
-                            logger,
-                            "fatal: unable to retrieve tx ancestor {} in block {ordinal_block_number} (satpoint {}:{inscription_input_index})",
-                            hex::encode(txid),
-                            transaction_identifier.get_hash_bytes_str(),
-                        )
-                    });
-                    std::process::exit(1);
-                }
-            };
+            return None;
+        }
 
-            let mut sats_out = 0;
-            for (index, output_value) in tx_bytes_cursor.outputs.iter().enumerate() {
-                if index == tx_cursor.1 {
-                    break;
-                }
-                sats_out += output_value;
-            }
-            sats_out += ordinal_offset;
-
-            let mut sats_in = 0;
-            for input in tx_bytes_cursor.inputs.iter() {
-                sats_in += input.txin_value;
-
-                if sats_out < sats_in {
-                    back_track.push((ordinal_block_number, tx_cursor.0.clone(), tx_cursor.1));
-                    traversals_cache
-                        .insert((ordinal_block_number, tx_cursor.0), tx_bytes_cursor.clone());
-                    ordinal_offset = sats_out - (sats_in - input.txin_value);
-                    ordinal_block_number = input.block_height;
-                    tx_cursor = (input.txin.clone(), input.vout as usize);
-                    break;
+        // isolate the target transaction
+        let key = (self.ordinal_block_number, txid);
+        let cacheable = !block_cursor.has_full_txids();
+        let resolved = match ancestor {
+            Some((_, tx)) => Some(tx),
+            None if !cacheable => None,
+            None => match round_transactions.get(&key) {
+                Some(tx) => Some(tx.clone()),
+                None => {
+                    let tx = block_cursor.find_and_serialize_transaction_with_txid(&txid);
+                    if let Some(ref tx) = tx {
+                        round_transactions.insert(key, tx.clone());
+                    }
+                    tx
                 }
-            }
-
-            if sats_in == 0 {
+            },
+        };
+        let tx_bytes_cursor = match resolved {
+            Some(tx) => tx,
+            None => {
                 ctx.try_log(|logger| {
                     error!(
                         logger,
-                        "Transaction {} is originating from a non spending transaction",
-                        transaction_identifier.hash
+                        "fatal: unable to retrieve tx ancestor {} in block {} (satpoint {}:{})",
+                        hex::encode(txid),
+                        self.ordinal_block_number,
+                        self.request.transaction_identifier.get_hash_bytes_str(),
+                        self.request.inscription_input_index,
                     )
                 });
-                return Ok((
-                    TraversalResult {
-                        inscription_number: OrdinalInscriptionNumber::zero(),
-                        ordinal_number: 0,
-                        transfers: 0,
-                        inscription_input_index,
-                        transaction_identifier_inscription: transaction_identifier.clone(),
-                    },
-                    inscription_pointer,
-                    back_track,
-                ));
+                std::process::exit(1);
+            }
+        };
+
+        if self.spend(&tx_bytes_cursor, false) {
+            // (height, txid prefix) is not identifying a transaction in blocks with full txids
+            if cacheable {
+                traversals_cache.insert(key, tx_bytes_cursor);
+            }
+            return None;
+        }
+        if tx_bytes_cursor
+            .inputs
+            .iter()
+            .all(|input| input.txin_value == 0)
+        {
+            return Some(self.originating_from_non_spending_transaction(ctx));
+        }
+        None
+    }
+}
+
+/// Trace the sats of `requests` back to their coinbase. Traversals are advanced in lockstep:
+/// at each round, the blocks required by all the pending traversals are fetched with a single
+/// `multi_get`, each block being read once per round whatever the number of traversals
+/// crossing it. Results are returned in the order of `requests`.
+pub fn compute_satoshi_numbers(
+    blocks_db: &DB,
+    requests: &[TraversalRequest],
+    traversals_cache: &TraversalsCache,
+    ctx: &Context,
+) -> Vec<Result<TraversalOutput, String>> {
+    let mut results = requests.iter().map(|_| None).collect::<Vec<_>>();
+    let mut pending = requests
+        .iter()
+        .enumerate()
+        .map(|(index, request)| (index, Traversal::new(request)))
+        .collect::<Vec<_>>();
+
+    while !pending.is_empty() {
+        let mut blocked = vec![];
+        for (index, mut traversal) in pending.into_iter() {
+            match traversal.advance_with_cache(traversals_cache, ctx) {
+                Hop::Fetch(block_height) => blocked.push((index, traversal, block_height)),
+                Hop::Done(result) => results[index] = Some(result),
+            }
+        }
+
+        let mut block_heights = blocked
+            .iter()
+            .map(|(_, _, block_height)| *block_height)
+            .collect::<Vec<_>>();
+        block_heights.sort_unstable();
+        block_heights.dedup();
+        let mut blocks = HashMap::new();
+        let fetched = blocks_db.multi_get(block_heights.iter().map(|h| h.to_be_bytes()));
+        for (block_height, res) in block_heights.into_iter().zip(fetched) {
+            let block_bytes = match res {
+                Ok(Some(block_bytes)) => Some(block_bytes),
+                // Retry with backoff, the block might be in the process of being written
+                _ => find_pinned_block_bytes_at_block_height(block_height, 3, blocks_db, ctx)
+                    .map(|block_bytes| block_bytes.to_vec()),
+            };
+            if let Some(block_bytes) = block_bytes {
+                blocks.insert(block_height, block_bytes);
+            }
+        }
+
+        pending = vec![];
+        let mut round_transactions = HashMap::new();
+        for (index, mut traversal, block_height) in blocked.into_iter() {
+            let Some(block_bytes) = blocks.get(&block_height) else {
+                results[index] = Some(Err(if traversal.locating {
+                    format!("block #{block_height} not in database")
+                } else {
+                    format!(
+                        "block #{block_height} not in database (traversing {} / {} in progress)",
+                        traversal.request.transaction_identifier.hash,
+                        traversal.request.block_identifier.index
+                    )
+                }));
+                continue;
+            };
+            let block_cursor = BlockBytesCursor::new(block_bytes);
+            match traversal.advance_with_block(
+                &block_cursor,
+                &mut round_transactions,
+                traversals_cache,
+                ctx,
+            ) {
+                Some(result) => results[index] = Some(result),
+                None => pending.push((index, traversal)),
             }
         }
     }
 
-    let height = Height(ordinal_block_number.into());
-    let ordinal_number = height.starting_sat().0 + ordinal_offset;
-
-    Ok((
-        TraversalResult {
-            inscription_number: OrdinalInscriptionNumber::zero(),
-            ordinal_number,
-            transfers: hops,
-            inscription_input_index,
-            transaction_identifier_inscription: transaction_identifier.clone(),
-        },
+    results
+        .into_iter()
+        .map(|result| result.expect("traversal not completed"))
+        .collect()
+}
+
+pub fn compute_satoshi_number(
+    blocks_db_dir: &PathBuf,
+    block_identifier: &BlockIdentifier,
+    transaction_identifier: &TransactionIdentifier,
+    inscription_input_index: usize,
+    inscription_pointer: u64,
+    traversals_cache: &Arc<TraversalsCache>,
+    resources: &ResourcesConfig,
+    _back_tracking: bool,
+    ctx: &Context,
+) -> Result<TraversalOutput, String> {
+    let blocks_db = open_ordhook_db_conn_rocks_db_loop(false, &blocks_db_dir, resources, &ctx);
+    let request = TraversalRequest {
+        block_identifier: block_identifier.clone(),
+        transaction_identifier: transaction_identifier.clone(),
+        inscription_input_index,
         inscription_pointer,
-        back_track,
-    ))
+    };
+    compute_satoshi_numbers(&blocks_db, &[request], traversals_cache, ctx)
+        .pop()
+        .expect("traversal not completed")
 }
@@ -0,0 +1,246 @@
+use std::time::Duration;
+
+use crate::config::{Config, MemoryBudget, MemoryReservation};
+
+use super::pipeline::PipelineStats;
+
+/// Batch length used until the throughput of the current phase has been measured.
+const DEFAULT_BATCH_SIZE: u64 = 1_000;
+const MIN_BATCH_SIZE: u64 = 100;
+const MAX_BATCH_SIZE: u64 = 100_000;
+/// Batches are sized to be processed in about this duration, so that the chain tip and the
+/// orphaned blocks are checked regularly.
+const TARGET_BATCH_DURATION: Duration = Duration::from_secs(10 * 60);
+/// Size assumed for the blocks until their size has been measured: 4MB blocks packed with
+/// witness data, about twice as large once formatted as JSON.
+const DEFAULT_BLOCK_BYTES: u64 = 8 * 1024 * 1024;
+const MIN_QUEUE_DEPTH: u64 = 100;
+const MAX_QUEUE_DEPTH: u64 = 10_000;
+
+/// The chain is split in phases processed at very different speeds: blocks prior to the first
+/// inscription are only compacted, inscriptions are numbered classically until the jubilee.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncPhase {
+    PreInscriptions,
+    Inscriptions,
+    Jubilee,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyncBatch {
+    pub start_block: u64,
+    pub end_block: u64,
+    /// Number of blocks the pipeline can hold in memory, waiting to be processed.
+    pub queue_depth: usize,
+    /// Memory used by a full queue, reserved in the memory budget during the batch.
+    pub queue_memory: u64,
+}
+
+/// Split the blocks to sync in batches, sized from the network parameters and from the
+/// throughput and block sizes measured on the previous batch.
+#[derive(Clone, Debug)]
+pub struct SyncPlanner {
+    first_inscription_height: u64,
+    jubilee_height: u64,
+    /// Shared with the traversals caches: the queue is sized from the memory they left.
+    memory_budget: MemoryBudget,
+    /// Phase and queue depth of the last batch, with its measurements.
+    last_batch: Option<(SyncPhase, usize, PipelineStats)>,
+}
+
+impl SyncPlanner {
+    pub fn new(config: &Config) -> SyncPlanner {
+        SyncPlanner {
+            first_inscription_height: config.network_params.first_inscription_height,
+            jubilee_height: config.network_params.jubilee_height,
+            memory_budget: config.resources.get_memory_budget(),
+            last_batch: None,
+        }
+    }
+
+    pub fn phase(&self, block_height: u64) -> SyncPhase {
+        if block_height < self.first_inscription_height {
+            SyncPhase::PreInscriptions
+        } else if block_height < self.jubilee_height {
+            SyncPhase::Inscriptions
+        } else {
+            SyncPhase::Jubilee
+        }
+    }
+
+    /// Queue depth and stats of the last batch, when it was processed in the same phase as
+    /// `block_height`.
+    fn last_stats(&self, block_height: u64) -> Option<(u64, &PipelineStats)> {
+        match self.last_batch {
+            Some((phase, queue_depth, ref stats)) if phase == self.phase(block_height) => {
+                Some((queue_depth as u64, stats))
+            }
+            _ => None,
+        }
+    }
+
+    /// Next batch of blocks to sync, from `start_block` to `chain_tip` at most. A batch never
+    /// spans over two phases.
+    pub fn plan_batch(&self, start_block: u64, chain_tip: u64) -> Option<SyncBatch> {
+        if start_block > chain_tip {
+            return None;
+        }
+        let last_batch = self.last_stats(start_block);
+        let stats = last_batch.map(|(_, stats)| stats);
+
+        let batch_size = stats
+            .and_then(|s| s.blocks_per_second())
+            .map_or(DEFAULT_BATCH_SIZE, |blocks_per_second| {
+                (blocks_per_second * TARGET_BATCH_DURATION.as_secs_f64()) as u64
+            })
+            .clamp(MIN_BATCH_SIZE, MAX_BATCH_SIZE);
+        let mut end_block = chain_tip.min(start_block + batch_size - 1);
+        for boundary in [self.first_inscription_height, self.jubilee_height] {
+            if start_block < boundary && end_block >= boundary {
+                end_block = boundary - 1;
+            }
+        }
+
+        let block_bytes = stats
+            .and_then(|s| s.average_block_bytes())
+            .unwrap_or(DEFAULT_BLOCK_BYTES)
+            .max(1);
+        let memory_queue_depth = self.memory_budget.available() / block_bytes;
+        let queue_depth = match last_batch {
+            // The queue filled up: the post processor is lagging, give it more room
+            Some((queue_depth, stats)) if stats.peak_queued_blocks >= queue_depth => {
+                queue_depth * 2
+            }
+            // The queue never filled up: twice the peak measured leaves enough headroom
+            Some((_, stats)) => stats.peak_queued_blocks * 2,
+            None => memory_queue_depth,
+        }
+        .min(memory_queue_depth)
+        .clamp(MIN_QUEUE_DEPTH, MAX_QUEUE_DEPTH);
+
+        Some(SyncBatch {
+            start_block,
+            end_block,
+            queue_depth: queue_depth as usize,
+            queue_memory: queue_depth * block_bytes,
+        })
+    }
+
+    /// Reserve the memory of the queue of `batch` in the memory budget, until dropped.
+    pub fn reserve_queue_memory(&self, batch: &SyncBatch) -> MemoryReservation {
+        self.memory_budget.reserve(batch.queue_memory)
+    }
+
+    pub fn record_batch(&mut self, batch: &SyncBatch, stats: PipelineStats) {
+        self.last_batch = Some((self.phase(batch.start_block), batch.queue_depth, stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::Config;
+    use crate::core::pipeline::PipelineStats;
+
+    use super::{SyncBatch, SyncPlanner};
+
+    fn mainnet_planner() -> SyncPlanner {
+        let mut config = Config::mainnet_default();
+        config.resources.memory_available = 8;
+        SyncPlanner::new(&config)
+    }
+
+    #[test]
+    fn test_batches_stop_at_phase_boundaries() {
+        let planner = mainnet_planner();
+        let batch = planner.plan_batch(767_000, 900_000).unwrap();
+        assert_eq!(batch.end_block, 767_429);
+        let batch = planner.plan_batch(824_000, 900_000).unwrap();
+        assert_eq!(batch.end_block, 824_543);
+        let batch = planner.plan_batch(850_000, 850_010).unwrap();
+        assert_eq!(batch.end_block, 850_010);
+        assert_eq!(planner.plan_batch(850_011, 850_010), None);
+    }
+
+    #[test]
+    fn test_batches_adapt_to_measurements() {
+        let mut planner = mainnet_planner();
+        let batch = planner.plan_batch(100_000, 900_000).unwrap();
+        assert_eq!(batch.end_block, 100_999);
+        assert_eq!(batch.queue_depth, 512);
+
+        // Small blocks processed quickly, filling up the queue: longer batches, deeper queue
+        planner.record_batch(
+            &batch,
+            PipelineStats {
+                blocks_processed: 1_000,
+                bytes_downloaded: 1_000 * 50_000,
+                peak_queued_blocks: 512,
+                elapsed: Duration::from_secs(10),
+            },
+        );
+        let batch = planner.plan_batch(101_000, 900_000).unwrap();
+        assert_eq!(batch.end_block, 101_000 + 60_000 - 1);
+        assert_eq!(batch.queue_depth, 1_024);
+
+        // The queue never filled up: sized from the peak measured
+        planner.record_batch(
+            &batch,
+            PipelineStats {
+                blocks_processed: 60_000,
+                bytes_downloaded: 60_000 * 50_000,
+                peak_queued_blocks: 150,
+                elapsed: Duration::from_secs(600),
+            },
+        );
+        let batch = planner.plan_batch(161_000, 900_000).unwrap();
+        assert_eq!(batch.queue_depth, 300);
+
+        // Measurements are not carried over the next phase
+        let next_phase = SyncBatch {
+            start_block: 800_000,
+            end_block: 800_000,
+            queue_depth: 150,
+            queue_memory: 150 * 20 * 1024 * 1024,
+        };
+        assert_eq!(
+            planner.plan_batch(800_000, 900_000).unwrap().end_block,
+            800_999
+        );
+
+        // Large blocks processed slowly: shorter batches, queue bounded by the memory budget
+        planner.record_batch(
+            &next_phase,
+            PipelineStats {
+                blocks_processed: 10,
+                bytes_downloaded: 10 * 20 * 1024 * 1024,
+                peak_queued_blocks: 150,
+                elapsed: Duration::from_secs(60),
+            },
+        );
+        let batch = planner.plan_batch(800_001, 900_000).unwrap();
+        assert_eq!(batch.end_block, 800_100);
+        assert_eq!(batch.queue_depth, 204);
+    }
+
+    #[test]
+    fn test_queue_shares_memory_budget() {
+        let mut config = Config::mainnet_default();
+        config.resources.memory_available = 8;
+        let planner = SyncPlanner::new(&config);
+
+        // 3GB of the 4GB budget held by the traversals caches
+        let memory_budget = config.resources.get_memory_budget();
+        let caches_memory = memory_budget.reserve(3 * 1024 * 1024 * 1024);
+        let batch = planner.plan_batch(100_000, 900_000).unwrap();
+        assert_eq!(batch.queue_depth, 128);
+        assert_eq!(batch.queue_memory, 128 * 8 * 1024 * 1024);
+
+        let queue_memory = planner.reserve_queue_memory(&batch);
+        assert_eq!(memory_budget.available(), 0);
+        drop(queue_memory);
+        drop(caches_memory);
+        assert_eq!(memory_budget.in_use(), 0);
+    }
+}
@@ -0,0 +1,638 @@
+use std::collections::{BTreeSet, HashMap};
+
+use chainhook_sdk::utils::Context;
+use rocksdb::DB;
+use rusqlite::Connection;
+
+use super::{
+    delete_inscriptions_in_block_range, find_last_block_inserted, parse_outpoint_to_watch,
+    store::SequenceMetadata, BlockBytesCursor,
+};
+
+/// Inscription number expected by the sequence but not found.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InscriptionNumberGap {
+    pub expected_number: i64,
+    pub found_number: i64,
+    pub inscription_id: String,
+    pub block_height: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InscriptionNumberDuplicate {
+    pub number: i64,
+    pub inscription_id: String,
+    pub block_height: u64,
+}
+
+/// Latest location of an inscription, at an outpoint spent afterwards.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpentLocation {
+    pub ordinal_number: u64,
+    pub outpoint_to_watch: String,
+    pub block_height: u64,
+    pub spent_at_block_height: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InscriptionWithoutLocation {
+    pub inscription_id: String,
+    pub block_height: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SequenceMetadataMismatch {
+    pub block_height: u64,
+    pub recorded: SequenceMetadata,
+    pub expected: SequenceMetadata,
+}
+
+/// Transactions of a block sharing the same 8 bytes txid prefix. Blocks written before the
+/// compacted format was versioned can't tell them apart.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TxidPrefixCollision {
+    pub block_height: u64,
+    pub txid_prefix: String,
+    pub encoded_with_full_txids: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrdhookDbCheckReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub tip: u64,
+    pub missing_blocks: Vec<u64>,
+    pub classic_number_gaps: Vec<InscriptionNumberGap>,
+    pub classic_number_duplicates: Vec<InscriptionNumberDuplicate>,
+    pub jubilee_number_gaps: Vec<InscriptionNumberGap>,
+    pub jubilee_number_duplicates: Vec<InscriptionNumberDuplicate>,
+    pub spent_locations: Vec<SpentLocation>,
+    pub inscriptions_without_location: Vec<InscriptionWithoutLocation>,
+    pub sequence_metadata_mismatches: Vec<SequenceMetadataMismatch>,
+    pub txid_prefix_collisions: Vec<TxidPrefixCollision>,
+    /// Fixes applied (only populated when fixes were requested).
+    pub fixes: Vec<String>,
+}
+
+impl OrdhookDbCheckReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_blocks.is_empty()
+            && self.classic_number_gaps.is_empty()
+            && self.classic_number_duplicates.is_empty()
+            && self.jubilee_number_gaps.is_empty()
+            && self.jubilee_number_duplicates.is_empty()
+            && self.spent_locations.is_empty()
+            && self.inscriptions_without_location.is_empty()
+            && self.sequence_metadata_mismatches.is_empty()
+            && self
+                .txid_prefix_collisions
+                .iter()
+                .all(|collision| collision.encoded_with_full_txids)
+    }
+
+    /// Blocks at which the inscriptions index diverges, sequence metadata aside.
+    fn inconsistent_block_heights(&self) -> Vec<u64> {
+        let numbers = self
+            .classic_number_gaps
+            .iter()
+            .chain(self.jubilee_number_gaps.iter())
+            .map(|gap| gap.block_height);
+        let duplicates = self
+            .classic_number_duplicates
+            .iter()
+            .chain(self.jubilee_number_duplicates.iter())
+            .map(|duplicate| duplicate.block_height);
+        let locations = self
+            .spent_locations
+            .iter()
+            .map(|location| location.block_height);
+        let inscriptions = self
+            .inscriptions_without_location
+            .iter()
+            .map(|inscription| inscription.block_height);
+        numbers
+            .chain(duplicates)
+            .chain(locations)
+            .chain(inscriptions)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+fn query_error(e: rusqlite::Error) -> String {
+    format!("unable to query hord.sqlite: {}", e.to_string())
+}
+
+/// Positive numbers are expected to be 0, 1, 2, ... and negative numbers -1, -2, -3, ...
+/// Expects `numbers` to be sorted by ascending absolute value within each sign, and to follow
+/// `previous_positive` and `previous_negative` when checking a range of the sequence.
+fn check_inscription_number_sequence<I>(
+    numbers: I,
+    mut previous_positive: Option<i64>,
+    mut previous_negative: Option<i64>,
+) -> (Vec<InscriptionNumberGap>, Vec<InscriptionNumberDuplicate>)
+where
+    I: Iterator<Item = (i64, String, u64)>,
+{
+    let mut gaps = vec![];
+    let mut duplicates = vec![];
+    for (number, inscription_id, block_height) in numbers {
+        let (previous, expected) = match number >= 0 {
+            true => (
+                &mut previous_positive,
+                previous_positive.map_or(0, |n| n + 1),
+            ),
+            false => (
+                &mut previous_negative,
+                previous_negative.map_or(-1, |n| n - 1),
+            ),
+        };
+        if *previous == Some(number) {
+            duplicates.push(InscriptionNumberDuplicate {
+                number,
+                inscription_id,
+                block_height,
+            });
+            continue;
+        }
+        if number != expected {
+            gaps.push(InscriptionNumberGap {
+                expected_number: expected,
+                found_number: number,
+                inscription_id,
+                block_height,
+            });
+        }
+        *previous = Some(number);
+    }
+    (gaps, duplicates)
+}
+
+fn check_inscription_numbers(
+    column: &str,
+    start_block: u64,
+    end_block: u64,
+    inscriptions_db_conn: &Connection,
+) -> Result<(Vec<InscriptionNumberGap>, Vec<InscriptionNumberDuplicate>), String> {
+    // The sequence continues from the inscriptions preceding the range
+    let (previous_positive, previous_negative) = inscriptions_db_conn
+        .query_row(
+            &format!(
+                "SELECT MAX(CASE WHEN {column} >= 0 THEN {column} END), MIN(CASE WHEN {column} < 0 THEN {column} END)
+                FROM inscriptions WHERE block_height < ?1"
+            ),
+            [&start_block],
+            |row| Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?)),
+        )
+        .map_err(query_error)?;
+
+    // Positive numbers ascending, then negative numbers descending
+    let query = format!(
+        "SELECT {column}, inscription_id, block_height FROM inscriptions
+        WHERE block_height >= ?1 AND block_height <= ?2
+        ORDER BY {column} < 0, ABS({column}) ASC"
+    );
+    let mut stmt = inscriptions_db_conn.prepare(&query).map_err(query_error)?;
+    let rows = stmt
+        .query_map(rusqlite::params![&start_block, &end_block], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u64>(2)?,
+            ))
+        })
+        .map_err(query_error)?;
+    Ok(check_inscription_number_sequence(
+        rows.filter_map(|row| row.ok()),
+        previous_positive,
+        previous_negative,
+    ))
+}
+
+fn check_inscriptions_without_location(
+    start_block: u64,
+    end_block: u64,
+    inscriptions_db_conn: &Connection,
+) -> Result<Vec<InscriptionWithoutLocation>, String> {
+    let mut stmt = inscriptions_db_conn
+        .prepare(
+            "SELECT i.inscription_id, i.block_height FROM inscriptions i
+            WHERE i.block_height >= ?1 AND i.block_height <= ?2 AND NOT EXISTS (
+                SELECT 1 FROM locations l WHERE l.ordinal_number = i.ordinal_number AND l.block_height = i.block_height
+            )",
+        )
+        .map_err(query_error)?;
+    let rows = stmt
+        .query_map(rusqlite::params![&start_block, &end_block], |row| {
+            Ok(InscriptionWithoutLocation {
+                inscription_id: row.get(0)?,
+                block_height: row.get(1)?,
+            })
+        })
+        .map_err(query_error)?;
+    Ok(rows.filter_map(|row| row.ok()).collect())
+}
+
+fn check_sequence_metadata(
+    start_block: u64,
+    end_block: u64,
+    inscriptions_db_conn: &Connection,
+) -> Result<Vec<SequenceMetadataMismatch>, String> {
+    // Sequence metadata are cumulative: start from the inscriptions preceding the range
+    let mut expected = inscriptions_db_conn
+        .query_row(
+            "SELECT MAX(classic_inscription_number), MIN(classic_inscription_number), MAX(jubilee_inscription_number)
+            FROM inscriptions WHERE block_height < ?1",
+            [&start_block],
+            |row| {
+                Ok(SequenceMetadata {
+                    nth_classic_pos_number: row.get::<_, Option<i64>>(0)?.unwrap_or(0).max(0),
+                    nth_classic_neg_number: row.get::<_, Option<i64>>(1)?.unwrap_or(0).min(0),
+                    nth_jubilee_number: row.get::<_, Option<i64>>(2)?.unwrap_or(0).max(0),
+                })
+            },
+        )
+        .map_err(query_error)?;
+
+    let mut stmt = inscriptions_db_conn
+        .prepare(
+            "SELECT block_height, MAX(classic_inscription_number), MIN(classic_inscription_number), MAX(jubilee_inscription_number)
+            FROM inscriptions WHERE block_height >= ?1 AND block_height <= ?2 GROUP BY block_height",
+        )
+        .map_err(query_error)?;
+    let per_block = stmt
+        .query_map(rusqlite::params![&start_block, &end_block], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })
+        .map_err(query_error)?
+        .filter_map(|row| row.ok())
+        .map(|(block_height, max_classic, min_classic, max_jubilee)| {
+            (block_height, (max_classic, min_classic, max_jubilee))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut stmt = inscriptions_db_conn
+        .prepare(
+            "SELECT block_height, nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number
+            FROM sequence_metadata WHERE block_height >= ?1 AND block_height <= ?2 ORDER BY block_height ASC",
+        )
+        .map_err(query_error)?;
+    let recorded_rows = stmt
+        .query_map(rusqlite::params![&start_block, &end_block], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                SequenceMetadata {
+                    nth_classic_pos_number: row.get(1)?,
+                    nth_classic_neg_number: row.get(2)?,
+                    nth_jubilee_number: row.get(3)?,
+                },
+            ))
+        })
+        .map_err(query_error)?
+        .filter_map(|row| row.ok())
+        .collect::<Vec<_>>();
+
+    let mut mismatches = vec![];
+    let mut block_heights = per_block.keys().cloned().collect::<Vec<_>>();
+    block_heights.sort();
+    let mut block_heights = block_heights.into_iter().peekable();
+    for (block_height, recorded) in recorded_rows.into_iter() {
+        while let Some(inscriptions_block_height) = block_heights.peek() {
+            if *inscriptions_block_height > block_height {
+                break;
+            }
+            let (max_classic, min_classic, max_jubilee) = per_block[inscriptions_block_height];
+            expected.nth_classic_pos_number = expected.nth_classic_pos_number.max(max_classic);
+            expected.nth_classic_neg_number = expected.nth_classic_neg_number.min(min_classic);
+            expected.nth_jubilee_number = expected.nth_jubilee_number.max(max_jubilee);
+            block_heights.next();
+        }
+        if recorded != expected {
+            mismatches.push(SequenceMetadataMismatch {
+                block_height,
+                recorded,
+                expected: expected.clone(),
+            });
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Only the blocks of the checked range are scanned for the inputs spending the locations.
+fn check_spent_locations(
+    start_block: u64,
+    end_block: u64,
+    tip: u64,
+    blocks_db: &DB,
+    inscriptions_db_conn: &Connection,
+    ctx: &Context,
+) -> Result<Vec<SpentLocation>, String> {
+    // Latest location of each ordinal, when written in the range
+    let mut stmt = inscriptions_db_conn
+        .prepare(
+            "SELECT l.ordinal_number, l.outpoint_to_watch, l.block_height FROM locations l
+            WHERE l.block_height >= ?1 AND l.block_height <= ?2 AND NOT EXISTS (
+                SELECT 1 FROM locations n WHERE n.ordinal_number = l.ordinal_number
+                AND (n.block_height > l.block_height OR (n.block_height = l.block_height AND n.tx_index > l.tx_index))
+            )",
+        )
+        .map_err(query_error)?;
+    let rows = stmt
+        .query_map(rusqlite::params![&start_block, &end_block], |row| {
+            Ok((
+                row.get::<_, u64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u64>(2)?,
+            ))
+        })
+        .map_err(query_error)?;
+
+    let mut watched_outpoints: HashMap<([u8; 8], u16), Vec<(u64, String, u64)>> = HashMap::new();
+    let mut lowest_block_height = u64::MAX;
+    for (ordinal_number, outpoint_to_watch, block_height) in rows.filter_map(|row| row.ok()) {
+        let (txid, vout) = parse_outpoint_to_watch(&outpoint_to_watch);
+        let txid_bytes = match hex::decode(txid.get_hash_bytes_str()) {
+            Ok(bytes) if bytes.len() >= 8 => bytes,
+            _ => continue,
+        };
+        let mut txid_prefix = [0u8; 8];
+        txid_prefix.copy_from_slice(&txid_bytes[0..8]);
+        lowest_block_height = lowest_block_height.min(block_height);
+        watched_outpoints
+            .entry((txid_prefix, vout as u16))
+            .or_default()
+            .push((ordinal_number, outpoint_to_watch, block_height));
+    }
+
+    let mut spent_locations = vec![];
+    if watched_outpoints.is_empty() {
+        return Ok(spent_locations);
+    }
+    for block_height in lowest_block_height..=end_block.min(tip) {
+        let block_bytes = match blocks_db.get_pinned((block_height as u32).to_be_bytes()) {
+            Ok(Some(block_bytes)) => block_bytes,
+            _ => continue,
+        };
+        let block = BlockBytesCursor::new(&block_bytes);
+        for tx in block.iter_tx() {
+            for input in tx.inputs.iter() {
+                let Some(locations) = watched_outpoints.get(&(input.txin, input.vout)) else {
+                    continue;
+                };
+                for (ordinal_number, outpoint_to_watch, location_block_height) in locations.iter() {
+                    if block_height > *location_block_height {
+                        spent_locations.push(SpentLocation {
+                            ordinal_number: *ordinal_number,
+                            outpoint_to_watch: outpoint_to_watch.clone(),
+                            block_height: *location_block_height,
+                            spent_at_block_height: block_height,
+                        });
+                    }
+                }
+            }
+        }
+        if block_height % 10_000 == 0 {
+            ctx.try_log(|logger| info!(logger, "Checking spent locations: block #{block_height}"));
+        }
+    }
+    Ok(spent_locations)
+}
+
+/// Check the consistency of hord.sqlite (inscriptions between `start_block` and `end_block`)
+/// against itself and the blocks stored in hord.rocksdb.
+/// Only the sqlite inscriptions index is supported: the checks are performed with SQL queries.
+pub fn check_ordhook_db(
+    start_block: u64,
+    end_block: u64,
+    blocks_db: &DB,
+    inscriptions_db_conn: &Connection,
+    ctx: &Context,
+) -> Result<OrdhookDbCheckReport, String> {
+    let tip = find_last_block_inserted(blocks_db) as u64;
+    let mut report = OrdhookDbCheckReport {
+        start_block,
+        end_block,
+        tip,
+        ..Default::default()
+    };
+
+    ctx.try_log(|logger| info!(logger, "Checking blocks"));
+    for block_height in start_block..=end_block.min(tip) {
+        match blocks_db.get_pinned((block_height as u32).to_be_bytes()) {
+            Ok(Some(block_bytes)) => {
+                let block = BlockBytesCursor::new(&block_bytes);
+                for txid_prefix in block.find_txid_prefix_collisions() {
+                    report.txid_prefix_collisions.push(TxidPrefixCollision {
+                        block_height,
+                        txid_prefix: hex::encode(txid_prefix),
+                        encoded_with_full_txids: block.has_full_txids(),
+                    });
+                }
+            }
+            _ => report.missing_blocks.push(block_height),
+        }
+    }
+
+    ctx.try_log(|logger| info!(logger, "Checking inscription numbers"));
+    (report.classic_number_gaps, report.classic_number_duplicates) = check_inscription_numbers(
+        "classic_inscription_number",
+        start_block,
+        end_block,
+        inscriptions_db_conn,
+    )?;
+    (report.jubilee_number_gaps, report.jubilee_number_duplicates) = check_inscription_numbers(
+        "jubilee_inscription_number",
+        start_block,
+        end_block,
+        inscriptions_db_conn,
+    )?;
+
+    ctx.try_log(|logger| info!(logger, "Checking inscriptions locations"));
+    report.inscriptions_without_location =
+        check_inscriptions_without_location(start_block, end_block, inscriptions_db_conn)?;
+    report.spent_locations = check_spent_locations(
+        start_block,
+        end_block,
+        tip,
+        blocks_db,
+        inscriptions_db_conn,
+        ctx,
+    )?;
+
+    ctx.try_log(|logger| info!(logger, "Checking sequence metadata"));
+    report.sequence_metadata_mismatches =
+        check_sequence_metadata(start_block, end_block, inscriptions_db_conn)?;
+
+    Ok(report)
+}
+
+/// Apply the fixes available for the issues listed in `report`:
+/// - sequence metadata are rewritten with the values computed from the inscriptions table,
+/// - inscriptions data are dropped at each inconsistent block, the blocks returned having to be
+///   re-indexed.
+pub fn fix_ordhook_db(
+    report: &mut OrdhookDbCheckReport,
+    inscriptions_db_conn_rw: &Connection,
+    ctx: &Context,
+) -> Result<Vec<u64>, String> {
+    for mismatch in report.sequence_metadata_mismatches.iter() {
+        inscriptions_db_conn_rw
+            .execute(
+                "UPDATE sequence_metadata SET nth_classic_pos_number = ?1, nth_classic_neg_number = ?2, nth_jubilee_number = ?3 WHERE block_height = ?4",
+                rusqlite::params![
+                    &mismatch.expected.nth_classic_pos_number,
+                    &mismatch.expected.nth_classic_neg_number,
+                    &mismatch.expected.nth_jubilee_number,
+                    &mismatch.block_height
+                ],
+            )
+            .map_err(query_error)?;
+        report.fixes.push(format!(
+            "sequence_metadata at block #{} rewritten",
+            mismatch.block_height
+        ));
+    }
+
+    let block_heights = report.inconsistent_block_heights();
+    for block_height in block_heights.iter() {
+        delete_inscriptions_in_block_range(
+            *block_height as u32,
+            *block_height as u32,
+            inscriptions_db_conn_rw,
+            ctx,
+        );
+        report.fixes.push(format!(
+            "inscriptions data dropped at block #{block_height}"
+        ));
+    }
+    Ok(block_heights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_inscription_number_sequence() {
+        let numbers = vec![
+            (0, "a".to_string(), 1),
+            (1, "b".to_string(), 1),
+            (1, "c".to_string(), 2),
+            (3, "d".to_string(), 3),
+            (-1, "e".to_string(), 1),
+            (-3, "f".to_string(), 4),
+        ];
+        let (gaps, duplicates) = check_inscription_number_sequence(numbers.into_iter(), None, None);
+        assert_eq!(
+            duplicates,
+            vec![InscriptionNumberDuplicate {
+                number: 1,
+                inscription_id: "c".into(),
+                block_height: 2
+            }]
+        );
+        assert_eq!(
+            gaps.iter()
+                .map(|gap| (gap.expected_number, gap.found_number))
+                .collect::<Vec<_>>(),
+            vec![(2, 3), (-2, -3)]
+        );
+    }
+
+    #[test]
+    fn test_check_inscription_numbers_in_range() {
+        let ctx = Context::empty();
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::migrate_ordhook_db(&mut conn, &ctx).unwrap();
+        for (inscription_id, block_height, number) in [
+            ("ai0", 10, 0),
+            ("bi0", 11, 1),
+            ("ci0", 12, 3),
+            ("di0", 13, 4),
+        ] {
+            conn.execute(
+                "INSERT INTO inscriptions (inscription_id, input_index, block_height, ordinal_number, jubilee_inscription_number, classic_inscription_number) VALUES (?1, 0, ?2, 0, ?3, ?3)",
+                rusqlite::params![&inscription_id, &block_height, &number],
+            )
+            .unwrap();
+        }
+
+        // Ranges following the gap continue from the numbers preceding them
+        let (gaps, duplicates) =
+            check_inscription_numbers("classic_inscription_number", 13, 13, &conn).unwrap();
+        assert!(gaps.is_empty() && duplicates.is_empty());
+        let (gaps, _) =
+            check_inscription_numbers("classic_inscription_number", 11, 13, &conn).unwrap();
+        assert_eq!(
+            gaps.iter()
+                .map(|gap| (gap.block_height, gap.expected_number))
+                .collect::<Vec<_>>(),
+            vec![(12, 2)]
+        );
+
+        // Only the reported blocks are dropped, not the blocks in between
+        let mut report = OrdhookDbCheckReport {
+            classic_number_gaps: gaps,
+            inscriptions_without_location: vec![InscriptionWithoutLocation {
+                inscription_id: "ai0".into(),
+                block_height: 10,
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            fix_ordhook_db(&mut report, &conn, &ctx).unwrap(),
+            vec![10, 12]
+        );
+        let remaining = conn
+            .prepare("SELECT block_height FROM inscriptions ORDER BY block_height")
+            .unwrap()
+            .query_map([], |row| row.get::<_, u64>(0))
+            .unwrap()
+            .filter_map(|row| row.ok())
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, vec![11, 13]);
+    }
+
+    #[test]
+    fn test_check_sequence_metadata() {
+        let ctx = Context::empty();
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::migrate_ordhook_db(&mut conn, &ctx).unwrap();
+        for (inscription_id, block_height, classic) in
+            [("ai0", 10, 0), ("bi0", 10, -1), ("ci0", 11, 1)]
+        {
+            conn.execute(
+                "INSERT INTO inscriptions (inscription_id, input_index, block_height, ordinal_number, jubilee_inscription_number, classic_inscription_number) VALUES (?1, 0, ?2, 0, ?3, ?3)",
+                rusqlite::params![&inscription_id, &block_height, &classic],
+            )
+            .unwrap();
+        }
+        conn.execute_batch(
+            "INSERT INTO sequence_metadata VALUES (10, 0, -1, 0);
+            INSERT INTO sequence_metadata VALUES (11, 2, -1, 1);",
+        )
+        .unwrap();
+
+        let mut report = OrdhookDbCheckReport {
+            sequence_metadata_mismatches: check_sequence_metadata(0, 20, &conn).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(report.sequence_metadata_mismatches.len(), 1);
+        assert_eq!(report.sequence_metadata_mismatches[0].block_height, 11);
+        assert_eq!(
+            report.sequence_metadata_mismatches[0]
+                .expected
+                .nth_classic_pos_number,
+            1
+        );
+
+        fix_ordhook_db(&mut report, &conn, &ctx).unwrap();
+        assert!(check_sequence_metadata(0, 20, &conn).unwrap().is_empty());
+    }
+}
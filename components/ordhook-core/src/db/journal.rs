@@ -0,0 +1,476 @@
+use chainhook_sdk::{
+    types::{BitcoinBlockData, BlockIdentifier},
+    utils::Context,
+};
+use rusqlite::{Connection, ToSql};
+
+use super::{
+    perform_query_one, perform_query_set,
+    store::{InscriptionStore, SequenceMetadata},
+};
+
+/// Inscription indexed at a given block height.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournaledInscription {
+    pub inscription_id: String,
+    pub input_index: usize,
+    pub ordinal_number: u64,
+    pub classic_inscription_number: i64,
+    pub jubilee_inscription_number: i64,
+}
+
+/// Location of an inscription, recorded at a given block height.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournaledLocation {
+    pub ordinal_number: u64,
+    pub outpoint_to_watch: String,
+    pub offset: u64,
+    pub block_height: u64,
+    pub tx_index: usize,
+}
+
+/// Entries of the inscriptions index keyed by one block height.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlockEntries {
+    pub block_height: u64,
+    pub inscriptions: Vec<JournaledInscription>,
+    pub locations: Vec<JournaledLocation>,
+    pub sequence_metadata: Vec<SequenceMetadata>,
+    pub block_hash: Option<String>,
+}
+
+/// State overwritten by a block. Every write of a block (inscriptions, locations, sequence
+/// metadata and block hash) is keyed by its height, and undoing the block deletes everything
+/// stored at that height: the entries present at that height before the block was applied are
+/// the state to restore.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BlockUndoJournal {
+    pub block_height: u64,
+    pub block_hash: String,
+    pub overwritten_entries: BlockEntries,
+}
+
+pub fn find_latest_location_of_ordinal(
+    ordinal_number: u64,
+    db_conn: &Connection,
+    ctx: &Context,
+) -> Option<JournaledLocation> {
+    let args: &[&dyn ToSql] = &[&ordinal_number.to_sql().unwrap()];
+    let query = "SELECT outpoint_to_watch, offset, block_height, tx_index FROM locations WHERE ordinal_number = ? ORDER BY block_height DESC, tx_index DESC LIMIT 1";
+    perform_query_one(query, args, db_conn, ctx, |row| JournaledLocation {
+        ordinal_number,
+        outpoint_to_watch: row.get(0).unwrap(),
+        offset: row.get(1).unwrap(),
+        block_height: row.get(2).unwrap(),
+        tx_index: row.get(3).unwrap(),
+    })
+}
+
+pub fn find_block_entries(block_height: u64, db_conn: &Connection, ctx: &Context) -> BlockEntries {
+    let args: &[&dyn ToSql] = &[&block_height.to_sql().unwrap()];
+    BlockEntries {
+        block_height,
+        inscriptions: perform_query_set(
+            "SELECT inscription_id, input_index, ordinal_number, classic_inscription_number, jubilee_inscription_number FROM inscriptions WHERE block_height = ? ORDER BY inscription_id",
+            args,
+            db_conn,
+            ctx,
+            |row| JournaledInscription {
+                inscription_id: row.get(0).unwrap(),
+                input_index: row.get(1).unwrap(),
+                ordinal_number: row.get(2).unwrap(),
+                classic_inscription_number: row.get(3).unwrap(),
+                jubilee_inscription_number: row.get(4).unwrap(),
+            },
+        ),
+        locations: perform_query_set(
+            "SELECT ordinal_number, outpoint_to_watch, offset, block_height, tx_index FROM locations WHERE block_height = ? ORDER BY tx_index, ordinal_number",
+            args,
+            db_conn,
+            ctx,
+            |row| JournaledLocation {
+                ordinal_number: row.get(0).unwrap(),
+                outpoint_to_watch: row.get(1).unwrap(),
+                offset: row.get(2).unwrap(),
+                block_height: row.get(3).unwrap(),
+                tx_index: row.get(4).unwrap(),
+            },
+        ),
+        sequence_metadata: perform_query_set(
+            "SELECT nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number FROM sequence_metadata WHERE block_height = ?",
+            args,
+            db_conn,
+            ctx,
+            |row| SequenceMetadata {
+                nth_classic_pos_number: row.get(0).unwrap(),
+                nth_classic_neg_number: row.get(1).unwrap(),
+                nth_jubilee_number: row.get(2).unwrap(),
+            },
+        ),
+        block_hash: perform_query_one(
+            "SELECT block_hash FROM blocks WHERE block_height = ?",
+            args,
+            db_conn,
+            ctx,
+            |row| row.get(0).unwrap(),
+        ),
+    }
+}
+
+pub fn insert_journaled_inscription(
+    inscription: &JournaledInscription,
+    block_height: u64,
+    inscriptions_db_conn_rw: &Connection,
+    ctx: &Context,
+) {
+    while let Err(e) = inscriptions_db_conn_rw.execute(
+        "INSERT INTO inscriptions (inscription_id, ordinal_number, jubilee_inscription_number, classic_inscription_number, block_height, input_index) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![&inscription.inscription_id, &inscription.ordinal_number, &inscription.jubilee_inscription_number, &inscription.classic_inscription_number, &block_height, &inscription.input_index],
+    ) {
+        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+pub fn insert_block_undo_journal(
+    journal: &BlockUndoJournal,
+    inscriptions_db_conn_rw: &Connection,
+    ctx: &Context,
+) {
+    let serialized_journal = serde_json::to_string(journal).expect("unable to serialize journal");
+    while let Err(e) = inscriptions_db_conn_rw.execute(
+        "INSERT OR REPLACE INTO undo_journal (block_height, block_hash, journal) VALUES (?1, ?2, ?3)",
+        rusqlite::params![&journal.block_height, &journal.block_hash, &serialized_journal],
+    ) {
+        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+pub fn find_block_undo_journal(
+    block_height: u64,
+    db_conn: &Connection,
+    ctx: &Context,
+) -> Option<BlockUndoJournal> {
+    let args: &[&dyn ToSql] = &[&block_height.to_sql().unwrap()];
+    let query = "SELECT journal FROM undo_journal WHERE block_height = ?";
+    perform_query_one(query, args, db_conn, ctx, |row| {
+        let serialized_journal: String = row.get(0).unwrap();
+        serialized_journal
+    })
+    .and_then(|serialized_journal| serde_json::from_str(&serialized_journal).ok())
+}
+
+/// Delete the journals of the blocks up to `block_height` (included).
+pub fn prune_undo_journal(block_height: u64, inscriptions_db_conn_rw: &Connection, ctx: &Context) {
+    while let Err(e) = inscriptions_db_conn_rw.execute(
+        "DELETE FROM undo_journal WHERE block_height <= ?1",
+        rusqlite::params![&block_height],
+    ) {
+        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+/// Capture the state that the block is about to overwrite, when journaling is enabled
+/// (`depth` > 0). Must be called before applying the block.
+pub fn build_block_undo_journal(
+    block_identifier: &BlockIdentifier,
+    depth: u64,
+    inscriptions_store: &dyn InscriptionStore,
+    ctx: &Context,
+) -> Option<BlockUndoJournal> {
+    if depth == 0 {
+        return None;
+    }
+    Some(BlockUndoJournal {
+        block_height: block_identifier.index,
+        block_hash: block_identifier.hash.clone(),
+        overwritten_entries: inscriptions_store.find_block_entries(block_identifier.index, ctx),
+    })
+}
+
+/// Store the journal of a block once applied, only keeping the journals of the last `depth` blocks.
+pub fn record_block_undo_journal(
+    journal: &BlockUndoJournal,
+    depth: u64,
+    inscriptions_store: &dyn InscriptionStore,
+    ctx: &Context,
+) {
+    inscriptions_store.insert_block_undo_journal(journal, ctx);
+    inscriptions_store.prune_block_undo_journals(journal.block_height.saturating_sub(depth), ctx);
+}
+
+/// Apply `block` to the inscriptions index, journaling the state it overwrites when `depth` > 0.
+pub fn apply_block_with_undo_journal(
+    block: &BitcoinBlockData,
+    depth: u64,
+    inscriptions_store: &dyn InscriptionStore,
+    ctx: &Context,
+) {
+    let journal = build_block_undo_journal(&block.block_identifier, depth, inscriptions_store, ctx);
+
+    inscriptions_store.update_inscriptions_with_block(block, ctx);
+    inscriptions_store.update_locations_with_block(block, ctx);
+    inscriptions_store.update_sequence_metadata_with_block(block, ctx);
+    inscriptions_store.insert_block_hash(&block.block_identifier, ctx);
+
+    if let Some(journal) = journal {
+        record_block_undo_journal(&journal, depth, inscriptions_store, ctx);
+    }
+}
+
+/// Restore the state of the inscriptions index prior to `block_identifier`, using its undo journal.
+/// Returns false if no journal matching the block is available (block too deep, or journal disabled).
+pub fn undo_block_with_journal(
+    block_identifier: &BlockIdentifier,
+    inscriptions_store: &dyn InscriptionStore,
+    ctx: &Context,
+) -> bool {
+    let journal = match inscriptions_store.find_block_undo_journal(block_identifier.index, ctx) {
+        Some(journal) if journal.block_hash == block_identifier.hash => journal,
+        _ => return false,
+    };
+    inscriptions_store.delete_data_in_block_range(journal.block_height, journal.block_height, ctx);
+    inscriptions_store.insert_block_entries(&journal.overwritten_entries, ctx);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{
+        find_inscriptions_at_wached_outpoint, migrate_ordhook_db, store::SqliteInscriptionStore,
+    };
+    use chainhook_sdk::{
+        indexer::bitcoin::{parse_downloaded_block, standardize_bitcoin_block},
+        types::{
+            BitcoinNetwork, OrdinalInscriptionNumber, OrdinalInscriptionRevealData,
+            OrdinalInscriptionTransferData, OrdinalInscriptionTransferDestination,
+            OrdinalOperation,
+        },
+    };
+
+    const TX_A: &str = "b61b0172d95e266c18aea0c624db987e971a5d6d4ebc2aaed85da4642d635735";
+    const TX_B: &str = "c1d7aaf6d9ae1b8b2e5a8a1b3d3c6a5f9c1e2d3f4a5b6c7d8e9f0a1b2c3d4e5f";
+    const TX_C: &str = "d2e8bbf7eabf2c9c3f6b9b2c4e4d7b6fad2f3e4f5b6c7d8e9fa0b1c2d3e4f5a6";
+    const TX_D: &str = "e3f9ccf8fbc03dad4f7cac3d5f5e8c7fbe3f4f5f6c7d8e9fab0c1d2e3f4f5a6b7";
+
+    fn synthetic_block(
+        block_height: u64,
+        block_hash: &str,
+        ordinal_operations: Vec<OrdinalOperation>,
+        ctx: &Context,
+    ) -> BitcoinBlockData {
+        let block = include_str!("./fixtures/blocks_json/279671.json");
+        let decoded_block =
+            parse_downloaded_block(block.as_bytes().to_vec()).expect("unable to decode block");
+        let mut block = standardize_bitcoin_block(decoded_block, &BitcoinNetwork::Mainnet, ctx)
+            .expect("unable to standardize block");
+        block.block_identifier = BlockIdentifier {
+            index: block_height,
+            hash: block_hash.to_string(),
+        };
+        for tx in block.transactions.iter_mut() {
+            tx.metadata.ordinal_operations.clear();
+        }
+        block.transactions[0].metadata.ordinal_operations = ordinal_operations;
+        block
+    }
+
+    fn reveal(txid: &str, ordinal_number: u64, classic: i64) -> OrdinalOperation {
+        OrdinalOperation::InscriptionRevealed(OrdinalInscriptionRevealData {
+            content_type: "text/plain".into(),
+            content_bytes: "0x".into(),
+            content_length: 0,
+            inscription_id: format!("{txid}i0"),
+            inscription_input_index: 0,
+            tx_index: 0,
+            inscription_output_value: 546,
+            inscription_pointer: 0,
+            inscription_fee: 0,
+            inscription_number: OrdinalInscriptionNumber {
+                classic,
+                jubilee: classic,
+            },
+            inscriber_address: None,
+            parent: None,
+            delegate: None,
+            metaprotocol: None,
+            metadata: None,
+            ordinal_number,
+            ordinal_block_height: 0,
+            ordinal_offset: 0,
+            transfers_pre_inscription: 0,
+            satpoint_post_inscription: format!("{txid}:0:0"),
+            curse_type: None,
+        })
+    }
+
+    fn transfer(ordinal_number: u64, from_txid: &str, to_txid: &str) -> OrdinalOperation {
+        OrdinalOperation::InscriptionTransferred(OrdinalInscriptionTransferData {
+            ordinal_number,
+            destination: OrdinalInscriptionTransferDestination::Transferred(
+                "bc1qyhnl3jkxk5lmm66z6sdtcljqh3mmw5t5p6kusz".into(),
+            ),
+            tx_index: 1,
+            satpoint_pre_transfer: format!("{from_txid}:0:0"),
+            satpoint_post_transfer: format!("{to_txid}:0:0"),
+            post_transfer_output_value: Some(546),
+        })
+    }
+
+    fn dump_inscriptions_index(conn: &Connection) -> Vec<String> {
+        let mut rows = vec![];
+        for query in [
+            "SELECT inscription_id, ordinal_number, classic_inscription_number, block_height FROM inscriptions ORDER BY inscription_id",
+            "SELECT ordinal_number, outpoint_to_watch, offset, block_height FROM locations ORDER BY block_height, ordinal_number",
+            "SELECT block_height, nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number FROM sequence_metadata ORDER BY block_height",
+            "SELECT block_height, block_hash, 0, 0 FROM blocks ORDER BY block_height",
+        ] {
+            let mut stmt = conn.prepare(query).unwrap();
+            let mut res = stmt.query([]).unwrap();
+            while let Some(row) = res.next().unwrap() {
+                let values: Vec<String> = (0..4)
+                    .map(|i| match row.get_ref(i).unwrap() {
+                        rusqlite::types::ValueRef::Text(text) => {
+                            String::from_utf8_lossy(text).to_string()
+                        }
+                        rusqlite::types::ValueRef::Integer(value) => value.to_string(),
+                        _ => String::new(),
+                    })
+                    .collect();
+                rows.push(values.join("|"));
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_undo_blocks_across_fork() {
+        let ctx = Context::empty();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_ordhook_db(&mut conn, &ctx).unwrap();
+        let store = SqliteInscriptionStore::new(&conn);
+        let before_fork = dump_inscriptions_index(&conn);
+
+        // Common ancestor: inscription revealed at TX_A
+        let block_100 = synthetic_block(100, "0xa100", vec![reveal(TX_A, 1_000, 0)], &ctx);
+        apply_block_with_undo_journal(&block_100, 12, &store, &ctx);
+        let state_100 = dump_inscriptions_index(&conn);
+
+        // Fork A: inscription moved to TX_B
+        let block_101_a = synthetic_block(101, "0xa101", vec![transfer(1_000, TX_A, TX_B)], &ctx);
+        apply_block_with_undo_journal(&block_101_a, 12, &store, &ctx);
+        assert_eq!(
+            find_inscriptions_at_wached_outpoint(&format!("{TX_B}:0"), &conn, &ctx).len(),
+            1
+        );
+        let journal = find_block_undo_journal(101, &conn, &ctx).unwrap();
+        assert_eq!(
+            journal.overwritten_entries,
+            BlockEntries {
+                block_height: 101,
+                ..Default::default()
+            }
+        );
+
+        // Journal of another fork can't be used
+        let block_101_b_id = BlockIdentifier {
+            index: 101,
+            hash: "0xb101".into(),
+        };
+        assert!(!undo_block_with_journal(&block_101_b_id, &store, &ctx));
+
+        // Re-org: fork A is undone, fork B moves the inscription to TX_C and reveals a new one
+        assert!(undo_block_with_journal(
+            &block_101_a.block_identifier,
+            &store,
+            &ctx
+        ));
+        assert_eq!(dump_inscriptions_index(&conn), state_100);
+
+        let block_101_b = synthetic_block(
+            101,
+            "0xb101",
+            vec![transfer(1_000, TX_A, TX_C), reveal(TX_D, 2_000, 1)],
+            &ctx,
+        );
+        apply_block_with_undo_journal(&block_101_b, 12, &store, &ctx);
+        assert!(find_inscriptions_at_wached_outpoint(&format!("{TX_B}:0"), &conn, &ctx).is_empty());
+        assert_eq!(
+            find_inscriptions_at_wached_outpoint(&format!("{TX_C}:0"), &conn, &ctx).len(),
+            1
+        );
+
+        assert!(undo_block_with_journal(&block_101_b_id, &store, &ctx));
+        assert_eq!(dump_inscriptions_index(&conn), state_100);
+        assert!(undo_block_with_journal(
+            &block_100.block_identifier,
+            &store,
+            &ctx
+        ));
+        assert_eq!(dump_inscriptions_index(&conn), before_fork);
+    }
+
+    #[test]
+    fn test_undo_restores_overwritten_entries() {
+        let ctx = Context::empty();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_ordhook_db(&mut conn, &ctx).unwrap();
+        let store = SqliteInscriptionStore::new(&conn);
+
+        let block_100 = synthetic_block(100, "0xa100", vec![reveal(TX_A, 1_000, 0)], &ctx);
+        apply_block_with_undo_journal(&block_100, 12, &store, &ctx);
+
+        // Fork A was indexed without journal, and is still present at height 101 when fork B is
+        // applied on top of it
+        let block_101_a = synthetic_block(
+            101,
+            "0xa101",
+            vec![transfer(1_000, TX_A, TX_B), reveal(TX_C, 2_000, 1)],
+            &ctx,
+        );
+        apply_block_with_undo_journal(&block_101_a, 0, &store, &ctx);
+        let state_101_a = dump_inscriptions_index(&conn);
+
+        let block_101_b = synthetic_block(101, "0xb101", vec![reveal(TX_D, 3_000, 2)], &ctx);
+        apply_block_with_undo_journal(&block_101_b, 12, &store, &ctx);
+        let journal = find_block_undo_journal(101, &conn, &ctx).unwrap();
+        assert_eq!(journal.overwritten_entries.inscriptions.len(), 1);
+        assert_eq!(journal.overwritten_entries.locations.len(), 2);
+        assert_eq!(journal.overwritten_entries.sequence_metadata.len(), 1);
+        assert_eq!(
+            journal.overwritten_entries.block_hash,
+            Some("0xa101".to_string())
+        );
+
+        // Deleting the entries at height 101 would drop fork A as well
+        assert!(undo_block_with_journal(
+            &block_101_b.block_identifier,
+            &store,
+            &ctx
+        ));
+        assert_eq!(dump_inscriptions_index(&conn), state_101_a);
+    }
+
+    #[test]
+    fn test_undo_journal_depth() {
+        let ctx = Context::empty();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_ordhook_db(&mut conn, &ctx).unwrap();
+        let store = SqliteInscriptionStore::new(&conn);
+
+        for block_height in 100..105 {
+            let block = synthetic_block(block_height, &format!("0x{block_height}"), vec![], &ctx);
+            apply_block_with_undo_journal(&block, 2, &store, &ctx);
+        }
+        assert!(find_block_undo_journal(102, &conn, &ctx).is_none());
+        assert!(find_block_undo_journal(103, &conn, &ctx).is_some());
+        assert!(find_block_undo_journal(104, &conn, &ctx).is_some());
+
+        let block = synthetic_block(105, "0x105", vec![], &ctx);
+        apply_block_with_undo_journal(&block, 0, &store, &ctx);
+        assert!(find_block_undo_journal(105, &conn, &ctx).is_none());
+    }
+}
@@ -1,14 +1,24 @@
+pub mod check;
+pub mod journal;
+pub mod store;
+pub mod sync_checkpoint;
+pub mod traversals_cache;
+
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::OnceLock,
     thread::sleep,
     time::Duration,
 };
 
 use rand::{thread_rng, Rng};
 
-use rocksdb::{DBPinnableSlice, DB};
+use rocksdb::{
+    BlockBasedOptions, Cache, ColumnFamilyDescriptor, DBCompactionStyle, DBCompressionType,
+    DBPinnableSlice, IteratorMode, WriteBatch, DB,
+};
 use rusqlite::{Connection, OpenFlags, ToSql, Transaction};
 use std::io::Cursor;
 
@@ -22,12 +32,94 @@ use chainhook_sdk::{
 };
 
 use crate::{
-    core::protocol::inscription_parsing::{
-        get_inscriptions_revealed_in_block, get_inscriptions_transferred_in_block,
-    },
+    config::{BlocksCompression, CompactionStyle, InscriptionsIndexLayout, ResourcesConfig},
+    core::protocol::inscription_parsing::get_inscriptions_revealed_in_block,
     ord::sat::Sat,
 };
 
+use journal::undo_block_with_journal;
+use store::{
+    rocksdb::RocksDbInscriptionStore, ConfiguredInscriptionStore, InscriptionStore,
+    SqliteInscriptionStore,
+};
+use sync_checkpoint::{record_block_in_sync_checkpoint, update_sync_checkpoint};
+use traversals_cache::delete_traversals_cache_entries_from_block_height;
+
+/// A schema change applied to hord.sqlite. Migrations are applied in order, each one in its
+/// own transaction, and recorded in the `schema_version` table.
+pub struct OrdhookDbMigration {
+    pub version: u32,
+    pub description: &'static str,
+    pub statements: &'static [&'static str],
+    /// Additive migrations only create tables or indexes, and are applied on startup.
+    /// Others rewrite existing data and are applied explicitly with `ordhook db migrate`.
+    pub additive: bool,
+}
+
+/// Ordered list of the hord.sqlite migrations. New migrations must be appended with an
+/// incremented version, existing entries must never be edited.
+pub const ORDHOOK_DB_MIGRATIONS: &[OrdhookDbMigration] = &[OrdhookDbMigration {
+    version: 1,
+    description: "create inscriptions, locations and sequence_metadata tables",
+    statements: &[
+        "CREATE TABLE IF NOT EXISTS inscriptions (
+            inscription_id TEXT NOT NULL PRIMARY KEY,
+            input_index INTEGER NOT NULL,
+            block_height INTEGER NOT NULL,
+            ordinal_number INTEGER NOT NULL,
+            jubilee_inscription_number INTEGER NOT NULL,
+            classic_inscription_number INTEGER NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS index_inscriptions_on_ordinal_number ON inscriptions(ordinal_number);",
+        "CREATE INDEX IF NOT EXISTS index_inscriptions_on_jubilee_inscription_number ON inscriptions(jubilee_inscription_number);",
+        "CREATE INDEX IF NOT EXISTS index_inscriptions_on_classic_inscription_number ON inscriptions(classic_inscription_number);",
+        "CREATE INDEX IF NOT EXISTS index_inscriptions_on_block_height ON inscriptions(block_height);",
+        "CREATE TABLE IF NOT EXISTS locations (
+            ordinal_number INTEGER NOT NULL,
+            block_height INTEGER NOT NULL,
+            tx_index INTEGER NOT NULL,
+            outpoint_to_watch TEXT NOT NULL,
+            offset INTEGER NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS locations_indexed_on_block_height ON locations(block_height);",
+        "CREATE INDEX IF NOT EXISTS locations_indexed_on_outpoint_to_watch ON locations(outpoint_to_watch);",
+        "CREATE INDEX IF NOT EXISTS locations_indexed_on_ordinal_number ON locations(ordinal_number);",
+        "CREATE TABLE IF NOT EXISTS sequence_metadata (
+            block_height INTEGER NOT NULL,
+            nth_classic_pos_number INTEGER NOT NULL,
+            nth_classic_neg_number INTEGER NOT NULL,
+            nth_jubilee_number INTEGER NOT NULL
+        )",
+        "CREATE INDEX IF NOT EXISTS sequence_metadata_indexed_on_block_height ON sequence_metadata(block_height);",
+    ],
+    additive: true,
+}, OrdhookDbMigration {
+    version: 2,
+    description: "create blocks table, tracking the hash of indexed blocks",
+    statements: &[
+        "CREATE TABLE IF NOT EXISTS blocks (
+            block_height INTEGER NOT NULL PRIMARY KEY,
+            block_hash TEXT NOT NULL
+        )",
+    ],
+    additive: true,
+}, OrdhookDbMigration {
+    version: 3,
+    description: "create undo_journal table, keeping the state overwritten by recent blocks",
+    statements: &[
+        "CREATE TABLE IF NOT EXISTS undo_journal (
+            block_height INTEGER NOT NULL PRIMARY KEY,
+            block_hash TEXT NOT NULL,
+            journal TEXT NOT NULL
+        )",
+    ],
+    additive: true,
+}];
+
+/// Version of the hord.sqlite schema expected by this build.
+pub const ORDHOOK_DB_SCHEMA_VERSION: u32 =
+    ORDHOOK_DB_MIGRATIONS[ORDHOOK_DB_MIGRATIONS.len() - 1].version;
+
 pub fn get_default_ordhook_db_file_path(base_dir: &PathBuf) -> PathBuf {
     let mut destination_path = base_dir.clone();
     destination_path.push("hord.sqlite");
@@ -54,118 +146,152 @@ pub fn open_readwrite_ordhook_db_conn(
 
 pub fn initialize_ordhook_db(base_dir: &PathBuf, ctx: &Context) -> Connection {
     let db_path = get_default_ordhook_db_file_path(&base_dir);
-    let conn = create_or_open_readwrite_db(&db_path, ctx);
-    // TODO: introduce initial output
-    if let Err(e) = conn.execute(
-        "CREATE TABLE IF NOT EXISTS inscriptions (
-            inscription_id TEXT NOT NULL PRIMARY KEY,
-            input_index INTEGER NOT NULL,
-            block_height INTEGER NOT NULL,
-            ordinal_number INTEGER NOT NULL,
-            jubilee_inscription_number INTEGER NOT NULL,
-            classic_inscription_number INTEGER NOT NULL
-        )",
-        [],
-    ) {
-        ctx.try_log(|logger| {
-            warn!(
-                logger,
-                "Unable to create table inscriptions: {}",
-                e.to_string()
-            )
-        });
-    } else {
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS index_inscriptions_on_ordinal_number ON inscriptions(ordinal_number);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+    let mut conn = create_or_open_readwrite_db(&db_path, ctx);
+    // Brand new databases are created with the latest schema, existing databases
+    // are upgraded on startup (see `upgrade_ordhook_db_schema`).
+    match get_ordhook_db_schema_version(&conn) {
+        Ok(0) => {
+            if let Err(e) = migrate_ordhook_db(&mut conn, ctx) {
+                ctx.try_log(|logger| warn!(logger, "Unable to initialize hord.sqlite: {}", e));
+            }
         }
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS index_inscriptions_on_jubilee_inscription_number ON inscriptions(jubilee_inscription_number);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        Ok(_) => {}
+        Err(e) => {
+            ctx.try_log(|logger| warn!(logger, "Unable to initialize hord.sqlite: {}", e));
         }
+    }
+    conn
+}
 
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS index_inscriptions_on_classic_inscription_number ON inscriptions(classic_inscription_number);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        }
+fn table_exists(conn: &Connection, table_name: &str) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        [table_name],
+        |row| row.get::<_, u32>(0),
+    )
+    .map(|count| count > 0)
+    .map_err(|e| format!("unable to query hord.sqlite: {}", e.to_string()))
+}
 
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS index_inscriptions_on_block_height ON inscriptions(block_height);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        }
+/// Returns the version of the schema of hord.sqlite, 0 for an empty database.
+/// Databases created before schema versioning was introduced are reported at version 1.
+pub fn get_ordhook_db_schema_version(conn: &Connection) -> Result<u32, String> {
+    if !table_exists(conn, "schema_version")? {
+        return match table_exists(conn, "inscriptions")? {
+            true => Ok(1),
+            false => Ok(0),
+        };
     }
-    if let Err(e) = conn.execute(
-        "CREATE TABLE IF NOT EXISTS locations (
-            ordinal_number INTEGER NOT NULL,
-            block_height INTEGER NOT NULL,
-            tx_index INTEGER NOT NULL,
-            outpoint_to_watch TEXT NOT NULL,
-            offset INTEGER NOT NULL
-        )",
+    conn.query_row(
+        "SELECT COALESCE(MAX(version), 0) FROM schema_version",
         [],
-    ) {
-        ctx.try_log(|logger| {
-            warn!(
-                logger,
-                "Unable to create table locations: {}",
-                e.to_string()
-            )
-        });
-    } else {
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS locations_indexed_on_block_height ON locations(block_height);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        }
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS locations_indexed_on_outpoint_to_watch ON locations(outpoint_to_watch);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        }
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS locations_indexed_on_ordinal_number ON locations(ordinal_number);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        }
+        |row| row.get::<_, u32>(0),
+    )
+    .map_err(|e| format!("unable to query hord.sqlite: {}", e.to_string()))
+}
+
+/// Make sure that hord.sqlite can be used by this build: newer schemas are unknown to us,
+/// pending additive migrations are applied, other migrations must be applied with
+/// `ordhook db migrate` first.
+pub fn upgrade_ordhook_db_schema(conn: &mut Connection, ctx: &Context) -> Result<(), String> {
+    let version = get_ordhook_db_schema_version(conn)?;
+    if version > ORDHOOK_DB_SCHEMA_VERSION {
+        return Err(format!(
+            "hord.sqlite schema version {} is newer than the latest version supported by this build ({}), please upgrade ordhook",
+            version, ORDHOOK_DB_SCHEMA_VERSION
+        ));
+    }
+    if version == ORDHOOK_DB_SCHEMA_VERSION {
+        return Ok(());
     }
+    if let Some(migration) = ORDHOOK_DB_MIGRATIONS
+        .iter()
+        .find(|migration| migration.version > version && !migration.additive)
+    {
+        return Err(format!(
+            "hord.sqlite schema version {} is outdated (expected {}), migration {} ({}) must be applied with `ordhook db migrate` first",
+            version, ORDHOOK_DB_SCHEMA_VERSION, migration.version, migration.description
+        ));
+    }
+    migrate_ordhook_db(conn, ctx)?;
+    Ok(())
+}
 
-    if let Err(e) = conn.execute(
-        "CREATE TABLE IF NOT EXISTS sequence_metadata (
-            block_height INTEGER NOT NULL,
-            nth_classic_pos_number INTEGER NOT NULL,
-            nth_classic_neg_number INTEGER NOT NULL,
-            nth_jubilee_number INTEGER NOT NULL
+/// Apply pending migrations. Returns the schema versions before and after the migration.
+pub fn migrate_ordhook_db(conn: &mut Connection, ctx: &Context) -> Result<(u32, u32), String> {
+    let initial_version = get_ordhook_db_schema_version(conn)?;
+    if initial_version > ORDHOOK_DB_SCHEMA_VERSION {
+        return Err(format!(
+            "hord.sqlite schema version {} is unknown (latest supported: {})",
+            initial_version, ORDHOOK_DB_SCHEMA_VERSION
+        ));
+    }
+    let is_versioned = table_exists(conn, "schema_version")?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER NOT NULL PRIMARY KEY,
+            description TEXT NOT NULL,
+            applied_at INTEGER NOT NULL
         )",
         [],
-    ) {
+    )
+    .map_err(|e| format!("unable to create table schema_version: {}", e.to_string()))?;
+
+    if !is_versioned && initial_version > 0 {
+        // Databases created before schema versioning are matching the first migration
+        let baseline = &ORDHOOK_DB_MIGRATIONS[0];
+        conn.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, 0)",
+            rusqlite::params![&baseline.version, &baseline.description],
+        )
+        .map_err(|e| format!("unable to update schema_version: {}", e.to_string()))?;
+    }
+
+    let mut current_version = initial_version;
+    for migration in ORDHOOK_DB_MIGRATIONS.iter() {
+        if migration.version <= current_version {
+            continue;
+        }
+        let tx = conn
+            .transaction()
+            .map_err(|e| format!("unable to begin transaction: {}", e.to_string()))?;
+        for statement in migration.statements.iter() {
+            tx.execute_batch(statement).map_err(|e| {
+                format!(
+                    "migration {} ({}) failed: {}",
+                    migration.version,
+                    migration.description,
+                    e.to_string()
+                )
+            })?;
+        }
+        let applied_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        tx.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, ?2, ?3)",
+            rusqlite::params![&migration.version, &migration.description, &applied_at],
+        )
+        .map_err(|e| format!("unable to update schema_version: {}", e.to_string()))?;
+        tx.commit().map_err(|e| {
+            format!(
+                "unable to commit migration {}: {}",
+                migration.version,
+                e.to_string()
+            )
+        })?;
         ctx.try_log(|logger| {
-            warn!(
+            info!(
                 logger,
-                "Unable to create table sequence_metadata: {}",
-                e.to_string()
+                "hord.sqlite migrated to schema version {} ({})",
+                migration.version,
+                migration.description
             )
         });
-    } else {
-        if let Err(e) = conn.execute(
-            "CREATE INDEX IF NOT EXISTS sequence_metadata_indexed_on_block_height ON sequence_metadata(block_height);",
-            [],
-        ) {
-            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        }
+        current_version = migration.version;
     }
-
-    conn
+    Ok((initial_version, current_version))
 }
 
 pub fn create_or_open_readwrite_db(db_path: &PathBuf, ctx: &Context) -> Connection {
@@ -248,7 +374,11 @@ fn get_default_ordhook_db_file_path_rocks_db(base_dir: &PathBuf) -> PathBuf {
     destination_path
 }
 
-fn rocks_db_default_options(ulimit: usize, memory_available: usize) -> rocksdb::Options {
+/// Block cache shared by every column family and handle of hord.rocksdb opened by the process,
+/// sized by the configuration of the first one.
+static BLOCK_CACHE: OnceLock<Cache> = OnceLock::new();
+
+fn rocks_db_default_options(resources: &ResourcesConfig) -> rocksdb::Options {
     let mut opts = rocksdb::Options::default();
     // Per rocksdb's documentation:
     // If cache_index_and_filter_blocks is false (which is default),
@@ -259,11 +389,27 @@ fn rocks_db_default_options(ulimit: usize, memory_available: usize) -> rocksdb::
     // Setting max_open_files to -1 will get you the best possible performance.
     // Additional documentation:
     // https://betterprogramming.pub/navigating-the-minefield-of-rocksdb-configuration-options-246af1e1d3f9
-    // opts.set_write_buffer_size(64 * 1024 * 1024);
     // opts.set_blob_file_size(1 * 1024 * 1024 * 1024);
     // opts.set_target_file_size_base(64 * 1024 * 1024);
-    opts.set_max_open_files(ulimit as i32);
+    opts.set_max_open_files(resources.ulimit as i32);
     opts.create_if_missing(true);
+
+    let rocksdb = &resources.rocksdb;
+    let mut block_opts = BlockBasedOptions::default();
+    block_opts.set_block_cache(
+        BLOCK_CACHE.get_or_init(|| Cache::new_lru_cache(rocksdb.block_cache_size_mb * 1024 * 1024)),
+    );
+    if rocksdb.bloom_filter_bits_per_key > 0 {
+        block_opts.set_bloom_filter(rocksdb.bloom_filter_bits_per_key as f64, false);
+    }
+    opts.set_block_based_table_factory(&block_opts);
+    opts.set_write_buffer_size(rocksdb.write_buffer_size_mb * 1024 * 1024);
+    opts.set_max_write_buffer_number(rocksdb.max_write_buffer_number);
+    opts.set_compaction_style(match rocksdb.compaction_style {
+        CompactionStyle::Level => DBCompactionStyle::Level,
+        CompactionStyle::Universal => DBCompactionStyle::Universal,
+    });
+    opts.set_max_background_jobs(rocksdb.max_background_jobs);
     // opts.set_allow_mmap_reads(true);
 
     // set_arena_block_size
@@ -275,40 +421,153 @@ fn rocks_db_default_options(ulimit: usize, memory_available: usize) -> rocksdb::
     // opts.set_enable_blob_gc(true);
     // opts.set_use_fsync(false);
     // opts.set_bytes_per_sync(8388608);
-    // opts.set_compaction_style(DBCompactionStyle::Universal);
     // opts.set_compression_type(rocksdb::DBCompressionType::Lz4);
     // opts.set_blob_compression_type(rocksdb::DBCompressionType::Lz4);
     opts
 }
 
+/// Log the options hord.rocksdb is effectively running with, as persisted by RocksDB in the
+/// latest OPTIONS file of the db directory.
+pub fn log_rocks_db_options(blocks_db: &DB, ctx: &Context) {
+    let options = match read_rocks_db_options_file(blocks_db.path()) {
+        Ok(options) => options,
+        Err(e) => {
+            ctx.try_log(|logger| warn!(logger, "Unable to read hord.rocksdb options: {e}"));
+            return;
+        }
+    };
+    let block_cache_capacity_mb = blocks_db
+        .property_int_value(rocksdb::properties::BLOCK_CACHE_CAPACITY)
+        .ok()
+        .flatten()
+        .map(|capacity| (capacity / 1024 / 1024).to_string())
+        .unwrap_or("?".to_string());
+    let option = |key: &str| options.get(key).map(|v| v.as_str()).unwrap_or("?");
+    ctx.try_log(|logger| {
+        info!(
+            logger,
+            "hord.rocksdb options: max_open_files={}, block_cache_capacity={}MB, write_buffer_size={}, max_write_buffer_number={}, compaction_style={}, filter_policy={}, max_background_jobs={}, compression={}, compression_opts={}",
+            option("max_open_files"),
+            block_cache_capacity_mb,
+            option("write_buffer_size"),
+            option("max_write_buffer_number"),
+            option("compaction_style"),
+            option("filter_policy"),
+            option("max_background_jobs"),
+            option("compression"),
+            option("compression_opts"),
+        )
+    });
+}
+
+/// Read the db options and the options of the default (blocks) column family from the
+/// latest OPTIONS-<number> file RocksDB wrote in `path`.
+fn read_rocks_db_options_file(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    let entries = std::fs::read_dir(path)
+        .map_err(|e| format!("unable to list {}: {}", path.display(), e.to_string()))?;
+    let options_file = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let number = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("OPTIONS-")?
+                .parse::<u64>()
+                .ok()?;
+            Some((number, entry.path()))
+        })
+        .max_by_key(|(number, _)| *number)
+        .map(|(_, path)| path)
+        .ok_or(format!("no OPTIONS file found in {}", path.display()))?;
+    let content = std::fs::read_to_string(&options_file).map_err(|e| {
+        format!(
+            "unable to read {}: {}",
+            options_file.display(),
+            e.to_string()
+        )
+    })?;
+    Ok(parse_rocks_db_options(&content))
+}
+
+fn parse_rocks_db_options(content: &str) -> BTreeMap<String, String> {
+    let mut options = BTreeMap::new();
+    let mut in_section = false;
+    for line in content.lines().map(|line| line.trim()) {
+        if line.starts_with('[') {
+            in_section = matches!(
+                line,
+                "[DBOptions]"
+                    | "[CFOptions \"default\"]"
+                    | "[TableOptions/BlockBasedTable \"default\"]"
+            );
+            continue;
+        }
+        if !in_section || line.starts_with('#') {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            options.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    options
+}
+
+/// Options of the blocks column family (default column family), compressed as configured.
+fn rocks_db_blocks_options(resources: &ResourcesConfig) -> rocksdb::Options {
+    let mut opts = rocks_db_default_options(resources);
+    match resources.rocksdb.blocks_compression {
+        BlocksCompression::Snappy => {
+            opts.set_compression_type(DBCompressionType::Snappy);
+        }
+        BlocksCompression::Zstd {
+            level,
+            dictionary_size_kb,
+        } => {
+            let max_dict_bytes = dictionary_size_kb as i32 * 1024;
+            opts.set_compression_type(DBCompressionType::Zstd);
+            opts.set_compression_options(-14, level, 0, max_dict_bytes);
+            if max_dict_bytes > 0 {
+                // zstd recommends training dictionaries on ~100x their size
+                opts.set_zstd_max_train_bytes(max_dict_bytes * 100);
+            }
+        }
+    }
+    opts
+}
+
 pub fn open_readonly_ordhook_db_conn_rocks_db(
     base_dir: &PathBuf,
-    ulimit: usize,
-    memory_available: usize,
+    resources: &ResourcesConfig,
     _ctx: &Context,
 ) -> Result<DB, String> {
     let path = get_default_ordhook_db_file_path_rocks_db(&base_dir);
-    let mut opts = rocks_db_default_options(ulimit, memory_available);
+    let mut opts = rocks_db_default_options(resources);
     opts.set_disable_auto_compactions(true);
     opts.set_max_background_jobs(0);
-    let db = DB::open_for_read_only(&opts, path, false)
+    let column_families = list_rocks_db_column_families(&opts, &path);
+    let db = DB::open_cf_for_read_only(&opts, path, column_families, false)
         .map_err(|e| format!("unable to read hord.rocksdb: {}", e.to_string()))?;
     Ok(db)
 }
 
+// Column families have to be opened explicitly: the inscriptions index column families
+// (see store::rocksdb) are only present when the all-RocksDB layout is enabled.
+fn list_rocks_db_column_families(opts: &rocksdb::Options, path: &PathBuf) -> Vec<String> {
+    DB::list_cf(opts, path).unwrap_or(vec![rocksdb::DEFAULT_COLUMN_FAMILY_NAME.to_string()])
+}
+
 pub fn open_ordhook_db_conn_rocks_db_loop(
     readwrite: bool,
     base_dir: &PathBuf,
-    ulimit: usize,
-    memory_available: usize,
+    resources: &ResourcesConfig,
     ctx: &Context,
 ) -> DB {
     let mut retries = 0;
     let blocks_db = loop {
         let res = if readwrite {
-            open_readwrite_ordhook_db_conn_rocks_db(&base_dir, ulimit, memory_available, &ctx)
+            open_readwrite_ordhook_db_conn_rocks_db(&base_dir, resources, &ctx)
         } else {
-            open_readonly_ordhook_db_conn_rocks_db(&base_dir, ulimit, memory_available, &ctx)
+            open_readonly_ordhook_db_conn_rocks_db(&base_dir, resources, &ctx)
         };
         match res {
             Ok(db) => break db,
@@ -331,25 +590,32 @@ pub fn open_ordhook_db_conn_rocks_db_loop(
 
 pub fn open_readwrite_ordhook_dbs(
     base_dir: &PathBuf,
-    ulimit: usize,
-    memory_available: usize,
+    resources: &ResourcesConfig,
     ctx: &Context,
 ) -> Result<(DB, Connection), String> {
-    let blocks_db =
-        open_ordhook_db_conn_rocks_db_loop(true, &base_dir, ulimit, memory_available, &ctx);
+    let blocks_db = open_ordhook_db_conn_rocks_db_loop(true, &base_dir, resources, &ctx);
     let inscriptions_db = open_readwrite_ordhook_db_conn(&base_dir, &ctx)?;
     Ok((blocks_db, inscriptions_db))
 }
 
-fn open_readwrite_ordhook_db_conn_rocks_db(
+pub fn open_readwrite_ordhook_db_conn_rocks_db(
     base_dir: &PathBuf,
-    ulimit: usize,
-    memory_available: usize,
+    resources: &ResourcesConfig,
     _ctx: &Context,
 ) -> Result<DB, String> {
     let path = get_default_ordhook_db_file_path_rocks_db(&base_dir);
-    let opts = rocks_db_default_options(ulimit, memory_available);
-    let db = DB::open(&opts, path)
+    let opts = rocks_db_default_options(resources);
+    // Compression only applies to the files written: read-only connections use the defaults
+    let column_families = list_rocks_db_column_families(&opts, &path)
+        .into_iter()
+        .map(|name| {
+            let cf_opts = match name.as_str() {
+                rocksdb::DEFAULT_COLUMN_FAMILY_NAME => rocks_db_blocks_options(resources),
+                _ => rocks_db_default_options(resources),
+            };
+            ColumnFamilyDescriptor::new(name, cf_opts)
+        });
+    let db = DB::open_cf_descriptors(&opts, path, column_families)
         .map_err(|e| format!("unable to read-write hord.rocksdb: {}", e.to_string()))?;
     Ok(db)
 }
@@ -390,6 +656,96 @@ pub fn insert_entry_in_blocks(
     }
 }
 
+/// Outcome of `migrate_blocks_format`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BlocksMigrationReport {
+    pub blocks_scanned: u64,
+    pub blocks_rewritten: u64,
+    /// Legacy blocks including colliding txid prefixes, to be downloaded again
+    /// (`ordhook db repair blocks`).
+    pub blocks_with_txid_prefix_collisions: Vec<u64>,
+    /// Size of the blocks column family files, before and after the migration, in bytes.
+    pub size_before: u64,
+    pub size_after: u64,
+}
+
+impl BlocksMigrationReport {
+    pub fn space_savings(&self) -> i64 {
+        self.size_before as i64 - self.size_after as i64
+    }
+}
+
+const BLOCKS_MIGRATION_BATCH_SIZE: usize = 10_000;
+
+fn get_blocks_column_family_size(blocks_db: &DB) -> u64 {
+    blocks_db
+        .property_int_value("rocksdb.total-sst-files-size")
+        .ok()
+        .flatten()
+        .unwrap_or(0)
+}
+
+/// Rewrite the blocks stored with the legacy (unversioned) layout in the current format, then
+/// compact the blocks column family so that every file is written with the configured
+/// compression.
+pub fn migrate_blocks_format(
+    blocks_db_rw: &DB,
+    ctx: &Context,
+) -> Result<BlocksMigrationReport, String> {
+    let mut report = BlocksMigrationReport {
+        size_before: get_blocks_column_family_size(blocks_db_rw),
+        ..Default::default()
+    };
+    let mut batch = WriteBatch::default();
+    for entry in blocks_db_rw.iterator(IteratorMode::Start) {
+        let (key, block_bytes) =
+            entry.map_err(|e| format!("unable to read hord.rocksdb: {}", e.to_string()))?;
+        // Blocks are keyed by their height, metadata keys are longer
+        if key.len() != 4 {
+            continue;
+        }
+        let block_height = u32::from_be_bytes([key[0], key[1], key[2], key[3]]) as u64;
+        report.blocks_scanned += 1;
+        let block = BlockBytesCursor::new(&block_bytes);
+        if block.version != BLOCK_VERSION_LEGACY {
+            continue;
+        }
+        // Transactions sharing a txid prefix can't be told apart without their full txid
+        if !block.find_txid_prefix_collisions().is_empty() {
+            report.blocks_with_txid_prefix_collisions.push(block_height);
+            continue;
+        }
+        let mut migrated_block_bytes = Vec::with_capacity(BLOCK_HEADER_LEN + block_bytes.len());
+        migrated_block_bytes.extend_from_slice(&BLOCK_VERSION_MARKER);
+        migrated_block_bytes.extend_from_slice(&[BLOCK_VERSION_1, 0]);
+        migrated_block_bytes.extend_from_slice(&block_bytes);
+        batch.put(&key, migrated_block_bytes);
+        report.blocks_rewritten += 1;
+        if batch.len() >= BLOCKS_MIGRATION_BATCH_SIZE {
+            blocks_db_rw
+                .write(std::mem::take(&mut batch))
+                .map_err(|e| format!("unable to write hord.rocksdb: {}", e.to_string()))?;
+            ctx.try_log(|logger| {
+                info!(
+                    logger,
+                    "{} blocks rewritten (block #{block_height})", report.blocks_rewritten
+                )
+            });
+        }
+    }
+    blocks_db_rw
+        .write(batch)
+        .map_err(|e| format!("unable to write hord.rocksdb: {}", e.to_string()))?;
+
+    ctx.try_log(|logger| info!(logger, "Compacting blocks"));
+    blocks_db_rw
+        .flush()
+        .map_err(|e| format!("unable to flush hord.rocksdb: {}", e.to_string()))?;
+    blocks_db_rw.compact_range(None::<&[u8]>, None::<&[u8]>);
+    report.size_after = get_blocks_column_family_size(blocks_db_rw);
+    Ok(report)
+}
+
 pub fn find_last_block_inserted(blocks_db: &DB) -> u32 {
     match blocks_db.get(b"metadata::last_insert") {
         Ok(Some(bytes)) => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
@@ -397,6 +753,161 @@ pub fn find_last_block_inserted(blocks_db: &DB) -> u32 {
     }
 }
 
+/// Write-ahead marker of a block being committed (or reverted) across hord.rocksdb and hord.sqlite.
+/// Persisted before touching any of the stores, and cleared once both of them are up to date.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PendingBlockCommit {
+    pub block_height: u64,
+    pub block_hash: String,
+    pub kind: PendingBlockCommitKind,
+}
+
+/// Change interrupted when a `PendingBlockCommit` is found on startup.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PendingBlockCommitKind {
+    ApplyBlock,
+    UndoBlock,
+}
+
+const PENDING_BLOCK_COMMIT_KEY: &[u8] = b"metadata::pending_commit";
+
+pub fn write_pending_block_commit(
+    pending_commit: &PendingBlockCommit,
+    blocks_db_rw: &DB,
+    ctx: &Context,
+) {
+    let value =
+        serde_json::to_vec(pending_commit).expect("unable to serialize pending commit marker");
+    let mut write_options = rocksdb::WriteOptions::default();
+    write_options.set_sync(true);
+    while let Err(e) = blocks_db_rw.put_opt(PENDING_BLOCK_COMMIT_KEY, &value, &write_options) {
+        ctx.try_log(|logger| {
+            warn!(
+                logger,
+                "unable to write pending commit marker: {}",
+                e.to_string()
+            )
+        });
+        sleep(Duration::from_secs(1));
+    }
+}
+
+pub fn find_pending_block_commit(blocks_db: &DB) -> Option<PendingBlockCommit> {
+    match blocks_db.get(PENDING_BLOCK_COMMIT_KEY) {
+        Ok(Some(bytes)) => serde_json::from_slice(&bytes).ok(),
+        _ => None,
+    }
+}
+
+pub fn clear_pending_block_commit(blocks_db_rw: &DB, ctx: &Context) {
+    let mut write_options = rocksdb::WriteOptions::default();
+    write_options.set_sync(true);
+    while let Err(e) = blocks_db_rw.delete_opt(PENDING_BLOCK_COMMIT_KEY, &write_options) {
+        ctx.try_log(|logger| {
+            warn!(
+                logger,
+                "unable to clear pending commit marker: {}",
+                e.to_string()
+            )
+        });
+        sleep(Duration::from_secs(1));
+    }
+}
+
+/// Detect a block left half-applied by a crash, and bring hord.rocksdb and the inscriptions store
+/// of `layout` back in line, depending on the change interrupted:
+/// - a block being applied is rolled forward if all of its writes landed, and rolled back
+///   otherwise (with its undo journal when available), to be re-applied by the next catch-up;
+/// - a block being reverted has its revert completed.
+///
+/// The marker is only cleared once both stores are up to date.
+pub fn recover_pending_block_commit(
+    blocks_db_rw: &DB,
+    inscriptions_db_conn_rw: &mut Connection,
+    layout: &InscriptionsIndexLayout,
+    ctx: &Context,
+) -> Result<Option<PendingBlockCommit>, String> {
+    let pending_commit = match find_pending_block_commit(blocks_db_rw) {
+        Some(pending_commit) => pending_commit,
+        None => return Ok(None),
+    };
+    let block_height = pending_commit.block_height;
+    let block_identifier = BlockIdentifier {
+        index: block_height,
+        hash: pending_commit.block_hash.clone(),
+    };
+    let inscriptions_db_tx = inscriptions_db_conn_rw
+        .transaction()
+        .map_err(|e| format!("unable to open hord.sqlite transaction: {}", e.to_string()))?;
+    let inscriptions_store =
+        match ConfiguredInscriptionStore::open(layout, blocks_db_rw, &inscriptions_db_tx) {
+            Ok(inscriptions_store) => Some(inscriptions_store),
+            // Column families not created yet: nothing indexed
+            Err(_) if *layout == InscriptionsIndexLayout::RocksDb => None,
+            Err(e) => return Err(e),
+        };
+
+    // The block hash is written (and deleted) in the same transaction as the rest of the block
+    let block_indexed = inscriptions_store
+        .as_ref()
+        .is_some_and(|inscriptions_store| {
+            inscriptions_store
+                .as_store()
+                .find_block_hash_at_block_height(block_height, ctx)
+                .as_deref()
+                == Some(pending_commit.block_hash.as_str())
+        });
+    let block_archived = matches!(
+        blocks_db_rw.get_pinned((block_height as u32).to_be_bytes()),
+        Ok(Some(_))
+    );
+    let roll_forward = pending_commit.kind == PendingBlockCommitKind::ApplyBlock
+        && block_indexed
+        && block_archived;
+    ctx.try_log(|logger| match (&pending_commit.kind, roll_forward) {
+        (_, true) => warn!(
+            logger,
+            "Block {} was committed before its marker was cleared, rolling it forward",
+            block_identifier
+        ),
+        (PendingBlockCommitKind::ApplyBlock, false) => warn!(
+            logger,
+            "Block {} was not fully committed, rolling it back", block_identifier
+        ),
+        (PendingBlockCommitKind::UndoBlock, false) => warn!(
+            logger,
+            "Block {} was not fully reverted, completing the revert", block_identifier
+        ),
+    });
+
+    if let Some(inscriptions_store) = inscriptions_store.filter(|_| block_indexed && !roll_forward)
+    {
+        inscriptions_store.begin(ctx);
+        if !undo_block_with_journal(&block_identifier, inscriptions_store.as_store(), ctx) {
+            inscriptions_store.as_store().delete_data_in_block_range(
+                block_height,
+                block_height,
+                ctx,
+            );
+        }
+        inscriptions_store.commit(block_height, ctx)?;
+    }
+    inscriptions_db_tx.commit().map_err(|e| {
+        format!(
+            "unable to commit hord.sqlite transaction: {}",
+            e.to_string()
+        )
+    })?;
+
+    if roll_forward {
+        record_block_in_sync_checkpoint(block_height, blocks_db_rw, ctx);
+    } else {
+        delete_blocks_in_block_range(block_height as u32, block_height as u32, blocks_db_rw, ctx);
+    }
+    clear_pending_block_commit(blocks_db_rw, ctx);
+    Ok(Some(pending_commit))
+}
+
 pub fn find_pinned_block_bytes_at_block_height<'a>(
     block_height: u32,
     retry: u8,
@@ -500,6 +1011,13 @@ pub fn delete_blocks_in_block_range(
     blocks_db_rw
         .put(b"metadata::last_insert", start_block_bytes)
         .expect("unable to insert metadata");
+    update_sync_checkpoint(blocks_db_rw, ctx, |checkpoint| {
+        checkpoint.rewind(start_block as u64)
+    });
+    // The transactions of the blocks rolled back can't be traversed anymore
+    if let Err(e) = delete_traversals_cache_entries_from_block_height(start_block, blocks_db_rw) {
+        ctx.try_log(|logger| warn!(logger, "Unable to clean traversals cache: {e}"));
+    }
 }
 
 pub fn insert_entry_in_inscriptions(
@@ -523,16 +1041,11 @@ pub fn insert_inscription_in_locations(
     inscriptions_db_conn_rw: &Connection,
     ctx: &Context,
 ) {
-    let (tx, output_index, offset) =
-        parse_satpoint_to_watch(&inscription_data.satpoint_post_inscription);
-    let outpoint_to_watch = format_outpoint_to_watch(&tx, output_index);
-    while let Err(e) = inscriptions_db_conn_rw.execute(
-        "INSERT INTO locations (ordinal_number, outpoint_to_watch, offset, block_height, tx_index) VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![&inscription_data.ordinal_number, &outpoint_to_watch, offset, &block_identifier.index, &inscription_data.tx_index],
-    ) {
-        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        std::thread::sleep(std::time::Duration::from_secs(1));
-    }
+    SqliteInscriptionStore::new(inscriptions_db_conn_rw).insert_inscription_in_locations(
+        inscription_data,
+        block_identifier,
+        ctx,
+    )
 }
 
 pub fn update_inscriptions_with_block(
@@ -540,20 +1053,7 @@ pub fn update_inscriptions_with_block(
     inscriptions_db_conn_rw: &Connection,
     ctx: &Context,
 ) {
-    for inscription_data in get_inscriptions_revealed_in_block(&block).iter() {
-        insert_entry_in_inscriptions(
-            inscription_data,
-            &block.block_identifier,
-            inscriptions_db_conn_rw,
-            &ctx,
-        );
-        insert_inscription_in_locations(
-            &inscription_data,
-            &block.block_identifier,
-            &inscriptions_db_conn_rw,
-            ctx,
-        );
-    }
+    SqliteInscriptionStore::new(inscriptions_db_conn_rw).update_inscriptions_with_block(block, ctx)
 }
 
 pub fn update_locations_with_block(
@@ -561,14 +1061,7 @@ pub fn update_locations_with_block(
     inscriptions_db_conn_rw: &Connection,
     ctx: &Context,
 ) {
-    for transfer_data in get_inscriptions_transferred_in_block(&block).iter() {
-        insert_transfer_in_locations(
-            &transfer_data,
-            &block.block_identifier,
-            &inscriptions_db_conn_rw,
-            ctx,
-        );
-    }
+    SqliteInscriptionStore::new(inscriptions_db_conn_rw).update_locations_with_block(block, ctx)
 }
 
 pub fn update_sequence_metadata_with_block(
@@ -576,38 +1069,8 @@ pub fn update_sequence_metadata_with_block(
     inscriptions_db_conn_rw: &Connection,
     ctx: &Context,
 ) {
-    let mut nth_classic_pos_number = find_nth_classic_pos_number_at_block_height(
-        &block.block_identifier.index,
-        inscriptions_db_conn_rw,
-        ctx,
-    )
-    .unwrap_or(0);
-    let mut nth_classic_neg_number = find_nth_classic_neg_number_at_block_height(
-        &block.block_identifier.index,
-        inscriptions_db_conn_rw,
-        ctx,
-    )
-    .unwrap_or(0);
-    let mut nth_jubilee_number = find_nth_jubilee_number_at_block_height(
-        &block.block_identifier.index,
-        inscriptions_db_conn_rw,
-        ctx,
-    )
-    .unwrap_or(0);
-    for inscription_data in get_inscriptions_revealed_in_block(&block).iter() {
-        nth_classic_pos_number =
-            nth_classic_pos_number.max(inscription_data.inscription_number.classic);
-        nth_classic_neg_number =
-            nth_classic_neg_number.min(inscription_data.inscription_number.classic);
-        nth_jubilee_number = nth_jubilee_number.max(inscription_data.inscription_number.jubilee);
-    }
-    while let Err(e) = inscriptions_db_conn_rw.execute(
-        "INSERT INTO sequence_metadata (block_height, nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number) VALUES (?1, ?2, ?3, ?4)",
-        rusqlite::params![&block.block_identifier.index, nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number],
-    ) {
-        ctx.try_log(|logger| warn!(logger, "unable to update sequence_metadata: {}", e.to_string()));
-        std::thread::sleep(std::time::Duration::from_secs(1));
-    }
+    SqliteInscriptionStore::new(inscriptions_db_conn_rw)
+        .update_sequence_metadata_with_block(block, ctx)
 }
 
 pub fn insert_new_inscriptions_from_block_in_locations(
@@ -615,12 +1078,12 @@ pub fn insert_new_inscriptions_from_block_in_locations(
     inscriptions_db_conn_rw: &Connection,
     ctx: &Context,
 ) {
+    let inscriptions_store = SqliteInscriptionStore::new(inscriptions_db_conn_rw);
     for inscription_data in get_inscriptions_revealed_in_block(&block).iter() {
-        insert_inscription_in_locations(
+        inscriptions_store.insert_inscription_in_locations(
             inscription_data,
             &block.block_identifier,
-            inscriptions_db_conn_rw,
-            &ctx,
+            ctx,
         );
     }
 }
@@ -631,15 +1094,11 @@ pub fn insert_transfer_in_locations_tx(
     inscriptions_db_conn_rw: &Transaction,
     ctx: &Context,
 ) {
-    let (tx, output_index, offset) = parse_satpoint_to_watch(&transfer_data.satpoint_post_transfer);
-    let outpoint_to_watch = format_outpoint_to_watch(&tx, output_index);
-    while let Err(e) = inscriptions_db_conn_rw.execute(
-        "INSERT INTO locations (ordinal_number, outpoint_to_watch, offset, block_height, tx_index) VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![&transfer_data.ordinal_number, &outpoint_to_watch, offset, &block_identifier.index, &transfer_data.tx_index],
-    ) {
-        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        std::thread::sleep(std::time::Duration::from_secs(1));
-    }
+    SqliteInscriptionStore::new(inscriptions_db_conn_rw).insert_transfer_in_locations(
+        transfer_data,
+        block_identifier,
+        ctx,
+    )
 }
 
 pub fn insert_transfer_in_locations(
@@ -648,15 +1107,11 @@ pub fn insert_transfer_in_locations(
     inscriptions_db_conn_rw: &Connection,
     ctx: &Context,
 ) {
-    let (tx, output_index, offset) = parse_satpoint_to_watch(&transfer_data.satpoint_post_transfer);
-    let outpoint_to_watch = format_outpoint_to_watch(&tx, output_index);
-    while let Err(e) = inscriptions_db_conn_rw.execute(
-        "INSERT INTO locations (ordinal_number, outpoint_to_watch, offset, block_height, tx_index) VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![&transfer_data.ordinal_number, &outpoint_to_watch, offset, &block_identifier.index, &transfer_data.tx_index],
-    ) {
-        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
-        std::thread::sleep(std::time::Duration::from_secs(1));
-    }
+    SqliteInscriptionStore::new(inscriptions_db_conn_rw).insert_transfer_in_locations(
+        transfer_data,
+        block_identifier,
+        ctx,
+    )
 }
 
 pub fn perform_query_exists(
@@ -788,6 +1243,33 @@ pub fn find_latest_inscription_block_height(
     Ok(entry)
 }
 
+pub fn insert_entry_in_blocks_hashes(
+    block_identifier: &BlockIdentifier,
+    inscriptions_db_conn_rw: &Connection,
+    ctx: &Context,
+) {
+    while let Err(e) = inscriptions_db_conn_rw.execute(
+        "INSERT OR REPLACE INTO blocks (block_height, block_hash) VALUES (?1, ?2)",
+        rusqlite::params![&block_identifier.index, &block_identifier.hash],
+    ) {
+        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+}
+
+pub fn find_block_hash_at_block_height(
+    block_height: u64,
+    db_conn: &Connection,
+    ctx: &Context,
+) -> Option<String> {
+    let args: &[&dyn ToSql] = &[&block_height.to_sql().unwrap()];
+    let query = "SELECT block_hash FROM blocks WHERE block_height = ?";
+    perform_query_one(query, args, db_conn, ctx, |row| {
+        let block_hash: String = row.get(0).unwrap();
+        block_hash
+    })
+}
+
 pub fn find_initial_inscription_transfer_data(
     ordinal_number: &u64,
     db_conn: &Connection,
@@ -1218,6 +1700,27 @@ pub fn delete_inscriptions_in_block_range(
         ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
         std::thread::sleep(std::time::Duration::from_secs(1));
     }
+    // Databases not migrated yet don't track block hashes
+    if let Ok(false) = table_exists(inscriptions_db_conn_rw, "blocks") {
+        return;
+    }
+    while let Err(e) = inscriptions_db_conn_rw.execute(
+        "DELETE FROM blocks WHERE block_height >= ?1 AND block_height <= ?2",
+        rusqlite::params![&start_block, &end_block],
+    ) {
+        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
+    if let Ok(false) = table_exists(inscriptions_db_conn_rw, "undo_journal") {
+        return;
+    }
+    while let Err(e) = inscriptions_db_conn_rw.execute(
+        "DELETE FROM undo_journal WHERE block_height >= ?1 AND block_height <= ?2",
+        rusqlite::params![&start_block, &end_block],
+    ) {
+        ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+        std::thread::sleep(std::time::Duration::from_secs(1));
+    }
 }
 
 pub fn remove_entry_from_inscriptions(
@@ -1301,6 +1804,10 @@ pub fn delete_data_in_ordhook_db(
         inscriptions_db_conn_rw,
         &ctx,
     );
+    // Inscriptions index column families, only present with the all-RocksDB layout
+    if let Ok(inscriptions_store) = RocksDbInscriptionStore::new(blocks_db_rw) {
+        inscriptions_store.delete_data_in_block_range(start_block, end_block, &ctx);
+    }
     Ok(())
 }
 
@@ -1402,10 +1909,21 @@ pub fn parse_outpoint_to_watch(outpoint_to_watch: &str) -> (TransactionIdentifie
     (tx, output_index)
 }
 
+/// Compacted block, as stored in hord.rocksdb:
+/// - header (versioned blocks only): `0xffff` marker, version (1 byte), flags (1 byte)
+/// - number of transactions (2 bytes), then the number of inputs and outputs of each transaction (2 + 2 bytes)
+/// - for each transaction: txid, then its inputs (txin prefix, block height, vout, value) and outputs (value)
+///
+/// Transactions are identified by the 8 first bytes of their txid. When two transactions of a
+/// block share a prefix, the block is encoded with full txids (`BLOCK_FLAG_FULL_TXIDS`).
+/// Legacy blocks, written before the format was versioned, have no header: their transactions
+/// count can't be 0xffff, a block can't include that many transactions.
 #[derive(Debug)]
 pub struct BlockBytesCursor<'a> {
     pub bytes: &'a [u8],
     pub tx_len: u16,
+    pub version: u8,
+    pub flags: u8,
 }
 
 #[derive(Debug, Clone)]
@@ -1457,22 +1975,82 @@ impl TransactionInputBytesCursor {
 }
 
 const TXID_LEN: usize = 8;
+const FULL_TXID_LEN: usize = 32;
 const SATS_LEN: usize = 8;
 const INPUT_SIZE: usize = TXID_LEN + 4 + 2 + SATS_LEN;
 const OUTPUT_SIZE: usize = 8;
 
+const BLOCK_VERSION_MARKER: [u8; 2] = [0xff, 0xff];
+const BLOCK_HEADER_LEN: usize = 4;
+/// Blocks written before the format was versioned.
+pub const BLOCK_VERSION_LEGACY: u8 = 0;
+pub const BLOCK_VERSION_1: u8 = 1;
+/// Transactions are identified by their full txid, some of their 8 bytes prefixes colliding.
+pub const BLOCK_FLAG_FULL_TXIDS: u8 = 0b0000_0001;
+
+/// Does `txid` (8 bytes prefix or full txid) match `searched_txid` (8 bytes prefix or full txid).
+fn txid_matches(txid: &[u8], searched_txid: &[u8]) -> bool {
+    let len = txid.len().min(searched_txid.len());
+    txid[..len].eq(&searched_txid[..len])
+}
+
+/// Flags of the block to encode, from the txids of its transactions.
+fn get_block_flags(txids: &[Vec<u8>]) -> u8 {
+    let mut prefixes = HashSet::new();
+    let collision = txids
+        .iter()
+        .any(|txid| !prefixes.insert(&txid[0..TXID_LEN]));
+    if collision {
+        BLOCK_FLAG_FULL_TXIDS
+    } else {
+        0
+    }
+}
+
 impl<'a> BlockBytesCursor<'a> {
     pub fn new(bytes: &[u8]) -> BlockBytesCursor {
-        let tx_len = u16::from_be_bytes([bytes[0], bytes[1]]);
-        BlockBytesCursor { bytes, tx_len }
+        let (version, flags) = match bytes[0..2] == BLOCK_VERSION_MARKER {
+            true => (bytes[2], bytes[3]),
+            false => (BLOCK_VERSION_LEGACY, 0),
+        };
+        let mut block = BlockBytesCursor {
+            bytes,
+            tx_len: 0,
+            version,
+            flags,
+        };
+        let pos = block.get_header_len();
+        block.tx_len = u16::from_be_bytes([bytes[pos], bytes[pos + 1]]);
+        block
+    }
+
+    fn get_header_len(&self) -> usize {
+        match self.version {
+            BLOCK_VERSION_LEGACY => 0,
+            _ => BLOCK_HEADER_LEN,
+        }
+    }
+
+    /// The block is including transactions sharing the same 8 bytes txid prefix, and is
+    /// identifying its transactions with their full txid.
+    pub fn has_full_txids(&self) -> bool {
+        self.flags & BLOCK_FLAG_FULL_TXIDS != 0
+    }
+
+    fn get_txid_len(&self) -> usize {
+        match self.has_full_txids() {
+            true => FULL_TXID_LEN,
+            false => TXID_LEN,
+        }
     }
 
     pub fn get_coinbase_data_pos(&self) -> usize {
-        (2 + self.tx_len * 2 * 2) as usize
+        self.get_header_len() + 2 + self.tx_len as usize * 2 * 2
     }
 
     pub fn get_coinbase_outputs_len(&self) -> usize {
-        u16::from_be_bytes([self.bytes[4], self.bytes[5]]) as usize
+        let pos = self.get_header_len() + 4;
+        u16::from_be_bytes([self.bytes[pos], self.bytes[pos + 1]]) as usize
     }
 
     pub fn get_u64_at_pos(&self, pos: usize) -> u64 {
@@ -1498,14 +2076,15 @@ impl<'a> BlockBytesCursor<'a> {
     }
 
     pub fn get_transaction_format(&self, index: u16) -> (u16, u16, usize) {
-        let inputs_len_pos = (2 + index * 2 * 2) as usize;
+        let inputs_len_pos = self.get_header_len() + 2 + index as usize * 2 * 2;
         let inputs =
             u16::from_be_bytes([self.bytes[inputs_len_pos], self.bytes[inputs_len_pos + 1]]);
         let outputs = u16::from_be_bytes([
             self.bytes[inputs_len_pos + 2],
             self.bytes[inputs_len_pos + 3],
         ]);
-        let size = TXID_LEN + (inputs as usize * INPUT_SIZE) + (outputs as usize * OUTPUT_SIZE);
+        let size =
+            self.get_txid_len() + (inputs as usize * INPUT_SIZE) + (outputs as usize * OUTPUT_SIZE);
         (inputs, outputs, size)
     }
 
@@ -1548,39 +2127,73 @@ impl<'a> BlockBytesCursor<'a> {
         }
     }
 
+    /// First transaction of the block matching `searched_txid`, an 8 bytes prefix or a full txid.
     pub fn find_and_serialize_transaction_with_txid(
         &self,
         searched_txid: &[u8],
     ) -> Option<TransactionBytesCursor> {
-        // println!("{:?}", hex::encode(searched_txid));
-        let mut entry = None;
         let mut cursor = Cursor::new(self.bytes);
         let mut cumulated_offset = 0;
-        let mut i = 0;
-        while entry.is_none() {
+        let mut txid = vec![0u8; self.get_txid_len()];
+        for i in 0..self.tx_len {
             let pos = self.get_transactions_data_pos() + cumulated_offset;
             let (inputs_len, outputs_len, size) = self.get_transaction_format(i);
-            // println!("{inputs_len} / {outputs_len} / {size}");
             cursor.set_position(pos as u64);
-            let mut txid = [0u8; 8]; // todo 20 bytes
             let _ = cursor.read_exact(&mut txid);
-            // println!("-> {}", hex::encode(txid));
-            if searched_txid.eq(&txid) {
-                entry = Some(self.get_transaction_bytes_cursor_at_pos(
+            if txid_matches(&txid, searched_txid) {
+                return Some(self.get_transaction_bytes_cursor_at_pos(
                     &mut cursor,
-                    txid,
+                    txid[0..TXID_LEN].try_into().unwrap(),
                     inputs_len,
                     outputs_len,
                 ));
-            } else {
-                cumulated_offset += size;
-                i += 1;
-                if i >= self.tx_len {
-                    break;
-                }
             }
+            cumulated_offset += size;
         }
-        entry
+        None
+    }
+
+    /// Transactions of the block matching `searched_txid` (8 bytes prefix or full txid), with
+    /// their index. Several transactions can match a prefix in blocks with full txids.
+    pub fn find_and_serialize_transactions_with_txid(
+        &self,
+        searched_txid: &[u8],
+    ) -> Vec<(u16, TransactionBytesCursor)> {
+        let mut transactions = vec![];
+        let mut cursor = Cursor::new(self.bytes);
+        let mut cumulated_offset = 0;
+        let mut txid = vec![0u8; self.get_txid_len()];
+        for i in 0..self.tx_len {
+            let pos = self.get_transactions_data_pos() + cumulated_offset;
+            let (inputs_len, outputs_len, size) = self.get_transaction_format(i);
+            cursor.set_position(pos as u64);
+            let _ = cursor.read_exact(&mut txid);
+            if txid_matches(&txid, searched_txid) {
+                transactions.push((
+                    i,
+                    self.get_transaction_bytes_cursor_at_pos(
+                        &mut cursor,
+                        txid[0..TXID_LEN].try_into().unwrap(),
+                        inputs_len,
+                        outputs_len,
+                    ),
+                ));
+            }
+            cumulated_offset += size;
+        }
+        transactions
+    }
+
+    /// 8 bytes prefixes shared by several transactions of the block.
+    pub fn find_txid_prefix_collisions(&self) -> Vec<[u8; 8]> {
+        let mut prefixes = HashSet::new();
+        let mut collisions = vec![];
+        for tx in self.iter_tx() {
+            if !prefixes.insert(tx.txid) && !collisions.contains(&tx.txid) {
+                collisions.push(tx.txid);
+            }
+        }
+        collisions
     }
 
     pub fn iter_tx(&self) -> TransactionBytesCursorIterator {
@@ -1588,7 +2201,19 @@ impl<'a> BlockBytesCursor<'a> {
     }
 
     pub fn from_full_block<'b>(block: &BitcoinBlockFullBreakdown) -> std::io::Result<Vec<u8>> {
+        let txids = block
+            .tx
+            .iter()
+            .map(|tx| hex::decode(tx.txid.to_string()).unwrap())
+            .collect::<Vec<_>>();
+        let flags = get_block_flags(&txids);
+        let txid_len = match flags & BLOCK_FLAG_FULL_TXIDS {
+            0 => TXID_LEN,
+            _ => FULL_TXID_LEN,
+        };
         let mut buffer = vec![];
+        buffer.write_all(&BLOCK_VERSION_MARKER)?;
+        buffer.write_all(&[BLOCK_VERSION_1, flags])?;
         // Number of transactions in the block (not including coinbase)
         let tx_len = block.tx.len() as u16;
         buffer.write(&tx_len.to_be_bytes())?;
@@ -1614,15 +2239,9 @@ impl<'a> BlockBytesCursor<'a> {
             buffer.write(&outputs_len.to_be_bytes())?;
         }
         // For each transaction:
-        for tx in block.tx.iter() {
-            // txid - 8 first bytes
-            let txid = {
-                let txid = hex::decode(tx.txid.to_string()).unwrap();
-                [
-                    txid[0], txid[1], txid[2], txid[3], txid[4], txid[5], txid[6], txid[7],
-                ]
-            };
-            buffer.write_all(&txid)?;
+        for (tx, txid) in block.tx.iter().zip(txids.iter()) {
+            // txid - 8 first bytes, or full txid
+            buffer.write_all(&txid[0..txid_len])?;
 
             let inputs_len = if tx.vin.len() > u16_max {
                 0
@@ -1670,7 +2289,19 @@ impl<'a> BlockBytesCursor<'a> {
     }
 
     pub fn from_standardized_block<'b>(block: &BitcoinBlockData) -> std::io::Result<Vec<u8>> {
+        let txids = block
+            .transactions
+            .iter()
+            .map(|tx| tx.transaction_identifier.get_hash_bytes())
+            .collect::<Vec<_>>();
+        let flags = get_block_flags(&txids);
+        let txid_len = match flags & BLOCK_FLAG_FULL_TXIDS {
+            0 => TXID_LEN,
+            _ => FULL_TXID_LEN,
+        };
         let mut buffer = vec![];
+        buffer.write_all(&BLOCK_VERSION_MARKER)?;
+        buffer.write_all(&[BLOCK_VERSION_1, flags])?;
         // Number of transactions in the block (not including coinbase)
         let tx_len = block.transactions.len() as u16;
         buffer.write(&tx_len.to_be_bytes())?;
@@ -1688,10 +2319,9 @@ impl<'a> BlockBytesCursor<'a> {
             buffer.write(&outputs_len.to_be_bytes())?;
         }
         // For each transaction:
-        for (i, tx) in block.transactions.iter().enumerate() {
-            // txid - 8 first bytes
-            let txid = tx.transaction_identifier.get_8_hash_bytes();
-            buffer.write_all(&txid)?;
+        for (i, (tx, txid)) in block.transactions.iter().zip(txids.iter()).enumerate() {
+            // txid - 8 first bytes, or full txid
+            buffer.write_all(&txid[0..txid_len])?;
             // For each non coinbase transaction input:
             if i > 0 {
                 for input in tx.metadata.inputs.iter() {
@@ -1749,13 +2379,13 @@ impl<'a> Iterator for TransactionBytesCursorIterator<'a> {
         // println!("{inputs_len} / {outputs_len} / {size}");
         let mut cursor = Cursor::new(self.block_bytes_cursor.bytes);
         cursor.set_position(pos as u64);
-        let mut txid = [0u8; 8];
+        let mut txid = vec![0u8; self.block_bytes_cursor.get_txid_len()];
         let _ = cursor.read_exact(&mut txid);
         self.cumulated_offset += size;
         self.tx_index += 1;
         Some(self.block_bytes_cursor.get_transaction_bytes_cursor_at_pos(
             &mut cursor,
-            txid,
+            txid[0..TXID_LEN].try_into().unwrap(),
             inputs_len,
             outputs_len,
         ))
@@ -1765,6 +2395,10 @@ impl<'a> Iterator for TransactionBytesCursorIterator<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::db::journal::{BlockEntries, BlockUndoJournal};
+    use crate::db::store::SequenceMetadata;
+    use crate::testing::TempDir;
     use chainhook_sdk::{
         indexer::bitcoin::{parse_downloaded_block, standardize_bitcoin_block},
         types::BitcoinNetwork,
@@ -1860,4 +2494,419 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_block_cursor_txid_prefix_collision() {
+        let ctx = Context::empty();
+        let block = include_str!("./fixtures/blocks_json/279671.json");
+        let mut json: serde_json::Value = serde_json::from_str(block).unwrap();
+        // Give the third transaction a txid sharing the 8 bytes prefix of the second one
+        let txs = json["result"]["tx"].as_array_mut().unwrap();
+        let prefix = txs[1]["txid"].as_str().unwrap()[0..16].to_string();
+        let suffix = txs[2]["txid"].as_str().unwrap()[16..].to_string();
+        txs[2]["txid"] = serde_json::Value::String(format!("{prefix}{suffix}"));
+        let decoded_block = parse_downloaded_block(serde_json::to_vec(&json).unwrap())
+            .expect("unable to decode block");
+        let standardized_block =
+            standardize_bitcoin_block(decoded_block.clone(), &BitcoinNetwork::Mainnet, &ctx)
+                .expect("unable to standardize block");
+
+        let bytes = BlockBytesCursor::from_full_block(&decoded_block).expect("unable to serialize");
+        let bytes_via_standardized = BlockBytesCursor::from_standardized_block(&standardized_block)
+            .expect("unable to serialize");
+        assert_eq!(bytes, bytes_via_standardized);
+
+        let block_bytes_cursor = BlockBytesCursor::new(&bytes);
+        assert!(block_bytes_cursor.has_full_txids());
+        assert_eq!(decoded_block.tx.len(), block_bytes_cursor.tx_len as usize);
+        assert_eq!(decoded_block.tx.len(), block_bytes_cursor.iter_tx().count());
+        let prefix: [u8; 8] = hex::decode(&prefix).unwrap().try_into().unwrap();
+        assert_eq!(
+            block_bytes_cursor.find_txid_prefix_collisions(),
+            vec![prefix]
+        );
+
+        // Prefix lookups are ambiguous, full txid lookups are not
+        let candidates = block_bytes_cursor.find_and_serialize_transactions_with_txid(&prefix);
+        assert_eq!(
+            candidates.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        for (index, tx) in standardized_block
+            .transactions
+            .iter()
+            .enumerate()
+            .skip(1)
+            .take(2)
+        {
+            let txid = tx.transaction_identifier.get_hash_bytes();
+            let found = block_bytes_cursor.find_and_serialize_transactions_with_txid(&txid);
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].0 as usize, index);
+            assert_eq!(
+                found[0].1.outputs,
+                tx.metadata
+                    .outputs
+                    .iter()
+                    .map(|o| o.value)
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_cursor_legacy_format() {
+        let block = include_str!("./fixtures/blocks_json/279671.json");
+        let decoded_block =
+            parse_downloaded_block(block.as_bytes().to_vec()).expect("unable to decode block");
+        let bytes = BlockBytesCursor::from_full_block(&decoded_block).expect("unable to serialize");
+        let block_bytes_cursor = BlockBytesCursor::new(&bytes);
+        assert_eq!(block_bytes_cursor.version, BLOCK_VERSION_1);
+        assert!(!block_bytes_cursor.has_full_txids());
+        assert!(block_bytes_cursor.find_txid_prefix_collisions().is_empty());
+
+        // Blocks written before the format was versioned have no header
+        let legacy_block_bytes_cursor = BlockBytesCursor::new(&bytes[BLOCK_HEADER_LEN..]);
+        assert_eq!(legacy_block_bytes_cursor.version, BLOCK_VERSION_LEGACY);
+        assert_eq!(
+            legacy_block_bytes_cursor.get_coinbase_txid(),
+            block_bytes_cursor.get_coinbase_txid()
+        );
+        assert_eq!(
+            legacy_block_bytes_cursor.get_coinbase_outputs_len(),
+            block_bytes_cursor.get_coinbase_outputs_len()
+        );
+        for (legacy_tx, tx) in legacy_block_bytes_cursor
+            .iter_tx()
+            .zip(block_bytes_cursor.iter_tx())
+        {
+            assert_eq!(legacy_tx.txid, tx.txid);
+            assert_eq!(legacy_tx.outputs, tx.outputs);
+            assert_eq!(legacy_tx.inputs.len(), tx.inputs.len());
+        }
+        let tx = block_bytes_cursor.iter_tx().nth(10).unwrap();
+        assert_eq!(
+            legacy_block_bytes_cursor
+                .find_and_serialize_transaction_with_txid(&tx.txid)
+                .map(|tx| tx.outputs),
+            Some(tx.outputs)
+        );
+    }
+
+    #[test]
+    fn test_migrate_blocks_format() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("blocks");
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, dir.path()).unwrap();
+
+        let block = include_str!("./fixtures/blocks_json/279671.json");
+        let decoded_block =
+            parse_downloaded_block(block.as_bytes().to_vec()).expect("unable to decode block");
+        let bytes = BlockBytesCursor::from_full_block(&decoded_block).expect("unable to serialize");
+        insert_entry_in_blocks(279671, &bytes[BLOCK_HEADER_LEN..], true, &db, &ctx);
+        insert_entry_in_blocks(279672, &bytes, false, &db, &ctx);
+
+        let report = migrate_blocks_format(&db, &ctx).unwrap();
+        assert_eq!(report.blocks_scanned, 2);
+        assert_eq!(report.blocks_rewritten, 1);
+        assert!(report.blocks_with_txid_prefix_collisions.is_empty());
+        assert_eq!(
+            find_block_bytes_at_block_height(279671, 0, &db, &ctx),
+            Some(bytes.clone())
+        );
+        assert_eq!(find_last_block_inserted(&db), 279671);
+
+        // Already migrated
+        let report = migrate_blocks_format(&db, &ctx).unwrap();
+        assert_eq!(report.blocks_rewritten, 0);
+    }
+
+    #[test]
+    fn test_read_rocks_db_options_file() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("options");
+        let mut resources = Config::devnet_default().resources;
+        resources.rocksdb.max_write_buffer_number = 3;
+        resources.rocksdb.blocks_compression = BlocksCompression::Zstd {
+            level: 5,
+            dictionary_size_kb: 0,
+        };
+        let blocks_db_rw =
+            open_readwrite_ordhook_db_conn_rocks_db(dir.path(), &resources, &ctx).unwrap();
+
+        let options = read_rocks_db_options_file(blocks_db_rw.path()).unwrap();
+        assert_eq!(
+            options.get("max_write_buffer_number").map(|v| v.as_str()),
+            Some("3")
+        );
+        assert_eq!(
+            options.get("compression").map(|v| v.as_str()),
+            Some("kZSTD")
+        );
+        log_rocks_db_options(&blocks_db_rw, &ctx);
+
+        drop(blocks_db_rw);
+    }
+
+    #[test]
+    fn test_ordhook_db_migrations() {
+        let ctx = Context::empty();
+        let mut previous_version = 0;
+        for migration in ORDHOOK_DB_MIGRATIONS.iter() {
+            assert!(migration.version > previous_version);
+            previous_version = migration.version;
+        }
+
+        // Brand new database
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(get_ordhook_db_schema_version(&conn), Ok(0));
+        assert_eq!(
+            migrate_ordhook_db(&mut conn, &ctx),
+            Ok((0, ORDHOOK_DB_SCHEMA_VERSION))
+        );
+        assert!(upgrade_ordhook_db_schema(&mut conn, &ctx).is_ok());
+        assert_eq!(
+            migrate_ordhook_db(&mut conn, &ctx),
+            Ok((ORDHOOK_DB_SCHEMA_VERSION, ORDHOOK_DB_SCHEMA_VERSION))
+        );
+
+        // Database created by a more recent build
+        conn.execute(
+            "INSERT INTO schema_version (version, description, applied_at) VALUES (?1, 'unknown', 0)",
+            [ORDHOOK_DB_SCHEMA_VERSION + 1],
+        )
+        .unwrap();
+        assert!(upgrade_ordhook_db_schema(&mut conn, &ctx).is_err());
+        assert!(migrate_ordhook_db(&mut conn, &ctx).is_err());
+
+        // Database with pending additive migrations, upgraded on startup
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(ORDHOOK_DB_MIGRATIONS[0].statements.join(";").as_str())
+            .unwrap();
+        assert!(upgrade_ordhook_db_schema(&mut conn, &ctx).is_ok());
+        assert_eq!(
+            get_ordhook_db_schema_version(&conn),
+            Ok(ORDHOOK_DB_SCHEMA_VERSION)
+        );
+
+        // Database created before schema versioning
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(ORDHOOK_DB_MIGRATIONS[0].statements.join(";").as_str())
+            .unwrap();
+        assert_eq!(get_ordhook_db_schema_version(&conn), Ok(1));
+        assert_eq!(
+            migrate_ordhook_db(&mut conn, &ctx),
+            Ok((1, ORDHOOK_DB_SCHEMA_VERSION))
+        );
+        assert_eq!(
+            get_ordhook_db_schema_version(&conn),
+            Ok(ORDHOOK_DB_SCHEMA_VERSION)
+        );
+    }
+
+    const PENDING_BLOCK_HASH: &str =
+        "0x00000000000000000002a7c4c1e48d76c5a37902165a270156b7a8d72728a054";
+
+    fn open_recovery_dbs(dir: &TempDir, ctx: &Context) -> (DB, Connection) {
+        let blocks_db_rw = open_readwrite_ordhook_db_conn_rocks_db(
+            dir.path(),
+            &Config::devnet_default().resources,
+            ctx,
+        )
+        .unwrap();
+        let mut inscriptions_db_conn_rw = Connection::open_in_memory().unwrap();
+        migrate_ordhook_db(&mut inscriptions_db_conn_rw, ctx).unwrap();
+        insert_entry_in_blocks(100, &[1], true, &blocks_db_rw, ctx);
+        // Overwritten by the block pending, to be restored when it is rolled back
+        SqliteInscriptionStore::new(&inscriptions_db_conn_rw).insert_sequence_metadata(
+            101,
+            &SequenceMetadata {
+                nth_classic_pos_number: 7,
+                nth_classic_neg_number: -3,
+                nth_jubilee_number: 9,
+            },
+            ctx,
+        );
+        (blocks_db_rw, inscriptions_db_conn_rw)
+    }
+
+    /// Index block #101 in hord.sqlite, journaling the entries it overwrites.
+    fn index_pending_block(inscriptions_db_conn_rw: &Connection, ctx: &Context) -> BlockEntries {
+        let inscriptions_store = SqliteInscriptionStore::new(inscriptions_db_conn_rw);
+        let overwritten_entries = inscriptions_store.find_block_entries(101, ctx);
+        inscriptions_store.insert_block_undo_journal(
+            &BlockUndoJournal {
+                block_height: 101,
+                block_hash: PENDING_BLOCK_HASH.to_string(),
+                overwritten_entries: overwritten_entries.clone(),
+            },
+            ctx,
+        );
+        inscriptions_store.insert_location(
+            1_252_201_400_444_387,
+            "b61b0172d95e266c18aea0c624db987e971a5d6d4ebc2aaed85da4642d635735:0",
+            0,
+            101,
+            1,
+            ctx,
+        );
+        inscriptions_store.insert_block_hash(
+            &BlockIdentifier {
+                index: 101,
+                hash: PENDING_BLOCK_HASH.to_string(),
+            },
+            ctx,
+        );
+        overwritten_entries
+    }
+
+    #[test]
+    fn test_recover_pending_apply_block() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("recovery");
+        let (blocks_db_rw, mut inscriptions_db_conn_rw) = open_recovery_dbs(&dir, &ctx);
+        let layout = InscriptionsIndexLayout::Sqlite;
+        assert_eq!(
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &layout,
+                &ctx
+            ),
+            Ok(None)
+        );
+        let pending_commit = PendingBlockCommit {
+            block_height: 101,
+            block_hash: PENDING_BLOCK_HASH.to_string(),
+            kind: PendingBlockCommitKind::ApplyBlock,
+        };
+
+        // Crash after the block was archived, before it was indexed: rolled back
+        write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+        insert_entry_in_blocks(101, &[2], true, &blocks_db_rw, &ctx);
+        assert_eq!(
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &layout,
+                &ctx
+            ),
+            Ok(Some(pending_commit.clone()))
+        );
+        assert_eq!(find_pending_block_commit(&blocks_db_rw), None);
+        assert_eq!(find_last_block_inserted(&blocks_db_rw), 100);
+        assert!(blocks_db_rw.get(101u32.to_be_bytes()).unwrap().is_none());
+
+        // Crash after the block was indexed, before it was archived: restored from the journal
+        write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+        let overwritten_entries = index_pending_block(&inscriptions_db_conn_rw, &ctx);
+        assert_eq!(
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &layout,
+                &ctx
+            ),
+            Ok(Some(pending_commit.clone()))
+        );
+        assert_eq!(find_pending_block_commit(&blocks_db_rw), None);
+        let inscriptions_store = SqliteInscriptionStore::new(&inscriptions_db_conn_rw);
+        assert_eq!(
+            inscriptions_store.find_block_entries(101, &ctx),
+            overwritten_entries
+        );
+        assert_eq!(inscriptions_store.find_block_undo_journal(101, &ctx), None);
+
+        // Crash after the block was indexed and archived, before the marker was cleared: rolled
+        // forward
+        write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+        index_pending_block(&inscriptions_db_conn_rw, &ctx);
+        insert_entry_in_blocks(101, &[2], true, &blocks_db_rw, &ctx);
+        assert_eq!(
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &layout,
+                &ctx
+            ),
+            Ok(Some(pending_commit))
+        );
+        assert_eq!(find_pending_block_commit(&blocks_db_rw), None);
+        assert_eq!(find_last_block_inserted(&blocks_db_rw), 101);
+        assert_eq!(
+            SqliteInscriptionStore::new(&inscriptions_db_conn_rw)
+                .find_block_hash_at_block_height(101, &ctx),
+            Some(PENDING_BLOCK_HASH.to_string())
+        );
+
+        drop(blocks_db_rw);
+    }
+
+    #[test]
+    fn test_recover_pending_undo_block() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("recovery");
+        let (blocks_db_rw, mut inscriptions_db_conn_rw) = open_recovery_dbs(&dir, &ctx);
+        let layout = InscriptionsIndexLayout::Sqlite;
+        let pending_commit = PendingBlockCommit {
+            block_height: 101,
+            block_hash: PENDING_BLOCK_HASH.to_string(),
+            kind: PendingBlockCommitKind::UndoBlock,
+        };
+
+        // Crash before the revert was committed: the journal is applied
+        let overwritten_entries = index_pending_block(&inscriptions_db_conn_rw, &ctx);
+        insert_entry_in_blocks(101, &[2], true, &blocks_db_rw, &ctx);
+        write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+        assert_eq!(
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &layout,
+                &ctx
+            ),
+            Ok(Some(pending_commit.clone()))
+        );
+        assert_eq!(find_pending_block_commit(&blocks_db_rw), None);
+        assert_eq!(
+            SqliteInscriptionStore::new(&inscriptions_db_conn_rw).find_block_entries(101, &ctx),
+            overwritten_entries
+        );
+        assert_eq!(find_last_block_inserted(&blocks_db_rw), 100);
+        assert!(blocks_db_rw.get(101u32.to_be_bytes()).unwrap().is_none());
+
+        // Crash after the revert was committed, before the block was deleted: the entries
+        // restored are left untouched
+        index_pending_block(&inscriptions_db_conn_rw, &ctx);
+        insert_entry_in_blocks(101, &[2], true, &blocks_db_rw, &ctx);
+        write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+        let block_identifier = BlockIdentifier {
+            index: 101,
+            hash: PENDING_BLOCK_HASH.to_string(),
+        };
+        assert!(undo_block_with_journal(
+            &block_identifier,
+            &SqliteInscriptionStore::new(&inscriptions_db_conn_rw),
+            &ctx
+        ));
+        assert_eq!(
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &layout,
+                &ctx
+            ),
+            Ok(Some(pending_commit))
+        );
+        assert_eq!(find_pending_block_commit(&blocks_db_rw), None);
+        assert_eq!(
+            SqliteInscriptionStore::new(&inscriptions_db_conn_rw).find_block_entries(101, &ctx),
+            overwritten_entries
+        );
+        assert!(blocks_db_rw.get(101u32.to_be_bytes()).unwrap().is_none());
+
+        drop(blocks_db_rw);
+    }
 }
@@ -0,0 +1,542 @@
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod rocksdb;
+
+#[cfg(feature = "postgres")]
+use self::postgres::PostgresInscriptionStore;
+use self::rocksdb::RocksDbInscriptionStore;
+
+use std::collections::BTreeMap;
+
+use ::rocksdb::DB;
+use chainhook_sdk::{
+    types::{
+        BitcoinBlockData, BlockIdentifier, OrdinalInscriptionRevealData,
+        OrdinalInscriptionTransferData,
+    },
+    utils::Context,
+};
+use rusqlite::Connection;
+
+use crate::config::InscriptionsIndexLayout;
+
+use crate::core::protocol::inscription_parsing::{
+    get_inscriptions_revealed_in_block, get_inscriptions_transferred_in_block,
+};
+
+use super::journal::{self, BlockEntries, BlockUndoJournal, JournaledInscription};
+use super::{
+    delete_inscriptions_in_block_range, find_all_inscriptions_in_block,
+    find_blessed_inscription_with_ordinal_number, find_block_hash_at_block_height,
+    find_inscriptions_at_wached_outpoint, find_latest_inscription_block_height,
+    find_nth_classic_neg_number_at_block_height, find_nth_classic_pos_number_at_block_height,
+    find_nth_jubilee_number_at_block_height, format_outpoint_to_watch,
+    get_any_entry_in_ordinal_activities, insert_entry_in_blocks_hashes,
+    insert_entry_in_inscriptions, parse_satpoint_to_watch, TraversalResult, WatchedSatpoint,
+};
+
+/// Inscription numbers attributed until a given block (included).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SequenceMetadata {
+    pub nth_classic_pos_number: i64,
+    pub nth_classic_neg_number: i64,
+    pub nth_jubilee_number: i64,
+}
+
+/// Storage of the inscriptions index (`inscriptions`, `locations` and `sequence_metadata`).
+///
+/// Inscriptions sequencing and tracking are only relying on this trait, so that the index can be
+/// kept in hord.sqlite (default), in column families of hord.rocksdb, or in a database shared
+/// with other readers (PostgreSQL).
+/// Like the rest of the db module, implementations are retrying write failures until they succeed.
+pub trait InscriptionStore {
+    fn insert_inscription(
+        &self,
+        inscription_data: &OrdinalInscriptionRevealData,
+        block_identifier: &BlockIdentifier,
+        ctx: &Context,
+    );
+
+    fn insert_location(
+        &self,
+        ordinal_number: u64,
+        outpoint_to_watch: &str,
+        offset: u64,
+        block_height: u64,
+        tx_index: usize,
+        ctx: &Context,
+    );
+
+    fn insert_sequence_metadata(
+        &self,
+        block_height: u64,
+        sequence_metadata: &SequenceMetadata,
+        ctx: &Context,
+    );
+
+    fn find_all_inscriptions_in_block(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> BTreeMap<String, TraversalResult>;
+
+    fn find_blessed_inscription_with_ordinal_number(
+        &self,
+        ordinal_number: &u64,
+        ctx: &Context,
+    ) -> Option<String>;
+
+    fn find_inscriptions_at_watched_outpoint(
+        &self,
+        outpoint: &str,
+        ctx: &Context,
+    ) -> Vec<WatchedSatpoint>;
+
+    fn find_latest_inscription_block_height(&self, ctx: &Context) -> Option<u64>;
+
+    /// Returns true if some inscriptions or locations were already indexed at `block_height`.
+    fn any_entry_at_block_height(&self, block_height: u64, ctx: &Context) -> bool;
+
+    fn find_nth_classic_pos_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64>;
+
+    fn find_nth_classic_neg_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64>;
+
+    fn find_nth_jubilee_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64>;
+
+    /// Record the hash of an indexed block, used for detecting re-orgs that happened while ordhook was down.
+    fn insert_block_hash(&self, block_identifier: &BlockIdentifier, ctx: &Context);
+
+    fn find_block_hash_at_block_height(&self, block_height: u64, ctx: &Context) -> Option<String>;
+
+    /// Delete inscriptions, locations, sequence metadata, block hashes and undo journals between `start_block` and `end_block` (included).
+    fn delete_data_in_block_range(&self, start_block: u64, end_block: u64, ctx: &Context);
+
+    /// Entries stored at `block_height`, pending writes excluded.
+    fn find_block_entries(&self, block_height: u64, ctx: &Context) -> BlockEntries;
+
+    fn insert_journaled_inscription(
+        &self,
+        inscription: &JournaledInscription,
+        block_height: u64,
+        ctx: &Context,
+    );
+
+    fn insert_block_undo_journal(&self, journal: &BlockUndoJournal, ctx: &Context);
+
+    fn find_block_undo_journal(&self, block_height: u64, ctx: &Context)
+        -> Option<BlockUndoJournal>;
+
+    /// Delete the undo journals of the blocks up to `block_height` (included).
+    fn prune_block_undo_journals(&self, block_height: u64, ctx: &Context);
+
+    /// Restore entries returned by `find_block_entries`.
+    fn insert_block_entries(&self, entries: &BlockEntries, ctx: &Context) {
+        for inscription in entries.inscriptions.iter() {
+            self.insert_journaled_inscription(inscription, entries.block_height, ctx);
+        }
+        for location in entries.locations.iter() {
+            self.insert_location(
+                location.ordinal_number,
+                &location.outpoint_to_watch,
+                location.offset,
+                location.block_height,
+                location.tx_index,
+                ctx,
+            );
+        }
+        for sequence_metadata in entries.sequence_metadata.iter() {
+            self.insert_sequence_metadata(entries.block_height, sequence_metadata, ctx);
+        }
+        if let Some(ref block_hash) = entries.block_hash {
+            self.insert_block_hash(
+                &BlockIdentifier {
+                    index: entries.block_height,
+                    hash: block_hash.clone(),
+                },
+                ctx,
+            );
+        }
+    }
+
+    fn insert_transfer_in_locations(
+        &self,
+        transfer_data: &OrdinalInscriptionTransferData,
+        block_identifier: &BlockIdentifier,
+        ctx: &Context,
+    ) {
+        let (tx, output_index, offset) =
+            parse_satpoint_to_watch(&transfer_data.satpoint_post_transfer);
+        let outpoint_to_watch = format_outpoint_to_watch(&tx, output_index);
+        self.insert_location(
+            transfer_data.ordinal_number,
+            &outpoint_to_watch,
+            offset,
+            block_identifier.index,
+            transfer_data.tx_index,
+            ctx,
+        );
+    }
+
+    fn insert_inscription_in_locations(
+        &self,
+        inscription_data: &OrdinalInscriptionRevealData,
+        block_identifier: &BlockIdentifier,
+        ctx: &Context,
+    ) {
+        let (tx, output_index, offset) =
+            parse_satpoint_to_watch(&inscription_data.satpoint_post_inscription);
+        let outpoint_to_watch = format_outpoint_to_watch(&tx, output_index);
+        self.insert_location(
+            inscription_data.ordinal_number,
+            &outpoint_to_watch,
+            offset,
+            block_identifier.index,
+            inscription_data.tx_index,
+            ctx,
+        );
+    }
+
+    fn update_inscriptions_with_block(&self, block: &BitcoinBlockData, ctx: &Context) {
+        for inscription_data in get_inscriptions_revealed_in_block(&block).iter() {
+            self.insert_inscription(inscription_data, &block.block_identifier, ctx);
+            self.insert_inscription_in_locations(inscription_data, &block.block_identifier, ctx);
+        }
+    }
+
+    fn update_locations_with_block(&self, block: &BitcoinBlockData, ctx: &Context) {
+        for transfer_data in get_inscriptions_transferred_in_block(&block).iter() {
+            self.insert_transfer_in_locations(transfer_data, &block.block_identifier, ctx);
+        }
+    }
+
+    fn update_sequence_metadata_with_block(&self, block: &BitcoinBlockData, ctx: &Context) {
+        let block_height = &block.block_identifier.index;
+        let mut sequence_metadata = SequenceMetadata {
+            nth_classic_pos_number: self
+                .find_nth_classic_pos_number_at_block_height(block_height, ctx)
+                .unwrap_or(0),
+            nth_classic_neg_number: self
+                .find_nth_classic_neg_number_at_block_height(block_height, ctx)
+                .unwrap_or(0),
+            nth_jubilee_number: self
+                .find_nth_jubilee_number_at_block_height(block_height, ctx)
+                .unwrap_or(0),
+        };
+        for inscription_data in get_inscriptions_revealed_in_block(&block).iter() {
+            sequence_metadata.nth_classic_pos_number = sequence_metadata
+                .nth_classic_pos_number
+                .max(inscription_data.inscription_number.classic);
+            sequence_metadata.nth_classic_neg_number = sequence_metadata
+                .nth_classic_neg_number
+                .min(inscription_data.inscription_number.classic);
+            sequence_metadata.nth_jubilee_number = sequence_metadata
+                .nth_jubilee_number
+                .max(inscription_data.inscription_number.jubilee);
+        }
+        self.insert_sequence_metadata(*block_height, &sequence_metadata, ctx);
+    }
+}
+
+/// Default implementation, backed by hord.sqlite.
+/// Wraps a connection or a transaction (the caller remains in charge of committing).
+pub struct SqliteInscriptionStore<'a> {
+    conn: &'a Connection,
+}
+
+impl<'a> SqliteInscriptionStore<'a> {
+    pub fn new(conn: &'a Connection) -> SqliteInscriptionStore<'a> {
+        SqliteInscriptionStore { conn }
+    }
+}
+
+impl<'a> InscriptionStore for SqliteInscriptionStore<'a> {
+    fn insert_inscription(
+        &self,
+        inscription_data: &OrdinalInscriptionRevealData,
+        block_identifier: &BlockIdentifier,
+        ctx: &Context,
+    ) {
+        insert_entry_in_inscriptions(inscription_data, block_identifier, self.conn, ctx)
+    }
+
+    fn insert_location(
+        &self,
+        ordinal_number: u64,
+        outpoint_to_watch: &str,
+        offset: u64,
+        block_height: u64,
+        tx_index: usize,
+        ctx: &Context,
+    ) {
+        while let Err(e) = self.conn.execute(
+            "INSERT INTO locations (ordinal_number, outpoint_to_watch, offset, block_height, tx_index) VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![&ordinal_number, &outpoint_to_watch, &offset, &block_height, &tx_index],
+        ) {
+            ctx.try_log(|logger| warn!(logger, "unable to query hord.sqlite: {}", e.to_string()));
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    fn insert_sequence_metadata(
+        &self,
+        block_height: u64,
+        sequence_metadata: &SequenceMetadata,
+        ctx: &Context,
+    ) {
+        while let Err(e) = self.conn.execute(
+            "INSERT INTO sequence_metadata (block_height, nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number) VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![&block_height, &sequence_metadata.nth_classic_pos_number, &sequence_metadata.nth_classic_neg_number, &sequence_metadata.nth_jubilee_number],
+        ) {
+            ctx.try_log(|logger| warn!(logger, "unable to update sequence_metadata: {}", e.to_string()));
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+
+    fn find_all_inscriptions_in_block(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> BTreeMap<String, TraversalResult> {
+        find_all_inscriptions_in_block(block_height, self.conn, ctx)
+    }
+
+    fn find_blessed_inscription_with_ordinal_number(
+        &self,
+        ordinal_number: &u64,
+        ctx: &Context,
+    ) -> Option<String> {
+        find_blessed_inscription_with_ordinal_number(ordinal_number, self.conn, ctx)
+    }
+
+    fn find_inscriptions_at_watched_outpoint(
+        &self,
+        outpoint: &str,
+        ctx: &Context,
+    ) -> Vec<WatchedSatpoint> {
+        find_inscriptions_at_wached_outpoint(outpoint, self.conn, ctx)
+    }
+
+    fn find_latest_inscription_block_height(&self, ctx: &Context) -> Option<u64> {
+        find_latest_inscription_block_height(self.conn, ctx)
+            .ok()
+            .flatten()
+    }
+
+    fn any_entry_at_block_height(&self, block_height: u64, ctx: &Context) -> bool {
+        get_any_entry_in_ordinal_activities(&block_height, self.conn, ctx)
+    }
+
+    fn find_nth_classic_pos_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64> {
+        find_nth_classic_pos_number_at_block_height(block_height, self.conn, ctx)
+    }
+
+    fn find_nth_classic_neg_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64> {
+        find_nth_classic_neg_number_at_block_height(block_height, self.conn, ctx)
+    }
+
+    fn find_nth_jubilee_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64> {
+        find_nth_jubilee_number_at_block_height(block_height, self.conn, ctx)
+    }
+
+    fn insert_block_hash(&self, block_identifier: &BlockIdentifier, ctx: &Context) {
+        insert_entry_in_blocks_hashes(block_identifier, self.conn, ctx)
+    }
+
+    fn find_block_hash_at_block_height(&self, block_height: u64, ctx: &Context) -> Option<String> {
+        find_block_hash_at_block_height(block_height, self.conn, ctx)
+    }
+
+    fn delete_data_in_block_range(&self, start_block: u64, end_block: u64, ctx: &Context) {
+        delete_inscriptions_in_block_range(start_block as u32, end_block as u32, self.conn, ctx)
+    }
+
+    fn find_block_entries(&self, block_height: u64, ctx: &Context) -> BlockEntries {
+        journal::find_block_entries(block_height, self.conn, ctx)
+    }
+
+    fn insert_journaled_inscription(
+        &self,
+        inscription: &JournaledInscription,
+        block_height: u64,
+        ctx: &Context,
+    ) {
+        journal::insert_journaled_inscription(inscription, block_height, self.conn, ctx)
+    }
+
+    fn insert_block_undo_journal(&self, undo_journal: &BlockUndoJournal, ctx: &Context) {
+        journal::insert_block_undo_journal(undo_journal, self.conn, ctx)
+    }
+
+    fn find_block_undo_journal(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> Option<BlockUndoJournal> {
+        journal::find_block_undo_journal(block_height, self.conn, ctx)
+    }
+
+    fn prune_block_undo_journals(&self, block_height: u64, ctx: &Context) {
+        journal::prune_undo_journal(block_height, self.conn, ctx)
+    }
+}
+
+/// Inscriptions store of the layout selected with `storage.inscriptions_index`.
+pub enum ConfiguredInscriptionStore<'a> {
+    Sqlite(SqliteInscriptionStore<'a>),
+    RocksDb(RocksDbInscriptionStore<'a>),
+    #[cfg(feature = "postgres")]
+    Postgres(PostgresInscriptionStore),
+}
+
+impl<'a> ConfiguredInscriptionStore<'a> {
+    /// `inscriptions_db_conn` (hord.sqlite, or one of its transactions) is only used by the
+    /// SQLite layout, `blocks_db` (hord.rocksdb) by the all-RocksDB layout.
+    pub fn open(
+        layout: &InscriptionsIndexLayout,
+        blocks_db: &'a DB,
+        inscriptions_db_conn: &'a Connection,
+    ) -> Result<ConfiguredInscriptionStore<'a>, String> {
+        match layout {
+            InscriptionsIndexLayout::Sqlite => Ok(ConfiguredInscriptionStore::Sqlite(
+                SqliteInscriptionStore::new(inscriptions_db_conn),
+            )),
+            InscriptionsIndexLayout::RocksDb => Ok(ConfiguredInscriptionStore::RocksDb(
+                RocksDbInscriptionStore::new(blocks_db)?,
+            )),
+            #[cfg(feature = "postgres")]
+            InscriptionsIndexLayout::Postgres(database_url) => {
+                Ok(ConfiguredInscriptionStore::Postgres(
+                    PostgresInscriptionStore::connect(database_url)?,
+                ))
+            }
+        }
+    }
+
+    /// Start buffering the writes, until `discard` is called. With the SQLite layout, the writes
+    /// are buffered by the transaction the store was opened with.
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    pub fn begin(&self, ctx: &Context) {
+        match self {
+            ConfiguredInscriptionStore::Sqlite(_) | ConfiguredInscriptionStore::RocksDb(_) => {}
+            #[cfg(feature = "postgres")]
+            ConfiguredInscriptionStore::Postgres(store) => store.begin(ctx),
+        }
+    }
+
+    /// Drop the writes performed since `begin`. Deletions are not buffered by the all-RocksDB
+    /// layout.
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    pub fn discard(&self, ctx: &Context) {
+        match self {
+            ConfiguredInscriptionStore::Sqlite(_) => {}
+            ConfiguredInscriptionStore::RocksDb(store) => store.discard(),
+            #[cfg(feature = "postgres")]
+            ConfiguredInscriptionStore::Postgres(store) => store.rollback(ctx),
+        }
+    }
+
+    /// Persist the writes performed since `begin`. With the SQLite layout, the writes are
+    /// committed with the transaction the store was opened with.
+    #[cfg_attr(not(feature = "postgres"), allow(unused_variables))]
+    pub fn commit(&self, block_height: u64, ctx: &Context) -> Result<(), String> {
+        match self {
+            ConfiguredInscriptionStore::Sqlite(_) => Ok(()),
+            ConfiguredInscriptionStore::RocksDb(store) => {
+                store.commit(block_height, None, ctx);
+                Ok(())
+            }
+            #[cfg(feature = "postgres")]
+            ConfiguredInscriptionStore::Postgres(store) => store.commit(ctx),
+        }
+    }
+
+    pub fn as_store(&self) -> &dyn InscriptionStore {
+        match self {
+            ConfiguredInscriptionStore::Sqlite(store) => store,
+            ConfiguredInscriptionStore::RocksDb(store) => store,
+            #[cfg(feature = "postgres")]
+            ConfiguredInscriptionStore::Postgres(store) => store,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migrate_ordhook_db;
+
+    #[test]
+    fn test_sqlite_inscription_store() {
+        let ctx = Context::empty();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate_ordhook_db(&mut conn, &ctx).unwrap();
+        let store = SqliteInscriptionStore::new(&conn);
+
+        let txid = "b61b0172d95e266c18aea0c624db987e971a5d6d4ebc2aaed85da4642d635735";
+        store.insert_location(
+            1_252_201_400_444_387,
+            &format!("{txid}:0"),
+            0,
+            767_430,
+            1,
+            &ctx,
+        );
+        store.insert_sequence_metadata(
+            767_430,
+            &SequenceMetadata {
+                nth_classic_pos_number: 0,
+                nth_classic_neg_number: -1,
+                nth_jubilee_number: 0,
+            },
+            &ctx,
+        );
+
+        let block_identifier = BlockIdentifier {
+            index: 767_430,
+            hash: "0x000000000000000000029730547464f056f8b6e2e0a02eaf69c24389983a04f5".into(),
+        };
+        store.insert_block_hash(&block_identifier, &ctx);
+        assert_eq!(
+            store.find_block_hash_at_block_height(767_430, &ctx),
+            Some(block_identifier.hash.clone())
+        );
+
+        let watched = store.find_inscriptions_at_watched_outpoint(&format!("{txid}:0"), &ctx);
+        assert_eq!(watched.len(), 1);
+        assert_eq!(watched[0].ordinal_number, 1_252_201_400_444_387);
+        assert_eq!(
+            store.find_nth_classic_neg_number_at_block_height(&767_431, &ctx),
+            Some(-1)
+        );
+
+        store.delete_data_in_block_range(767_430, 767_430, &ctx);
+        assert!(store
+            .find_inscriptions_at_watched_outpoint(&format!("{txid}:0"), &ctx)
+            .is_empty());
+        assert_eq!(store.find_block_hash_at_block_height(767_430, &ctx), None);
+    }
+}
@@ -0,0 +1,959 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use chainhook_sdk::{
+    types::{BlockIdentifier, OrdinalInscriptionNumber, OrdinalInscriptionRevealData},
+    utils::Context,
+};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, WriteBatch, DB};
+
+use crate::config::ResourcesConfig;
+use crate::db::journal::{BlockEntries, BlockUndoJournal, JournaledInscription, JournaledLocation};
+use crate::db::{parse_inscription_id, rocks_db_default_options, TraversalResult, WatchedSatpoint};
+
+use super::{InscriptionStore, SequenceMetadata};
+
+const INSCRIPTIONS_CF: &str = "inscriptions";
+const INSCRIPTIONS_BY_ORDINAL_CF: &str = "inscriptions_by_ordinal";
+const LOCATIONS_CF: &str = "locations";
+const LOCATIONS_BY_BLOCK_CF: &str = "locations_by_block";
+const SEQUENCE_METADATA_CF: &str = "sequence_metadata";
+const BLOCK_HASHES_CF: &str = "block_hashes";
+const UNDO_JOURNAL_CF: &str = "undo_journal";
+
+/// Column families used by the all-RocksDB layout, living next to the blocks (default column family) in hord.rocksdb.
+pub const INSCRIPTIONS_COLUMN_FAMILIES: [&str; 7] = [
+    INSCRIPTIONS_CF,
+    INSCRIPTIONS_BY_ORDINAL_CF,
+    LOCATIONS_CF,
+    LOCATIONS_BY_BLOCK_CF,
+    SEQUENCE_METADATA_CF,
+    BLOCK_HASHES_CF,
+    UNDO_JOURNAL_CF,
+];
+
+/// Column families are created with the configured RocksDB options, the ones they are reopened with.
+pub fn create_inscriptions_column_families(
+    db: &mut DB,
+    resources: &ResourcesConfig,
+) -> Result<(), String> {
+    let opts = rocks_db_default_options(resources);
+    for name in INSCRIPTIONS_COLUMN_FAMILIES {
+        if db.cf_handle(name).is_none() {
+            db.create_cf(name, &opts)
+                .map_err(|e| format!("unable to create column family {name}: {}", e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+struct InscriptionEntry {
+    inscription_id: String,
+    block_height: u64,
+    ordinal_number: u64,
+    inscription_number: OrdinalInscriptionNumber,
+    input_index: usize,
+}
+
+impl InscriptionEntry {
+    // key: block_height (8) | inscription_id
+    fn key(&self) -> Vec<u8> {
+        let mut key = self.block_height.to_be_bytes().to_vec();
+        key.extend_from_slice(self.inscription_id.as_bytes());
+        key
+    }
+
+    // value: ordinal_number (8) | classic (8) | jubilee (8) | input_index (8)
+    fn value(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(32);
+        value.extend_from_slice(&self.ordinal_number.to_be_bytes());
+        value.extend_from_slice(&self.inscription_number.classic.to_be_bytes());
+        value.extend_from_slice(&self.inscription_number.jubilee.to_be_bytes());
+        value.extend_from_slice(&(self.input_index as u64).to_be_bytes());
+        value
+    }
+
+    fn ordinal_key(&self) -> Vec<u8> {
+        let mut key = self.ordinal_number.to_be_bytes().to_vec();
+        key.extend_from_slice(self.inscription_id.as_bytes());
+        key
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<InscriptionEntry, String> {
+        let block_height = read_u64(key, 0)?;
+        Ok(InscriptionEntry {
+            block_height,
+            inscription_id: String::from_utf8_lossy(&key[8..]).to_string(),
+            ordinal_number: read_u64(value, 0)?,
+            inscription_number: OrdinalInscriptionNumber {
+                classic: read_u64(value, 8)? as i64,
+                jubilee: read_u64(value, 16)? as i64,
+            },
+            input_index: read_u64(value, 24)? as usize,
+        })
+    }
+
+    fn to_traversal(&self) -> TraversalResult {
+        let (transaction_identifier_inscription, _) = parse_inscription_id(&self.inscription_id);
+        TraversalResult {
+            inscription_number: self.inscription_number.clone(),
+            ordinal_number: self.ordinal_number,
+            inscription_input_index: self.input_index,
+            transaction_identifier_inscription,
+            transfers: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LocationEntry {
+    ordinal_number: u64,
+    outpoint_to_watch: String,
+    offset: u64,
+    block_height: u64,
+    tx_index: usize,
+}
+
+impl LocationEntry {
+    fn outpoint_prefix(outpoint_to_watch: &str) -> Vec<u8> {
+        let mut prefix = outpoint_to_watch.as_bytes().to_vec();
+        prefix.push(b'/');
+        prefix
+    }
+
+    // key: outpoint_to_watch | '/' | offset (8) | ordinal_number (8)
+    fn key(&self) -> Vec<u8> {
+        let mut key = LocationEntry::outpoint_prefix(&self.outpoint_to_watch);
+        key.extend_from_slice(&self.offset.to_be_bytes());
+        key.extend_from_slice(&self.ordinal_number.to_be_bytes());
+        key
+    }
+
+    // value: block_height (8) | tx_index (8)
+    fn value(&self) -> Vec<u8> {
+        let mut value = self.block_height.to_be_bytes().to_vec();
+        value.extend_from_slice(&(self.tx_index as u64).to_be_bytes());
+        value
+    }
+
+    fn block_key(&self) -> Vec<u8> {
+        let mut key = self.block_height.to_be_bytes().to_vec();
+        key.extend_from_slice(&self.key());
+        key
+    }
+
+    fn decode(key: &[u8], value: &[u8]) -> Result<LocationEntry, String> {
+        let outpoint_len = key
+            .len()
+            .checked_sub(17)
+            .ok_or(format!("location key too short ({} bytes)", key.len()))?;
+        Ok(LocationEntry {
+            outpoint_to_watch: String::from_utf8_lossy(&key[0..outpoint_len]).to_string(),
+            offset: read_u64(key, outpoint_len + 1)?,
+            ordinal_number: read_u64(key, outpoint_len + 9)?,
+            block_height: read_u64(value, 0)?,
+            tx_index: read_u64(value, 8)? as usize,
+        })
+    }
+
+    fn watched_satpoint(&self) -> WatchedSatpoint {
+        WatchedSatpoint {
+            ordinal_number: self.ordinal_number,
+            offset: self.offset,
+        }
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    let buffer: [u8; 8] = bytes
+        .get(offset..offset + 8)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(format!(
+            "unable to read u64 at offset {offset} of a {} bytes entry",
+            bytes.len()
+        ))?;
+    Ok(u64::from_be_bytes(buffer))
+}
+
+fn encode_sequence_metadata(sequence_metadata: &SequenceMetadata) -> Vec<u8> {
+    let mut value = Vec::with_capacity(24);
+    value.extend_from_slice(&sequence_metadata.nth_classic_pos_number.to_be_bytes());
+    value.extend_from_slice(&sequence_metadata.nth_classic_neg_number.to_be_bytes());
+    value.extend_from_slice(&sequence_metadata.nth_jubilee_number.to_be_bytes());
+    value
+}
+
+fn decode_sequence_metadata(value: &[u8]) -> Result<SequenceMetadata, String> {
+    Ok(SequenceMetadata {
+        nth_classic_pos_number: read_u64(value, 0)? as i64,
+        nth_classic_neg_number: read_u64(value, 8)? as i64,
+        nth_jubilee_number: read_u64(value, 16)? as i64,
+    })
+}
+
+fn warn_corrupted_entry(cf_name: &str, e: String, ctx: &Context) {
+    ctx.try_log(|logger| warn!(logger, "skipping corrupted {cf_name} entry: {e}"));
+}
+
+#[derive(Default)]
+struct PendingWrites {
+    inscriptions: Vec<InscriptionEntry>,
+    locations: Vec<LocationEntry>,
+    sequence_metadata: BTreeMap<u64, SequenceMetadata>,
+    block_hashes: BTreeMap<u64, String>,
+    undo_journals: BTreeMap<u64, BlockUndoJournal>,
+    pruned_undo_journals: Option<u64>,
+}
+
+/// All-RocksDB implementation of the inscriptions index, stored in column families of hord.rocksdb.
+///
+/// Writes are buffered (and visible to subsequent reads through this store) until `commit` is called:
+/// everything written for a block is then persisted in one atomic `WriteBatch`, along with the
+/// compacted block when the caller provides it (blocks applied live). During syncs, compacted
+/// blocks are stored beforehand by the archiving step, traversals of the next blocks of a batch
+/// reading them.
+pub struct RocksDbInscriptionStore<'a> {
+    db: &'a DB,
+    pending: RefCell<PendingWrites>,
+}
+
+impl<'a> RocksDbInscriptionStore<'a> {
+    /// `db` must have been opened with the column families created by `create_inscriptions_column_families`.
+    pub fn new(db: &'a DB) -> Result<RocksDbInscriptionStore<'a>, String> {
+        for name in INSCRIPTIONS_COLUMN_FAMILIES {
+            if db.cf_handle(name).is_none() {
+                return Err(format!("column family {name} missing in hord.rocksdb"));
+            }
+        }
+        Ok(RocksDbInscriptionStore {
+            db,
+            pending: RefCell::new(PendingWrites::default()),
+        })
+    }
+
+    fn cf(&self, name: &str) -> &ColumnFamily {
+        self.db.cf_handle(name).expect("column family missing")
+    }
+
+    fn scan_prefix(&self, cf_name: &str, prefix: &[u8], ctx: &Context) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut results = vec![];
+        let iter = self.db.iterator_cf(
+            self.cf(cf_name),
+            IteratorMode::From(prefix, Direction::Forward),
+        );
+        for item in iter {
+            match item {
+                Ok((key, value)) => {
+                    if !key.starts_with(prefix) {
+                        break;
+                    }
+                    results.push((key.to_vec(), value.to_vec()));
+                }
+                Err(e) => {
+                    ctx.try_log(|logger| {
+                        warn!(logger, "unable to read hord.rocksdb: {}", e.to_string())
+                    });
+                    break;
+                }
+            }
+        }
+        results
+    }
+
+    fn get(&self, cf_name: &str, key: &[u8], ctx: &Context) -> Option<Vec<u8>> {
+        match self.db.get_cf(self.cf(cf_name), key) {
+            Ok(value) => value,
+            Err(e) => {
+                ctx.try_log(|logger| {
+                    warn!(logger, "unable to read hord.rocksdb: {}", e.to_string())
+                });
+                None
+            }
+        }
+    }
+
+    fn scan_block_range(
+        &self,
+        cf_name: &str,
+        start_block: u64,
+        end_block: u64,
+        ctx: &Context,
+    ) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut results = vec![];
+        let start_key = start_block.to_be_bytes();
+        let iter = self.db.iterator_cf(
+            self.cf(cf_name),
+            IteratorMode::From(&start_key, Direction::Forward),
+        );
+        for item in iter {
+            match item {
+                Ok((key, value)) => match read_u64(&key, 0) {
+                    Ok(block_height) if block_height > end_block => break,
+                    Ok(_) => results.push((key.to_vec(), value.to_vec())),
+                    Err(e) => warn_corrupted_entry(cf_name, e, ctx),
+                },
+                Err(e) => {
+                    ctx.try_log(|logger| {
+                        warn!(logger, "unable to read hord.rocksdb: {}", e.to_string())
+                    });
+                    break;
+                }
+            }
+        }
+        results
+    }
+
+    fn find_sequence_metadata_before_block_height(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> Option<SequenceMetadata> {
+        if let Some((_, sequence_metadata)) = self
+            .pending
+            .borrow()
+            .sequence_metadata
+            .range(..block_height)
+            .next_back()
+        {
+            return Some(sequence_metadata.clone());
+        }
+        if block_height == 0 {
+            return None;
+        }
+        let start_key = (block_height - 1).to_be_bytes();
+        let mut iter = self.db.iterator_cf(
+            self.cf(SEQUENCE_METADATA_CF),
+            IteratorMode::From(&start_key, Direction::Reverse),
+        );
+        match iter.next() {
+            Some(Ok((_, value))) => match decode_sequence_metadata(&value) {
+                Ok(sequence_metadata) => Some(sequence_metadata),
+                Err(e) => {
+                    warn_corrupted_entry(SEQUENCE_METADATA_CF, e, ctx);
+                    None
+                }
+            },
+            Some(Err(e)) => {
+                ctx.try_log(|logger| {
+                    warn!(logger, "unable to read hord.rocksdb: {}", e.to_string())
+                });
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Atomically persist the pending writes, along with the compacted block when provided.
+    pub fn commit(&self, block_height: u64, block_bytes: Option<&[u8]>, ctx: &Context) {
+        let pending = std::mem::take(&mut *self.pending.borrow_mut());
+        loop {
+            let mut batch = WriteBatch::default();
+            if let Some(block_bytes) = block_bytes {
+                let block_height_bytes = (block_height as u32).to_be_bytes();
+                batch.put(&block_height_bytes, block_bytes);
+                batch.put(b"metadata::last_insert", block_height_bytes);
+            }
+            for entry in pending.inscriptions.iter() {
+                batch.put_cf(self.cf(INSCRIPTIONS_CF), entry.key(), entry.value());
+                batch.put_cf(
+                    self.cf(INSCRIPTIONS_BY_ORDINAL_CF),
+                    entry.ordinal_key(),
+                    entry.inscription_number.classic.to_be_bytes(),
+                );
+            }
+            for entry in pending.locations.iter() {
+                batch.put_cf(self.cf(LOCATIONS_CF), entry.key(), entry.value());
+                batch.put_cf(self.cf(LOCATIONS_BY_BLOCK_CF), entry.block_key(), b"");
+            }
+            for (height, block_hash) in pending.block_hashes.iter() {
+                batch.put_cf(
+                    self.cf(BLOCK_HASHES_CF),
+                    height.to_be_bytes(),
+                    block_hash.as_bytes(),
+                );
+            }
+            for (height, sequence_metadata) in pending.sequence_metadata.iter() {
+                batch.put_cf(
+                    self.cf(SEQUENCE_METADATA_CF),
+                    height.to_be_bytes(),
+                    encode_sequence_metadata(sequence_metadata),
+                );
+            }
+            if let Some(height) = pending.pruned_undo_journals {
+                batch.delete_range_cf(
+                    self.cf(UNDO_JOURNAL_CF),
+                    0u64.to_be_bytes(),
+                    height.saturating_add(1).to_be_bytes(),
+                );
+            }
+            for (height, journal) in pending.undo_journals.iter() {
+                batch.put_cf(
+                    self.cf(UNDO_JOURNAL_CF),
+                    height.to_be_bytes(),
+                    serde_json::to_vec(journal).expect("unable to serialize journal"),
+                );
+            }
+            match self.db.write(batch) {
+                Ok(_) => break,
+                Err(e) => {
+                    ctx.try_log(|logger| {
+                        warn!(
+                            logger,
+                            "unable to commit block #{block_height} in hord.rocksdb: {}",
+                            e.to_string()
+                        )
+                    });
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    /// Drop the pending writes.
+    pub fn discard(&self) {
+        let _ = std::mem::take(&mut *self.pending.borrow_mut());
+    }
+}
+
+impl<'a> InscriptionStore for RocksDbInscriptionStore<'a> {
+    fn insert_inscription(
+        &self,
+        inscription_data: &OrdinalInscriptionRevealData,
+        block_identifier: &BlockIdentifier,
+        _ctx: &Context,
+    ) {
+        self.pending
+            .borrow_mut()
+            .inscriptions
+            .push(InscriptionEntry {
+                inscription_id: inscription_data.inscription_id.clone(),
+                block_height: block_identifier.index,
+                ordinal_number: inscription_data.ordinal_number,
+                inscription_number: inscription_data.inscription_number.clone(),
+                input_index: inscription_data.inscription_input_index,
+            });
+    }
+
+    fn insert_location(
+        &self,
+        ordinal_number: u64,
+        outpoint_to_watch: &str,
+        offset: u64,
+        block_height: u64,
+        tx_index: usize,
+        _ctx: &Context,
+    ) {
+        self.pending.borrow_mut().locations.push(LocationEntry {
+            ordinal_number,
+            outpoint_to_watch: outpoint_to_watch.to_string(),
+            offset,
+            block_height,
+            tx_index,
+        });
+    }
+
+    fn insert_sequence_metadata(
+        &self,
+        block_height: u64,
+        sequence_metadata: &SequenceMetadata,
+        _ctx: &Context,
+    ) {
+        self.pending
+            .borrow_mut()
+            .sequence_metadata
+            .insert(block_height, sequence_metadata.clone());
+    }
+
+    fn find_all_inscriptions_in_block(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> BTreeMap<String, TraversalResult> {
+        let mut results = BTreeMap::new();
+        for (key, value) in self
+            .scan_prefix(INSCRIPTIONS_CF, &block_height.to_be_bytes(), ctx)
+            .iter()
+        {
+            match InscriptionEntry::decode(key, value) {
+                Ok(entry) => {
+                    results.insert(entry.inscription_id.clone(), entry.to_traversal());
+                }
+                Err(e) => warn_corrupted_entry(INSCRIPTIONS_CF, e, ctx),
+            }
+        }
+        for entry in self.pending.borrow().inscriptions.iter() {
+            if entry.block_height == *block_height {
+                results.insert(entry.inscription_id.clone(), entry.to_traversal());
+            }
+        }
+        results
+    }
+
+    fn find_blessed_inscription_with_ordinal_number(
+        &self,
+        ordinal_number: &u64,
+        ctx: &Context,
+    ) -> Option<String> {
+        for entry in self.pending.borrow().inscriptions.iter() {
+            if entry.ordinal_number == *ordinal_number && entry.inscription_number.classic >= 0 {
+                return Some(entry.inscription_id.clone());
+            }
+        }
+        for (key, value) in self
+            .scan_prefix(
+                INSCRIPTIONS_BY_ORDINAL_CF,
+                &ordinal_number.to_be_bytes(),
+                ctx,
+            )
+            .iter()
+        {
+            match read_u64(value, 0) {
+                Ok(classic) if classic as i64 >= 0 => {
+                    return Some(String::from_utf8_lossy(&key[8..]).to_string());
+                }
+                Ok(_) => {}
+                Err(e) => warn_corrupted_entry(INSCRIPTIONS_BY_ORDINAL_CF, e, ctx),
+            }
+        }
+        None
+    }
+
+    fn find_inscriptions_at_watched_outpoint(
+        &self,
+        outpoint: &str,
+        ctx: &Context,
+    ) -> Vec<WatchedSatpoint> {
+        let prefix = LocationEntry::outpoint_prefix(outpoint);
+        let mut results = self
+            .scan_prefix(LOCATIONS_CF, &prefix, ctx)
+            .iter()
+            .filter_map(|(key, _)| {
+                match (read_u64(key, prefix.len()), read_u64(key, prefix.len() + 8)) {
+                    (Ok(offset), Ok(ordinal_number)) => Some(WatchedSatpoint {
+                        offset,
+                        ordinal_number,
+                    }),
+                    (Err(e), _) | (_, Err(e)) => {
+                        warn_corrupted_entry(LOCATIONS_CF, e, ctx);
+                        None
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        for entry in self.pending.borrow().locations.iter() {
+            if entry.outpoint_to_watch == outpoint {
+                results.push(entry.watched_satpoint());
+            }
+        }
+        results.sort_by_key(|satpoint| satpoint.offset);
+        results
+    }
+
+    fn find_latest_inscription_block_height(&self, ctx: &Context) -> Option<u64> {
+        let pending_height = self
+            .pending
+            .borrow()
+            .inscriptions
+            .iter()
+            .map(|entry| entry.block_height)
+            .max();
+        let mut iter = self
+            .db
+            .iterator_cf(self.cf(INSCRIPTIONS_CF), IteratorMode::End);
+        let stored_height = match iter.next() {
+            Some(Ok((key, _))) => read_u64(&key, 0)
+                .map_err(|e| warn_corrupted_entry(INSCRIPTIONS_CF, e, ctx))
+                .ok(),
+            Some(Err(e)) => {
+                ctx.try_log(|logger| {
+                    warn!(logger, "unable to read hord.rocksdb: {}", e.to_string())
+                });
+                None
+            }
+            None => None,
+        };
+        pending_height.max(stored_height)
+    }
+
+    fn any_entry_at_block_height(&self, block_height: u64, ctx: &Context) -> bool {
+        let prefix = block_height.to_be_bytes();
+        !self.scan_prefix(INSCRIPTIONS_CF, &prefix, ctx).is_empty()
+            || !self
+                .scan_prefix(LOCATIONS_BY_BLOCK_CF, &prefix, ctx)
+                .is_empty()
+    }
+
+    fn find_nth_classic_pos_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64> {
+        self.find_sequence_metadata_before_block_height(*block_height, ctx)
+            .map(|sequence_metadata| sequence_metadata.nth_classic_pos_number)
+    }
+
+    fn find_nth_classic_neg_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64> {
+        self.find_sequence_metadata_before_block_height(*block_height, ctx)
+            .map(|sequence_metadata| sequence_metadata.nth_classic_neg_number)
+    }
+
+    fn find_nth_jubilee_number_at_block_height(
+        &self,
+        block_height: &u64,
+        ctx: &Context,
+    ) -> Option<i64> {
+        self.find_sequence_metadata_before_block_height(*block_height, ctx)
+            .map(|sequence_metadata| sequence_metadata.nth_jubilee_number)
+    }
+
+    fn insert_block_hash(&self, block_identifier: &BlockIdentifier, _ctx: &Context) {
+        self.pending
+            .borrow_mut()
+            .block_hashes
+            .insert(block_identifier.index, block_identifier.hash.clone());
+    }
+
+    fn find_block_hash_at_block_height(&self, block_height: u64, ctx: &Context) -> Option<String> {
+        if let Some(block_hash) = self.pending.borrow().block_hashes.get(&block_height) {
+            return Some(block_hash.clone());
+        }
+        match self
+            .db
+            .get_cf(self.cf(BLOCK_HASHES_CF), block_height.to_be_bytes())
+        {
+            Ok(value) => value.map(|bytes| String::from_utf8_lossy(&bytes).to_string()),
+            Err(e) => {
+                ctx.try_log(|logger| {
+                    warn!(logger, "unable to read hord.rocksdb: {}", e.to_string())
+                });
+                None
+            }
+        }
+    }
+
+    fn delete_data_in_block_range(&self, start_block: u64, end_block: u64, ctx: &Context) {
+        let mut keys_to_delete: Vec<(&str, Vec<u8>)> = vec![];
+        for (key, value) in self
+            .scan_block_range(INSCRIPTIONS_CF, start_block, end_block, ctx)
+            .into_iter()
+        {
+            match InscriptionEntry::decode(&key, &value) {
+                Ok(entry) => keys_to_delete.push((INSCRIPTIONS_BY_ORDINAL_CF, entry.ordinal_key())),
+                Err(e) => warn_corrupted_entry(INSCRIPTIONS_CF, e, ctx),
+            }
+            keys_to_delete.push((INSCRIPTIONS_CF, key));
+        }
+        for (key, _) in self
+            .scan_block_range(LOCATIONS_BY_BLOCK_CF, start_block, end_block, ctx)
+            .into_iter()
+        {
+            keys_to_delete.push((LOCATIONS_CF, key[8..].to_vec()));
+            keys_to_delete.push((LOCATIONS_BY_BLOCK_CF, key));
+        }
+        for (key, _) in self
+            .scan_block_range(SEQUENCE_METADATA_CF, start_block, end_block, ctx)
+            .into_iter()
+        {
+            keys_to_delete.push((SEQUENCE_METADATA_CF, key));
+        }
+        for (key, _) in self
+            .scan_block_range(BLOCK_HASHES_CF, start_block, end_block, ctx)
+            .into_iter()
+        {
+            keys_to_delete.push((BLOCK_HASHES_CF, key));
+        }
+        for (key, _) in self
+            .scan_block_range(UNDO_JOURNAL_CF, start_block, end_block, ctx)
+            .into_iter()
+        {
+            keys_to_delete.push((UNDO_JOURNAL_CF, key));
+        }
+        loop {
+            let mut batch = WriteBatch::default();
+            for (cf_name, key) in keys_to_delete.iter() {
+                batch.delete_cf(self.cf(cf_name), key);
+            }
+            match self.db.write(batch) {
+                Ok(_) => break,
+                Err(e) => {
+                    ctx.try_log(|logger| {
+                        warn!(logger, "unable to write hord.rocksdb: {}", e.to_string())
+                    });
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+        }
+    }
+
+    fn find_block_entries(&self, block_height: u64, ctx: &Context) -> BlockEntries {
+        let prefix = block_height.to_be_bytes();
+        let inscriptions = self
+            .scan_prefix(INSCRIPTIONS_CF, &prefix, ctx)
+            .iter()
+            .filter_map(|(key, value)| match InscriptionEntry::decode(key, value) {
+                Ok(entry) => Some(JournaledInscription {
+                    inscription_id: entry.inscription_id,
+                    input_index: entry.input_index,
+                    ordinal_number: entry.ordinal_number,
+                    classic_inscription_number: entry.inscription_number.classic,
+                    jubilee_inscription_number: entry.inscription_number.jubilee,
+                }),
+                Err(e) => {
+                    warn_corrupted_entry(INSCRIPTIONS_CF, e, ctx);
+                    None
+                }
+            })
+            .collect();
+        let mut locations = vec![];
+        for (key, _) in self.scan_prefix(LOCATIONS_BY_BLOCK_CF, &prefix, ctx).iter() {
+            if let Some(value) = self.get(LOCATIONS_CF, &key[8..], ctx) {
+                let entry = match LocationEntry::decode(&key[8..], &value) {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        warn_corrupted_entry(LOCATIONS_CF, e, ctx);
+                        continue;
+                    }
+                };
+                locations.push(JournaledLocation {
+                    ordinal_number: entry.ordinal_number,
+                    outpoint_to_watch: entry.outpoint_to_watch,
+                    offset: entry.offset,
+                    block_height,
+                    tx_index: entry.tx_index,
+                });
+            }
+        }
+        BlockEntries {
+            block_height,
+            inscriptions,
+            locations,
+            sequence_metadata: self
+                .get(SEQUENCE_METADATA_CF, &prefix, ctx)
+                .and_then(|value| {
+                    decode_sequence_metadata(&value)
+                        .map_err(|e| warn_corrupted_entry(SEQUENCE_METADATA_CF, e, ctx))
+                        .ok()
+                })
+                .into_iter()
+                .collect(),
+            block_hash: self
+                .get(BLOCK_HASHES_CF, &prefix, ctx)
+                .map(|bytes| String::from_utf8_lossy(&bytes).to_string()),
+        }
+    }
+
+    fn insert_journaled_inscription(
+        &self,
+        inscription: &JournaledInscription,
+        block_height: u64,
+        _ctx: &Context,
+    ) {
+        self.pending
+            .borrow_mut()
+            .inscriptions
+            .push(InscriptionEntry {
+                inscription_id: inscription.inscription_id.clone(),
+                block_height,
+                ordinal_number: inscription.ordinal_number,
+                inscription_number: OrdinalInscriptionNumber {
+                    classic: inscription.classic_inscription_number,
+                    jubilee: inscription.jubilee_inscription_number,
+                },
+                input_index: inscription.input_index,
+            });
+    }
+
+    fn insert_block_undo_journal(&self, journal: &BlockUndoJournal, _ctx: &Context) {
+        self.pending
+            .borrow_mut()
+            .undo_journals
+            .insert(journal.block_height, journal.clone());
+    }
+
+    fn find_block_undo_journal(
+        &self,
+        block_height: u64,
+        ctx: &Context,
+    ) -> Option<BlockUndoJournal> {
+        if let Some(journal) = self.pending.borrow().undo_journals.get(&block_height) {
+            return Some(journal.clone());
+        }
+        self.get(UNDO_JOURNAL_CF, &block_height.to_be_bytes(), ctx)
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+    }
+
+    fn prune_block_undo_journals(&self, block_height: u64, _ctx: &Context) {
+        let mut pending = self.pending.borrow_mut();
+        let undo_journals = pending.undo_journals.split_off(&(block_height + 1));
+        pending.undo_journals = undo_journals;
+        pending.pruned_undo_journals = pending.pruned_undo_journals.max(Some(block_height));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::TempDir;
+    use rocksdb::Options;
+
+    #[test]
+    fn test_rocks_db_inscription_store() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("rocksdb");
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let mut db = DB::open(&opts, dir.path()).unwrap();
+        create_inscriptions_column_families(&mut db, &Config::devnet_default().resources).unwrap();
+        let store = RocksDbInscriptionStore::new(&db).unwrap();
+
+        let txid = "b61b0172d95e266c18aea0c624db987e971a5d6d4ebc2aaed85da4642d635735";
+        let outpoint = format!("{txid}:0");
+        store.insert_location(1_252_201_400_444_387, &outpoint, 0, 767_430, 1, &ctx);
+        store.insert_sequence_metadata(
+            767_430,
+            &SequenceMetadata {
+                nth_classic_pos_number: 0,
+                nth_classic_neg_number: -1,
+                nth_jubilee_number: 0,
+            },
+            &ctx,
+        );
+
+        // Pending writes are visible through the store, but not persisted
+        assert_eq!(
+            store.find_inscriptions_at_watched_outpoint(&outpoint, &ctx)[0].ordinal_number,
+            1_252_201_400_444_387
+        );
+        store.discard();
+        assert!(store
+            .find_inscriptions_at_watched_outpoint(&outpoint, &ctx)
+            .is_empty());
+
+        store.insert_location(1_252_201_400_444_387, &outpoint, 0, 767_430, 1, &ctx);
+        store.insert_sequence_metadata(
+            767_430,
+            &SequenceMetadata {
+                nth_classic_pos_number: 0,
+                nth_classic_neg_number: -1,
+                nth_jubilee_number: 0,
+            },
+            &ctx,
+        );
+        store.commit(767_430, Some(&[1, 2, 3]), &ctx);
+        assert!(store.any_entry_at_block_height(767_430, &ctx));
+        assert_eq!(
+            store.find_nth_classic_neg_number_at_block_height(&767_431, &ctx),
+            Some(-1)
+        );
+        assert_eq!(
+            store.find_nth_classic_neg_number_at_block_height(&767_430, &ctx),
+            None
+        );
+        assert_eq!(
+            db.get(767_430u32.to_be_bytes()).unwrap(),
+            Some(vec![1, 2, 3])
+        );
+
+        store.delete_data_in_block_range(767_430, 767_430, &ctx);
+        assert!(!store.any_entry_at_block_height(767_430, &ctx));
+        assert!(store
+            .find_inscriptions_at_watched_outpoint(&outpoint, &ctx)
+            .is_empty());
+        assert_eq!(
+            store.find_nth_classic_neg_number_at_block_height(&767_431, &ctx),
+            None
+        );
+
+        drop(store);
+        drop(db);
+    }
+
+    #[test]
+    fn test_rocks_db_undo_journal() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("rocksdb");
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let mut db = DB::open(&opts, dir.path()).unwrap();
+        create_inscriptions_column_families(&mut db, &Config::devnet_default().resources).unwrap();
+        let store = RocksDbInscriptionStore::new(&db).unwrap();
+
+        let txid = "b61b0172d95e266c18aea0c624db987e971a5d6d4ebc2aaed85da4642d635735";
+        store.insert_location(
+            1_252_201_400_444_387,
+            &format!("{txid}:0"),
+            0,
+            767_430,
+            1,
+            &ctx,
+        );
+        store.insert_sequence_metadata(
+            767_430,
+            &SequenceMetadata {
+                nth_classic_pos_number: 0,
+                nth_classic_neg_number: -1,
+                nth_jubilee_number: 0,
+            },
+            &ctx,
+        );
+        store.insert_block_hash(
+            &BlockIdentifier {
+                index: 767_430,
+                hash: "0x767430".into(),
+            },
+            &ctx,
+        );
+        store.commit(767_430, None, &ctx);
+
+        let entries = store.find_block_entries(767_430, &ctx);
+        assert_eq!(entries.locations.len(), 1);
+        assert_eq!(entries.locations[0].outpoint_to_watch, format!("{txid}:0"));
+        assert_eq!(entries.sequence_metadata.len(), 1);
+        assert_eq!(entries.block_hash, Some("0x767430".to_string()));
+
+        // Entries are restored as found
+        store.delete_data_in_block_range(767_430, 767_430, &ctx);
+        assert!(!store.any_entry_at_block_height(767_430, &ctx));
+        store.insert_block_entries(&entries, &ctx);
+        store.commit(767_430, None, &ctx);
+        assert_eq!(store.find_block_entries(767_430, &ctx), entries);
+
+        let journal = BlockUndoJournal {
+            block_height: 767_431,
+            block_hash: "0x767431".into(),
+            overwritten_entries: BlockEntries {
+                block_height: 767_431,
+                ..Default::default()
+            },
+        };
+        store.insert_block_undo_journal(&journal, &ctx);
+        store.commit(767_431, None, &ctx);
+        assert_eq!(store.find_block_undo_journal(767_431, &ctx), Some(journal));
+        store.prune_block_undo_journals(767_431, &ctx);
+        store.commit(767_432, None, &ctx);
+        assert_eq!(store.find_block_undo_journal(767_431, &ctx), None);
+
+        drop(store);
+        drop(db);
+    }
+
+    #[test]
+    fn test_decode_short_entries() {
+        assert!(InscriptionEntry::decode(&[0; 8], &[0; 24]).is_err());
+        assert!(InscriptionEntry::decode(&[0; 3], &[0; 32]).is_err());
+        assert!(LocationEntry::decode(&[0; 16], &[0; 16]).is_err());
+        assert!(LocationEntry::decode(b"txid:0/0000000011111111", &[0; 8]).is_err());
+        assert!(decode_sequence_metadata(&[0; 16]).is_err());
+
+        let entry = LocationEntry::decode(b"txid:0/0000000011111111", &[0; 16]).unwrap();
+        assert_eq!(entry.outpoint_to_watch, "txid:0");
+        assert_eq!(entry.offset, u64::from_be_bytes(*b"00000000"));
+        assert_eq!(entry.ordinal_number, u64::from_be_bytes(*b"11111111"));
+    }
+}
@@ -0,0 +1,201 @@
+use std::thread::sleep;
+use std::time::Duration;
+
+use chainhook_sdk::utils::Context;
+use rocksdb::DB;
+
+const SYNC_CHECKPOINT_KEY: &[u8] = b"metadata::sync_checkpoint";
+
+/// Progress of each stage of the sync, persisted in hord.rocksdb and updated as the post
+/// processors commit blocks, so that an interrupted sync resumes every stage where it stopped.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SyncCheckpoint {
+    /// Highest block fetched and stored in hord.rocksdb. Blocks only archived are stored out
+    /// of order, so some of the blocks below can be missing.
+    pub downloaded: Option<u64>,
+    /// Highest block such that all the blocks up to it are stored in hord.rocksdb.
+    pub archived: Option<u64>,
+    /// Highest block whose inscriptions were indexed.
+    pub sequenced: Option<u64>,
+}
+
+fn next_block_height(block_height: Option<u64>) -> u64 {
+    block_height.map_or(0, |block_height| block_height + 1)
+}
+
+fn is_block_archived(block_height: u64, blocks_db: &DB) -> bool {
+    matches!(
+        blocks_db.get_pinned((block_height as u32).to_be_bytes()),
+        Ok(Some(_))
+    )
+}
+
+impl SyncCheckpoint {
+    /// Height from which the blocks have to be sequenced, blocks below being only archived.
+    pub fn sequencing_start(&self, first_inscription_height: u64) -> u64 {
+        next_block_height(self.sequenced).max(first_inscription_height)
+    }
+
+    /// Lowest height the sync has to resume from, across all the stages.
+    pub fn resume_height(&self, first_inscription_height: u64) -> u64 {
+        next_block_height(self.archived).min(self.sequencing_start(first_inscription_height))
+    }
+
+    /// Drop the blocks already stored in hord.rocksdb from the blocks to archive. Blocks from
+    /// `start_sequencing` are all kept, the pipeline sequencing them in order.
+    pub fn filter_blocks_to_download(
+        &self,
+        blocks: Vec<u64>,
+        start_sequencing: u64,
+        blocks_db: &DB,
+    ) -> Vec<u64> {
+        blocks
+            .into_iter()
+            .filter(|block_height| {
+                if *block_height >= start_sequencing {
+                    return true;
+                }
+                if *block_height < next_block_height(self.archived) {
+                    return false;
+                }
+                *block_height >= next_block_height(self.downloaded)
+                    || !is_block_archived(*block_height, blocks_db)
+            })
+            .collect()
+    }
+
+    /// Record blocks stored in hord.rocksdb, advancing `archived` over the blocks stored
+    /// contiguously.
+    pub fn record_archived_blocks(&mut self, block_heights: &[u64], blocks_db: &DB) {
+        let Some(highest) = block_heights.iter().max() else {
+            return;
+        };
+        self.downloaded = self.downloaded.max(Some(*highest));
+        let mut next = next_block_height(self.archived);
+        while Some(next) <= self.downloaded
+            && (block_heights.contains(&next) || is_block_archived(next, blocks_db))
+        {
+            self.archived = Some(next);
+            next += 1;
+        }
+    }
+
+    pub fn record_sequenced_block(&mut self, block_height: u64) {
+        self.sequenced = self.sequenced.max(Some(block_height));
+    }
+
+    /// Forget the progress made from `block_height`, when blocks are rolled back.
+    pub fn rewind(&mut self, block_height: u64) {
+        let last_kept = block_height.checked_sub(1);
+        self.downloaded = self.downloaded.min(last_kept);
+        self.archived = self.archived.min(last_kept);
+        self.sequenced = self.sequenced.min(last_kept);
+    }
+}
+
+pub fn find_sync_checkpoint(blocks_db: &DB) -> Option<SyncCheckpoint> {
+    match blocks_db.get(SYNC_CHECKPOINT_KEY) {
+        Ok(Some(bytes)) => serde_json::from_slice(&bytes).ok(),
+        _ => None,
+    }
+}
+
+pub fn write_sync_checkpoint(checkpoint: &SyncCheckpoint, blocks_db_rw: &DB, ctx: &Context) {
+    let value = serde_json::to_vec(checkpoint).expect("unable to serialize sync checkpoint");
+    while let Err(e) = blocks_db_rw.put(SYNC_CHECKPOINT_KEY, &value) {
+        ctx.try_log(|logger| warn!(logger, "unable to write sync checkpoint: {}", e.to_string()));
+        sleep(Duration::from_secs(1));
+    }
+}
+
+/// Apply `update` to the persisted checkpoint. Databases without checkpoint are left untouched,
+/// their checkpoint being initialized by the next sync (see `get_sync_checkpoint`).
+pub fn update_sync_checkpoint<F>(blocks_db_rw: &DB, ctx: &Context, update: F)
+where
+    F: FnOnce(&mut SyncCheckpoint),
+{
+    let Some(mut checkpoint) = find_sync_checkpoint(blocks_db_rw) else {
+        return;
+    };
+    update(&mut checkpoint);
+    write_sync_checkpoint(&checkpoint, blocks_db_rw, ctx);
+}
+
+/// Blocks applied live are both archived and sequenced.
+pub fn record_block_in_sync_checkpoint(block_height: u64, blocks_db_rw: &DB, ctx: &Context) {
+    update_sync_checkpoint(blocks_db_rw, ctx, |checkpoint| {
+        checkpoint.record_archived_blocks(&[block_height], blocks_db_rw);
+        checkpoint.record_sequenced_block(block_height);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use chainhook_sdk::utils::Context;
+    use rocksdb::DB;
+
+    use super::{
+        find_sync_checkpoint, update_sync_checkpoint, write_sync_checkpoint, SyncCheckpoint,
+    };
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_sync_checkpoint_progress() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("checkpoint");
+        let mut opts = rocksdb::Options::default();
+        opts.create_if_missing(true);
+        let db = DB::open(&opts, dir.path()).unwrap();
+
+        // Without checkpoint, nothing is recorded
+        update_sync_checkpoint(&db, &ctx, |checkpoint| checkpoint.record_sequenced_block(3));
+        assert_eq!(find_sync_checkpoint(&db), None);
+        write_sync_checkpoint(&SyncCheckpoint::default(), &db, &ctx);
+
+        // Blocks #2 and #3 archived before #0 and #1
+        for block_height in [2u32, 3] {
+            db.put(block_height.to_be_bytes(), b"block").unwrap();
+        }
+        update_sync_checkpoint(&db, &ctx, |checkpoint| {
+            checkpoint.record_archived_blocks(&[2, 3], &db)
+        });
+        let checkpoint = find_sync_checkpoint(&db).unwrap();
+        assert_eq!(checkpoint.downloaded, Some(3));
+        assert_eq!(checkpoint.archived, None);
+        assert_eq!(checkpoint.resume_height(10), 0);
+        assert_eq!(
+            checkpoint.filter_blocks_to_download((0..=12).collect(), 10, &db),
+            vec![0, 1, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+        );
+
+        db.put(0u32.to_be_bytes(), b"block").unwrap();
+        db.put(1u32.to_be_bytes(), b"block").unwrap();
+        update_sync_checkpoint(&db, &ctx, |checkpoint| {
+            checkpoint.record_archived_blocks(&[0, 1], &db)
+        });
+        let checkpoint = find_sync_checkpoint(&db).unwrap();
+        assert_eq!(checkpoint.archived, Some(3));
+        assert_eq!(checkpoint.resume_height(10), 4);
+
+        // Blocks already sequenced are only archived
+        let mut checkpoint = SyncCheckpoint {
+            downloaded: Some(3),
+            archived: Some(3),
+            sequenced: Some(11),
+        };
+        assert_eq!(checkpoint.sequencing_start(10), 12);
+        assert_eq!(checkpoint.resume_height(10), 4);
+
+        checkpoint.rewind(2);
+        assert_eq!(
+            checkpoint,
+            SyncCheckpoint {
+                downloaded: Some(1),
+                archived: Some(1),
+                sequenced: Some(1),
+            }
+        );
+        checkpoint.rewind(0);
+        assert_eq!(checkpoint, SyncCheckpoint::default());
+    }
+}
@@ -0,0 +1,634 @@
+use chainhook_sdk::utils::Context;
+use dashmap::DashMap;
+use fxhash::{FxBuildHasher, FxHasher};
+use rocksdb::{ColumnFamily, Direction, IteratorMode, Options, WriteBatch, DB};
+use std::hash::BuildHasherDefault;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+
+use super::{TransactionBytesCursor, TransactionInputBytesCursor};
+use crate::config::MemoryBudget;
+
+/// Once the memory budget is exceeded, entries are evicted until the memory in use is back to
+/// this share of the budget (in %), so that evictions are performed in bulk.
+const EVICTION_TARGET_PERCENT: u64 = 90;
+
+struct TraversalsCacheEntry {
+    tx: TransactionBytesCursor,
+    /// Read since the entry was last considered for eviction.
+    referenced: AtomicBool,
+    /// Inserted since the last `save_traversals_cache`.
+    unsaved: AtomicBool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraversalsCacheStats {
+    pub entries: usize,
+    pub size: u64,
+    pub memory_budget: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// Transactions traversed while computing satoshi numbers (L2 cache), shared by the workers
+/// of the thread pool. The cache is bounded by a `MemoryBudget`, possibly shared with other
+/// caches: once it is exceeded, entries are evicted in insertion order, the ones read since
+/// they were last considered being given a second chance (CLOCK).
+pub struct TraversalsCache {
+    entries: DashMap<(u32, [u8; 8]), TraversalsCacheEntry, BuildHasherDefault<FxHasher>>,
+    /// Keys in the order they are considered for eviction.
+    eviction_queue: (Sender<(u32, [u8; 8])>, Receiver<(u32, [u8; 8])>),
+    memory_budget: MemoryBudget,
+    size: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    eviction_lock: Mutex<()>,
+}
+
+impl TraversalsCache {
+    pub fn new(memory_budget: MemoryBudget) -> TraversalsCache {
+        TraversalsCache {
+            entries: DashMap::with_hasher(FxBuildHasher::default()),
+            eviction_queue: unbounded(),
+            memory_budget,
+            size: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            eviction_lock: Mutex::new(()),
+        }
+    }
+
+    /// Approximate memory used by an entry, including its key (in the map and in the eviction
+    /// queue).
+    fn entry_size(tx: &TransactionBytesCursor) -> u64 {
+        (2 * size_of::<(u32, [u8; 8])>()
+            + size_of::<TraversalsCacheEntry>()
+            + tx.inputs.capacity() * size_of::<TransactionInputBytesCursor>()
+            + tx.outputs.capacity() * size_of::<u64>()) as u64
+    }
+
+    pub fn get(&self, key: &(u32, [u8; 8])) -> Option<TransactionBytesCursor> {
+        match self.entries.get(key) {
+            Some(entry) => {
+                entry.referenced.store(true, Ordering::Relaxed);
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(entry.tx.clone())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, key: (u32, [u8; 8]), tx: TransactionBytesCursor) {
+        self.insert_entry(key, tx, true);
+    }
+
+    fn insert_entry(&self, key: (u32, [u8; 8]), tx: TransactionBytesCursor, unsaved: bool) {
+        let size = TraversalsCache::entry_size(&tx);
+        let entry = TraversalsCacheEntry {
+            tx,
+            referenced: AtomicBool::new(false),
+            unsaved: AtomicBool::new(unsaved),
+        };
+        match self.entries.insert(key, entry) {
+            Some(previous_entry) => {
+                self.release(TraversalsCache::entry_size(&previous_entry.tx));
+            }
+            None => {
+                let _ = self.eviction_queue.0.send(key);
+            }
+        }
+        self.size.fetch_add(size, Ordering::Relaxed);
+        if self.memory_budget.allocate(size) > self.memory_budget.limit() {
+            self.evict();
+        }
+    }
+
+    fn release(&self, size: u64) {
+        self.size.fetch_sub(size, Ordering::Relaxed);
+        self.memory_budget.release(size);
+    }
+
+    /// Evict entries until the memory in use is back under the eviction target, or until this
+    /// cache is empty. Each step is only considering the next key of the eviction queue.
+    fn evict(&self) {
+        // Another worker is already evicting
+        let Ok(_guard) = self.eviction_lock.try_lock() else {
+            return;
+        };
+        let target = self.memory_budget.limit() * EVICTION_TARGET_PERCENT / 100;
+        while self.memory_budget.in_use() > target {
+            let Ok(key) = self.eviction_queue.1.try_recv() else {
+                break;
+            };
+            // Entry read since last considered: second chance
+            if let Some(entry) = self.entries.get(&key) {
+                if entry.referenced.swap(false, Ordering::Relaxed) {
+                    let _ = self.eviction_queue.0.send(key);
+                    continue;
+                }
+            }
+            // Entries removed since queued are skipped
+            if let Some((_, entry)) = self.entries.remove(&key) {
+                self.release(TraversalsCache::entry_size(&entry.tx));
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Remove the transactions confirmed at or above `block_height`, rolled back by a re-org.
+    pub fn remove_from_block_height(&self, block_height: u32) {
+        self.entries.retain(|(entry_block_height, _), entry| {
+            if *entry_block_height < block_height {
+                return true;
+            }
+            self.release(TraversalsCache::entry_size(&entry.tx));
+            false
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn stats(&self) -> TraversalsCacheStats {
+        TraversalsCacheStats {
+            entries: self.entries.len(),
+            size: self.size.load(Ordering::Relaxed),
+            memory_budget: self.memory_budget.limit(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for TraversalsCache {
+    fn drop(&mut self) {
+        self.memory_budget
+            .release(self.size.load(Ordering::Relaxed));
+    }
+}
+
+/// Column family of hord.rocksdb where the L2 traversals cache is persisted across restarts.
+const TRAVERSALS_CACHE_CF: &str = "traversals_cache";
+
+// 'e' | block_height (4) | txid (8) -> sequence (8) | transaction
+// 'q' | sequence (8) -> block_height (4) | txid (8)
+// 'm' -> next sequence (8) | total size of the entries (8)
+// Entries are evicted by ascending sequence, a sequence being assigned each time an entry is saved.
+const ENTRY_PREFIX: u8 = b'e';
+const SEQUENCE_PREFIX: u8 = b'q';
+const METADATA_KEY: &[u8] = b"m";
+
+pub fn create_traversals_cache_column_family(db: &mut DB) -> Result<(), String> {
+    if db.cf_handle(TRAVERSALS_CACHE_CF).is_none() {
+        db.create_cf(TRAVERSALS_CACHE_CF, &Options::default())
+            .map_err(|e| {
+                format!(
+                    "unable to create column family {TRAVERSALS_CACHE_CF}: {}",
+                    e.to_string()
+                )
+            })?;
+    }
+    Ok(())
+}
+
+fn entry_key(block_height: u32, txid: &[u8; 8]) -> Vec<u8> {
+    let mut key = vec![ENTRY_PREFIX];
+    key.extend_from_slice(&block_height.to_be_bytes());
+    key.extend_from_slice(txid);
+    key
+}
+
+fn sequence_key(sequence: u64) -> Vec<u8> {
+    let mut key = vec![SEQUENCE_PREFIX];
+    key.extend_from_slice(&sequence.to_be_bytes());
+    key
+}
+
+// inputs_len (2) | outputs_len (2) | inputs (txin (8) | block_height (4) | vout (2) | txin_value (8)) | outputs (8)
+fn serialize_transaction(tx: &TransactionBytesCursor) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(4 + tx.inputs.len() * 22 + tx.outputs.len() * 8);
+    bytes.extend_from_slice(&(tx.inputs.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(&(tx.outputs.len() as u16).to_be_bytes());
+    for input in tx.inputs.iter() {
+        bytes.extend_from_slice(&input.txin);
+        bytes.extend_from_slice(&input.block_height.to_be_bytes());
+        bytes.extend_from_slice(&input.vout.to_be_bytes());
+        bytes.extend_from_slice(&input.txin_value.to_be_bytes());
+    }
+    for output_value in tx.outputs.iter() {
+        bytes.extend_from_slice(&output_value.to_be_bytes());
+    }
+    bytes
+}
+
+fn deserialize_transaction(txid: [u8; 8], bytes: &[u8]) -> Option<TransactionBytesCursor> {
+    let inputs_len = u16::from_be_bytes(bytes.get(0..2)?.try_into().ok()?) as usize;
+    let outputs_len = u16::from_be_bytes(bytes.get(2..4)?.try_into().ok()?) as usize;
+    if bytes.len() != 4 + inputs_len * 22 + outputs_len * 8 {
+        return None;
+    }
+    let mut inputs = Vec::with_capacity(inputs_len);
+    let mut pos = 4;
+    for _ in 0..inputs_len {
+        let input = &bytes[pos..pos + 22];
+        inputs.push(TransactionInputBytesCursor {
+            txin: input[0..8].try_into().ok()?,
+            block_height: u32::from_be_bytes(input[8..12].try_into().ok()?),
+            vout: u16::from_be_bytes(input[12..14].try_into().ok()?),
+            txin_value: u64::from_be_bytes(input[14..22].try_into().ok()?),
+        });
+        pos += 22;
+    }
+    let outputs = bytes[pos..]
+        .chunks_exact(8)
+        .map(|output| u64::from_be_bytes(output.try_into().unwrap()))
+        .collect();
+    Some(TransactionBytesCursor {
+        txid,
+        inputs,
+        outputs,
+    })
+}
+
+fn write_metadata(batch: &mut WriteBatch, cf: &ColumnFamily, next_sequence: u64, total_size: u64) {
+    let mut metadata = next_sequence.to_be_bytes().to_vec();
+    metadata.extend_from_slice(&total_size.to_be_bytes());
+    batch.put_cf(cf, METADATA_KEY, metadata);
+}
+
+fn read_metadata(db: &DB, cf: &ColumnFamily) -> Result<(u64, u64), String> {
+    match db.get_cf(cf, METADATA_KEY) {
+        Ok(Some(bytes)) if bytes.len() == 16 => Ok((
+            u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+        )),
+        Ok(_) => Ok((0, 0)),
+        Err(e) => Err(format!(
+            "unable to read traversals cache metadata: {}",
+            e.to_string()
+        )),
+    }
+}
+
+/// Save the entries inserted in `traversals_cache` since the last save (refreshing the ones already
+/// saved), then evict the entries saved least recently until the column family fits in `size_cap`
+/// bytes. Returns the number of entries saved and evicted.
+pub fn save_traversals_cache(
+    traversals_cache: &TraversalsCache,
+    size_cap: u64,
+    db: &mut DB,
+    _ctx: &Context,
+) -> Result<(usize, usize), String> {
+    create_traversals_cache_column_family(db)?;
+    let cf = db.cf_handle(TRAVERSALS_CACHE_CF).unwrap();
+    let (mut next_sequence, mut total_size) = read_metadata(db, cf)?;
+
+    let mut batch = WriteBatch::default();
+    let mut saved_keys = vec![];
+    for entry in traversals_cache.entries.iter() {
+        if !entry.unsaved.load(Ordering::Relaxed) {
+            continue;
+        }
+        let (block_height, txid) = entry.key();
+        let key = entry_key(*block_height, txid);
+        let previous_value = db
+            .get_cf(cf, &key)
+            .map_err(|e| format!("unable to read traversals cache: {}", e.to_string()))?;
+        if let Some(previous_value) = previous_value {
+            if let Some(sequence) = previous_value.get(0..8) {
+                batch.delete_cf(
+                    cf,
+                    sequence_key(u64::from_be_bytes(sequence.try_into().unwrap())),
+                );
+            }
+            total_size = total_size.saturating_sub((key.len() + previous_value.len()) as u64);
+        }
+        let mut value = next_sequence.to_be_bytes().to_vec();
+        value.extend(serialize_transaction(&entry.tx));
+        batch.put_cf(cf, sequence_key(next_sequence), &key[1..]);
+        total_size += (key.len() + value.len()) as u64;
+        batch.put_cf(cf, &key, value);
+        next_sequence += 1;
+        saved_keys.push(*entry.key());
+    }
+    db.write(batch)
+        .map_err(|e| format!("unable to save traversals cache: {}", e.to_string()))?;
+    for key in saved_keys.iter() {
+        if let Some(entry) = traversals_cache.entries.get(key) {
+            entry.unsaved.store(false, Ordering::Relaxed);
+        }
+    }
+
+    let mut batch = WriteBatch::default();
+    let mut evicted = 0;
+    if total_size > size_cap {
+        let iter = db.iterator_cf(
+            cf,
+            IteratorMode::From(&[SEQUENCE_PREFIX], Direction::Forward),
+        );
+        for item in iter {
+            let (oldest_sequence_key, entry_suffix) =
+                item.map_err(|e| format!("unable to read traversals cache: {}", e.to_string()))?;
+            if oldest_sequence_key[0] != SEQUENCE_PREFIX || total_size <= size_cap {
+                break;
+            }
+            let mut key = vec![ENTRY_PREFIX];
+            key.extend_from_slice(&entry_suffix);
+            if let Ok(Some(value)) = db.get_cf(cf, &key) {
+                total_size = total_size.saturating_sub((key.len() + value.len()) as u64);
+            }
+            batch.delete_cf(cf, &key);
+            batch.delete_cf(cf, &oldest_sequence_key);
+            evicted += 1;
+        }
+    }
+    write_metadata(&mut batch, cf, next_sequence, total_size);
+    db.write(batch)
+        .map_err(|e| format!("unable to evict traversals cache: {}", e.to_string()))?;
+
+    Ok((saved_keys.len(), evicted))
+}
+
+/// Delete the entries saved for the transactions confirmed at or above `block_height`, rolled
+/// back by a re-org. Returns the number of entries deleted.
+pub fn delete_traversals_cache_entries_from_block_height(
+    block_height: u32,
+    db: &DB,
+) -> Result<usize, String> {
+    let Some(cf) = db.cf_handle(TRAVERSALS_CACHE_CF) else {
+        return Ok(0);
+    };
+    let (next_sequence, mut total_size) = read_metadata(db, cf)?;
+    let mut batch = WriteBatch::default();
+    let mut deleted = 0;
+    let iter = db.iterator_cf(
+        cf,
+        IteratorMode::From(&entry_key(block_height, &[0; 8]), Direction::Forward),
+    );
+    for item in iter {
+        let (key, value) =
+            item.map_err(|e| format!("unable to read traversals cache: {}", e.to_string()))?;
+        if key[0] != ENTRY_PREFIX {
+            break;
+        }
+        if let Some(sequence) = value.get(0..8) {
+            batch.delete_cf(
+                cf,
+                sequence_key(u64::from_be_bytes(sequence.try_into().unwrap())),
+            );
+        }
+        batch.delete_cf(cf, &key);
+        total_size = total_size.saturating_sub((key.len() + value.len()) as u64);
+        deleted += 1;
+    }
+    if deleted > 0 {
+        write_metadata(&mut batch, cf, next_sequence, total_size);
+        db.write(batch)
+            .map_err(|e| format!("unable to delete traversals cache: {}", e.to_string()))?;
+    }
+    Ok(deleted)
+}
+
+/// Load the entries saved by `save_traversals_cache` in `traversals_cache`.
+/// Returns the number of entries loaded.
+pub fn load_traversals_cache(
+    traversals_cache: &TraversalsCache,
+    db: &DB,
+    ctx: &Context,
+) -> Result<usize, String> {
+    let Some(cf) = db.cf_handle(TRAVERSALS_CACHE_CF) else {
+        return Ok(0);
+    };
+    let mut loaded = 0;
+    let iter = db.iterator_cf(cf, IteratorMode::From(&[ENTRY_PREFIX], Direction::Forward));
+    for item in iter {
+        let (key, value) =
+            item.map_err(|e| format!("unable to read traversals cache: {}", e.to_string()))?;
+        if key[0] != ENTRY_PREFIX {
+            break;
+        }
+        let block_height = u32::from_be_bytes(key[1..5].try_into().unwrap());
+        let txid: [u8; 8] = key[5..13].try_into().unwrap();
+        // sequence (8) | transaction
+        match value
+            .get(8..)
+            .and_then(|bytes| deserialize_transaction(txid, bytes))
+        {
+            Some(tx) => {
+                traversals_cache.insert_entry((block_height, txid), tx, false);
+                loaded += 1;
+            }
+            None => ctx.try_log(|logger| {
+                warn!(
+                    logger,
+                    "Skipping corrupted traversals cache entry (block #{block_height}, {})",
+                    hex::encode(txid)
+                )
+            }),
+        }
+    }
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn transaction(txid: u8, block_height: u32) -> TransactionBytesCursor {
+        TransactionBytesCursor {
+            txid: [txid; 8],
+            inputs: vec![TransactionInputBytesCursor {
+                txin: [txid + 1; 8],
+                block_height,
+                vout: 1,
+                txin_value: 10_000,
+            }],
+            outputs: vec![5_000, 4_000],
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_traversals_cache() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("traversals");
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let mut db = DB::open(&opts, dir.path()).unwrap();
+
+        // Each entry (1 input, 2 outputs) takes 63 bytes: a 13 bytes key and a 50 bytes value
+        let cache = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        cache.insert((1, [1; 8]), transaction(1, 0));
+        cache.insert((2, [2; 8]), transaction(2, 1));
+        assert_eq!(
+            save_traversals_cache(&cache, 1_000, &mut db, &ctx),
+            Ok((2, 0))
+        );
+
+        // Entries saved again are refreshed, the least recently saved is evicted
+        let cache = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        cache.insert((1, [1; 8]), transaction(1, 0));
+        cache.insert((3, [3; 8]), transaction(3, 2));
+        assert_eq!(
+            save_traversals_cache(&cache, 126, &mut db, &ctx),
+            Ok((2, 1))
+        );
+
+        // Only the entries inserted since the last save are saved
+        assert_eq!(
+            save_traversals_cache(&cache, 126, &mut db, &ctx),
+            Ok((0, 0))
+        );
+
+        let loaded = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        assert_eq!(load_traversals_cache(&loaded, &db, &ctx), Ok(2));
+        assert_eq!(
+            save_traversals_cache(&loaded, 126, &mut db, &ctx),
+            Ok((0, 0))
+        );
+        assert!(loaded.get(&(2, [2; 8])).is_none());
+        let tx = loaded.get(&(3, [3; 8])).unwrap();
+        assert_eq!(tx.txid, [3; 8]);
+        assert_eq!(tx.inputs[0].txin, [4; 8]);
+        assert_eq!(tx.inputs[0].block_height, 2);
+        assert_eq!(tx.inputs[0].vout, 1);
+        assert_eq!(tx.inputs[0].txin_value, 10_000);
+        assert_eq!(tx.outputs, vec![5_000, 4_000]);
+    }
+
+    #[test]
+    fn test_traversals_cache_entries_rolled_back() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("traversals");
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let mut db = DB::open(&opts, dir.path()).unwrap();
+
+        let cache = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        for i in 1..=4 {
+            cache.insert((i as u32, [i; 8]), transaction(i, 0));
+        }
+        assert_eq!(
+            save_traversals_cache(&cache, 1_000, &mut db, &ctx),
+            Ok((4, 0))
+        );
+
+        // Blocks #3 and #4 are rolled back
+        assert_eq!(
+            delete_traversals_cache_entries_from_block_height(3, &db),
+            Ok(2)
+        );
+        cache.remove_from_block_height(3);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(
+            cache.stats().size,
+            2 * TraversalsCache::entry_size(&transaction(0, 0))
+        );
+
+        let loaded = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        assert_eq!(load_traversals_cache(&loaded, &db, &ctx), Ok(2));
+        assert!(loaded.get(&(2, [2; 8])).is_some());
+        assert!(loaded.get(&(3, [3; 8])).is_none());
+
+        // The size of the entries deleted is released: 3 entries of 63 bytes fit in 200 bytes
+        cache.insert((5, [5; 8]), transaction(5, 0));
+        assert_eq!(
+            save_traversals_cache(&cache, 200, &mut db, &ctx),
+            Ok((1, 0))
+        );
+    }
+
+    #[test]
+    fn test_corrupted_traversals_cache_entries_skipped() {
+        let ctx = Context::empty();
+        let dir = TempDir::new("traversals");
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        let mut db = DB::open(&opts, dir.path()).unwrap();
+
+        let cache = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        cache.insert((1, [1; 8]), transaction(1, 0));
+        cache.insert((2, [2; 8]), transaction(2, 1));
+        assert_eq!(
+            save_traversals_cache(&cache, 1_000, &mut db, &ctx),
+            Ok((2, 0))
+        );
+
+        // Truncated value, shorter than its sequence
+        let cf = db.cf_handle(TRAVERSALS_CACHE_CF).unwrap();
+        db.put_cf(cf, entry_key(2, &[2; 8]), [0, 0, 1]).unwrap();
+
+        let loaded = TraversalsCache::new(MemoryBudget::new(1024 * 1024));
+        assert_eq!(load_traversals_cache(&loaded, &db, &ctx), Ok(1));
+        assert!(loaded.get(&(1, [1; 8])).is_some());
+        assert!(loaded.get(&(2, [2; 8])).is_none());
+        assert_eq!(
+            delete_traversals_cache_entries_from_block_height(1, &db),
+            Ok(2)
+        );
+    }
+
+    #[test]
+    fn test_least_recently_used_entries_evicted() {
+        let entry_size = TraversalsCache::entry_size(&transaction(0, 0));
+        let cache = TraversalsCache::new(MemoryBudget::new(10 * entry_size));
+        for i in 0..10 {
+            cache.insert((i as u32, [i; 8]), transaction(i, 0));
+        }
+        assert_eq!(cache.stats().evictions, 0);
+        assert!(cache.get(&(0, [0; 8])).is_some());
+        assert!(cache.get(&(42, [42; 8])).is_none());
+
+        // Over budget: back to 90% of the budget, entry 0 having been read since inserted
+        cache.insert((10, [10; 8]), transaction(10, 0));
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 9);
+        assert_eq!(stats.size, 9 * entry_size);
+        assert_eq!(stats.evictions, 2);
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert!(cache.get(&(0, [0; 8])).is_some());
+        assert!(cache.get(&(1, [1; 8])).is_none());
+        assert!(cache.get(&(2, [2; 8])).is_none());
+        assert!(cache.get(&(10, [10; 8])).is_some());
+    }
+
+    #[test]
+    fn test_memory_budget_shared_between_caches() {
+        let entry_size = TraversalsCache::entry_size(&transaction(0, 0));
+        let memory_budget = MemoryBudget::new(10 * entry_size);
+        let sidecar_cache = TraversalsCache::new(memory_budget.clone());
+        for i in 0..8 {
+            sidecar_cache.insert((i as u32, [i; 8]), transaction(i, 0));
+        }
+
+        // Over budget: the processor cache is only evicting its own entries
+        let processor_cache = TraversalsCache::new(memory_budget.clone());
+        for i in 8..11 {
+            processor_cache.insert((i as u32, [i; 8]), transaction(i, 0));
+        }
+        assert_eq!(sidecar_cache.len(), 8);
+        assert_eq!(processor_cache.len(), 1);
+        assert_eq!(memory_budget.in_use(), 9 * entry_size);
+
+        // Memory is released when a cache is dropped
+        drop(processor_cache);
+        assert_eq!(memory_budget.in_use(), 8 * entry_size);
+        sidecar_cache.remove_from_block_height(4);
+        assert_eq!(memory_budget.in_use(), 4 * entry_size);
+    }
+}
@@ -1,17 +1,21 @@
 use crate::config::Config;
-use crate::utils::read_file_content_at_path;
+use crate::snapshot::{compute_sha256_of_file, read_snapshot_manifest, verify_snapshot_manifest};
+use crate::utils::{read_file_content_at_path, write_file_content_at_path};
 use chainhook_sdk::types::BitcoinNetwork;
 use chainhook_sdk::utils::Context;
 use flate2::read::GzDecoder;
 use futures_util::StreamExt;
 use progressing::mapping::Bar as MappingBar;
 use progressing::Baring;
-use std::fs::{self, File};
-use std::io::{self, Cursor};
-use std::io::{Read, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, Write};
 use std::path::PathBuf;
 use tar::Archive;
 
+const SNAPSHOT_STAGING_DIR_NAME: &str = "snapshot.staging";
+const SNAPSHOT_ARCHIVE_FILE_NAME: &str = "snapshot.tar.gz";
+const MAX_DOWNLOAD_ATTEMPTS: u32 = 10;
+
 pub fn default_sqlite_file_path(_network: &BitcoinNetwork) -> String {
     format!("hord.sqlite").to_lowercase()
 }
@@ -28,150 +32,326 @@ pub async fn download_sqlite_file(config: &Config, ctx: &Context) -> Result<(),
         }
     });
 
-    // let remote_sha_url = config.expected_remote_ordinals_sqlite_sha256();
-    // let res = reqwest::get(&remote_sha_url)
-    //     .await
-    //     .or(Err(format!("Failed to GET from '{}'", &remote_sha_url)))?
-    //     .bytes()
-    //     .await
-    //     .or(Err(format!("Failed to GET from '{}'", &remote_sha_url)))?;
-
-    // let mut local_sha_file_path = destination_path.clone();
-    // local_sha_file_path.push(default_sqlite_sha_file_path(
-    //     &config.network.bitcoin_network,
-    // ));
-    // write_file_content_at_path(&local_sha_file_path, &res.to_vec())?;
+    let remote_sha_url = config.expected_remote_ordinals_sqlite_sha256();
+    let remote_sha_file = reqwest::get(&remote_sha_url)
+        .await
+        .or(Err(format!("Failed to GET from '{}'", &remote_sha_url)))?
+        .error_for_status()
+        .or(Err(format!("Failed to GET from '{}'", &remote_sha_url)))?
+        .bytes()
+        .await
+        .or(Err(format!("Failed to GET from '{}'", &remote_sha_url)))?;
+    let expected_sha256 = parse_sha256_file(&remote_sha_file)?;
 
     let file_url = config.expected_remote_ordinals_sqlite_url();
     if ctx.logger.is_some() {
         println!("=> {file_url}");
     }
-    let res = reqwest::get(&file_url)
-        .await
-        .or(Err(format!("Failed to GET from '{}'", &file_url)))?;
-
-    // Download chunks
-    let (tx, rx) = flume::bounded(0);
-    if res.status() == reqwest::StatusCode::OK {
-        let limit = res.content_length().unwrap_or(10_000_000_000) as i64;
-        let archive_tmp_file = PathBuf::from("db.tar");
-        let decoder_thread = std::thread::spawn(move || {
-            {
-                let input = ChannelRead::new(rx);
-                let mut decoder = GzDecoder::new(input);
-                let mut tmp = File::create(&archive_tmp_file).unwrap();
-                let mut buffer = [0; 512_000];
-                loop {
-                    match decoder.read(&mut buffer) {
-                        Ok(0) => break,
-                        Ok(n) => {
-                            if let Err(e) = tmp.write_all(&buffer[..n]) {
-                                let err = format!(
-                                    "unable to update compressed archive: {}",
-                                    e.to_string()
-                                );
-                                return Err(err);
-                            }
-                        }
-                        Err(e) => {
-                            let err =
-                                format!("unable to write compressed archive: {}", e.to_string());
-                            return Err(err);
-                        }
-                    }
+
+    // The archive is downloaded in a partial file that survives interruptions: the next
+    // attempt (or the next run) resumes from its current length.
+    let mut archive_path = destination_path.clone();
+    archive_path.push(SNAPSHOT_ARCHIVE_FILE_NAME);
+    let mut partial_archive_path = destination_path.clone();
+    partial_archive_path.push(format!("{SNAPSHOT_ARCHIVE_FILE_NAME}.partial"));
+
+    let mut attempts = 0;
+    loop {
+        match download_file_with_resume(&file_url, &partial_archive_path, ctx).await {
+            Ok(_) => break,
+            Err(e) => {
+                attempts += 1;
+                if attempts >= MAX_DOWNLOAD_ATTEMPTS {
+                    return Err(e);
                 }
-                let _ = tmp.flush();
+                ctx.try_log(|logger| {
+                    warn!(
+                        logger,
+                        "{}, resuming download (attempt {}/{})",
+                        e,
+                        attempts + 1,
+                        MAX_DOWNLOAD_ATTEMPTS
+                    )
+                });
+                tokio::time::sleep(std::time::Duration::from_secs(3)).await;
             }
-            let archive_file = File::open(&archive_tmp_file).unwrap();
-            let mut archive = Archive::new(archive_file);
-            if let Err(e) = archive.unpack(&destination_path) {
-                let err = format!("unable to decompress file: {}", e.to_string());
-                return Err(err);
-            }
-            let _ = fs::remove_file(archive_tmp_file);
-            Ok(())
-        });
-
-        let mut progress_bar = MappingBar::with_range(0i64, limit);
-        progress_bar.set_len(60);
-        let mut stdout = std::io::stdout();
-        if ctx.logger.is_some() {
-            print!("{}", progress_bar);
-            let _ = stdout.flush();
         }
-        let mut stream = res.bytes_stream();
-        let mut progress = 0;
-        let mut steps = 0;
-        let mut tx_err = None;
-        while let Some(item) = stream.next().await {
-            let chunk = item.or(Err(format!("Error while downloading file")))?;
-            if chunk.is_empty() {
-                continue;
+    }
+
+    if let Err(e) = verify_file_sha256(&partial_archive_path, &expected_sha256) {
+        // Corrupted archives can not be resumed, the next attempt will start from scratch.
+        discard_partial_file(&partial_archive_path);
+        return Err(e);
+    }
+    let _ = fs::remove_file(validator_path(&partial_archive_path));
+    fs::rename(&partial_archive_path, &archive_path)
+        .map_err(|e| format!("unable to move {}: {}", archive_path.display(), e))?;
+
+    // The archive is unpacked in a staging directory, and only moved in place
+    // once its manifest has been checked.
+    let mut staging_path = destination_path.clone();
+    staging_path.push(SNAPSHOT_STAGING_DIR_NAME);
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path)
+            .map_err(|e| format!("unable to clean {}: {}", staging_path.display(), e))?;
+    }
+    let unpack_path = staging_path.clone();
+    let archive_file = File::open(&archive_path)
+        .map_err(|e| format!("unable to open {}: {}", archive_path.display(), e))?;
+    tokio::task::spawn_blocking(move || {
+        let mut archive = Archive::new(GzDecoder::new(BufReader::new(archive_file)));
+        archive
+            .unpack(&unpack_path)
+            .map_err(|e| format!("unable to decompress file: {}", e.to_string()))
+    })
+    .await
+    .map_err(|e| format!("unable to decompress file: {}", e.to_string()))??;
+    let _ = fs::remove_file(&archive_path);
+
+    match read_snapshot_manifest(&staging_path)? {
+        Some(manifest) => {
+            if let Err(e) = verify_snapshot_manifest(&manifest, config, ctx) {
+                let _ = fs::remove_dir_all(&staging_path);
+                return Err(format!("snapshot refused: {e}"));
             }
-            progress += chunk.len() as i64;
-            steps += chunk.len() as i64;
-            if steps > 5_000_000 {
-                steps = 0;
+            ctx.try_log(|logger| {
+                info!(
+                    logger,
+                    "Snapshot verified (block #{}, {})",
+                    manifest.tip_height,
+                    manifest.tip_block_hash
+                )
+            });
+        }
+        None if config.should_allow_snapshot_without_manifest() => {
+            ctx.try_log(|logger| {
+                warn!(
+                    logger,
+                    "Snapshot is not including a manifest, network and chain tip can not be verified"
+                )
+            });
+        }
+        None => {
+            let _ = fs::remove_dir_all(&staging_path);
+            return Err("snapshot refused: archive is not including a manifest (set snapshot.allow_missing_manifest to accept it)".to_string());
+        }
+    }
+    install_unpacked_snapshot(&staging_path, &destination_path)?;
+
+    let mut local_sha_file_path = destination_path.clone();
+    local_sha_file_path.push(default_sqlite_sha_file_path(
+        &config.network.bitcoin_network,
+    ));
+    write_file_content_at_path(&local_sha_file_path, &remote_sha_file.to_vec())?;
+
+    Ok(())
+}
+
+/// Path of the file keeping the validator (ETag or Last-Modified) of the resource being
+/// downloaded in `partial_path`.
+fn validator_path(partial_path: &PathBuf) -> PathBuf {
+    PathBuf::from(format!("{}.validator", partial_path.display()))
+}
+
+fn discard_partial_file(partial_path: &PathBuf) {
+    let _ = fs::remove_file(partial_path);
+    let _ = fs::remove_file(validator_path(partial_path));
+}
+
+/// Start offset of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn parse_content_range_start(content_range: &str) -> Option<u64> {
+    content_range
+        .strip_prefix("bytes ")?
+        .split_once('-')?
+        .0
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Download `url` into `partial_path`. If `partial_path` already exists, only the missing
+/// bytes are requested (HTTP Range request), conditioned on the resource being unchanged
+/// (If-Range request). Servers ignoring ranges, or serving another version of the resource,
+/// make us start over.
+pub async fn download_file_with_resume(
+    url: &str,
+    partial_path: &PathBuf,
+    ctx: &Context,
+) -> Result<u64, String> {
+    let validator = read_file_content_at_path(&validator_path(partial_path))
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok());
+    let mut downloaded = match (fs::metadata(partial_path), &validator) {
+        (Ok(metadata), Some(_)) => metadata.len(),
+        // Without a validator, a partial file can't be matched with the remote resource
+        _ => 0,
+    };
+
+    let client = reqwest::Client::new();
+    let mut request = client.get(url);
+    if let (true, Some(validator)) = (downloaded > 0, &validator) {
+        request = request
+            .header(reqwest::header::RANGE, format!("bytes={downloaded}-"))
+            .header(reqwest::header::IF_RANGE, validator);
+    }
+    let res = request
+        .send()
+        .await
+        .or(Err(format!("Failed to GET from '{}'", url)))?;
+
+    let mut file = match res.status() {
+        reqwest::StatusCode::PARTIAL_CONTENT => {
+            let range_start = res
+                .headers()
+                .get(reqwest::header::CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_content_range_start);
+            if range_start != Some(downloaded) {
+                discard_partial_file(partial_path);
+                return Err(format!(
+                    "Unexpected range served by '{}', restarting download from scratch",
+                    url
+                ));
             }
-            progress_bar.set(progress);
-            if steps == 0 {
-                if ctx.logger.is_some() {
-                    print!("\r{}", progress_bar);
-                    let _ = stdout.flush();
+            ctx.try_log(|logger| info!(logger, "Resuming download at byte {}", downloaded));
+            OpenOptions::new()
+                .append(true)
+                .open(partial_path)
+                .map_err(|e| format!("unable to open {}: {}", partial_path.display(), e))?
+        }
+        reqwest::StatusCode::OK => {
+            downloaded = 0;
+            let file = File::create(partial_path)
+                .map_err(|e| format!("unable to create {}: {}", partial_path.display(), e))?;
+            let validator = res
+                .headers()
+                .get(reqwest::header::ETAG)
+                .and_then(|value| value.to_str().ok())
+                // Weak validators can't be used in If-Range requests
+                .filter(|etag| !etag.starts_with("W/"))
+                .or(res
+                    .headers()
+                    .get(reqwest::header::LAST_MODIFIED)
+                    .and_then(|value| value.to_str().ok()));
+            match validator {
+                Some(validator) => {
+                    write_file_content_at_path(&validator_path(partial_path), validator.as_bytes())?
+                }
+                None => {
+                    let _ = fs::remove_file(validator_path(partial_path));
                 }
             }
-            if let Err(e) = tx.send_async(chunk.to_vec()).await {
-                let err = format!("unable to download archive: {}", e.to_string());
-                tx_err = Some(err);
+            file
+        }
+        // The partial file is already complete
+        reqwest::StatusCode::RANGE_NOT_SATISFIABLE if downloaded > 0 => return Ok(downloaded),
+        status => return Err(format!("Failed to GET from '{}' ({})", url, status)),
+    };
+
+    let limit = (downloaded + res.content_length().unwrap_or(10_000_000_000)) as i64;
+    let mut progress_bar = MappingBar::with_range(0i64, limit);
+    progress_bar.set_len(60);
+    progress_bar.set(downloaded as i64);
+    let mut stdout = std::io::stdout();
+    if ctx.logger.is_some() {
+        print!("{}", progress_bar);
+        let _ = stdout.flush();
+    }
+    let mut stream = res.bytes_stream();
+    let mut steps = 0;
+    let mut result = Ok(());
+    while let Some(item) = stream.next().await {
+        let chunk = match item {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                result = Err(format!("Error while downloading file: {}", e.to_string()));
                 break;
             }
+        };
+        if chunk.is_empty() {
+            continue;
         }
-        progress_bar.set(limit);
-        if ctx.logger.is_some() {
-            print!("\r{}", progress_bar);
-            let _ = stdout.flush();
-            println!();
+        if let Err(e) = file.write_all(&chunk) {
+            result = Err(format!("unable to write {}: {}", partial_path.display(), e));
+            break;
+        }
+        downloaded += chunk.len() as u64;
+        steps += chunk.len() as i64;
+        if steps > 5_000_000 {
+            steps = 0;
+        }
+        progress_bar.set(downloaded as i64);
+        if steps == 0 {
+            if ctx.logger.is_some() {
+                print!("\r{}", progress_bar);
+                let _ = stdout.flush();
+            }
         }
-        drop(tx);
-
-        decoder_thread.join().unwrap()?;
-        if let Some(_e) = tx_err.take() {}
     }
-
-    Ok(())
+    // Whatever happened, keep what was received so far for the next attempt
+    let _ = file.flush();
+    if ctx.logger.is_some() {
+        print!("\r{}", progress_bar);
+        let _ = stdout.flush();
+        println!();
+    }
+    result?;
+    Ok(downloaded)
 }
 
-// Wrap a channel into something that impls `io::Read`
-struct ChannelRead {
-    rx: flume::Receiver<Vec<u8>>,
-    current: Cursor<Vec<u8>>,
+/// Extract the digest from a `.sha256` file (`<digest>  <file name>`).
+pub fn parse_sha256_file(bytes: &[u8]) -> Result<String, String> {
+    let content = std::str::from_utf8(bytes).map_err(|e| format!("invalid sha256 file: {e}"))?;
+    match content.split_whitespace().next() {
+        Some(digest) if digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(digest.to_lowercase())
+        }
+        _ => Err(format!("invalid sha256 file: {}", content.trim())),
+    }
 }
 
-impl ChannelRead {
-    fn new(rx: flume::Receiver<Vec<u8>>) -> ChannelRead {
-        ChannelRead {
-            rx,
-            current: Cursor::new(vec![]),
-        }
+pub fn verify_file_sha256(file_path: &PathBuf, expected_sha256: &str) -> Result<(), String> {
+    let sha256 = compute_sha256_of_file(file_path)?;
+    if sha256 != expected_sha256 {
+        return Err(format!(
+            "integrity check failed for {}: expected sha256 {}, got {}",
+            file_path.display(),
+            expected_sha256,
+            sha256
+        ));
     }
+    Ok(())
 }
 
-impl Read for ChannelRead {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.current.position() == self.current.get_ref().len() as u64 {
-            // We've exhausted the previous chunk, get a new one.
-            if let Ok(vec) = self.rx.recv() {
-                self.current = io::Cursor::new(vec);
-            }
-            // If recv() "fails", it means the sender closed its part of
-            // the channel, which means EOF. Propagate EOF by allowing
-            // a read from the exhausted cursor.
+fn install_unpacked_snapshot(
+    staging_path: &PathBuf,
+    destination_path: &PathBuf,
+) -> Result<(), String> {
+    let entries = fs::read_dir(staging_path)
+        .map_err(|e| format!("unable to read {}: {}", staging_path.display(), e))?;
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| format!("unable to read {}: {}", staging_path.display(), e))?;
+        let mut target_path = destination_path.clone();
+        target_path.push(entry.file_name());
+        if target_path.is_dir() {
+            fs::remove_dir_all(&target_path)
+                .map_err(|e| format!("unable to replace {}: {}", target_path.display(), e))?;
+        } else if target_path.exists() {
+            fs::remove_file(&target_path)
+                .map_err(|e| format!("unable to replace {}: {}", target_path.display(), e))?;
         }
-        self.current.read(buf)
+        fs::rename(entry.path(), &target_path)
+            .map_err(|e| format!("unable to move {}: {}", target_path.display(), e))?;
     }
+    let _ = fs::remove_dir_all(staging_path);
+    Ok(())
 }
 
-pub async fn download_ordinals_dataset_if_required(config: &Config, ctx: &Context) -> bool {
+pub async fn download_ordinals_dataset_if_required(
+    config: &Config,
+    ctx: &Context,
+) -> Result<bool, String> {
     if config.should_bootstrap_through_download() {
         let url = config.expected_remote_ordinals_sqlite_url();
         let mut sqlite_file_path = config.expected_cache_path();
@@ -211,13 +391,7 @@ pub async fn download_ordinals_dataset_if_required(config: &Config, ctx: &Contex
         };
         if should_download {
             info!(ctx.expect_logger(), "Downloading {}", url);
-            match download_sqlite_file(&config, &ctx).await {
-                Ok(_) => {}
-                Err(e) => {
-                    error!(ctx.expect_logger(), "{}", e);
-                    std::process::exit(1);
-                }
-            }
+            download_sqlite_file(&config, &ctx).await?;
         } else {
             info!(
                 ctx.expect_logger(),
@@ -226,8 +400,247 @@ pub async fn download_ordinals_dataset_if_required(config: &Config, ctx: &Contex
             );
         }
         // config.add_local_ordinals_sqlite_source(&sqlite_file_path);
-        true
+        Ok(true)
     } else {
-        false
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{SnapshotConfig, SnapshotDownloadConfig};
+    use crate::testing::http::{
+        start_http_server, start_raw_http_server, HttpRequest, HttpResponse,
+    };
+    use crate::testing::TempDir;
+    use std::sync::{Arc, Mutex};
+
+    const ETAG: &str = "\"snapshot-v2\"";
+
+    /// Static file server honoring `Range: bytes=<start>-` requests, conditioned by `If-Range`,
+    /// built on the shared test server. The first response can be cut after
+    /// `truncate_first_response_at` bytes, to simulate a dropped connection. The `Range` and
+    /// `If-Range` headers of each request are recorded.
+    fn spawn_static_file_server(
+        content: Vec<u8>,
+        truncate_first_response_at: Option<usize>,
+    ) -> (String, Arc<Mutex<Vec<(Option<String>, Option<String>)>>>) {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let moved_requests = requests.clone();
+        let url = start_raw_http_server(Arc::new(move |request: &HttpRequest| {
+            let range = request.header("range").map(|r| r.to_string());
+            let if_range = request.header("if-range").map(|r| r.to_string());
+            let mut requests = moved_requests.lock().unwrap();
+            let is_first_request = requests.is_empty();
+            requests.push((range.clone(), if_range.clone()));
+            let start = range
+                .as_ref()
+                .filter(|_| if_range.as_deref() == Some(ETAG))
+                .and_then(|r| r.strip_prefix("bytes=")?.strip_suffix('-')?.parse().ok())
+                .unwrap_or(0usize);
+            let mut response = if start > 0 {
+                let mut response = HttpResponse::new(206, content[start..].to_vec());
+                response.headers.push((
+                    "Content-Range".into(),
+                    format!("bytes {}-{}/{}", start, content.len() - 1, content.len()),
+                ));
+                response
+            } else {
+                HttpResponse::new(200, content.clone())
+            };
+            response.headers.push(("ETag".into(), ETAG.into()));
+            if is_first_request {
+                response.truncate_body_at = truncate_first_response_at;
+            }
+            response
+        }));
+        (format!("{url}/snapshot.tar.gz"), requests)
+    }
+
+    fn sha256_of(content: &[u8], dir: &TempDir) -> String {
+        let path = dir.join("expected");
+        fs::write(&path, content).unwrap();
+        compute_sha256_of_file(&path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_download_resumes_from_partial_file() {
+        let dir = TempDir::new("download");
+        let content: Vec<u8> = (0..2_000_000u32).map(|i| (i % 251) as u8).collect();
+        let expected_sha256 = sha256_of(&content, &dir);
+        let (url, requests) = spawn_static_file_server(content.clone(), Some(700_000));
+
+        let partial_path = dir.join("snapshot.tar.gz.partial");
+        let ctx = Context::empty();
+
+        assert!(download_file_with_resume(&url, &partial_path, &ctx)
+            .await
+            .is_err());
+        let interrupted_at = fs::metadata(&partial_path).unwrap().len();
+        assert!(interrupted_at > 0 && interrupted_at < content.len() as u64);
+
+        let downloaded = download_file_with_resume(&url, &partial_path, &ctx)
+            .await
+            .unwrap();
+        assert_eq!(downloaded, content.len() as u64);
+        assert_eq!(
+            requests.lock().unwrap().clone(),
+            vec![
+                (None, None),
+                (
+                    Some(format!("bytes={}-", interrupted_at)),
+                    Some(ETAG.to_string())
+                )
+            ]
+        );
+        assert!(verify_file_sha256(&partial_path, &expected_sha256).is_ok());
+        assert_eq!(fs::read(&partial_path).unwrap(), content);
+
+        // A corrupted file is detected
+        fs::write(&partial_path, &content[1..]).unwrap();
+        assert!(verify_file_sha256(&partial_path, &expected_sha256).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_download_restarts_when_partial_file_is_stale() {
+        let dir = TempDir::new("download");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let (url, requests) = spawn_static_file_server(content.clone(), None);
+        let ctx = Context::empty();
+
+        // Partial file left by the download of a previous snapshot
+        let partial_path = dir.join("snapshot.tar.gz.partial");
+        fs::write(&partial_path, vec![0u8; 50_000]).unwrap();
+        fs::write(validator_path(&partial_path), "\"snapshot-v1\"").unwrap();
+
+        let downloaded = download_file_with_resume(&url, &partial_path, &ctx)
+            .await
+            .unwrap();
+        assert_eq!(downloaded, content.len() as u64);
+        assert_eq!(
+            requests.lock().unwrap().clone(),
+            vec![(
+                Some("bytes=50000-".to_string()),
+                Some("\"snapshot-v1\"".to_string())
+            )]
+        );
+        assert_eq!(fs::read(&partial_path).unwrap(), content);
+        assert_eq!(
+            fs::read(validator_path(&partial_path)).unwrap(),
+            ETAG.as_bytes()
+        );
+
+        // Partial file without validator
+        fs::write(&partial_path, &content[..50_000]).unwrap();
+        let _ = fs::remove_file(validator_path(&partial_path));
+        download_file_with_resume(&url, &partial_path, &ctx)
+            .await
+            .unwrap();
+        assert_eq!(requests.lock().unwrap().last(), Some(&(None, None)));
+        assert_eq!(fs::read(&partial_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_download_restarts_on_unexpected_content_range() {
+        let dir = TempDir::new("download");
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let served = content.clone();
+        // Always serves the last 10 bytes, whatever the range requested
+        let url = start_raw_http_server(Arc::new(move |request: &HttpRequest| {
+            let mut response = match request.header("range") {
+                Some(_) => {
+                    let mut response = HttpResponse::new(206, served[99_990..].to_vec());
+                    response
+                        .headers
+                        .push(("Content-Range".into(), "bytes 99990-99999/100000".into()));
+                    response
+                }
+                None => HttpResponse::new(200, served.clone()),
+            };
+            response.headers.push(("ETag".into(), ETAG.into()));
+            response
+        }));
+        let url = format!("{url}/snapshot.tar.gz");
+        let ctx = Context::empty();
+
+        let partial_path = dir.join("snapshot.tar.gz.partial");
+        fs::write(&partial_path, &content[..50_000]).unwrap();
+        fs::write(validator_path(&partial_path), ETAG).unwrap();
+
+        assert!(download_file_with_resume(&url, &partial_path, &ctx)
+            .await
+            .is_err());
+        assert!(!partial_path.exists());
+
+        // The next attempt starts from scratch
+        download_file_with_resume(&url, &partial_path, &ctx)
+            .await
+            .unwrap();
+        assert_eq!(fs::read(&partial_path).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_download_refuses_archive_without_manifest() {
+        let dir = TempDir::new("snapshot");
+        let archive = {
+            let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+                vec![],
+                flate2::Compression::default(),
+            ));
+            let content = b"sqlite";
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, "hord.sqlite", &content[..])
+                .unwrap();
+            builder.into_inner().unwrap().finish().unwrap()
+        };
+        let sha256 = sha256_of(&archive, &dir);
+        let url = start_http_server(Arc::new(move |_, path, _| match path {
+            "/snapshot.sha256" => (200, format!("{sha256}  snapshot.tar.gz\n").into_bytes()),
+            "/snapshot.tar.gz" => (200, archive.clone()),
+            _ => (404, vec![]),
+        }));
+
+        let mut config = Config::devnet_default();
+        let working_dir = dir.join("working_dir");
+        config.storage.working_dir = working_dir.display().to_string();
+        config.snapshot = SnapshotConfig::Download(SnapshotDownloadConfig {
+            url: format!("{url}/snapshot"),
+            allow_missing_manifest: false,
+        });
+        let ctx = Context::empty();
+        let sqlite_path = working_dir.join("hord.sqlite");
+
+        assert!(download_sqlite_file(&config, &ctx).await.is_err());
+        assert!(!sqlite_path.exists());
+
+        config.snapshot = SnapshotConfig::Download(SnapshotDownloadConfig {
+            url: format!("{url}/snapshot"),
+            allow_missing_manifest: true,
+        });
+        download_sqlite_file(&config, &ctx).await.unwrap();
+        assert_eq!(fs::read(&sqlite_path).unwrap(), b"sqlite");
+    }
+
+    #[test]
+    fn test_parse_sha256_file() {
+        let digest = "6f1ed002ab5595859014ebf0951522d9e2a2d5c3bc1b1a0a1e9ab67a77ee6bc1";
+        assert_eq!(
+            parse_sha256_file(
+                format!("{}  mainnet-ordhook-sqlite-latest.tar.gz\n", digest).as_bytes()
+            ),
+            Ok(digest.to_string())
+        );
+        assert!(parse_sha256_file(b"not a digest").is_err());
+    }
+
+    #[test]
+    fn test_parse_content_range_start() {
+        assert_eq!(parse_content_range_start("bytes 700-999/1000"), Some(700));
+        assert_eq!(parse_content_range_start("bytes */1000"), None);
     }
 }
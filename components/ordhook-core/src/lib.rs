@@ -19,4 +19,14 @@ pub mod download;
 pub mod ord;
 pub mod scan;
 pub mod service;
+pub mod snapshot;
 pub mod utils;
+pub mod verify;
+
+/// Mock bitcoind and HTTP servers and temporary directories, exposed to the other crates of
+/// the workspace by the `testing` feature.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+#[cfg(test)]
+mod tests;
@@ -1,24 +1,22 @@
 use crate::config::Config;
+use crate::core::pipeline::block_source::build_block_source;
 use crate::core::protocol::inscription_parsing::{
     get_inscriptions_revealed_in_block, get_inscriptions_transferred_in_block,
     parse_inscriptions_and_standardize_block,
 };
 use crate::core::protocol::inscription_sequencing::consolidate_block_with_pre_computed_ordinals_data;
-use crate::db::{get_any_entry_in_ordinal_activities, open_readonly_ordhook_db_conn};
+use crate::db::store::ConfiguredInscriptionStore;
+use crate::db::{open_readonly_ordhook_db_conn, open_readonly_ordhook_db_conn_rocks_db};
 use crate::download::download_ordinals_dataset_if_required;
 use crate::service::observers::{
     open_readwrite_observers_db_conn_or_panic, update_observer_progress,
 };
-use chainhook_sdk::bitcoincore_rpc::RpcApi;
-use chainhook_sdk::bitcoincore_rpc::{Auth, Client};
 use chainhook_sdk::chainhooks::bitcoin::{
     evaluate_bitcoin_chainhooks_on_chain_event, handle_bitcoin_hook_action,
     BitcoinChainhookOccurrence, BitcoinTriggerChainhook,
 };
 use chainhook_sdk::chainhooks::types::BitcoinChainhookSpecification;
-use chainhook_sdk::indexer::bitcoin::{
-    build_http_client, download_and_parse_block_with_retry, retrieve_block_hash_with_retry,
-};
+use chainhook_sdk::indexer::bitcoin::parse_downloaded_block;
 use chainhook_sdk::observer::{gather_proofs, DataHandlerEvent, EventObserverConfig};
 use chainhook_sdk::types::{
     BitcoinBlockData, BitcoinChainEvent, BitcoinChainUpdatedWithBlocksData,
@@ -32,19 +30,9 @@ pub async fn scan_bitcoin_chainstate_via_rpc_using_predicate(
     event_observer_config_override: Option<&EventObserverConfig>,
     ctx: &Context,
 ) -> Result<(), String> {
-    let _ = download_ordinals_dataset_if_required(config, ctx).await;
-
-    let auth = Auth::UserPass(
-        config.network.bitcoind_rpc_username.clone(),
-        config.network.bitcoind_rpc_password.clone(),
-    );
+    download_ordinals_dataset_if_required(config, ctx).await?;
 
-    let bitcoin_rpc = match Client::new(&config.network.bitcoind_rpc_url, auth) {
-        Ok(con) => con,
-        Err(message) => {
-            return Err(format!("Bitcoin RPC error: {}", message.to_string()));
-        }
-    };
+    let block_source = build_block_source(config, ctx)?;
     let mut floating_end_block = false;
 
     let mut block_heights_to_scan = if let Some(ref blocks) = predicate_spec.blocks {
@@ -61,15 +49,7 @@ pub async fn scan_bitcoin_chainstate_via_rpc_using_predicate(
         };
         let (end_block, update_end_block) = match predicate_spec.end_block {
             Some(end_block) => (end_block, false),
-            None => match bitcoin_rpc.get_blockchain_info() {
-                Ok(result) => (result.blocks, true),
-                Err(e) => {
-                    return Err(format!(
-                        "unable to retrieve Bitcoin chain tip ({})",
-                        e.to_string()
-                    ));
-                }
-            },
+            None => (block_source.get_chain_tip(ctx)?, true),
         };
         floating_end_block = update_end_block;
         BlockHeights::BlockRange(start_block, end_block).get_sorted_entries()
@@ -87,31 +67,34 @@ pub async fn scan_bitcoin_chainstate_via_rpc_using_predicate(
         Some(config_override) => config_override.clone(),
         None => config.get_event_observer_config(),
     };
-    let bitcoin_config = event_observer_config.get_bitcoin_config();
     let mut number_of_blocks_scanned = 0;
-    let http_client = build_http_client();
 
     while let Some(current_block_height) = block_heights_to_scan.pop_front() {
-        let mut inscriptions_db_conn =
+        let inscriptions_db_conn =
             open_readonly_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+        let blocks_db = open_readonly_ordhook_db_conn_rocks_db(
+            &config.expected_cache_path(),
+            &config.resources,
+            ctx,
+        )?;
+        let inscriptions_store = ConfiguredInscriptionStore::open(
+            &config.storage.inscriptions_index,
+            &blocks_db,
+            &inscriptions_db_conn,
+        )?;
 
         number_of_blocks_scanned += 1;
 
-        if !get_any_entry_in_ordinal_activities(&current_block_height, &inscriptions_db_conn, &ctx)
+        if !inscriptions_store
+            .as_store()
+            .any_entry_at_block_height(current_block_height, ctx)
         {
             continue;
         }
 
-        let block_hash = retrieve_block_hash_with_retry(
-            &http_client,
-            &current_block_height,
-            &bitcoin_config,
-            ctx,
-        )
-        .await?;
-        let block_breakdown =
-            download_and_parse_block_with_retry(&http_client, &block_hash, &bitcoin_config, ctx)
-                .await?;
+        let block_bytes = block_source.fetch_block(current_block_height, ctx).await?;
+        let block_breakdown = parse_downloaded_block(block_bytes)?;
+        let block_hash = block_breakdown.hash.clone();
         let mut block = match parse_inscriptions_and_standardize_block(
             block_breakdown,
             &event_observer_config.bitcoin_network,
@@ -127,15 +110,13 @@ pub async fn scan_bitcoin_chainstate_via_rpc_using_predicate(
             }
         };
 
-        {
-            let inscriptions_db_tx = inscriptions_db_conn.transaction().unwrap();
-            consolidate_block_with_pre_computed_ordinals_data(
-                &mut block,
-                &inscriptions_db_tx,
-                true,
-                &Context::empty(),
-            );
-        }
+        consolidate_block_with_pre_computed_ordinals_data(
+            &mut block,
+            inscriptions_store.as_store(),
+            true,
+            &config.network_params,
+            &Context::empty(),
+        );
 
         let inscriptions_revealed = get_inscriptions_revealed_in_block(&block)
             .iter()
@@ -178,9 +159,9 @@ pub async fn scan_bitcoin_chainstate_via_rpc_using_predicate(
             )
         }
         if block_heights_to_scan.is_empty() && floating_end_block {
-            match bitcoin_rpc.get_blockchain_info() {
-                Ok(result) => {
-                    for entry in (current_block_height + 1)..=result.blocks {
+            match block_source.get_chain_tip(ctx) {
+                Ok(chain_tip) => {
+                    for entry in (current_block_height + 1)..=chain_tip {
                         block_heights_to_scan.push_back(entry);
                     }
                 }
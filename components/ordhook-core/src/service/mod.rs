@@ -1,27 +1,44 @@
 mod http_api;
 pub mod observers;
 mod runloops;
+pub mod shutdown;
 
-use crate::config::{Config, PredicatesApi};
-use crate::core::pipeline::download_and_pipeline_blocks;
+use crate::config::{Config, InscriptionsIndexLayout, PredicatesApi};
+use crate::core::pipeline::block_source::{build_block_source, BlockSource};
 use crate::core::pipeline::processors::block_archiving::start_block_archiving_processor;
 use crate::core::pipeline::processors::inscription_indexing::process_block;
 use crate::core::pipeline::processors::start_inscription_indexing_processor;
 use crate::core::pipeline::processors::transfers_recomputing::start_transfers_recomputing_processor;
+use crate::core::pipeline::{
+    download_and_pipeline_blocks, ShutdownSignal, DEFAULT_SHUTDOWN_TIMEOUT,
+};
 use crate::core::protocol::inscription_parsing::{
     get_inscriptions_revealed_in_block, get_inscriptions_transferred_in_block,
     parse_inscriptions_in_standardized_block,
 };
 use crate::core::protocol::inscription_sequencing::SequenceCursor;
-use crate::core::{new_traversals_lazy_cache, should_sync_ordhook_db, should_sync_rocks_db};
+use crate::core::sync_planner::SyncPlanner;
+use crate::core::{
+    check_network_genesis, find_blocks_to_download, new_traversals_lazy_cache,
+    persist_traversals_cache, rollback_orphaned_blocks, should_sync_ordhook_db,
+    should_sync_rocks_db, warm_up_traversals_cache,
+};
+use crate::db::journal::{apply_block_with_undo_journal, undo_block_with_journal};
+#[cfg(feature = "postgres")]
+use crate::db::store::postgres::PostgresInscriptionStore;
+use crate::db::store::rocksdb::RocksDbInscriptionStore;
+use crate::db::store::{ConfiguredInscriptionStore, InscriptionStore, SqliteInscriptionStore};
+use crate::db::sync_checkpoint::record_block_in_sync_checkpoint;
+use crate::db::traversals_cache::TraversalsCache;
 use crate::db::{
-    delete_data_in_ordhook_db, insert_entry_in_blocks, open_ordhook_db_conn_rocks_db_loop,
-    open_readwrite_ordhook_db_conn, open_readwrite_ordhook_dbs, update_inscriptions_with_block,
-    update_locations_with_block, BlockBytesCursor, TransactionBytesCursor,
+    clear_pending_block_commit, delete_blocks_in_block_range, find_last_block_inserted,
+    find_missing_blocks, log_rocks_db_options, recover_pending_block_commit, run_compaction,
+    upgrade_ordhook_db_schema, write_pending_block_commit, PendingBlockCommit,
+    PendingBlockCommitKind,
 };
 use crate::db::{
-    find_last_block_inserted, find_missing_blocks, run_compaction,
-    update_sequence_metadata_with_block,
+    delete_data_in_ordhook_db, insert_entry_in_blocks, open_ordhook_db_conn_rocks_db_loop,
+    open_readwrite_ordhook_db_conn, open_readwrite_ordhook_dbs, BlockBytesCursor,
 };
 use crate::scan::bitcoin::process_block_with_predicates;
 use crate::service::http_api::start_predicate_api_server;
@@ -31,6 +48,7 @@ use crate::service::observers::{
     update_observer_streaming_enabled, ObserverReport,
 };
 use crate::service::runloops::start_bitcoin_scan_runloop;
+use crate::service::shutdown::{forward_shutdown_to_observer, listen_for_shutdown_signals};
 use chainhook_sdk::chainhooks::bitcoin::BitcoinChainhookOccurrencePayload;
 use chainhook_sdk::chainhooks::types::{
     BitcoinChainhookSpecification, ChainhookFullSpecification, ChainhookSpecification,
@@ -40,25 +58,52 @@ use chainhook_sdk::observer::{
     HandleBlock, ObserverCommand, ObserverEvent, ObserverSidecar,
 };
 use chainhook_sdk::types::{BitcoinBlockData, BlockIdentifier};
-use chainhook_sdk::utils::{BlockHeights, Context};
+use chainhook_sdk::utils::Context;
 use crossbeam_channel::unbounded;
 use crossbeam_channel::{select, Sender};
-use dashmap::DashMap;
-use fxhash::FxHasher;
+#[cfg(feature = "postgres")]
+use rocksdb::DB;
+use rusqlite::Connection;
 
 use std::collections::BTreeMap;
-use std::hash::BuildHasherDefault;
 use std::sync::mpsc::channel;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// Interval between two chain tip checks, when new blocks are polled from the block source.
+const BLOCK_SOURCE_POLLING_INTERVAL: Duration = Duration::from_secs(30);
 
 pub struct Service {
     pub config: Config,
     pub ctx: Context,
+    /// Triggered on SIGINT / SIGTERM (see `listen_for_shutdown_signals`).
+    pub shutdown: ShutdownSignal,
+    block_source: OnceLock<Arc<dyn BlockSource>>,
 }
 
 impl Service {
     pub fn new(config: Config, ctx: Context) -> Self {
-        Self { config, ctx }
+        Self {
+            config,
+            ctx,
+            shutdown: ShutdownSignal::new(DEFAULT_SHUTDOWN_TIMEOUT),
+            block_source: OnceLock::new(),
+        }
+    }
+
+    /// Block source of the configuration, built on first use and shared by all the syncs.
+    pub fn block_source(&self) -> Result<Arc<dyn BlockSource>, String> {
+        if let Some(block_source) = self.block_source.get() {
+            return Ok(block_source.clone());
+        }
+        let block_source = build_block_source(&self.config, &self.ctx)?;
+        Ok(self.block_source.get_or_init(|| block_source).clone())
+    }
+
+    /// Handle SIGINT and SIGTERM: the blocks in flight are drained and committed, the
+    /// processors terminated, and the service stops.
+    pub fn listen_for_shutdown_signals(&self) {
+        listen_for_shutdown_signals(self.shutdown.clone(), &self.ctx);
     }
 
     pub async fn run(
@@ -69,16 +114,62 @@ impl Service {
         >,
         check_blocks_integrity: bool,
     ) -> Result<(), String> {
+        // Without bitcoind RPC, new blocks are polled from the block source and predicates,
+        // evaluated by the event observer, can't be served
+        if !self.config.is_bitcoind_rpc_configured()
+            && (!predicates.is_empty() || self.config.is_http_api_enabled())
+        {
+            return Err("predicates require network.bitcoind_rpc_url to be set".to_string());
+        }
+
         let mut event_observer_config = self.config.get_event_observer_config();
+        self.listen_for_shutdown_signals();
+
+        // Refuse to operate on a schema we don't know about, apply pending additive migrations
+        {
+            let mut inscriptions_db_conn_rw =
+                open_readwrite_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx)?;
+            upgrade_ordhook_db_schema(&mut inscriptions_db_conn_rw, &self.ctx)?;
+        }
+
+        // Roll back any block left half-applied by a crash
+        {
+            let (blocks_db_rw, mut inscriptions_db_conn_rw) = open_readwrite_ordhook_dbs(
+                &self.config.expected_cache_path(),
+                &self.config.resources,
+                &self.ctx,
+            )?;
+            log_rocks_db_options(&blocks_db_rw, &self.ctx);
+            recover_pending_block_commit(
+                &blocks_db_rw,
+                &mut inscriptions_db_conn_rw,
+                &self.config.storage.inscriptions_index,
+                &self.ctx,
+            )?;
+        }
+
+        // Refuse to index a chain other than the one configured
+        let block_source = self.block_source()?;
+        check_network_genesis(&self.config, block_source.as_ref(), &self.ctx)?;
+
+        // Roll back blocks orphaned by a re-org that happened while we were down
+        rollback_orphaned_blocks(&self.config, block_source.as_ref(), &self.ctx)?;
 
         // Catch-up with chain tip
         let chain_tip_height = self
             .catch_up_with_chain_tip(false, check_blocks_integrity)
             .await?;
+        if self.shutdown.is_triggered() {
+            info!(self.ctx.expect_logger(), "Service stopped");
+            return Ok(());
+        }
         info!(
             self.ctx.expect_logger(),
             "Database up to date, service will start streaming blocks"
         );
+        if !self.config.is_bitcoind_rpc_configured() {
+            return self.stream_blocks_by_polling().await;
+        }
 
         // Sidecar channels setup
         let observer_sidecar = self.set_up_observer_sidecar_runloop()?;
@@ -120,6 +211,7 @@ impl Service {
             Some(observer_sidecar),
             inner_ctx,
         );
+        forward_shutdown_to_observer(self.shutdown.clone(), observer_command_tx.clone());
 
         // If HTTP Predicates API is on, we start:
         // - Thread pool in charge of performing replays
@@ -132,6 +224,31 @@ impl Service {
         Ok(())
     }
 
+    /// Index new blocks by polling the chain tip of the block source, until shutdown.
+    pub async fn stream_blocks_by_polling(&self) -> Result<(), String> {
+        let polling_step = Duration::from_millis(500);
+        while !self.shutdown.is_triggered() {
+            if let Err(e) = self.poll_block_source().await {
+                self.ctx
+                    .try_log(|logger| warn!(logger, "Unable to poll block source: {e}"));
+            }
+            let mut waited = Duration::ZERO;
+            while waited < BLOCK_SOURCE_POLLING_INTERVAL && !self.shutdown.is_triggered() {
+                std::thread::sleep(polling_step);
+                waited += polling_step;
+            }
+        }
+        info!(self.ctx.expect_logger(), "Service stopped");
+        Ok(())
+    }
+
+    /// Roll back the blocks orphaned since the last poll, then index the new blocks.
+    pub async fn poll_block_source(&self) -> Result<u64, String> {
+        let block_source = self.block_source()?;
+        rollback_orphaned_blocks(&self.config, block_source.as_ref(), &self.ctx)?;
+        self.update_state(None).await
+    }
+
     pub async fn start_event_observer(
         &mut self,
         observer_sidecar: ObserverSidecar,
@@ -413,9 +530,14 @@ impl Service {
             bitcoin_blocks_mutator: Some((block_mutator_in_tx, block_mutator_out_rx)),
             bitcoin_chain_event_notifier: Some(chain_event_notifier_tx),
         };
-        let cache_l2 = Arc::new(new_traversals_lazy_cache(100_000));
+        let cache_l2 = Arc::new(new_traversals_lazy_cache(
+            self.config.resources.get_memory_budget(),
+        ));
         let ctx = self.ctx.clone();
         let config = self.config.clone();
+        warm_up_traversals_cache(&config, &cache_l2, &ctx);
+        let persist_cache_every_n_blocks = 10;
+        let mut blocks_mutated_since_persist = 0;
 
         let _ = hiro_system_kit::thread_named("Observer Sidecar Runloop").spawn(move || loop {
             select! {
@@ -428,11 +550,19 @@ impl Service {
                             &config,
                             &ctx,
                         );
+                        blocks_mutated_since_persist += blocks_to_mutate.len();
+                        if blocks_mutated_since_persist >= persist_cache_every_n_blocks {
+                            persist_traversals_cache(&config, &cache_l2, &ctx);
+                            blocks_mutated_since_persist = 0;
+                        }
                         let _ = block_mutator_out_tx.send(blocks_to_mutate);
                     }
                 }
                 recv(chain_event_notifier_rx) -> msg => {
                     if let Ok(command) = msg {
+                        if let HandleBlock::UndoBlock(ref block) = command {
+                            cache_l2.remove_from_block_height(block.block_identifier.index as u32);
+                        }
                         chainhook_sidecar_mutate_ordhook_db(command, &config, &ctx)
                     }
                 }
@@ -453,8 +583,7 @@ impl Service {
                     let blocks_db = open_ordhook_db_conn_rocks_db_loop(
                         false,
                         &self.config.expected_cache_path(),
-                        self.config.resources.ulimit,
-                        self.config.resources.memory_available,
+                        &self.config.resources,
                         &self.ctx,
                     );
                     let tip = find_last_block_inserted(&blocks_db);
@@ -475,10 +604,12 @@ impl Service {
                         start_block_archiving_processor(&self.config, &self.ctx, false, None);
                     download_and_pipeline_blocks(
                         &self.config,
+                        self.block_source()?.as_ref(),
                         missing_blocks.into_iter().map(|x| x as u64).collect(),
                         tip.into(),
                         Some(&block_ingestion_processor),
                         10_000,
+                        Some(&self.shutdown),
                         &self.ctx,
                     )
                     .await?;
@@ -486,8 +617,7 @@ impl Service {
                 let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
                     false,
                     &self.config.expected_cache_path(),
-                    self.config.resources.ulimit,
-                    self.config.resources.memory_available,
+                    &self.config.resources,
                     &self.ctx,
                 );
                 info!(self.ctx.expect_logger(), "Running database compaction",);
@@ -498,8 +628,7 @@ impl Service {
                 let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
                     false,
                     &self.config.expected_cache_path(),
-                    self.config.resources.ulimit,
-                    self.config.resources.memory_available,
+                    &self.config.resources,
                     &self.ctx,
                 );
 
@@ -522,10 +651,14 @@ impl Service {
         &self,
         block_post_processor: Option<crossbeam_channel::Sender<BitcoinBlockData>>,
     ) -> Result<u64, String> {
+        let block_source = self.block_source()?;
+
         // First, make sure that rocksdb and sqlite are aligned.
         // If rocksdb.chain_tip.height <= sqlite.chain_tip.height
         // Perform some block compression until that height.
         if let Some((start_block, end_block)) = should_sync_rocks_db(&self.config, &self.ctx)? {
+            let (blocks, start_sequencing) =
+                find_blocks_to_download(&self.config, start_block, end_block, &self.ctx)?;
             let blocks_post_processor = start_block_archiving_processor(
                 &self.config,
                 &self.ctx,
@@ -540,15 +673,14 @@ impl Service {
                 )
             });
 
-            let ordhook_config = self.config.get_ordhook_config();
-            let first_inscription_height = ordhook_config.first_inscription_height;
-            let blocks = BlockHeights::BlockRange(start_block, end_block).get_sorted_entries();
             download_and_pipeline_blocks(
                 &self.config,
-                blocks.into(),
-                first_inscription_height,
+                block_source.as_ref(),
+                blocks,
+                start_sequencing,
                 Some(&blocks_post_processor),
                 10_000,
+                Some(&self.shutdown),
                 &self.ctx,
             )
             .await?;
@@ -556,12 +688,24 @@ impl Service {
 
         // Start predicate processor
         let mut last_block_processed = 0;
-        while let Some((start_block, end_block, speed)) =
-            should_sync_ordhook_db(&self.config, &self.ctx)?
-        {
+        let mut sync_planner = SyncPlanner::new(&self.config);
+        while let Some(batch) = should_sync_ordhook_db(
+            &self.config,
+            block_source.as_ref(),
+            &sync_planner,
+            &self.ctx,
+        )? {
+            let (start_block, end_block) = (batch.start_block, batch.end_block);
             if last_block_processed == end_block {
                 break;
             }
+            if self.shutdown.is_triggered() {
+                self.ctx
+                    .try_log(|logger| info!(logger, "Shutdown requested, sync interrupted"));
+                break;
+            }
+            let (blocks, start_sequencing) =
+                find_blocks_to_download(&self.config, start_block, end_block, &self.ctx)?;
             let blocks_post_processor = start_inscription_indexing_processor(
                 &self.config,
                 &self.ctx,
@@ -575,19 +719,40 @@ impl Service {
                 )
             });
 
-            let ordhook_config = self.config.get_ordhook_config();
-            let first_inscription_height = ordhook_config.first_inscription_height;
-            let blocks = BlockHeights::BlockRange(start_block, end_block).get_sorted_entries();
-            download_and_pipeline_blocks(
+            let blocks_skipped = (end_block - start_block + 1) - blocks.len() as u64;
+            if blocks_skipped > 0 {
+                self.ctx.try_log(|logger| {
+                    info!(
+                        logger,
+                        "Skipping {blocks_skipped} blocks already archived, sequencing from block #{start_sequencing}"
+                    )
+                });
+            }
+            let queue_memory = sync_planner.reserve_queue_memory(&batch);
+            let stats = download_and_pipeline_blocks(
                 &self.config,
-                blocks.into(),
-                first_inscription_height,
+                block_source.as_ref(),
+                blocks,
+                start_sequencing,
                 Some(&blocks_post_processor),
-                speed,
+                batch.queue_depth,
+                Some(&self.shutdown),
                 &self.ctx,
             )
             .await?;
 
+            self.ctx.try_log(|logger| {
+                info!(
+                    logger,
+                    "Blocks #{start_block} to #{end_block} processed in {}s ({:.1} blocks/s, {} bytes per block, up to {} blocks queued)",
+                    stats.elapsed.as_secs(),
+                    stats.blocks_per_second().unwrap_or(0.0),
+                    stats.average_block_bytes().unwrap_or(0),
+                    stats.peak_queued_blocks,
+                )
+            });
+            drop(queue_memory);
+            sync_planner.record_batch(&batch, stats);
             last_block_processed = end_block;
         }
 
@@ -599,18 +764,21 @@ impl Service {
         blocks: Vec<u64>,
         block_post_processor: Option<crossbeam_channel::Sender<BitcoinBlockData>>,
     ) -> Result<(), String> {
+        self.config
+            .check_sqlite_inscriptions_index("transfers recomputing")?;
         // Start predicate processor
         let blocks_post_processor =
             start_transfers_recomputing_processor(&self.config, &self.ctx, block_post_processor);
 
-        let ordhook_config = self.config.get_ordhook_config();
-        let first_inscription_height = ordhook_config.first_inscription_height;
+        let first_inscription_height = self.config.network_params.first_inscription_height;
         download_and_pipeline_blocks(
             &self.config,
+            self.block_source()?.as_ref(),
             blocks,
             first_inscription_height,
             Some(&blocks_post_processor),
             100,
+            Some(&self.shutdown),
             &self.ctx,
         )
         .await?;
@@ -619,19 +787,26 @@ impl Service {
     }
 }
 
-fn chainhook_sidecar_mutate_ordhook_db(command: HandleBlock, config: &Config, ctx: &Context) {
-    let (blocks_db_rw, inscriptions_db_conn_rw) = match open_readwrite_ordhook_dbs(
-        &config.expected_cache_path(),
-        config.resources.ulimit,
-        config.resources.memory_available,
-        &ctx,
-    ) {
-        Ok(dbs) => dbs,
-        Err(e) => {
-            ctx.try_log(|logger| error!(logger, "Unable to open readwtite connection: {e}",));
-            return;
-        }
-    };
+pub fn chainhook_sidecar_mutate_ordhook_db(command: HandleBlock, config: &Config, ctx: &Context) {
+    let (blocks_db_rw, mut inscriptions_db_conn_rw) =
+        match open_readwrite_ordhook_dbs(&config.expected_cache_path(), &config.resources, &ctx) {
+            Ok(dbs) => dbs,
+            Err(e) => {
+                ctx.try_log(|logger| error!(logger, "Unable to open readwtite connection: {e}",));
+                return;
+            }
+        };
+
+    #[cfg(feature = "postgres")]
+    if let InscriptionsIndexLayout::Postgres(ref database_url) = config.storage.inscriptions_index {
+        return chainhook_sidecar_mutate_postgres_db(
+            command,
+            database_url,
+            config.storage.undo_journal_depth,
+            &blocks_db_rw,
+            ctx,
+        );
+    }
 
     match command {
         HandleBlock::UndoBlock(block) => {
@@ -641,6 +816,56 @@ fn chainhook_sidecar_mutate_ordhook_db(command: HandleBlock, config: &Config, ct
                     "Re-org handling: reverting changes in block #{}", block.block_identifier.index
                 )
             });
+            let pending_commit = PendingBlockCommit {
+                block_height: block.block_identifier.index,
+                block_hash: block.block_identifier.hash.clone(),
+                kind: PendingBlockCommitKind::UndoBlock,
+            };
+            write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+
+            // Restore the exact prior state when the block is covered by the undo journal
+            let undone_with_journal = match config.storage.inscriptions_index {
+                InscriptionsIndexLayout::Sqlite => match undo_block_with_journal_tx(
+                    &block.block_identifier,
+                    &mut inscriptions_db_conn_rw,
+                    &ctx,
+                ) {
+                    Ok(undone) => undone,
+                    Err(e) => {
+                        ctx.try_log(|logger| {
+                            warn!(
+                                logger,
+                                "Unable to undo block {} with journal: {e}", block.block_identifier
+                            )
+                        });
+                        false
+                    }
+                },
+                InscriptionsIndexLayout::RocksDb => {
+                    match RocksDbInscriptionStore::new(&blocks_db_rw) {
+                        Ok(inscriptions_store) => {
+                            let undone = undo_block_with_journal(
+                                &block.block_identifier,
+                                &inscriptions_store,
+                                &ctx,
+                            );
+                            inscriptions_store.commit(block.block_identifier.index, None, &ctx);
+                            undone
+                        }
+                        Err(_) => false,
+                    }
+                }
+                // Handled by chainhook_sidecar_mutate_postgres_db
+                #[cfg(feature = "postgres")]
+                InscriptionsIndexLayout::Postgres(_) => false,
+            };
+            if undone_with_journal {
+                let block_height = block.block_identifier.index as u32;
+                delete_blocks_in_block_range(block_height, block_height, &blocks_db_rw, &ctx);
+                clear_pending_block_commit(&blocks_db_rw, &ctx);
+                return;
+            }
+
             if let Err(e) = delete_data_in_ordhook_db(
                 block.block_identifier.index,
                 block.block_identifier.index,
@@ -654,7 +879,9 @@ fn chainhook_sidecar_mutate_ordhook_db(command: HandleBlock, config: &Config, ct
                         "Unable to rollback bitcoin block {}: {e}", block.block_identifier
                     )
                 });
+                return;
             }
+            clear_pending_block_commit(&blocks_db_rw, &ctx);
         }
         HandleBlock::ApplyBlock(block) => {
             let block_bytes = match BlockBytesCursor::from_standardized_block(&block) {
@@ -671,6 +898,62 @@ fn chainhook_sidecar_mutate_ordhook_db(command: HandleBlock, config: &Config, ct
                     return;
                 }
             };
+
+            if config.storage.inscriptions_index == InscriptionsIndexLayout::RocksDb {
+                // Block and inscriptions index are written in one atomic batch
+                let inscriptions_store = match RocksDbInscriptionStore::new(&blocks_db_rw) {
+                    Ok(inscriptions_store) => inscriptions_store,
+                    Err(e) => {
+                        ctx.try_log(|logger| error!(logger, "{e}"));
+                        return;
+                    }
+                };
+                apply_block_with_undo_journal(
+                    &block,
+                    config.storage.undo_journal_depth,
+                    &inscriptions_store,
+                    &ctx,
+                );
+                inscriptions_store.commit(block.block_identifier.index, Some(&block_bytes), &ctx);
+                record_block_in_sync_checkpoint(block.block_identifier.index, &blocks_db_rw, &ctx);
+                return;
+            }
+
+            // The marker is cleared once both stores are up to date: a crash in between is
+            // detected and rolled forward or back on startup (see `recover_pending_block_commit`).
+            let pending_commit = PendingBlockCommit {
+                block_height: block.block_identifier.index,
+                block_hash: block.block_identifier.hash.clone(),
+                kind: PendingBlockCommitKind::ApplyBlock,
+            };
+            write_pending_block_commit(&pending_commit, &blocks_db_rw, &ctx);
+
+            let inscriptions_db_tx = match inscriptions_db_conn_rw.transaction() {
+                Ok(tx) => tx,
+                Err(e) => {
+                    ctx.try_log(|logger| {
+                        error!(logger, "Unable to open hord.sqlite transaction: {e}")
+                    });
+                    return;
+                }
+            };
+            apply_block_with_undo_journal(
+                &block,
+                config.storage.undo_journal_depth,
+                &SqliteInscriptionStore::new(&inscriptions_db_tx),
+                &ctx,
+            );
+            if let Err(e) = inscriptions_db_tx.commit() {
+                ctx.try_log(|logger| {
+                    error!(
+                        logger,
+                        "Unable to commit block #{} in hord.sqlite: {e}",
+                        block.block_identifier.index
+                    )
+                });
+                return;
+            }
+
             insert_entry_in_blocks(
                 block.block_identifier.index as u32,
                 &block_bytes,
@@ -678,17 +961,140 @@ fn chainhook_sidecar_mutate_ordhook_db(command: HandleBlock, config: &Config, ct
                 &blocks_db_rw,
                 &ctx,
             );
+            record_block_in_sync_checkpoint(block.block_identifier.index, &blocks_db_rw, &ctx);
             let _ = blocks_db_rw.flush();
 
-            update_inscriptions_with_block(&block, &inscriptions_db_conn_rw, &ctx);
+            clear_pending_block_commit(&blocks_db_rw, &ctx);
+        }
+    }
+}
 
-            update_locations_with_block(&block, &inscriptions_db_conn_rw, &ctx);
+/// Same as `chainhook_sidecar_mutate_ordhook_db`, for the PostgreSQL layout: the inscriptions
+/// index is updated in one transaction, and the blocks remain in hord.rocksdb.
+#[cfg(feature = "postgres")]
+fn chainhook_sidecar_mutate_postgres_db(
+    command: HandleBlock,
+    database_url: &str,
+    undo_journal_depth: u64,
+    blocks_db_rw: &DB,
+    ctx: &Context,
+) {
+    let inscriptions_store = match PostgresInscriptionStore::connect(database_url) {
+        Ok(inscriptions_store) => inscriptions_store,
+        Err(e) => {
+            ctx.try_log(|logger| error!(logger, "{e}"));
+            return;
+        }
+    };
+    match command {
+        HandleBlock::UndoBlock(block) => {
+            ctx.try_log(|logger| {
+                info!(
+                    logger,
+                    "Re-org handling: reverting changes in block #{}", block.block_identifier.index
+                )
+            });
+            let block_height = block.block_identifier.index;
+            let pending_commit = PendingBlockCommit {
+                block_height,
+                block_hash: block.block_identifier.hash.clone(),
+                kind: PendingBlockCommitKind::UndoBlock,
+            };
+            write_pending_block_commit(&pending_commit, blocks_db_rw, ctx);
+            inscriptions_store.begin(ctx);
+            // Restore the exact prior state when the block is covered by the undo journal
+            if !undo_block_with_journal(&block.block_identifier, &inscriptions_store, ctx) {
+                inscriptions_store.delete_data_in_block_range(block_height, block_height, ctx);
+            }
+            // The pending commit marker is left for the recovery performed on startup
+            if let Err(e) = inscriptions_store.commit(ctx) {
+                ctx.try_log(|logger| error!(logger, "Unable to revert block #{block_height}: {e}"));
+                return;
+            }
+            delete_blocks_in_block_range(
+                block_height as u32,
+                block_height as u32,
+                blocks_db_rw,
+                ctx,
+            );
+            clear_pending_block_commit(blocks_db_rw, ctx);
+        }
+        HandleBlock::ApplyBlock(block) => {
+            let block_bytes = match BlockBytesCursor::from_standardized_block(&block) {
+                Ok(block_bytes) => block_bytes,
+                Err(e) => {
+                    ctx.try_log(|logger| {
+                        error!(
+                            logger,
+                            "Unable to compress block #{}: #{}",
+                            block.block_identifier.index,
+                            e.to_string()
+                        )
+                    });
+                    return;
+                }
+            };
+            // Rolled forward or back on startup if the block is not fully committed (see
+            // `recover_pending_block_commit`)
+            let pending_commit = PendingBlockCommit {
+                block_height: block.block_identifier.index,
+                block_hash: block.block_identifier.hash.clone(),
+                kind: PendingBlockCommitKind::ApplyBlock,
+            };
+            write_pending_block_commit(&pending_commit, blocks_db_rw, ctx);
 
-            update_sequence_metadata_with_block(&block, &inscriptions_db_conn_rw, &ctx);
+            inscriptions_store.begin(ctx);
+            apply_block_with_undo_journal(&block, undo_journal_depth, &inscriptions_store, ctx);
+            // The pending commit marker is left for the recovery performed on startup
+            if let Err(e) = inscriptions_store.commit(ctx) {
+                ctx.try_log(|logger| {
+                    error!(
+                        logger,
+                        "Unable to apply block #{}: {e}", block.block_identifier.index
+                    )
+                });
+                return;
+            }
+
+            insert_entry_in_blocks(
+                block.block_identifier.index as u32,
+                &block_bytes,
+                true,
+                blocks_db_rw,
+                ctx,
+            );
+            record_block_in_sync_checkpoint(block.block_identifier.index, blocks_db_rw, ctx);
+            let _ = blocks_db_rw.flush();
+
+            clear_pending_block_commit(blocks_db_rw, ctx);
         }
     }
 }
 
+fn undo_block_with_journal_tx(
+    block_identifier: &BlockIdentifier,
+    inscriptions_db_conn_rw: &mut Connection,
+    ctx: &Context,
+) -> Result<bool, String> {
+    let inscriptions_db_tx = inscriptions_db_conn_rw
+        .transaction()
+        .map_err(|e| format!("unable to open hord.sqlite transaction: {}", e.to_string()))?;
+    if !undo_block_with_journal(
+        block_identifier,
+        &SqliteInscriptionStore::new(&inscriptions_db_tx),
+        ctx,
+    ) {
+        return Ok(false);
+    }
+    inscriptions_db_tx.commit().map_err(|e| {
+        format!(
+            "unable to commit hord.sqlite transaction: {}",
+            e.to_string()
+        )
+    })?;
+    Ok(true)
+}
+
 pub fn start_observer_forwarding(
     event_observer_config: &EventObserverConfig,
     ctx: &Context,
@@ -727,28 +1133,38 @@ pub fn start_observer_forwarding(
 pub fn chainhook_sidecar_mutate_blocks(
     blocks_to_mutate: &mut Vec<BitcoinBlockDataCached>,
     blocks_ids_to_rollback: &Vec<BlockIdentifier>,
-    cache_l2: &Arc<DashMap<(u32, [u8; 8]), TransactionBytesCursor, BuildHasherDefault<FxHasher>>>,
+    cache_l2: &Arc<TraversalsCache>,
     config: &Config,
     ctx: &Context,
 ) {
     let mut updated_blocks_ids = vec![];
 
-    let (blocks_db_rw, mut inscriptions_db_conn_rw) = match open_readwrite_ordhook_dbs(
-        &config.expected_cache_path(),
-        config.resources.ulimit,
-        config.resources.memory_available,
-        &ctx,
+    let (blocks_db_rw, mut inscriptions_db_conn_rw) =
+        match open_readwrite_ordhook_dbs(&config.expected_cache_path(), &config.resources, &ctx) {
+            Ok(dbs) => dbs,
+            Err(e) => {
+                ctx.try_log(|logger| error!(logger, "Unable to open readwtite connection: {e}",));
+                return;
+            }
+        };
+
+    let inscriptions_db_tx = inscriptions_db_conn_rw.transaction().unwrap();
+    // Writes are only performed for computing the updated blocks, and discarded once done
+    let inscriptions_store = match ConfiguredInscriptionStore::open(
+        &config.storage.inscriptions_index,
+        &blocks_db_rw,
+        &inscriptions_db_tx,
     ) {
-        Ok(dbs) => dbs,
+        Ok(inscriptions_store) => inscriptions_store,
         Err(e) => {
-            ctx.try_log(|logger| error!(logger, "Unable to open readwtite connection: {e}",));
+            ctx.try_log(|logger| error!(logger, "Unable to open inscriptions store: {e}"));
             return;
         }
     };
-
-    let inscriptions_db_tx = inscriptions_db_conn_rw.transaction().unwrap();
+    inscriptions_store.begin(&ctx);
 
     for block_id_to_rollback in blocks_ids_to_rollback.iter() {
+        cache_l2.remove_from_block_height(block_id_to_rollback.index as u32);
         if let Err(e) = delete_data_in_ordhook_db(
             block_id_to_rollback.index,
             block_id_to_rollback.index,
@@ -763,6 +1179,12 @@ pub fn chainhook_sidecar_mutate_blocks(
                 )
             });
         }
+        // Already done for hord.sqlite and hord.rocksdb, needed by the PostgreSQL layout
+        inscriptions_store.as_store().delete_data_in_block_range(
+            block_id_to_rollback.index,
+            block_id_to_rollback.index,
+            &ctx,
+        );
     }
 
     let ordhook_config = config.get_ordhook_config();
@@ -793,16 +1215,18 @@ pub fn chainhook_sidecar_mutate_blocks(
         let _ = blocks_db_rw.flush();
 
         if cache.processed_by_sidecar {
-            update_inscriptions_with_block(&cache.block, &inscriptions_db_tx, &ctx);
-            update_locations_with_block(&cache.block, &inscriptions_db_tx, &ctx);
-            update_sequence_metadata_with_block(&cache.block, &inscriptions_db_tx, &ctx);
+            let inscriptions_store = inscriptions_store.as_store();
+            inscriptions_store.update_inscriptions_with_block(&cache.block, &ctx);
+            inscriptions_store.update_locations_with_block(&cache.block, &ctx);
+            inscriptions_store.update_sequence_metadata_with_block(&cache.block, &ctx);
         } else {
             updated_blocks_ids.push(format!("{}", cache.block.block_identifier.index));
 
             parse_inscriptions_in_standardized_block(&mut cache.block, &ctx);
 
             let mut cache_l1 = BTreeMap::new();
-            let mut sequence_cursor = SequenceCursor::new(&inscriptions_db_tx);
+            let inscriptions_store = inscriptions_store.as_store();
+            let mut sequence_cursor = SequenceCursor::new(inscriptions_store);
 
             let _ = process_block(
                 &mut cache.block,
@@ -810,7 +1234,7 @@ pub fn chainhook_sidecar_mutate_blocks(
                 &mut sequence_cursor,
                 &mut cache_l1,
                 &cache_l2,
-                &inscriptions_db_tx,
+                inscriptions_store,
                 &ordhook_config,
                 &ctx,
             );
@@ -835,5 +1259,7 @@ pub fn chainhook_sidecar_mutate_blocks(
             cache.processed_by_sidecar = true;
         }
     }
+    inscriptions_store.discard(&ctx);
+    drop(inscriptions_store);
     let _ = inscriptions_db_tx.rollback();
 }
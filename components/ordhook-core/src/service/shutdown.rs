@@ -0,0 +1,56 @@
+use std::sync::mpsc::Sender;
+use std::thread::sleep;
+use std::time::Duration;
+
+use chainhook_sdk::observer::ObserverCommand;
+use chainhook_sdk::utils::Context;
+
+use crate::core::pipeline::ShutdownSignal;
+
+/// Trigger `shutdown` on the first SIGINT or SIGTERM received. A second signal exits the
+/// process without waiting for the pipeline to drain. Must be called from a tokio runtime.
+pub fn listen_for_shutdown_signals(shutdown: ShutdownSignal, ctx: &Context) {
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let signal = wait_for_shutdown_signal().await;
+        ctx.try_log(|logger| {
+            info!(
+                logger,
+                "{signal} received, shutting down (send it again to exit immediately)"
+            )
+        });
+        shutdown.trigger();
+        let signal = wait_for_shutdown_signal().await;
+        ctx.try_log(|logger| warn!(logger, "{signal} received, exiting"));
+        std::process::exit(1);
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigterm = signal(SignalKind::terminate()).expect("unable to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = sigterm.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "SIGINT"
+}
+
+/// Terminate the chainhook observer once `shutdown` is triggered, ending the main runloop.
+pub fn forward_shutdown_to_observer(
+    shutdown: ShutdownSignal,
+    observer_command_tx: Sender<ObserverCommand>,
+) {
+    let _ = hiro_system_kit::thread_named("Shutdown forwarder").spawn(move || {
+        while !shutdown.is_triggered() {
+            sleep(Duration::from_millis(500));
+        }
+        let _ = observer_command_tx.send(ObserverCommand::Terminate);
+    });
+}
@@ -0,0 +1,342 @@
+use crate::config::Config;
+use crate::core::pipeline::block_source::build_block_source;
+use crate::db::{
+    find_last_block_inserted, find_latest_inscription_block_height,
+    get_default_ordhook_db_file_path, open_readwrite_ordhook_db_conn,
+    open_readwrite_ordhook_db_conn_rocks_db, ORDHOOK_DB_SCHEMA_VERSION,
+};
+use crate::utils::{read_file_content_at_path, write_file_content_at_path};
+use chainhook_sdk::types::BitcoinNetwork;
+use chainhook_sdk::utils::Context;
+use flate2::write::GzEncoder;
+use flate2::{Compression, GzBuilder};
+use rocksdb::checkpoint::Checkpoint;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use tar::{Builder, HeaderMode};
+
+pub const SNAPSHOT_MANIFEST_FILE_NAME: &str = "hord.manifest.json";
+
+/// Describes the chain state captured in a snapshot archive.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SnapshotManifest {
+    pub network: String,
+    pub tip_height: u64,
+    pub tip_block_hash: String,
+    pub schema_version: u32,
+}
+
+impl SnapshotManifest {
+    /// Checks that can be performed without reaching out to the block source.
+    pub fn check_compatibility(&self, network: &BitcoinNetwork) -> Result<(), String> {
+        let expected_network = format!("{:?}", network).to_lowercase();
+        if self.network != expected_network {
+            return Err(format!(
+                "snapshot was built for {}, node is running on {}",
+                self.network, expected_network
+            ));
+        }
+        if self.schema_version > ORDHOOK_DB_SCHEMA_VERSION {
+            return Err(format!(
+                "snapshot schema version {} is not supported (latest supported: {})",
+                self.schema_version, ORDHOOK_DB_SCHEMA_VERSION
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn default_snapshot_base_name(network: &BitcoinNetwork) -> String {
+    format!("{:?}-ordhook-sqlite-latest", network).to_lowercase()
+}
+
+pub fn snapshot_archive_path(base_path: &PathBuf) -> PathBuf {
+    PathBuf::from(format!("{}.tar.gz", base_path.display()))
+}
+
+pub fn snapshot_sha256_path(base_path: &PathBuf) -> PathBuf {
+    PathBuf::from(format!("{}.sha256", base_path.display()))
+}
+
+pub fn snapshot_manifest_path(base_path: &PathBuf) -> PathBuf {
+    PathBuf::from(format!("{}.json", base_path.display()))
+}
+
+/// Build a bootstrap archive out of the local databases.
+///
+/// Writes are quiesced while the databases are being captured: hord.rocksdb is opened
+/// in read-write mode (failing if another process is holding it) and a write lock is held
+/// on hord.sqlite. The archive produced is laid out the way `download_sqlite_file` expects,
+/// and is accompanied by a `.sha256` file and a `.json` manifest.
+pub fn create_ordhook_db_snapshot(
+    config: &Config,
+    base_path: &PathBuf,
+    ctx: &Context,
+) -> Result<SnapshotManifest, String> {
+    config.check_sqlite_inscriptions_index("snapshot creation")?;
+    let mut staging_dir = PathBuf::from(format!("{}.staging", base_path.display()));
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)
+            .map_err(|e| format!("unable to clean {}: {}", staging_dir.display(), e))?;
+    }
+    fs::create_dir_all(&staging_dir)
+        .map_err(|e| format!("unable to create {}: {}", staging_dir.display(), e))?;
+
+    let blocks_db_rw = open_readwrite_ordhook_db_conn_rocks_db(
+        &config.expected_cache_path(),
+        &config.resources,
+        ctx,
+    )
+    .map_err(|e| format!("{e} (is ordhook still running?)"))?;
+
+    let inscriptions_db_conn_rw =
+        open_readwrite_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+    inscriptions_db_conn_rw
+        .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+        .map_err(|e| format!("unable to checkpoint hord.sqlite: {}", e.to_string()))?;
+    // Hold the write lock until both databases have been captured
+    inscriptions_db_conn_rw
+        .execute_batch("BEGIN IMMEDIATE")
+        .map_err(|e| format!("unable to lock hord.sqlite: {}", e.to_string()))?;
+
+    let tip_height = find_last_block_inserted(&blocks_db_rw) as u64;
+    // Blocks archived ahead of the sequencing would be advertised without their inscriptions
+    let inscriptions_tip_height =
+        find_latest_inscription_block_height(&inscriptions_db_conn_rw, ctx)?;
+    if inscriptions_tip_height != Some(tip_height) {
+        let _ = inscriptions_db_conn_rw.execute_batch("ROLLBACK");
+        return Err(format!(
+            "hord.rocksdb is at block #{tip_height}, hord.sqlite at block #{}: wait for the inscriptions to be indexed up to the same block",
+            inscriptions_tip_height
+                .map(|block_height| block_height.to_string())
+                .unwrap_or("-".to_string())
+        ));
+    }
+    ctx.try_log(|logger| info!(logger, "Capturing databases at block #{tip_height}"));
+
+    let captured = capture_databases(config, &blocks_db_rw, &staging_dir, ctx);
+    let _ = inscriptions_db_conn_rw.execute_batch("ROLLBACK");
+    captured?;
+
+    let tip_block_hash = build_block_source(config, ctx)?.get_block_hash(tip_height, ctx)?;
+    let manifest = SnapshotManifest {
+        network: format!("{:?}", config.network.bitcoin_network).to_lowercase(),
+        tip_height,
+        tip_block_hash,
+        schema_version: ORDHOOK_DB_SCHEMA_VERSION,
+    };
+    let manifest_bytes = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| format!("unable to serialize manifest: {}", e.to_string()))?;
+    staging_dir.push(SNAPSHOT_MANIFEST_FILE_NAME);
+    write_file_content_at_path(&staging_dir, &manifest_bytes)?;
+    staging_dir.pop();
+
+    let archive_path = snapshot_archive_path(base_path);
+    ctx.try_log(|logger| info!(logger, "Writing {}", archive_path.display()));
+    write_deterministic_archive(&staging_dir, &archive_path)?;
+
+    let sha256 = compute_sha256_of_file(&archive_path)?;
+    let archive_file_name = archive_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    write_file_content_at_path(
+        &snapshot_sha256_path(base_path),
+        format!("{sha256}  {archive_file_name}\n").as_bytes(),
+    )?;
+    write_file_content_at_path(&snapshot_manifest_path(base_path), &manifest_bytes)?;
+
+    let _ = fs::remove_dir_all(&staging_dir);
+    Ok(manifest)
+}
+
+fn capture_databases(
+    config: &Config,
+    blocks_db_rw: &rocksdb::DB,
+    staging_dir: &PathBuf,
+    ctx: &Context,
+) -> Result<(), String> {
+    let mut sqlite_snapshot_path = staging_dir.clone();
+    sqlite_snapshot_path.push("hord.sqlite");
+    // VACUUM INTO runs in its own read transaction, which is allowed to proceed
+    // while the write lock is being held by the other connection.
+    let inscriptions_db_conn = open_readwrite_ordhook_db_conn(&config.expected_cache_path(), ctx)?;
+    inscriptions_db_conn
+        .execute(
+            "VACUUM INTO ?1",
+            rusqlite::params![sqlite_snapshot_path.display().to_string()],
+        )
+        .map_err(|e| {
+            format!(
+                "unable to copy {}: {}",
+                get_default_ordhook_db_file_path(&config.expected_cache_path()).display(),
+                e.to_string()
+            )
+        })?;
+
+    blocks_db_rw
+        .flush()
+        .map_err(|e| format!("unable to flush hord.rocksdb: {}", e.to_string()))?;
+    blocks_db_rw
+        .flush_wal(true)
+        .map_err(|e| format!("unable to flush hord.rocksdb: {}", e.to_string()))?;
+    let mut rocksdb_snapshot_path = staging_dir.clone();
+    rocksdb_snapshot_path.push("hord.rocksdb");
+    Checkpoint::new(blocks_db_rw)
+        .and_then(|checkpoint| checkpoint.create_checkpoint(&rocksdb_snapshot_path))
+        .map_err(|e| format!("unable to checkpoint hord.rocksdb: {}", e.to_string()))?;
+    Ok(())
+}
+
+/// Read the manifest of a snapshot unpacked in `snapshot_dir`.
+/// Archives built before manifests were introduced are returning `None`.
+pub fn read_snapshot_manifest(snapshot_dir: &PathBuf) -> Result<Option<SnapshotManifest>, String> {
+    let mut manifest_path = snapshot_dir.clone();
+    manifest_path.push(SNAPSHOT_MANIFEST_FILE_NAME);
+    if !manifest_path.exists() {
+        return Ok(None);
+    }
+    let bytes = read_file_content_at_path(&manifest_path)?;
+    let manifest = serde_json::from_slice(&bytes)
+        .map_err(|e| format!("unable to parse {}: {}", manifest_path.display(), e))?;
+    Ok(Some(manifest))
+}
+
+/// Make sure that a snapshot is matching our network and database schema, and that its
+/// tip is part of the chain followed by the block source (and not a stale fork).
+pub fn verify_snapshot_manifest(
+    manifest: &SnapshotManifest,
+    config: &Config,
+    ctx: &Context,
+) -> Result<(), String> {
+    manifest.check_compatibility(&config.network.bitcoin_network)?;
+
+    let block_source = build_block_source(config, ctx)?;
+    let chain_tip = block_source.get_chain_tip(ctx)?;
+    if chain_tip < manifest.tip_height {
+        return Err(format!(
+            "block source is synchronized until block #{chain_tip}, snapshot tip is block #{}",
+            manifest.tip_height
+        ));
+    }
+    let block_hash = block_source.get_block_hash(manifest.tip_height, ctx)?;
+    if block_hash != manifest.tip_block_hash {
+        return Err(format!(
+            "snapshot tip {} is not part of the chain followed by the block source (block #{} is {})",
+            manifest.tip_block_hash, manifest.tip_height, block_hash
+        ));
+    }
+    Ok(())
+}
+
+/// Entries are sorted and stripped from their timestamps and ownership,
+/// so that archiving the same databases twice produces the same bytes.
+fn write_deterministic_archive(source_dir: &PathBuf, archive_path: &PathBuf) -> Result<(), String> {
+    let mut entries = vec![];
+    collect_files(source_dir, &PathBuf::new(), &mut entries)?;
+    entries.sort();
+
+    let file = File::create(archive_path)
+        .map_err(|e| format!("unable to create {}: {}", archive_path.display(), e))?;
+    let encoder: GzEncoder<BufWriter<File>> = GzBuilder::new()
+        .mtime(0)
+        .write(BufWriter::new(file), Compression::default());
+    let mut archive = Builder::new(encoder);
+    archive.mode(HeaderMode::Deterministic);
+    for relative_path in entries.iter() {
+        let mut path = source_dir.clone();
+        path.push(relative_path);
+        archive
+            .append_path_with_name(&path, relative_path)
+            .map_err(|e| format!("unable to archive {}: {}", path.display(), e))?;
+    }
+    let encoder = archive
+        .into_inner()
+        .map_err(|e| format!("unable to write {}: {}", archive_path.display(), e))?;
+    let mut writer = encoder
+        .finish()
+        .map_err(|e| format!("unable to write {}: {}", archive_path.display(), e))?;
+    writer
+        .flush()
+        .map_err(|e| format!("unable to write {}: {}", archive_path.display(), e))?;
+    Ok(())
+}
+
+fn collect_files(
+    base_dir: &PathBuf,
+    relative_dir: &PathBuf,
+    entries: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let mut dir = base_dir.clone();
+    dir.push(relative_dir);
+    let read_dir =
+        fs::read_dir(&dir).map_err(|e| format!("unable to read {}: {}", dir.display(), e))?;
+    for entry in read_dir {
+        let entry = entry.map_err(|e| format!("unable to read {}: {}", dir.display(), e))?;
+        let mut relative_path = relative_dir.clone();
+        relative_path.push(entry.file_name());
+        let file_type = entry
+            .file_type()
+            .map_err(|e| format!("unable to read {}: {}", entry.path().display(), e))?;
+        if file_type.is_dir() {
+            collect_files(base_dir, &relative_path, entries)?;
+        } else {
+            entries.push(relative_path);
+        }
+    }
+    Ok(())
+}
+
+pub fn compute_sha256_of_file(file_path: &PathBuf) -> Result<String, String> {
+    let mut file = File::open(file_path)
+        .map_err(|e| format!("unable to read file {}\n{:?}", file_path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) => {
+                return Err(format!(
+                    "unable to read file {}\n{:?}",
+                    file_path.display(),
+                    e
+                ))
+            }
+        }
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_manifest(network: &str, schema_version: u32) -> SnapshotManifest {
+        SnapshotManifest {
+            network: network.to_string(),
+            tip_height: 820_000,
+            tip_block_hash: "00000000000000000002a23d6df20eecec15b21d32c75833cce28f113de888b7"
+                .to_string(),
+            schema_version,
+        }
+    }
+
+    #[test]
+    fn test_snapshot_manifest_compatibility() {
+        let manifest = build_manifest("mainnet", ORDHOOK_DB_SCHEMA_VERSION);
+        assert!(manifest
+            .check_compatibility(&BitcoinNetwork::Mainnet)
+            .is_ok());
+        assert!(manifest
+            .check_compatibility(&BitcoinNetwork::Signet)
+            .is_err());
+
+        let manifest = build_manifest("mainnet", ORDHOOK_DB_SCHEMA_VERSION + 1);
+        assert!(manifest
+            .check_compatibility(&BitcoinNetwork::Mainnet)
+            .is_err());
+    }
+}
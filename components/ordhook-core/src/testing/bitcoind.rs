@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use chainhook_sdk::bitcoincore_rpc::bitcoin::absolute::LockTime;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::consensus::encode::serialize_hex;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{
+    OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+};
+use serde_json::{json, Value};
+
+use super::http::start_http_server;
+
+#[derive(Default)]
+struct MockBitcoindState {
+    blocks: BTreeMap<u64, Value>,
+    failures_to_inject: usize,
+    requests: Vec<String>,
+}
+
+impl MockBitcoindState {
+    fn tip(&self) -> Option<&Value> {
+        self.blocks.values().next_back()
+    }
+
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "getblockchaininfo" => {
+                let tip = self
+                    .tip()
+                    .ok_or((-28, "Loading block index...".to_string()))?;
+                Ok(json!({
+                    "chain": "regtest",
+                    "blocks": tip["height"],
+                    "headers": tip["height"],
+                    "bestblockhash": tip["hash"],
+                    "difficulty": tip["difficulty"],
+                    "time": tip["time"],
+                    "mediantime": tip["mediantime"],
+                    "verificationprogress": 1,
+                    "initialblockdownload": false,
+                    "chainwork": tip["chainwork"],
+                    "size_on_disk": 0,
+                    "pruned": false,
+                    "softforks": {},
+                    "warnings": "",
+                }))
+            }
+            "getblockcount" => {
+                let tip = self
+                    .tip()
+                    .ok_or((-28, "Loading block index...".to_string()))?;
+                Ok(tip["height"].clone())
+            }
+            "getblockhash" => {
+                let block_height = params[0]
+                    .as_u64()
+                    .ok_or((-1, "JSON value is not an integer as expected".to_string()))?;
+                match self.blocks.get(&block_height) {
+                    Some(block) => Ok(block["hash"].clone()),
+                    None => Err((-8, "Block height out of range".to_string())),
+                }
+            }
+            "getblock" | "getblockheader" => {
+                let block_hash = params[0]
+                    .as_str()
+                    .ok_or((-1, "JSON value is not a string as expected".to_string()))?;
+                let block = self
+                    .blocks
+                    .values()
+                    .find(|b| b["hash"] == block_hash)
+                    .ok_or((-5, "Block not found".to_string()))?;
+                match (method, params[1].as_u64()) {
+                    ("getblock", Some(2)) | ("getblock", Some(3)) => Ok(block.clone()),
+                    ("getblockheader", _) => {
+                        let mut header = block.clone();
+                        header.as_object_mut().unwrap().remove("tx");
+                        Ok(header)
+                    }
+                    _ => Err((-8, "Verbosity not supported by mock".to_string())),
+                }
+            }
+            "getrawtransaction" => {
+                let txid = params[0]
+                    .as_str()
+                    .ok_or((-1, "JSON value is not a string as expected".to_string()))?;
+                if params[1].as_bool() == Some(true) {
+                    return Err((-8, "Verbosity not supported by mock".to_string()));
+                }
+                let tx = self
+                    .blocks
+                    .values()
+                    .filter_map(|block| block["tx"].as_array())
+                    .flatten()
+                    .find(|tx| tx["txid"] == txid)
+                    .ok_or((-5, "No such mempool or blockchain transaction".to_string()))?;
+                serialize_outputs(tx).map(Value::from)
+            }
+            _ => Err((-32601, "Method not found".to_string())),
+        }
+    }
+}
+
+/// Serialized transaction paying the outputs of `tx` (as described by `getblock <hash> 3`).
+/// Inputs are replaced by a null input: the txid of the transaction served doesn't match.
+fn serialize_outputs(tx: &Value) -> Result<String, (i64, String)> {
+    let mut output = vec![];
+    for vout in tx["vout"].as_array().into_iter().flatten() {
+        let script_pubkey = vout["scriptPubKey"]["hex"]
+            .as_str()
+            .and_then(|hex| ScriptBuf::from_hex(hex).ok())
+            .ok_or((-22, "TX decode failed".to_string()))?;
+        output.push(TxOut {
+            value: (vout["value"].as_f64().unwrap_or_default() * 100_000_000.0).round() as u64,
+            script_pubkey,
+        });
+    }
+    let transaction = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output,
+    };
+    Ok(serialize_hex(&transaction))
+}
+
+/// Bitcoin Core JSON-RPC server serving blocks from memory or from fixture files
+/// (`getblock <hash> 3` responses), for exercising the code talking to bitcoind without a node.
+///
+/// Supported methods: `getblockchaininfo`, `getblockcount`, `getblockhash`, `getblockheader`,
+/// `getblock` (verbosity 2 and 3) and `getrawtransaction` (outputs only, not verbose).
+pub struct MockBitcoind {
+    pub url: String,
+    state: Arc<Mutex<MockBitcoindState>>,
+}
+
+impl MockBitcoind {
+    pub fn start() -> MockBitcoind {
+        let state = Arc::new(Mutex::new(MockBitcoindState::default()));
+        let moved_state = state.clone();
+        let url = start_http_server(Arc::new(move |_method, _path, body| {
+            let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+            let method = request["method"].as_str().unwrap_or_default().to_string();
+            let mut state = moved_state.lock().unwrap();
+            state.requests.push(method.clone());
+            if state.failures_to_inject > 0 {
+                state.failures_to_inject -= 1;
+                return (503, b"Work queue depth exceeded".to_vec());
+            }
+            let response = match state.handle(&method, &request["params"]) {
+                Ok(result) => (
+                    200,
+                    json!({ "result": result, "error": null, "id": request["id"] }),
+                ),
+                Err((code, message)) => (
+                    500,
+                    json!({
+                        "result": null,
+                        "error": { "code": code, "message": message },
+                        "id": request["id"],
+                    }),
+                ),
+            };
+            (response.0, response.1.to_string().into_bytes())
+        }));
+        MockBitcoind { url, state }
+    }
+
+    /// Serve the blocks found in `fixtures_dir` (one `getblock <hash> 3` response per file).
+    pub fn start_with_fixtures(fixtures_dir: &PathBuf) -> Result<MockBitcoind, String> {
+        let mock = MockBitcoind::start();
+        let mut paths = std::fs::read_dir(fixtures_dir)
+            .map_err(|e| format!("unable to read {}: {}", fixtures_dir.display(), e))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .collect::<Vec<_>>();
+        paths.sort();
+        for path in paths.iter() {
+            let bytes = std::fs::read(path)
+                .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+            let response: Value = serde_json::from_slice(&bytes)
+                .map_err(|e| format!("unable to parse {}: {}", path.display(), e))?;
+            mock.add_block(response.get("result").cloned().unwrap_or(response));
+        }
+        Ok(mock)
+    }
+
+    /// Add (or replace) the block at the height it's describing.
+    pub fn add_block(&self, block: Value) {
+        let block_height = block["height"].as_u64().expect("block height missing");
+        self.state
+            .lock()
+            .unwrap()
+            .blocks
+            .insert(block_height, block);
+    }
+
+    /// Replace the chain served.
+    pub fn set_chain(&self, blocks: Vec<Value>) {
+        self.state.lock().unwrap().blocks.clear();
+        for block in blocks.into_iter() {
+            self.add_block(block);
+        }
+    }
+
+    /// The next `count` requests are rejected, the way bitcoind does when its work queue is full.
+    pub fn fail_next_requests(&self, count: usize) {
+        self.state.lock().unwrap().failures_to_inject = count;
+    }
+
+    /// Methods of the requests received so far, rejected requests included.
+    pub fn requests(&self) -> Vec<String> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
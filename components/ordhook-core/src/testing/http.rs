@@ -0,0 +1,159 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+/// Request handler: (method, path, body) -> (status code, JSON or raw body).
+pub type RequestHandler = Arc<dyn Fn(&str, &str, &[u8]) -> (u16, Vec<u8>) + Send + Sync>;
+
+/// Handler with access to the request headers and full control over the response.
+pub type HttpHandler = Arc<dyn Fn(&HttpRequest) -> HttpResponse + Send + Sync>;
+
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Close the connection after sending this many bytes of the body, to simulate a
+    /// dropped connection. `Content-Length` still announces the full body.
+    pub truncate_body_at: Option<usize>,
+}
+
+impl HttpResponse {
+    pub fn new(status: u16, body: Vec<u8>) -> HttpResponse {
+        HttpResponse {
+            status,
+            headers: vec![],
+            body,
+            truncate_body_at: None,
+        }
+    }
+}
+
+/// Serve HTTP/1.1 requests on an ephemeral port of the loopback interface, one thread per
+/// connection. Returns the base URL of the server.
+pub fn start_http_server(handler: RequestHandler) -> String {
+    start_raw_http_server(Arc::new(move |request| {
+        let (status, body) = handler(&request.method, &request.path, &request.body);
+        let mut response = HttpResponse::new(status, body);
+        response
+            .headers
+            .push(("Content-Type".into(), "application/json".into()));
+        response
+    }))
+}
+
+/// Same as `start_http_server`, for handlers reading the request headers or setting the
+/// response headers.
+pub fn start_raw_http_server(handler: HttpHandler) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let handler = handler.clone();
+            std::thread::spawn(move || serve_connection(stream, handler));
+        }
+    });
+    url
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        404 => "Not Found",
+        416 => "Range Not Satisfiable",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+fn serve_connection(stream: TcpStream, handler: HttpHandler) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    // Connections are kept alive until closed by the client
+    loop {
+        let mut content_length = 0;
+        let mut headers = vec![];
+        let mut request_line = String::new();
+        match reader.read_line(&mut request_line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (name, value) = (name.trim().to_lowercase(), value.trim().to_string());
+            if name == "content-length" {
+                content_length = value.parse().unwrap_or(0);
+            }
+            headers.push((name, value));
+        }
+        let mut body = vec![0u8; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let request = HttpRequest {
+            method: parts.next().unwrap_or_default().to_string(),
+            path: parts.next().unwrap_or_default().to_string(),
+            headers,
+            body,
+        };
+        let response = handler(&request);
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\n",
+            response.status,
+            reason_phrase(response.status)
+        );
+        for (name, value) in response.headers.iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", response.body.len()));
+        let body = match response.truncate_body_at {
+            Some(len) => &response.body[..len.min(response.body.len())],
+            None => &response.body[..],
+        };
+        if writer.write_all(head.as_bytes()).is_err() || writer.write_all(body).is_err() {
+            return;
+        }
+        let _ = writer.flush();
+        if response.truncate_body_at.is_some() {
+            return;
+        }
+    }
+}
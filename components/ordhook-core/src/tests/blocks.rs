@@ -0,0 +1,59 @@
+use chainhook_sdk::bitcoincore_rpc::bitcoin::absolute::LockTime;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::block::{Header, Version};
+use chainhook_sdk::bitcoincore_rpc::bitcoin::hashes::Hash;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{
+    Block, BlockHash, CompactTarget, OutPoint, ScriptBuf, Sequence, Transaction, TxIn,
+    TxMerkleNode, TxOut, Witness,
+};
+
+/// Transaction spending `inputs`, and paying `outputs` to a P2WPKH script.
+pub fn craft_transaction(inputs: Vec<OutPoint>, outputs: Vec<u64>) -> Transaction {
+    let script_pubkey =
+        ScriptBuf::from_hex("0014751e76e8199196d454941c45d1b3a323f1433bd6").unwrap();
+    Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: inputs
+            .into_iter()
+            .map(|previous_output| TxIn {
+                previous_output,
+                script_sig: ScriptBuf::from_bytes(vec![0x51]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .into_iter()
+            .map(|value| TxOut {
+                value,
+                script_pubkey: script_pubkey.clone(),
+            })
+            .collect(),
+    }
+}
+
+/// Coinbase of the block at `block_height`, paying 50 BTC.
+pub fn craft_coinbase(block_height: u8) -> Transaction {
+    let mut tx = craft_transaction(vec![OutPoint::null()], vec![5_000_000_000]);
+    tx.input[0].script_sig = ScriptBuf::from_bytes(vec![0x01, block_height]);
+    tx
+}
+
+/// Chain of consensus-serializable blocks (proof of work and merkle roots are not valid).
+pub fn craft_chain(txdatas: Vec<Vec<Transaction>>) -> Vec<Block> {
+    let mut blocks: Vec<Block> = vec![];
+    for txdata in txdatas.into_iter() {
+        let header = Header {
+            version: Version::ONE,
+            prev_blockhash: blocks
+                .last()
+                .map_or(BlockHash::all_zeros(), |b| b.block_hash()),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time: 1_700_000_000 + blocks.len() as u32,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        };
+        blocks.push(Block { header, txdata });
+    }
+    blocks
+}
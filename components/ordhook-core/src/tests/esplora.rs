@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+
+use chainhook_sdk::bitcoincore_rpc::bitcoin::consensus::serialize;
+use chainhook_sdk::bitcoincore_rpc::bitcoin::{Block, OutPoint};
+use chainhook_sdk::indexer::bitcoin::parse_downloaded_block;
+use chainhook_sdk::utils::Context;
+use serde_json::{json, Value};
+
+use crate::config::{BlockSourceConfig, Config};
+use crate::core::pipeline::block_source::{build_block_source, BlockSource};
+use crate::db::BlockBytesCursor;
+use crate::testing::http::start_http_server;
+
+use super::blocks::{craft_chain, craft_coinbase, craft_transaction};
+
+/// Esplora REST API serving a chain of blocks: `/blocks/tip/height`, `/block-height/:height`,
+/// `/block/:hash`, `/block/:hash/raw` and `/tx/:txid`.
+pub struct MockEsplora {
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockEsplora {
+    pub fn start(chain: Vec<Block>) -> MockEsplora {
+        let requests = Arc::new(Mutex::new(vec![]));
+        let moved_requests = requests.clone();
+        let url = start_http_server(Arc::new(move |_method, path, _body| {
+            moved_requests.lock().unwrap().push(path.to_string());
+            let segments = path.trim_start_matches('/').split('/').collect::<Vec<_>>();
+            let find_block = |hash: &str| {
+                chain
+                    .iter()
+                    .enumerate()
+                    .find(|(_, block)| block.block_hash().to_string() == hash)
+            };
+            let not_found = (404, b"Not Found".to_vec());
+            match segments.as_slice() {
+                ["blocks", "tip", "height"] => (200, (chain.len() - 1).to_string().into_bytes()),
+                ["block-height", height] => match chain.get(height.parse::<usize>().unwrap()) {
+                    Some(block) => (200, block.block_hash().to_string().into_bytes()),
+                    None => (404, b"Block not found".to_vec()),
+                },
+                ["block", hash] => match find_block(hash) {
+                    Some((height, block)) => {
+                        let block_info = json!({
+                            "id": hash,
+                            "height": height,
+                            "timestamp": block.header.time,
+                            "mediantime": block.header.time,
+                            "tx_count": block.txdata.len(),
+                        });
+                        (200, block_info.to_string().into_bytes())
+                    }
+                    None => not_found,
+                },
+                ["block", hash, "raw"] => match find_block(hash) {
+                    Some((_, block)) => (200, serialize(block)),
+                    None => not_found,
+                },
+                ["tx", txid] => {
+                    let found = chain.iter().enumerate().find_map(|(height, block)| {
+                        block
+                            .txdata
+                            .iter()
+                            .find(|tx| tx.txid().to_string() == *txid)
+                            .map(|tx| (height, tx))
+                    });
+                    match found {
+                        Some((height, tx)) => {
+                            let tx = json!({
+                                "txid": txid,
+                                "vout": tx.output.iter().map(|output| json!({
+                                    "scriptpubkey": hex::encode(output.script_pubkey.as_bytes()),
+                                    "value": output.value,
+                                })).collect::<Vec<_>>(),
+                                "status": { "confirmed": true, "block_height": height },
+                            });
+                            (200, tx.to_string().into_bytes())
+                        }
+                        None => (404, b"Transaction not found".to_vec()),
+                    }
+                }
+                _ => not_found,
+            }
+        }));
+        MockEsplora { url, requests }
+    }
+
+    /// Paths of the requests received so far.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn test_fetch_blocks_from_esplora() {
+    let ctx = Context::empty();
+    let coinbase_0 = craft_coinbase(0);
+    let coinbase_1 = craft_coinbase(1);
+    let chain = craft_chain(vec![
+        vec![coinbase_0.clone()],
+        vec![coinbase_1.clone()],
+        vec![
+            craft_coinbase(2),
+            craft_transaction(
+                vec![
+                    OutPoint::new(coinbase_0.txid(), 0),
+                    OutPoint::new(coinbase_1.txid(), 0),
+                ],
+                vec![10_000, 9_999_989_000],
+            ),
+        ],
+    ]);
+    let esplora = MockEsplora::start(chain.clone());
+    let mut config = Config::devnet_default();
+    config.block_source = BlockSourceConfig::Esplora(format!("{}/", esplora.url));
+
+    let block_source = build_block_source(&config, &ctx).unwrap();
+    assert_eq!(block_source.get_chain_tip(&ctx), Ok(2));
+    assert_eq!(
+        block_source.get_block_hash(1, &ctx),
+        Ok(chain[1].block_hash().to_string())
+    );
+
+    // Prevouts are resolved with the transactions they were created by
+    let bytes = hiro_system_kit::nestable_block_on(block_source.fetch_block(2, &ctx)).unwrap();
+    let response: Value = serde_json::from_slice(&bytes).unwrap();
+    let vin = &response["result"]["tx"][1]["vin"];
+    assert_eq!(vin[0]["prevout"]["height"], 0);
+    assert_eq!(vin[0]["prevout"]["value"], 50.0);
+    assert_eq!(vin[1]["prevout"]["height"], 1);
+    let block = parse_downloaded_block(bytes).unwrap();
+    assert_eq!(block.height, 2);
+    BlockBytesCursor::from_full_block(&block).unwrap();
+    let requests = esplora.requests();
+    assert!(requests.contains(&format!("/tx/{}", coinbase_0.txid())));
+    assert!(requests.contains(&format!("/tx/{}", coinbase_1.txid())));
+
+    // Transactions of the blocks already fetched are not requested
+    let block_source = build_block_source(&config, &ctx).unwrap();
+    hiro_system_kit::nestable_block_on(block_source.fetch_block(1, &ctx)).unwrap();
+    let requests_count = esplora.requests().len();
+    hiro_system_kit::nestable_block_on(block_source.fetch_block(2, &ctx)).unwrap();
+    let requests = esplora.requests()[requests_count..].to_vec();
+    assert!(requests.contains(&format!("/tx/{}", coinbase_0.txid())));
+    assert!(!requests.contains(&format!("/tx/{}", coinbase_1.txid())));
+
+    assert!(hiro_system_kit::nestable_block_on(block_source.fetch_block(3, &ctx)).is_err());
+}
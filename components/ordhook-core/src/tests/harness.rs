@@ -0,0 +1,510 @@
+use std::sync::Arc;
+
+use chainhook_sdk::bitcoincore_rpc_json::bitcoin::{Address, Network, ScriptBuf};
+use chainhook_sdk::indexer::bitcoin::{parse_downloaded_block, BitcoinBlockFullBreakdown};
+use chainhook_sdk::observer::{BitcoinBlockDataCached, HandleBlock};
+use chainhook_sdk::types::{BitcoinBlockData, BitcoinNetwork};
+use chainhook_sdk::utils::Context;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::config::{Config, MemoryBudget};
+use crate::core::new_traversals_lazy_cache;
+use crate::core::pipeline::processors::inscription_indexing::process_blocks;
+use crate::core::protocol::inscription_parsing::parse_inscriptions_and_standardize_block;
+use crate::core::protocol::inscription_sequencing::SequenceCursor;
+use crate::db::journal::{
+    find_block_undo_journal, find_latest_location_of_ordinal, BlockUndoJournal, JournaledLocation,
+};
+use crate::db::store::SqliteInscriptionStore;
+use crate::db::traversals_cache::TraversalsCache;
+use crate::db::{
+    find_inscription_with_id, initialize_ordhook_db, insert_entry_in_blocks,
+    open_ordhook_db_conn_rocks_db_loop, open_readonly_ordhook_db_conn,
+    open_readwrite_ordhook_db_conn, BlockBytesCursor, TraversalResult,
+};
+use crate::ord::height::Height;
+use crate::ord::inscription::Inscription;
+use crate::service::{chainhook_sidecar_mutate_blocks, chainhook_sidecar_mutate_ordhook_db};
+use crate::testing::TempDir;
+
+/// Witness program shared by every output created by the harness.
+const P2WPKH_SCRIPT_PUBKEY: &str = "0014751e76e8199196d454941c45d1b3a323f1433bd6";
+
+/// Spendable output, as tracked by the harness.
+#[derive(Clone, Debug)]
+pub struct OutPoint {
+    pub txid: String,
+    pub vout: u32,
+    pub value: u64,
+    pub block_height: u64,
+}
+
+impl OutPoint {
+    pub fn satpoint(&self, offset: u64) -> String {
+        format!("{}:{}:{}", self.txid, self.vout, offset)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TransactionBuilder {
+    inputs: Vec<(OutPoint, Option<Vec<String>>)>,
+    outputs: Vec<u64>,
+}
+
+impl TransactionBuilder {
+    pub fn new() -> TransactionBuilder {
+        TransactionBuilder::default()
+    }
+
+    pub fn spend(mut self, outpoint: &OutPoint) -> TransactionBuilder {
+        self.inputs.push((outpoint.clone(), None));
+        self
+    }
+
+    /// Spend `outpoint` with a witness revealing `inscriptions`.
+    pub fn reveal(
+        mut self,
+        outpoint: &OutPoint,
+        inscriptions: &[Inscription],
+    ) -> TransactionBuilder {
+        let mut witness = chainhook_sdk::bitcoin::Witness::new();
+        witness.push(Inscription::append_batch_reveal_script(
+            inscriptions,
+            chainhook_sdk::bitcoin::blockdata::script::Builder::new(),
+        ));
+        witness.push([]);
+        self.inputs.push((
+            outpoint.clone(),
+            Some(witness.iter().map(|w| hex::encode(w)).collect()),
+        ));
+        self
+    }
+
+    pub fn output(mut self, value: u64) -> TransactionBuilder {
+        self.outputs.push(value);
+        self
+    }
+
+    fn fee(&self) -> u64 {
+        let total_in: u64 = self.inputs.iter().map(|(o, _)| o.value).sum();
+        let total_out: u64 = self.outputs.iter().sum();
+        total_in - total_out
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MinedTransaction {
+    pub txid: String,
+    pub outputs: Vec<OutPoint>,
+}
+
+impl MinedTransaction {
+    pub fn outpoint(&self, vout: usize) -> OutPoint {
+        self.outputs[vout].clone()
+    }
+
+    pub fn inscription_id(&self, index: usize) -> String {
+        format!("{}i{}", self.txid, index)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MinedBlock {
+    /// Block, as returned by `getblock <hash> 3`.
+    pub raw_block: Value,
+    pub block: BitcoinBlockData,
+    pub coinbase: MinedTransaction,
+    pub transactions: Vec<MinedTransaction>,
+}
+
+/// Path used to index blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndexingPath {
+    /// `process_blocks`, as invoked by the inscription indexing processor during catch-up.
+    Processor,
+    /// `chainhook_sidecar_mutate_blocks` followed by `chainhook_sidecar_mutate_ordhook_db`,
+    /// as invoked when streaming blocks from bitcoind.
+    Sidecar,
+    /// Block mined while ordhook is not running.
+    Unindexed,
+}
+
+/// Regtest chain driven in a temporary working directory: blocks are crafted on the fly,
+/// and indexed without any bitcoind.
+pub struct RegtestHarness {
+    pub config: Config,
+    pub ctx: Context,
+    pub chain: Vec<MinedBlock>,
+    cache_l2: Arc<TraversalsCache>,
+    nonce: u64,
+    /// Removed with the harness.
+    _working_dir: TempDir,
+}
+
+impl RegtestHarness {
+    /// Start a chain with a coinbase-only genesis block.
+    pub fn new() -> RegtestHarness {
+        let working_dir = TempDir::new("regtest");
+        let mut config = Config::devnet_default();
+        config.storage.working_dir = working_dir.path().display().to_string();
+        config.logs.ordinals_internals = false;
+        let ctx = Context::empty();
+        let _ = initialize_ordhook_db(&config.expected_cache_path(), &ctx);
+
+        let mut harness = RegtestHarness {
+            cache_l2: Arc::new(new_traversals_lazy_cache(MemoryBudget::new(
+                64 * 1024 * 1024,
+            ))),
+            config,
+            ctx,
+            chain: vec![],
+            nonce: 0,
+            _working_dir: working_dir,
+        };
+        harness.mine(vec![], IndexingPath::Processor);
+        harness
+    }
+
+    pub fn tip(&self) -> u64 {
+        self.chain
+            .last()
+            .map_or(0, |b| b.block.block_identifier.index)
+    }
+
+    fn hash(&mut self, label: &str) -> String {
+        self.nonce += 1;
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{label}:{}", self.nonce).as_bytes());
+        hex::encode(hasher.finalize())
+    }
+
+    fn script_pubkey_json() -> Value {
+        let script = ScriptBuf::from_hex(P2WPKH_SCRIPT_PUBKEY).unwrap();
+        let address = Address::from_script(&script, Network::Regtest).unwrap();
+        json!({
+            "asm": script.to_asm_string(),
+            "desc": format!("addr({address})"),
+            "hex": P2WPKH_SCRIPT_PUBKEY,
+            "address": address.to_string(),
+            "type": "witness_v0_keyhash",
+        })
+    }
+
+    fn btc(sats: u64) -> f64 {
+        sats as f64 / 100_000_000.0
+    }
+
+    /// Craft the next block, in the format returned by `getblock <hash> 3`.
+    fn craft_block(
+        &mut self,
+        transactions: &[TransactionBuilder],
+    ) -> (
+        Value,
+        BitcoinBlockFullBreakdown,
+        MinedTransaction,
+        Vec<MinedTransaction>,
+    ) {
+        let block_height = match self.chain.is_empty() {
+            true => 0,
+            false => self.tip() + 1,
+        };
+        let block_hash = self.hash("block");
+        let previous_block_hash = self.chain.last().map(|b| {
+            b.block
+                .block_identifier
+                .hash
+                .trim_start_matches("0x")
+                .to_string()
+        });
+
+        let fees: u64 = transactions.iter().map(|tx| tx.fee()).sum();
+        let coinbase_value = Height(block_height).subsidy() + fees;
+        let coinbase_txid = self.hash("tx");
+        let coinbase = MinedTransaction {
+            txid: coinbase_txid.clone(),
+            outputs: vec![OutPoint {
+                txid: coinbase_txid.clone(),
+                vout: 0,
+                value: coinbase_value,
+                block_height,
+            }],
+        };
+        let mut raw_transactions = vec![json!({
+            "txid": coinbase_txid,
+            "hash": coinbase_txid,
+            "version": 2,
+            "locktime": 0,
+            "vin": [{
+                "coinbase": format!("03{:06x}00", block_height),
+                "sequence": 4294967295u32,
+            }],
+            "vout": [{
+                "value": Self::btc(coinbase_value),
+                "n": 0,
+                "scriptPubKey": Self::script_pubkey_json(),
+            }],
+        })];
+
+        let mut mined_transactions = vec![];
+        for transaction in transactions.iter() {
+            let txid = self.hash("tx");
+            let vin = transaction
+                .inputs
+                .iter()
+                .map(|(outpoint, witness)| {
+                    let mut input = json!({
+                        "txid": outpoint.txid,
+                        "vout": outpoint.vout,
+                        "scriptSig": { "asm": "", "hex": "" },
+                        "prevout": {
+                            "generated": false,
+                            "height": outpoint.block_height,
+                            "value": Self::btc(outpoint.value),
+                            "scriptPubKey": Self::script_pubkey_json(),
+                        },
+                        "sequence": 4294967293u32,
+                    });
+                    if let Some(witness) = witness {
+                        input["txinwitness"] = json!(witness);
+                    }
+                    input
+                })
+                .collect::<Vec<_>>();
+            let vout = transaction
+                .outputs
+                .iter()
+                .enumerate()
+                .map(|(n, value)| {
+                    json!({
+                        "value": Self::btc(*value),
+                        "n": n,
+                        "scriptPubKey": Self::script_pubkey_json(),
+                    })
+                })
+                .collect::<Vec<_>>();
+            raw_transactions.push(json!({
+                "txid": txid,
+                "hash": txid,
+                "version": 2,
+                "locktime": 0,
+                "vin": vin,
+                "vout": vout,
+                "fee": Self::btc(transaction.fee()),
+            }));
+            mined_transactions.push(MinedTransaction {
+                txid: txid.clone(),
+                outputs: transaction
+                    .outputs
+                    .iter()
+                    .enumerate()
+                    .map(|(vout, value)| OutPoint {
+                        txid: txid.clone(),
+                        vout: vout as u32,
+                        value: *value,
+                        block_height,
+                    })
+                    .collect(),
+            });
+        }
+
+        let mut raw_block = json!({
+            "hash": block_hash,
+            "confirmations": 1,
+            "height": block_height,
+            "version": 536870912,
+            "versionHex": "20000000",
+            "merkleroot": "0".repeat(64),
+            "time": 1700000000 + block_height,
+            "mediantime": 1700000000 + block_height,
+            "nonce": 0,
+            "bits": "207fffff",
+            "difficulty": 4.656542373906925e-10,
+            "chainwork": "0".repeat(64),
+            "nTx": raw_transactions.len(),
+            "tx": raw_transactions,
+        });
+        if let Some(previous_block_hash) = previous_block_hash {
+            raw_block["previousblockhash"] = json!(previous_block_hash);
+        }
+        let decoded_block =
+            parse_downloaded_block(serde_json::to_vec(&json!({ "result": raw_block })).unwrap())
+                .expect("unable to decode crafted block");
+        (raw_block, decoded_block, coinbase, mined_transactions)
+    }
+
+    /// Mine a block on top of the current tip and index it.
+    pub fn mine(
+        &mut self,
+        transactions: Vec<TransactionBuilder>,
+        path: IndexingPath,
+    ) -> MinedBlock {
+        let (raw_block, decoded_block, coinbase, mined_transactions) =
+            self.craft_block(&transactions);
+        let block_bytes = BlockBytesCursor::from_full_block(&decoded_block).unwrap();
+        let mut block = parse_inscriptions_and_standardize_block(
+            decoded_block,
+            &BitcoinNetwork::Regtest,
+            &self.ctx,
+        )
+        .map_err(|(e, _)| e)
+        .expect("unable to standardize crafted block");
+
+        match path {
+            IndexingPath::Processor => {
+                {
+                    let blocks_db_rw = open_ordhook_db_conn_rocks_db_loop(
+                        true,
+                        &self.config.expected_cache_path(),
+                        &self.config.resources,
+                        &self.ctx,
+                    );
+                    insert_entry_in_blocks(
+                        block.block_identifier.index as u32,
+                        &block_bytes,
+                        true,
+                        &blocks_db_rw,
+                        &self.ctx,
+                    );
+                }
+                let mut inscriptions_db_conn_rw =
+                    open_readwrite_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx)
+                        .unwrap();
+                let inscriptions_db_conn =
+                    open_readonly_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx)
+                        .unwrap();
+                let inscriptions_store = SqliteInscriptionStore::new(&inscriptions_db_conn);
+                let mut sequence_cursor = SequenceCursor::new(&inscriptions_store);
+                block = process_blocks(
+                    &mut vec![block],
+                    &mut sequence_cursor,
+                    &self.cache_l2,
+                    &mut inscriptions_db_conn_rw,
+                    &self.config.get_ordhook_config(),
+                    &None,
+                    &self.ctx,
+                )
+                .remove(0);
+            }
+            IndexingPath::Sidecar => {
+                let mut blocks = vec![BitcoinBlockDataCached {
+                    block,
+                    processed_by_sidecar: false,
+                }];
+                chainhook_sidecar_mutate_blocks(
+                    &mut blocks,
+                    &vec![],
+                    &self.cache_l2,
+                    &self.config,
+                    &self.ctx,
+                );
+                block = blocks.remove(0).block;
+                chainhook_sidecar_mutate_ordhook_db(
+                    HandleBlock::ApplyBlock(block.clone()),
+                    &self.config,
+                    &self.ctx,
+                );
+            }
+            IndexingPath::Unindexed => {}
+        }
+
+        let mined_block = MinedBlock {
+            raw_block,
+            block,
+            coinbase,
+            transactions: mined_transactions,
+        };
+        self.chain.push(mined_block.clone());
+        mined_block
+    }
+
+    /// Mine `count` empty blocks.
+    pub fn mine_empty_blocks(&mut self, count: u64) {
+        for _ in 0..count {
+            self.mine(vec![], IndexingPath::Processor);
+        }
+    }
+
+    /// Re-org handling, as performed by the sidecar: the `depth` latest blocks are undone,
+    /// and the next blocks mined are building a fork.
+    pub fn reorg(&mut self, depth: usize) -> Vec<MinedBlock> {
+        let mut orphaned = vec![];
+        for _ in 0..depth {
+            let mined_block = self.chain.pop().expect("chain exhausted");
+            chainhook_sidecar_mutate_ordhook_db(
+                HandleBlock::UndoBlock(mined_block.block.clone()),
+                &self.config,
+                &self.ctx,
+            );
+            orphaned.push(mined_block);
+        }
+        orphaned
+    }
+
+    /// Re-org happening while ordhook is not running: the `depth` latest blocks are dropped
+    /// from the chain, without being undone.
+    pub fn disconnect(&mut self, depth: usize) -> Vec<MinedBlock> {
+        let mut orphaned = vec![];
+        for _ in 0..depth {
+            orphaned.push(self.chain.pop().expect("chain exhausted"));
+        }
+        orphaned
+    }
+
+    /// Blocks of the chain, as returned by `getblock <hash> 3`.
+    pub fn raw_blocks(&self) -> Vec<Value> {
+        self.chain.iter().map(|b| b.raw_block.clone()).collect()
+    }
+
+    /// Coinbase output of the block mined at `block_height`.
+    pub fn coinbase_outpoint(&self, block_height: u64) -> OutPoint {
+        self.chain[block_height as usize].coinbase.outpoint(0)
+    }
+
+    /// First sat mined in the block at `block_height`.
+    pub fn first_sat(block_height: u64) -> u64 {
+        Height(block_height).starting_sat().0
+    }
+
+    pub fn find_inscription(&self, inscription_id: &str) -> Option<(TraversalResult, u64)> {
+        let inscriptions_db_conn =
+            open_readonly_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx).unwrap();
+        find_inscription_with_id(inscription_id, &inscriptions_db_conn, &self.ctx).unwrap_or(None)
+    }
+
+    pub fn find_latest_location(&self, inscription_id: &str) -> Option<JournaledLocation> {
+        let (traversal, _) = self.find_inscription(inscription_id)?;
+        let inscriptions_db_conn =
+            open_readonly_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx).unwrap();
+        find_latest_location_of_ordinal(traversal.ordinal_number, &inscriptions_db_conn, &self.ctx)
+    }
+
+    pub fn find_block_undo_journal(&self, block_height: u64) -> Option<BlockUndoJournal> {
+        let inscriptions_db_conn =
+            open_readonly_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx).unwrap();
+        find_block_undo_journal(block_height, &inscriptions_db_conn, &self.ctx)
+    }
+
+    /// Dump of the inscriptions index, used to compare states.
+    pub fn dump_inscriptions_index(&self) -> Vec<String> {
+        let conn =
+            open_readonly_ordhook_db_conn(&self.config.expected_cache_path(), &self.ctx).unwrap();
+        let mut dump = vec![];
+        for (table, query) in [
+            ("inscriptions", "SELECT inscription_id, input_index, block_height, ordinal_number, jubilee_inscription_number, classic_inscription_number FROM inscriptions ORDER BY inscription_id"),
+            ("locations", "SELECT ordinal_number, block_height, tx_index, outpoint_to_watch, offset FROM locations ORDER BY ordinal_number, block_height, tx_index"),
+            ("sequence_metadata", "SELECT block_height, nth_classic_pos_number, nth_classic_neg_number, nth_jubilee_number FROM sequence_metadata ORDER BY block_height"),
+        ] {
+            let mut stmt = conn.prepare(query).unwrap();
+            let columns = stmt.column_count();
+            let mut rows = stmt.query([]).unwrap();
+            while let Some(row) = rows.next().unwrap() {
+                let values = (0..columns)
+                    .map(|i| format!("{:?}", row.get::<_, rusqlite::types::Value>(i).unwrap()))
+                    .collect::<Vec<_>>();
+                dump.push(format!("{table}: {}", values.join(", ")));
+            }
+        }
+        dump
+    }
+}
@@ -0,0 +1,6 @@
+pub mod blocks;
+mod esplora;
+mod harness;
+mod pipeline;
+mod sync;
+mod verify;
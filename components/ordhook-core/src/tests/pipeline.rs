@@ -0,0 +1,390 @@
+use chainhook_sdk::types::OrdinalOperation;
+
+use crate::ord::inscription::Inscription;
+
+use super::harness::{IndexingPath, RegtestHarness, TransactionBuilder};
+
+const FEE: u64 = 1_000;
+const POSTAGE: u64 = 10_000;
+
+fn text_inscription(body: &str) -> Inscription {
+    Inscription::new(
+        Some("text/plain;charset=utf-8".as_bytes().to_vec()),
+        Some(body.as_bytes().to_vec()),
+    )
+}
+
+fn assert_inscription_number(
+    harness: &RegtestHarness,
+    inscription_id: &str,
+    classic: i64,
+    jubilee: i64,
+) {
+    let (traversal, _) = harness
+        .find_inscription(inscription_id)
+        .expect("inscription not indexed");
+    assert_eq!(
+        (
+            traversal.inscription_number.classic,
+            traversal.inscription_number.jubilee
+        ),
+        (classic, jubilee),
+        "unexpected number for inscription {inscription_id}"
+    );
+}
+
+#[test]
+fn test_inscriptions_numbering() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(3);
+
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let coinbase_2 = harness.coinbase_outpoint(2);
+    let block = harness.mine(
+        vec![
+            TransactionBuilder::new()
+                .reveal(&coinbase_1, &[text_inscription("first")])
+                .output(POSTAGE)
+                .output(coinbase_1.value - POSTAGE - FEE),
+            TransactionBuilder::new()
+                .reveal(&coinbase_2, &[text_inscription("second")])
+                .output(POSTAGE)
+                .output(coinbase_2.value - POSTAGE - FEE),
+        ],
+        IndexingPath::Processor,
+    );
+    let first = block.transactions[0].inscription_id(0);
+    let second = block.transactions[1].inscription_id(0);
+
+    let coinbase_3 = harness.coinbase_outpoint(3);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_3, &[text_inscription("third")])
+            .output(POSTAGE)
+            .output(coinbase_3.value - POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let third = block.transactions[0].inscription_id(0);
+
+    assert_inscription_number(&harness, &first, 0, 0);
+    assert_inscription_number(&harness, &second, 1, 1);
+    assert_inscription_number(&harness, &third, 2, 2);
+
+    // Inscriptions are sitting on the first sat of the coinbase spent
+    let (traversal, block_height) = harness.find_inscription(&third).unwrap();
+    assert_eq!(block_height, 5);
+    assert_eq!(traversal.ordinal_number, RegtestHarness::first_sat(3));
+    let location = harness.find_latest_location(&third).unwrap();
+    assert_eq!(
+        format!("{}:{}", location.outpoint_to_watch, location.offset),
+        block.transactions[0].outpoint(0).satpoint(0)
+    );
+}
+
+#[test]
+fn test_cursed_inscriptions() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(3);
+
+    // Envelope revealed in the second input
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let coinbase_2 = harness.coinbase_outpoint(2);
+    let coinbase_3 = harness.coinbase_outpoint(3);
+    let block = harness.mine(
+        vec![
+            TransactionBuilder::new()
+                .spend(&coinbase_1)
+                .reveal(&coinbase_2, &[text_inscription("cursed")])
+                .output(coinbase_1.value)
+                .output(coinbase_2.value - FEE),
+            TransactionBuilder::new()
+                .reveal(&coinbase_3, &[text_inscription("blessed")])
+                .output(POSTAGE)
+                .output(coinbase_3.value - POSTAGE - FEE),
+        ],
+        IndexingPath::Processor,
+    );
+    let cursed = block.transactions[0].inscription_id(0);
+    let blessed = block.transactions[1].inscription_id(0);
+    assert_inscription_number(&harness, &cursed, -1, -1);
+    assert_inscription_number(&harness, &blessed, 0, 0);
+
+    // Re-inscribing a sat already carrying a blessed inscription
+    let inscribed_output = block.transactions[1].outpoint(0);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&inscribed_output, &[text_inscription("reinscription")])
+            .output(POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let reinscription = block.transactions[0].inscription_id(0);
+    assert_inscription_number(&harness, &reinscription, -2, -2);
+
+    // After the jubilee (block #110 on regtest), cursed inscriptions are getting positive jubilee numbers
+    harness.mine_empty_blocks(110 - harness.tip() - 1);
+    let coinbase_4 = harness.coinbase_outpoint(4);
+    let coinbase_5 = harness.coinbase_outpoint(5);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .spend(&coinbase_4)
+            .reveal(&coinbase_5, &[text_inscription("post-jubilee")])
+            .output(coinbase_4.value)
+            .output(coinbase_5.value - FEE)],
+        IndexingPath::Processor,
+    );
+    assert_eq!(block.block.block_identifier.index, 110);
+    let post_jubilee = block.transactions[0].inscription_id(0);
+    assert_inscription_number(&harness, &post_jubilee, -3, 1);
+}
+
+#[test]
+fn test_custom_network_params() {
+    let mut harness = RegtestHarness::new();
+    harness.config.network_params.jubilee_height = 5;
+    harness.config.network_params.address_hrp = "sb".into();
+    harness.mine_empty_blocks(3);
+
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_1, &[text_inscription("blessed")])
+            .output(POSTAGE)
+            .output(coinbase_1.value - POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let blessed = block.transactions[0].inscription_id(0);
+    assert_inscription_number(&harness, &blessed, 0, 0);
+    let OrdinalOperation::InscriptionRevealed(ref inscription) =
+        block.block.transactions[1].metadata.ordinal_operations[0]
+    else {
+        panic!("inscription reveal expected");
+    };
+    assert!(inscription
+        .inscriber_address
+        .as_ref()
+        .is_some_and(|address| address.starts_with("sb1q")));
+
+    // Jubilee numbering starting at the configured height
+    let coinbase_2 = harness.coinbase_outpoint(2);
+    let coinbase_3 = harness.coinbase_outpoint(3);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .spend(&coinbase_2)
+            .reveal(&coinbase_3, &[text_inscription("post-jubilee")])
+            .output(coinbase_2.value)
+            .output(coinbase_3.value - FEE)],
+        IndexingPath::Processor,
+    );
+    assert_eq!(block.block.block_identifier.index, 5);
+    let post_jubilee = block.transactions[0].inscription_id(0);
+    assert_inscription_number(&harness, &post_jubilee, -1, 1);
+}
+
+#[test]
+fn test_inscriptions_sharing_ancestors() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(3);
+
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .spend(&coinbase_1)
+            .output(POSTAGE)
+            .output(POSTAGE)
+            .output(POSTAGE)
+            .output(coinbase_1.value - 3 * POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let split = block.transactions[0].clone();
+
+    // Traversals of the same block are batched, going through the same ancestors
+    let block = harness.mine(
+        (0..4)
+            .map(|vout| {
+                TransactionBuilder::new()
+                    .reveal(&split.outpoint(vout), &[text_inscription("batched")])
+                    .output(POSTAGE - FEE)
+            })
+            .collect(),
+        IndexingPath::Processor,
+    );
+    for (i, transaction) in block.transactions.iter().enumerate() {
+        let (traversal, _) = harness
+            .find_inscription(&transaction.inscription_id(0))
+            .expect("inscription not indexed");
+        assert_eq!(
+            traversal.ordinal_number,
+            RegtestHarness::first_sat(1) + i as u64 * POSTAGE
+        );
+    }
+}
+
+#[test]
+fn test_inscription_transfers() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(3);
+
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_1, &[text_inscription("transferred")])
+            .output(POSTAGE)
+            .output(coinbase_1.value - POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let inscription_id = block.transactions[0].inscription_id(0);
+    let inscribed_output = block.transactions[0].outpoint(0);
+
+    // The inscribed sat is preceded by the sats of the first input
+    let coinbase_2 = harness.coinbase_outpoint(2);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .spend(&coinbase_2)
+            .spend(&inscribed_output)
+            .output(coinbase_2.value)
+            .output(POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let location = harness.find_latest_location(&inscription_id).unwrap();
+    assert_eq!(location.block_height, 5);
+    assert_eq!(
+        format!("{}:{}", location.outpoint_to_watch, location.offset),
+        block.transactions[0].outpoint(1).satpoint(0)
+    );
+
+    // Inscribed sat spent in fees, ending up in the coinbase
+    let inscribed_output = block.transactions[0].outpoint(1);
+    let coinbase_3 = harness.coinbase_outpoint(3);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .spend(&coinbase_3)
+            .spend(&inscribed_output)
+            .output(coinbase_3.value)],
+        IndexingPath::Processor,
+    );
+    let location = harness.find_latest_location(&inscription_id).unwrap();
+    assert_eq!(location.block_height, 6);
+    assert_eq!(
+        location.outpoint_to_watch,
+        format!("{}:0", block.coinbase.txid)
+    );
+
+    // Numbering is not affected by transfers
+    assert_inscription_number(&harness, &inscription_id, 0, 0);
+}
+
+#[test]
+fn test_reorg_rollback() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(3);
+    let initial_state = harness.dump_inscriptions_index();
+
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_1, &[text_inscription("orphaned")])
+            .output(POSTAGE)
+            .output(coinbase_1.value - POSTAGE - FEE)],
+        IndexingPath::Sidecar,
+    );
+    let orphaned = block.transactions[0].inscription_id(0);
+    let inscribed_output = block.transactions[0].outpoint(0);
+    let state_after_reveal = harness.dump_inscriptions_index();
+
+    harness.mine(
+        vec![TransactionBuilder::new()
+            .spend(&inscribed_output)
+            .output(POSTAGE - FEE)],
+        IndexingPath::Sidecar,
+    );
+    assert_eq!(
+        harness
+            .find_latest_location(&orphaned)
+            .unwrap()
+            .block_height,
+        5
+    );
+
+    // Undoing the transfer restores the location recorded at reveal
+    harness.reorg(1);
+    assert_eq!(harness.dump_inscriptions_index(), state_after_reveal);
+
+    harness.reorg(1);
+    assert_eq!(harness.dump_inscriptions_index(), initial_state);
+    assert!(harness.find_inscription(&orphaned).is_none());
+
+    // The fork is numbered from scratch
+    let coinbase_2 = harness.coinbase_outpoint(2);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_2, &[text_inscription("canonical")])
+            .output(POSTAGE)
+            .output(coinbase_2.value - POSTAGE - FEE)],
+        IndexingPath::Sidecar,
+    );
+    assert_eq!(block.block.block_identifier.index, 4);
+    let canonical = block.transactions[0].inscription_id(0);
+    assert_inscription_number(&harness, &canonical, 0, 0);
+}
+
+#[test]
+fn test_reorg_rollback_after_catch_up() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(2);
+    let initial_state = harness.dump_inscriptions_index();
+
+    // Blocks indexed during catch-up are journaled as well
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_1, &[text_inscription("orphaned")])
+            .output(POSTAGE)
+            .output(coinbase_1.value - POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let orphaned = block.transactions[0].inscription_id(0);
+    let journal = harness
+        .find_block_undo_journal(block.block.block_identifier.index)
+        .expect("block not journaled");
+    assert_eq!(journal.block_hash, block.block.block_identifier.hash);
+
+    harness.reorg(1);
+    assert_eq!(harness.dump_inscriptions_index(), initial_state);
+    assert!(harness.find_inscription(&orphaned).is_none());
+    assert!(harness
+        .find_block_undo_journal(block.block.block_identifier.index)
+        .is_none());
+}
+
+#[test]
+fn test_reorg_rollback_without_undo_journal() {
+    let mut harness = RegtestHarness::new();
+    harness.config.storage.undo_journal_depth = 0;
+    harness.mine_empty_blocks(2);
+    let initial_state = harness.dump_inscriptions_index();
+
+    // Without journal, the rollback drops the block data
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_1, &[text_inscription("orphaned")])
+            .output(POSTAGE)
+            .output(coinbase_1.value - POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let orphaned = block.transactions[0].inscription_id(0);
+
+    harness.reorg(1);
+    assert_eq!(harness.dump_inscriptions_index(), initial_state);
+    assert!(harness.find_inscription(&orphaned).is_none());
+
+    let coinbase_2 = harness.coinbase_outpoint(2);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_2, &[text_inscription("canonical")])
+            .output(POSTAGE)
+            .output(coinbase_2.value - POSTAGE - FEE)],
+        IndexingPath::Sidecar,
+    );
+    assert_inscription_number(&harness, &block.transactions[0].inscription_id(0), 0, 0);
+}
@@ -0,0 +1,392 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chainhook_sdk::bitcoincore_rpc::{Auth, Client, RpcApi};
+
+use crate::core::pipeline::block_source::build_block_source;
+use crate::core::pipeline::processors::block_archiving::start_block_archiving_processor;
+use crate::core::pipeline::processors::start_inscription_indexing_processor;
+use crate::core::pipeline::{download_and_pipeline_blocks, ShutdownSignal};
+use crate::core::sync_planner::SyncPlanner;
+use crate::core::{find_blocks_to_download, rollback_orphaned_blocks, should_sync_ordhook_db};
+use crate::db::sync_checkpoint::{find_sync_checkpoint, write_sync_checkpoint, SyncCheckpoint};
+use crate::db::{
+    find_last_block_inserted, insert_entry_in_blocks, open_readonly_ordhook_db_conn_rocks_db,
+    open_readwrite_ordhook_db_conn_rocks_db,
+};
+use crate::ord::inscription::Inscription;
+use crate::service::Service;
+use crate::testing::bitcoind::MockBitcoind;
+
+use super::harness::{IndexingPath, RegtestHarness, TransactionBuilder};
+
+fn text_inscription(body: &str) -> Inscription {
+    Inscription::new(
+        Some("text/plain;charset=utf-8".as_bytes().to_vec()),
+        Some(body.as_bytes().to_vec()),
+    )
+}
+
+fn find_last_block_indexed(harness: &RegtestHarness) -> u32 {
+    let blocks_db = open_readonly_ordhook_db_conn_rocks_db(
+        &harness.config.expected_cache_path(),
+        &harness.config.resources,
+        &harness.ctx,
+    )
+    .unwrap();
+    find_last_block_inserted(&blocks_db)
+}
+
+#[test]
+fn test_sync_from_mock_bitcoind() {
+    let mut harness = RegtestHarness::new();
+    for _ in 0..3 {
+        harness.mine(vec![], IndexingPath::Unindexed);
+    }
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(&coinbase_1, &[text_inscription("synced")])
+            .output(10_000)
+            .output(coinbase_1.value - 11_000)],
+        IndexingPath::Unindexed,
+    );
+    let inscription_id = block.transactions[0].inscription_id(0);
+
+    let bitcoind = MockBitcoind::start();
+    bitcoind.set_chain(harness.raw_blocks());
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    let sync_planner = SyncPlanner::new(&config);
+    let batch = should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx)
+        .unwrap()
+        .expect("sync expected");
+    assert_eq!(batch.end_block, 4);
+
+    // Rejected requests are retried
+    bitcoind.fail_next_requests(2);
+    let blocks_post_processor = start_inscription_indexing_processor(&config, &harness.ctx, None);
+    hiro_system_kit::nestable_block_on(download_and_pipeline_blocks(
+        &config,
+        block_source.as_ref(),
+        (batch.start_block..=batch.end_block).collect(),
+        batch.start_block,
+        Some(&blocks_post_processor),
+        batch.queue_depth,
+        None,
+        &harness.ctx,
+    ))
+    .unwrap();
+
+    let requests = bitcoind.requests();
+    assert!(requests.iter().filter(|m| *m == "getblock").count() >= 5);
+    assert_eq!(find_last_block_indexed(&harness), 4);
+    let (traversal, block_height) = harness.find_inscription(&inscription_id).unwrap();
+    assert_eq!(block_height, 4);
+    assert_eq!(traversal.inscription_number.classic, 0);
+    assert_eq!(traversal.ordinal_number, RegtestHarness::first_sat(1));
+
+    assert_eq!(
+        should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx),
+        Ok(None)
+    );
+}
+
+#[test]
+fn test_sync_interrupted_by_shutdown() {
+    let mut harness = RegtestHarness::new();
+    for _ in 0..12 {
+        harness.mine(vec![], IndexingPath::Unindexed);
+    }
+
+    let bitcoind = MockBitcoind::start();
+    bitcoind.set_chain(harness.raw_blocks());
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    let sync_planner = SyncPlanner::new(&config);
+    let batch = should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx)
+        .unwrap()
+        .expect("sync expected");
+    assert_eq!(batch.end_block, 12);
+
+    // Only the blocks requested before the shutdown are processed
+    let shutdown = ShutdownSignal::new(Duration::from_secs(10));
+    shutdown.trigger();
+    let blocks_post_processor = start_inscription_indexing_processor(&config, &harness.ctx, None);
+    let stats = hiro_system_kit::nestable_block_on(download_and_pipeline_blocks(
+        &config,
+        block_source.as_ref(),
+        (batch.start_block..=batch.end_block).collect(),
+        batch.start_block,
+        Some(&blocks_post_processor),
+        batch.queue_depth,
+        Some(&shutdown),
+        &harness.ctx,
+    ))
+    .unwrap();
+    let requested_blocks = config.resources.bitcoind_rpc_threads as u64;
+    assert!(stats.blocks_processed <= requested_blocks);
+    let last_block_indexed = find_last_block_indexed(&harness) as u64;
+    assert!(last_block_indexed < batch.end_block);
+    assert!(last_block_indexed + 1 >= batch.start_block + stats.blocks_processed);
+
+    // The blocks left are processed by the next sync
+    let batch = should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx)
+        .unwrap()
+        .expect("sync expected");
+    let blocks_post_processor = start_inscription_indexing_processor(&config, &harness.ctx, None);
+    hiro_system_kit::nestable_block_on(download_and_pipeline_blocks(
+        &config,
+        block_source.as_ref(),
+        (batch.start_block..=batch.end_block).collect(),
+        batch.start_block,
+        Some(&blocks_post_processor),
+        batch.queue_depth,
+        None,
+        &harness.ctx,
+    ))
+    .unwrap();
+    assert_eq!(find_last_block_indexed(&harness), 12);
+}
+
+#[test]
+fn test_sync_resumes_from_checkpoint() {
+    let mut harness = RegtestHarness::new();
+    for _ in 0..12 {
+        harness.mine(vec![], IndexingPath::Unindexed);
+    }
+
+    let bitcoind = MockBitcoind::start();
+    bitcoind.set_chain(harness.raw_blocks());
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    config.network_params.first_inscription_height = 8;
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    // A previous sync archived blocks #4 to #6 before being interrupted
+    let sync_planner = SyncPlanner::new(&config);
+    let batch = should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx)
+        .unwrap()
+        .expect("sync expected");
+    assert_eq!((batch.start_block, batch.end_block), (0, 7));
+    let blocks_post_processor = start_block_archiving_processor(&config, &harness.ctx, true, None);
+    hiro_system_kit::nestable_block_on(download_and_pipeline_blocks(
+        &config,
+        block_source.as_ref(),
+        vec![4, 5, 6],
+        8,
+        Some(&blocks_post_processor),
+        batch.queue_depth,
+        None,
+        &harness.ctx,
+    ))
+    .unwrap();
+    assert_eq!(
+        find_blocks_to_download(&config, 1, 7, &harness.ctx),
+        Ok((vec![1, 2, 3, 7], 8))
+    );
+
+    // Only the blocks missing are downloaded again
+    let blocks_downloaded = bitcoind
+        .requests()
+        .iter()
+        .filter(|m| *m == "getblock")
+        .count();
+    let service = Service::new(config.clone(), harness.ctx.clone());
+    hiro_system_kit::nestable_block_on(service.update_state(None)).unwrap();
+    let blocks_downloaded = bitcoind
+        .requests()
+        .iter()
+        .filter(|m| *m == "getblock")
+        .count()
+        - blocks_downloaded;
+    assert_eq!(blocks_downloaded, 9);
+
+    let blocks_db = open_readonly_ordhook_db_conn_rocks_db(
+        &config.expected_cache_path(),
+        &config.resources,
+        &harness.ctx,
+    )
+    .unwrap();
+    assert_eq!(
+        find_sync_checkpoint(&blocks_db),
+        Some(SyncCheckpoint {
+            downloaded: Some(12),
+            archived: Some(12),
+            sequenced: Some(12),
+        })
+    );
+    assert_eq!(
+        should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx),
+        Ok(None)
+    );
+
+    // A range already archived leaves nothing to download, the post processor is terminated
+    let (blocks, start_sequencing) = find_blocks_to_download(&config, 1, 12, &harness.ctx).unwrap();
+    assert_eq!((blocks.clone(), start_sequencing), (vec![], 13));
+    let requests = bitcoind.requests().len();
+    let blocks_post_processor = start_block_archiving_processor(&config, &harness.ctx, true, None);
+    let stats = hiro_system_kit::nestable_block_on(download_and_pipeline_blocks(
+        &config,
+        block_source.as_ref(),
+        blocks,
+        start_sequencing,
+        Some(&blocks_post_processor),
+        batch.queue_depth,
+        None,
+        &harness.ctx,
+    ))
+    .unwrap();
+    assert_eq!(stats.blocks_processed, 0);
+    assert_eq!(bitcoind.requests().len(), requests);
+    blocks_post_processor.thread_handle.join().unwrap();
+}
+
+#[test]
+fn test_rollback_orphaned_blocks_from_mock_bitcoind() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(5);
+
+    // Blocks #4 and #5 are re-orged while ordhook is not running
+    harness.disconnect(2);
+    harness.mine(vec![], IndexingPath::Unindexed);
+    harness.mine(vec![], IndexingPath::Unindexed);
+
+    let bitcoind = MockBitcoind::start();
+    bitcoind.set_chain(harness.raw_blocks());
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    assert_eq!(
+        rollback_orphaned_blocks(&config, block_source.as_ref(), &harness.ctx),
+        Ok(Some((4, 5)))
+    );
+    assert_eq!(find_last_block_indexed(&harness), 3);
+    assert_eq!(
+        rollback_orphaned_blocks(&config, block_source.as_ref(), &harness.ctx),
+        Ok(None)
+    );
+}
+
+#[test]
+fn test_rollback_orphaned_blocks_archived_above_sequencing_tip() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(5);
+
+    // Block #6 was archived, but not sequenced yet: its hash is not recorded
+    {
+        let blocks_db_rw = open_readwrite_ordhook_db_conn_rocks_db(
+            &harness.config.expected_cache_path(),
+            &harness.config.resources,
+            &harness.ctx,
+        )
+        .unwrap();
+        insert_entry_in_blocks(6, &[0], true, &blocks_db_rw, &harness.ctx);
+        let checkpoint = SyncCheckpoint {
+            downloaded: Some(6),
+            archived: Some(6),
+            sequenced: Some(5),
+        };
+        write_sync_checkpoint(&checkpoint, &blocks_db_rw, &harness.ctx);
+    }
+
+    // Blocks #4 to #6 are re-orged while ordhook is not running
+    harness.mine(vec![], IndexingPath::Unindexed);
+    harness.disconnect(3);
+    for _ in 0..3 {
+        harness.mine(vec![], IndexingPath::Unindexed);
+    }
+
+    let bitcoind = MockBitcoind::start();
+    bitcoind.set_chain(harness.raw_blocks());
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    assert_eq!(
+        rollback_orphaned_blocks(&config, block_source.as_ref(), &harness.ctx),
+        Ok(Some((4, 6)))
+    );
+    assert_eq!(find_last_block_indexed(&harness), 3);
+}
+
+#[test]
+fn test_rollback_orphaned_blocks_refused_when_block_source_is_behind() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(5);
+
+    // The block source only knows about the blocks up to #3
+    let bitcoind = MockBitcoind::start();
+    let mut raw_blocks = harness.raw_blocks();
+    raw_blocks.truncate(4);
+    bitcoind.set_chain(raw_blocks);
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    assert!(rollback_orphaned_blocks(&config, block_source.as_ref(), &harness.ctx).is_err());
+    assert_eq!(find_last_block_indexed(&harness), 5);
+}
+
+#[test]
+fn test_mock_bitcoind_with_fixtures() {
+    let fixtures_dir =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/db/fixtures/blocks_json");
+    let bitcoind = MockBitcoind::start_with_fixtures(&fixtures_dir).unwrap();
+
+    let bitcoin_rpc = Client::new(&bitcoind.url, Auth::None).unwrap();
+    assert_eq!(
+        bitcoin_rpc.get_block_hash(279671).unwrap().to_string(),
+        "0000000000000002fa7d943a10651794a50447c262216df7695da5d624761cbf"
+    );
+    assert!(bitcoin_rpc.get_block_hash(279672).is_err());
+
+    let harness = RegtestHarness::new();
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let block_source = build_block_source(&config, &harness.ctx).unwrap();
+
+    // Nothing indexed yet: the first sync batch stops before the first inscription height
+    let sync_planner = SyncPlanner::new(&config);
+    let batch = should_sync_ordhook_db(&config, block_source.as_ref(), &sync_planner, &harness.ctx)
+        .unwrap()
+        .expect("sync expected");
+    assert_eq!(batch.start_block, 0);
+    assert_eq!(batch.end_block, 0);
+    assert_eq!(
+        bitcoind.requests().last(),
+        Some(&"getblockchaininfo".to_string())
+    );
+}
+
+#[test]
+fn test_poll_block_source_follows_reorgs() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(5);
+
+    let bitcoind = MockBitcoind::start();
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+    let service = Service::new(config, harness.ctx.clone());
+
+    // Block #5 is re-orged and two blocks are mined on top of the new chain
+    harness.disconnect(1);
+    for _ in 0..3 {
+        harness.mine(vec![], IndexingPath::Unindexed);
+    }
+    bitcoind.set_chain(harness.raw_blocks());
+    assert_eq!(
+        hiro_system_kit::nestable_block_on(service.poll_block_source()),
+        Ok(7)
+    );
+    assert_eq!(find_last_block_indexed(&harness), 7);
+
+    // Nothing changed since the last poll
+    hiro_system_kit::nestable_block_on(service.poll_block_source()).unwrap();
+    assert_eq!(find_last_block_indexed(&harness), 7);
+}
@@ -0,0 +1,103 @@
+use serde_json::{json, Value};
+
+use crate::config::Config;
+use crate::db::open_readonly_ordhook_db_conn;
+use crate::ord::inscription::Inscription;
+use crate::testing::bitcoind::MockBitcoind;
+use crate::testing::TempDir;
+use crate::verify::{
+    verify_inscriptions, InscriptionNumbering, InscriptionsReference, VerificationReport,
+};
+
+use super::harness::{IndexingPath, RegtestHarness, TransactionBuilder};
+
+const FEE: u64 = 1_000;
+const POSTAGE: u64 = 10_000;
+
+fn verify(
+    harness: &RegtestHarness,
+    config: &Config,
+    fixtures_dir: &TempDir,
+    reference: Vec<Value>,
+) -> VerificationReport {
+    let fixture = fixtures_dir.join("inscriptions.json");
+    std::fs::write(&fixture, json!(reference).to_string()).unwrap();
+    let reference = InscriptionsReference::from_fixture_file(&fixture).unwrap();
+    let inscriptions_db_conn =
+        open_readonly_ordhook_db_conn(&config.expected_cache_path(), &harness.ctx).unwrap();
+    hiro_system_kit::nestable_block_on(verify_inscriptions(
+        config,
+        &reference,
+        0,
+        harness.tip(),
+        10,
+        &InscriptionNumbering::Jubilee,
+        &inscriptions_db_conn,
+        &harness.ctx,
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_verify_inscriptions_against_fixture() {
+    let mut harness = RegtestHarness::new();
+    harness.mine_empty_blocks(1);
+
+    let coinbase_1 = harness.coinbase_outpoint(1);
+    let block = harness.mine(
+        vec![TransactionBuilder::new()
+            .reveal(
+                &coinbase_1,
+                &[Inscription::new(
+                    Some("text/plain;charset=utf-8".as_bytes().to_vec()),
+                    Some("verified".as_bytes().to_vec()),
+                )],
+            )
+            .output(POSTAGE)
+            .output(coinbase_1.value - POSTAGE - FEE)],
+        IndexingPath::Processor,
+    );
+    let reveal = &block.transactions[0];
+    let inscription_id = reveal.inscription_id(0);
+    let (traversal, _) = harness.find_inscription(&inscription_id).unwrap();
+
+    let bitcoind = MockBitcoind::start();
+    bitcoind.set_chain(harness.raw_blocks());
+    let mut config = harness.config.clone();
+    config.network.bitcoind_rpc_url = bitcoind.url.clone();
+
+    let fixtures_dir = TempDir::new("verify");
+    let mut reference = json!({
+        "id": inscription_id,
+        "number": 0,
+        "sat": traversal.ordinal_number,
+        "satpoint": reveal.outpoint(0).satpoint(0),
+        "address": block.raw_block["tx"][1]["vout"][0]["scriptPubKey"]["address"],
+    });
+    let report = verify(&harness, &config, &fixtures_dir, vec![reference.clone()]);
+    assert_eq!(report.sampled, 1);
+    assert!(report.unverified_owners.is_empty());
+    assert!(report.is_consistent());
+
+    // Owner diverging, attributed to the transaction holding the inscription
+    reference["address"] = json!("bcrt1qq0tuvdfqk4ed8lzkzwxvr2gwmqjxzwyl8v6nfm");
+    let report = verify(&harness, &config, &fixtures_dir, vec![reference.clone()]);
+    assert_eq!(
+        report
+            .divergences
+            .iter()
+            .map(|d| (d.field.as_str(), d.block_height, d.txid.as_str()))
+            .collect::<Vec<_>>(),
+        vec![("owner", 2, reveal.txid.as_str())]
+    );
+
+    // Owners that can't be resolved are reported as unverified, not as consistent
+    bitcoind.set_chain(vec![]);
+    let report = verify(&harness, &config, &fixtures_dir, vec![reference]);
+    assert_eq!(report.unverified_owners, vec![inscription_id.clone()]);
+    assert!(report.divergences.is_empty());
+
+    let report = verify(&harness, &config, &fixtures_dir, vec![]);
+    assert_eq!(report.missing_from_reference, vec![inscription_id]);
+    assert!(!report.is_consistent());
+}
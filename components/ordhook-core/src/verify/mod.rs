@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chainhook_sdk::bitcoincore_rpc::bitcoin::Txid;
+use chainhook_sdk::utils::Context;
+use rusqlite::{Connection, ToSql};
+
+use crate::config::Config;
+use crate::core::pipeline::block_source::{build_block_source, BlockSource};
+use crate::db::journal::find_latest_location_of_ordinal;
+use crate::db::{parse_inscription_id, parse_outpoint_to_watch, perform_query_set};
+use crate::utils::read_file_content_at_path;
+
+/// Inscription, as described by `ord server` (`GET /inscription/<id>` with `Accept: application/json`).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ReferenceInscription {
+    #[serde(alias = "inscription_id")]
+    pub id: String,
+    pub number: i64,
+    pub sat: Option<u64>,
+    pub satpoint: String,
+    pub address: Option<String>,
+}
+
+/// Source of truth the local index is compared with.
+pub enum InscriptionsReference {
+    OrdServer(String),
+    Fixture(HashMap<String, ReferenceInscription>),
+}
+
+impl InscriptionsReference {
+    /// Load a JSON array of inscriptions, formatted like the `ord server` responses.
+    pub fn from_fixture_file(path: &PathBuf) -> Result<InscriptionsReference, String> {
+        let bytes = read_file_content_at_path(path)?;
+        let inscriptions: Vec<ReferenceInscription> = serde_json::from_slice(&bytes)
+            .map_err(|e| format!("unable to parse {}: {}", path.display(), e.to_string()))?;
+        Ok(InscriptionsReference::Fixture(
+            inscriptions
+                .into_iter()
+                .map(|inscription| (inscription.id.clone(), inscription))
+                .collect(),
+        ))
+    }
+
+    pub async fn find_inscription(
+        &self,
+        inscription_id: &str,
+    ) -> Result<Option<ReferenceInscription>, String> {
+        match self {
+            InscriptionsReference::Fixture(inscriptions) => {
+                Ok(inscriptions.get(inscription_id).cloned())
+            }
+            InscriptionsReference::OrdServer(base_url) => {
+                let url = format!(
+                    "{}/inscription/{}",
+                    base_url.trim_end_matches('/'),
+                    inscription_id
+                );
+                let res = reqwest::Client::new()
+                    .get(&url)
+                    .header(reqwest::header::ACCEPT, "application/json")
+                    .send()
+                    .await
+                    .or(Err(format!("Failed to GET from '{}'", url)))?;
+                match res.status() {
+                    reqwest::StatusCode::OK => res
+                        .json::<ReferenceInscription>()
+                        .await
+                        .map(Some)
+                        .map_err(|e| format!("unable to parse response from '{}': {}", url, e)),
+                    reqwest::StatusCode::NOT_FOUND => Ok(None),
+                    status => Err(format!("Failed to GET from '{}' ({})", url, status)),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InscriptionNumbering {
+    Classic,
+    Jubilee,
+}
+
+/// Inscription, as described by the local index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalInscription {
+    pub inscription_id: String,
+    pub block_height: u64,
+    pub number: i64,
+    pub sat: u64,
+    pub satpoint: Option<String>,
+    pub location_block_height: Option<u64>,
+    pub owner: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Divergence {
+    pub inscription_id: String,
+    pub field: String,
+    pub local: Option<String>,
+    pub reference: Option<String>,
+    /// Block and transaction that introduced the divergent value (reveal or latest transfer).
+    pub block_height: u64,
+    pub txid: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct VerificationReport {
+    pub start_block: u64,
+    pub end_block: u64,
+    pub sampled: usize,
+    pub missing_from_reference: Vec<String>,
+    pub divergences: Vec<Divergence>,
+    /// Inscriptions whose owner couldn't be resolved with the block source, and wasn't compared.
+    pub unverified_owners: Vec<String>,
+}
+
+impl VerificationReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing_from_reference.is_empty() && self.divergences.is_empty()
+    }
+}
+
+pub fn compare_inscription(
+    local: &LocalInscription,
+    reference: &ReferenceInscription,
+) -> Vec<Divergence> {
+    let mut divergences = vec![];
+    let (reveal_txid, _) = parse_inscription_id(&local.inscription_id);
+    if local.number != reference.number {
+        divergences.push(Divergence {
+            inscription_id: local.inscription_id.clone(),
+            field: "number".into(),
+            local: Some(local.number.to_string()),
+            reference: Some(reference.number.to_string()),
+            block_height: local.block_height,
+            txid: reveal_txid.get_hash_bytes_str().to_string(),
+        });
+    }
+    if let Some(sat) = reference.sat {
+        if local.sat != sat {
+            divergences.push(Divergence {
+                inscription_id: local.inscription_id.clone(),
+                field: "sat".into(),
+                local: Some(local.sat.to_string()),
+                reference: Some(sat.to_string()),
+                block_height: local.block_height,
+                txid: reveal_txid.get_hash_bytes_str().to_string(),
+            });
+        }
+    }
+
+    // Location divergences are attributed to the latest transfer known locally
+    let (location_block_height, location_txid) = match local.satpoint {
+        Some(ref satpoint) => (
+            local.location_block_height.unwrap_or(local.block_height),
+            parse_outpoint_to_watch(satpoint)
+                .0
+                .get_hash_bytes_str()
+                .to_string(),
+        ),
+        None => (
+            local.block_height,
+            reveal_txid.get_hash_bytes_str().to_string(),
+        ),
+    };
+    if local.satpoint.as_ref() != Some(&reference.satpoint) {
+        divergences.push(Divergence {
+            inscription_id: local.inscription_id.clone(),
+            field: "satpoint".into(),
+            local: local.satpoint.clone(),
+            reference: Some(reference.satpoint.clone()),
+            block_height: location_block_height,
+            txid: location_txid.clone(),
+        });
+    }
+    if let (Some(owner), Some(address)) = (&local.owner, &reference.address) {
+        if owner != address {
+            divergences.push(Divergence {
+                inscription_id: local.inscription_id.clone(),
+                field: "owner".into(),
+                local: Some(owner.clone()),
+                reference: Some(address.clone()),
+                block_height: location_block_height,
+                txid: location_txid,
+            });
+        }
+    }
+    divergences
+}
+
+/// Pick `sample_size` inscriptions revealed between `start_block` and `end_block`.
+pub fn sample_inscriptions(
+    start_block: u64,
+    end_block: u64,
+    sample_size: usize,
+    numbering: &InscriptionNumbering,
+    inscriptions_db_conn: &Connection,
+    ctx: &Context,
+) -> Vec<LocalInscription> {
+    let args: &[&dyn ToSql] = &[
+        &start_block.to_sql().unwrap(),
+        &end_block.to_sql().unwrap(),
+        &(sample_size as u64).to_sql().unwrap(),
+    ];
+    let query = "SELECT inscription_id, block_height, ordinal_number, classic_inscription_number, jubilee_inscription_number
+        FROM inscriptions WHERE block_height >= ? AND block_height <= ? ORDER BY RANDOM() LIMIT ?";
+    let mut inscriptions = perform_query_set(query, args, inscriptions_db_conn, ctx, |row| {
+        let classic: i64 = row.get(3).unwrap();
+        let jubilee: i64 = row.get(4).unwrap();
+        LocalInscription {
+            inscription_id: row.get(0).unwrap(),
+            block_height: row.get(1).unwrap(),
+            sat: row.get(2).unwrap(),
+            number: match numbering {
+                InscriptionNumbering::Classic => classic,
+                InscriptionNumbering::Jubilee => jubilee,
+            },
+            satpoint: None,
+            location_block_height: None,
+            owner: None,
+        }
+    });
+    for inscription in inscriptions.iter_mut() {
+        if let Some(location) =
+            find_latest_location_of_ordinal(inscription.sat, inscriptions_db_conn, ctx)
+        {
+            inscription.satpoint = Some(format!(
+                "{}:{}",
+                location.outpoint_to_watch, location.offset
+            ));
+            inscription.location_block_height = Some(location.block_height);
+        }
+    }
+    inscriptions.sort_by(|a, b| a.block_height.cmp(&b.block_height));
+    inscriptions
+}
+
+/// Resolve the address holding `satpoint`, using the configured block source.
+fn find_owner_of_satpoint(
+    satpoint: &str,
+    config: &Config,
+    block_source: &dyn BlockSource,
+    ctx: &Context,
+) -> Result<String, String> {
+    let (txid, vout) = parse_outpoint_to_watch(satpoint);
+    let txid = Txid::from_str(txid.get_hash_bytes_str())
+        .map_err(|e| format!("invalid txid {}: {}", txid.get_hash_bytes_str(), e))?;
+    let script = block_source.get_output_script(&txid, vout as u32, ctx)?;
+    config
+        .network_params
+        .address_from_script(&script)
+        .map_err(|e| format!("unable to retrieve address of {}: {}", satpoint, e))
+}
+
+/// Compare a sample of the local inscriptions with `reference`.
+pub async fn verify_inscriptions(
+    config: &Config,
+    reference: &InscriptionsReference,
+    start_block: u64,
+    end_block: u64,
+    sample_size: usize,
+    numbering: &InscriptionNumbering,
+    inscriptions_db_conn: &Connection,
+    ctx: &Context,
+) -> Result<VerificationReport, String> {
+    config.check_sqlite_inscriptions_index("inscriptions verification")?;
+    let mut inscriptions = sample_inscriptions(
+        start_block,
+        end_block,
+        sample_size,
+        numbering,
+        inscriptions_db_conn,
+        ctx,
+    );
+
+    let mut report = VerificationReport {
+        start_block,
+        end_block,
+        sampled: inscriptions.len(),
+        ..Default::default()
+    };
+
+    // Owners are not indexed: they are resolved with the block source when it can
+    let mut last_error = None;
+    {
+        let block_source = build_block_source(config, ctx);
+        for inscription in inscriptions.iter_mut() {
+            let Some(ref satpoint) = inscription.satpoint else {
+                continue;
+            };
+            let owner = match block_source {
+                Ok(ref block_source) => {
+                    find_owner_of_satpoint(satpoint, config, block_source.as_ref(), ctx)
+                }
+                Err(ref e) => Err(e.clone()),
+            };
+            match owner {
+                Ok(owner) => inscription.owner = Some(owner),
+                Err(e) => {
+                    report
+                        .unverified_owners
+                        .push(inscription.inscription_id.clone());
+                    last_error = Some(e);
+                }
+            }
+        }
+    }
+    if let Some(e) = last_error {
+        ctx.try_log(|logger| {
+            warn!(
+                logger,
+                "Owners of {} inscriptions won't be compared: {e}",
+                report.unverified_owners.len()
+            )
+        });
+    }
+
+    for inscription in inscriptions.iter() {
+        match reference
+            .find_inscription(&inscription.inscription_id)
+            .await?
+        {
+            Some(reference_inscription) => {
+                let divergences = compare_inscription(inscription, &reference_inscription);
+                for divergence in divergences.iter() {
+                    ctx.try_log(|logger| {
+                        warn!(
+                            logger,
+                            "Inscription {} diverging on {} (block #{}, transaction {})",
+                            divergence.inscription_id,
+                            divergence.field,
+                            divergence.block_height,
+                            divergence.txid
+                        )
+                    });
+                }
+                report.divergences.extend(divergences);
+            }
+            None => report
+                .missing_from_reference
+                .push(inscription.inscription_id.clone()),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_inscription() {
+        let txid = "b61b0172d95e266c18aea0c624db987e971a5d6d4ebc2aaed85da4642d635735";
+        let transfer_txid = "0b69a9b1b5d6a26b3f06d8d4f7b9e3a2a2e09c3e4c7ad4ac8a1f4e5c6d7e8f90";
+        let local = LocalInscription {
+            inscription_id: format!("{txid}i0"),
+            block_height: 779832,
+            number: 0,
+            sat: 1252201400444387,
+            satpoint: Some(format!("{transfer_txid}:0:0")),
+            location_block_height: Some(790000),
+            owner: Some("bc1pxyz".into()),
+        };
+        let reference = ReferenceInscription {
+            id: format!("{txid}i0"),
+            number: 0,
+            sat: Some(1252201400444387),
+            satpoint: format!("{transfer_txid}:0:0"),
+            address: Some("bc1pxyz".into()),
+        };
+        assert!(compare_inscription(&local, &reference).is_empty());
+
+        let reference = ReferenceInscription {
+            number: 1,
+            satpoint: format!("{transfer_txid}:1:0"),
+            ..reference
+        };
+        let divergences = compare_inscription(&local, &reference);
+        assert_eq!(
+            divergences
+                .iter()
+                .map(|d| (d.field.as_str(), d.block_height, d.txid.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("number", 779832, txid),
+                ("satpoint", 790000, transfer_txid)
+            ]
+        );
+    }
+}